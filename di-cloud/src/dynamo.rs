@@ -57,6 +57,48 @@ impl DynamoClient {
         }
     }
 
+    pub async fn delete_item(&self, table: &str, key: HashMap<String, AttributeValue>) -> Result<(), String> {
+        match self.db.delete_item(DeleteItemInput {
+            table_name: table.into(),
+            key, ..Default::default()
+        }).await {
+            Ok(_resp) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Deletes every item whose partition key `pk` equals `value`, returning
+    /// how many were removed. `sk` is the table's sort key.
+    pub async fn delete_partition(&self, table: &str, pk: &str, sk: &str, value: AttributeValue)
+        -> Result<usize, String>
+    {
+        let mut deleted = 0;
+        let mut start_key = None;
+        loop {
+            let page = self.db.query(QueryInput {
+                table_name: table.into(),
+                key_condition_expression: Some("#pk = :v".into()),
+                expression_attribute_names: Some(vec![
+                    ("#pk".to_string(), pk.to_string()),
+                    ("#sk".to_string(), sk.to_string()),
+                ].into_iter().collect()),
+                expression_attribute_values: Some(vec![(":v".to_string(), value.clone())]
+                    .into_iter().collect()),
+                projection_expression: Some("#pk, #sk".into()),
+                exclusive_start_key: start_key.take(),
+                ..Default::default()
+            }).await.map_err(|e| e.to_string())?;
+            for key in page.items.unwrap_or_default() {
+                self.delete_item(table, key).await?;
+                deleted += 1;
+            }
+            match page.last_evaluated_key {
+                Some(key) if !key.is_empty() => start_key = Some(key),
+                _ => return Ok(deleted),
+            }
+        }
+    }
+
     pub async fn delete_table(&self, _table: &str) -> () {}
 
     pub async fn create_table(&self,
@@ -88,18 +88,24 @@ impl Client {
         }
     }
 
+    /// Every object under the prefix, following continuation tokens until
+    /// the listing is complete. An empty prefix gives an empty list.
     pub async fn list_objects(&self, bucket: &str, path: Option<String>)
         -> Result<Vec<Object>, String> 
     {
-        match self.s3.list_objects_v2(ListObjectsV2Request {
-            bucket: bucket.into(),
-            prefix: path, ..Default::default()  //TODO should handle continuation token
-        }).await {
-            Ok(resp) => match resp.contents {
-                Some(contents) => Ok(contents),
-                None => Err("No objects in bucket + prefix".into()),
-            },
-            Err(err) => Err(err.to_string())
+        let mut objects = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let resp = self.s3.list_objects_v2(ListObjectsV2Request {
+                bucket: bucket.into(),
+                prefix: path.clone(),
+                continuation_token, ..Default::default()
+            }).await.map_err(|err| err.to_string())?;
+            objects.extend(resp.contents.unwrap_or_default());
+            match (resp.is_truncated, resp.next_continuation_token) {
+                (Some(true), Some(token)) => continuation_token = Some(token),
+                _ => return Ok(objects),
+            }
         }
     }
 
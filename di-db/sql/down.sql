@@ -1,10 +1,23 @@
 DROP TABLE Users CASCADE;
-DROP TABLE UserInfo CASCADE;
+DROP TABLE user_info CASCADE;
 DROP TABLE Items CASCADE;
 DROP TABLE FactTypes CASCADE;
 DROP TABLE FactEntries CASCADE;
 DROP TABLE Records CASCADE;
 DROP TABLE Groups CASCADE;
+DROP TABLE oauth_tokens CASCADE;
+DROP TABLE oauth_codes CASCADE;
+DROP TABLE oauth_clients CASCADE;
+DROP TABLE user_tokens CASCADE;
+DROP TABLE user_roles CASCADE;
+DROP TABLE admin_actions CASCADE;
+DROP TABLE record_items CASCADE;
+DROP TABLE audit_log CASCADE;
+DROP TABLE attributes CASCADE;
+DROP TABLE note_revisions CASCADE;
+DROP TABLE notes CASCADE;
+DROP TABLE record_entry_facts CASCADE;
+DROP TABLE record_entries CASCADE;
 
 /*
 DROP TABLE Fields CASCADE;
@@ -34,3 +47,17 @@ DROP TABLE FieldTags CASCADE;
 
 DROP TABLE Relations;
 */
+DROP TABLE entity_tags CASCADE;
+DROP TABLE tags CASCADE;
+DROP TABLE item_field_entries CASCADE;
+DROP TABLE item_fields CASCADE;
+DROP TABLE templated_records CASCADE;
+DROP TABLE record_templates CASCADE;
+DROP TABLE fact_entry_options CASCADE;
+DROP TABLE fact_type_ranges CASCADE;
+DROP TABLE fact_type_options CASCADE;
+DROP VIEW fact_entry_values;
+DROP TABLE units CASCADE;
+DROP TABLE timer_spans CASCADE;
+DROP TABLE timers CASCADE;
+DROP TABLE fact_entry_places CASCADE;
@@ -11,6 +11,18 @@ create type if not exists public.visibility as enum (
     'public'
 )
 
+create type if not exists public.token_kind as enum (
+    'password_reset',
+    'email_verification'
+)
+
+create type if not exists public.user_type as enum (
+    'administrator',
+    'associate',
+    'moderator',
+    'user'
+)
+
 create type if not exists public.value_type (
     'integer',
     'decimal',
@@ -30,16 +42,23 @@ CREATE TABLE IF NOT EXISTS public.users (
     email       TEXT NOT NULL UNIQUE,
     username    TEXT NOT NULL UNIQUE CHECK (char_length(username) < 40),
     password    TEXT DEFAULT NULL,
+    confirmed   BOOLEAN NOT NULL DEFAULT FALSE,
     created_at  TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- accounts created before email verification existed are treated as confirmed
+ALTER TABLE public.users ADD COLUMN IF NOT EXISTS confirmed BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE public.users ALTER COLUMN confirmed SET DEFAULT FALSE;
+ALTER TABLE public.users ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
 CREATE TABLE IF NOT EXISTS public.user_info (
     id UUID NOT NULL UNIQUE PRIMARY KEY DEFAULT gen_random_uuid(),
-    uid UUID NOT NULL REFERENCES Users(id),
+    uid UUID NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
     first_name   TEXT CHECK (CHAR_LENGTH(first_name) < 80),
     mid_initial  CHAR,
-    last_name    TEXT CHECK (CHAR_LENGTH(first_name) < 80),
-    phone_number TEXT CHECK (CHAR_LENGTH(phone_number) < 10),
+    last_name    TEXT CHECK (CHAR_LENGTH(last_name) < 80),
+    phone_number TEXT CHECK (CHAR_LENGTH(phone_number) <= 16),
     birth_date   DATE,
     occupation   TEXT,
     bio          TEXT,
@@ -48,11 +67,11 @@ CREATE TABLE IF NOT EXISTS public.user_info (
     city         TEXT,
     zip_code     TEXT,
     state        TEXT,
-    country      TEXT,
-    social_links JSON,
-    experience   INTEGER  NOT NULL,
-    user_type    INTEGER  NOT NULL,
-    updated_at   TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+    country      CHAR(2),
+    social_links JSONB,
+    experience   INTEGER  NOT NULL DEFAULT 0,
+    user_type    user_type NOT NULL DEFAULT 'user'::public.user_type,
+    updated_at   TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
     UNIQUE (uid)
 );
 
@@ -64,9 +83,12 @@ CREATE TABLE IF NOT EXISTS public.groups (
     visibility visibility DEFAULT 'private'::public.visibility,
     status status DEFAULT 'active'::public.status,
     attributes text[],
-    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    deleted_at TIMESTAMPTZ
 );
 
+ALTER TABLE public.groups ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
 CREATE TABLE IF NOT EXISTS public.records (
     id         UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
     uid UUID NOT NULL REFERENCES Users(id),
@@ -77,9 +99,14 @@ CREATE TABLE IF NOT EXISTS public.records (
     notes text[],
     attributes text[],
     created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at TIMESTAMPTZ,
     UNIQUE (name, uid)
 );
 
+ALTER TABLE public.records ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE public.records ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
 CREATE TABLE IF NOT EXISTS public.items (
     id         UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
     uid UUID NOT NULL REFERENCES Users(id),
@@ -90,9 +117,21 @@ CREATE TABLE IF NOT EXISTS public.items (
     notes text[],
     attributes text[],
     created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at TIMESTAMPTZ,
     UNIQUE (name, uid)
 );
 
+ALTER TABLE public.items ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE public.items ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS public.record_items (
+    rid UUID NOT NULL REFERENCES records(id) ON DELETE CASCADE,
+    iid UUID NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (rid, iid)
+);
+
 CREATE TABLE IF NOT EXISTS public.fact_types (
     id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
     uid UUID NOT NULL REFERENCES Users(id),
@@ -105,9 +144,12 @@ CREATE TABLE IF NOT EXISTS public.fact_types (
     notes text[],
     attributes text[],
     created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    deleted_at TIMESTAMPTZ,
     UNIQUE (uid, name)
 );
 
+ALTER TABLE public.fact_types ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
 CREATE TABLE IF NOT EXISTS public.fact_entries (
     id         UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
     uid UUID NOT NULL REFERENCES Users(id),
@@ -117,6 +159,319 @@ CREATE TABLE IF NOT EXISTS public.fact_entries (
     visibility visibility DEFAULT 'private'::public.visibility,
     notes text[],
     attributes text[],
-    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
-    UNIQUE (uid, name)
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE public.fact_entries DROP CONSTRAINT IF EXISTS fact_entries_uid_name_key;
+
+CREATE TABLE IF NOT EXISTS public.fact_type_options (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    tid UUID NOT NULL REFERENCES fact_types(id) ON DELETE CASCADE,
+    label TEXT NOT NULL CHECK (CHAR_LENGTH(label) < 80),
+    position INTEGER NOT NULL DEFAULT 0,
+    retired_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (tid, label)
 );
+
+CREATE TABLE IF NOT EXISTS public.fact_type_ranges (
+    tid UUID NOT NULL PRIMARY KEY REFERENCES fact_types(id) ON DELETE CASCADE,
+    min DOUBLE PRECISION NOT NULL,
+    max DOUBLE PRECISION NOT NULL,
+    step DOUBLE PRECISION CHECK (step > 0),
+    CHECK (min <= max)
+);
+
+CREATE TABLE IF NOT EXISTS public.fact_entry_options (
+    eid UUID NOT NULL REFERENCES fact_entries(id) ON DELETE CASCADE,
+    oid UUID NOT NULL REFERENCES fact_type_options(id) ON DELETE CASCADE,
+    PRIMARY KEY (eid, oid)
+);
+
+CREATE INDEX IF NOT EXISTS fact_entry_options_option ON public.fact_entry_options (oid);
+
+CREATE TABLE IF NOT EXISTS public.units (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID REFERENCES Users(id),
+    symbol TEXT NOT NULL CHECK (CHAR_LENGTH(symbol) BETWEEN 1 AND 20),
+    name TEXT NOT NULL CHECK (CHAR_LENGTH(name) < 80),
+    dimension TEXT NOT NULL CHECK (CHAR_LENGTH(dimension) BETWEEN 1 AND 40),
+    factor DOUBLE PRECISION NOT NULL CHECK (factor > 0),
+    shift DOUBLE PRECISION NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS units_builtin_symbol ON public.units (LOWER(symbol)) WHERE uid IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS units_user_symbol ON public.units (uid, LOWER(symbol)) WHERE uid IS NOT NULL;
+
+INSERT INTO public.units (symbol, name, dimension, factor, shift) VALUES
+    ('kg', 'kilogram', 'mass', 1, 0),
+    ('g', 'gram', 'mass', 0.001, 0),
+    ('mg', 'milligram', 'mass', 0.000001, 0),
+    ('lb', 'pound', 'mass', 0.45359237, 0),
+    ('oz', 'ounce', 'mass', 0.028349523125, 0),
+    ('st', 'stone', 'mass', 6.35029318, 0),
+    ('m', 'metre', 'length', 1, 0),
+    ('km', 'kilometre', 'length', 1000, 0),
+    ('cm', 'centimetre', 'length', 0.01, 0),
+    ('mm', 'millimetre', 'length', 0.001, 0),
+    ('mi', 'mile', 'length', 1609.344, 0),
+    ('yd', 'yard', 'length', 0.9144, 0),
+    ('ft', 'foot', 'length', 0.3048, 0),
+    ('in', 'inch', 'length', 0.0254, 0),
+    ('s', 'second', 'time', 1, 0),
+    ('min', 'minute', 'time', 60, 0),
+    ('h', 'hour', 'time', 3600, 0),
+    ('d', 'day', 'time', 86400, 0),
+    ('l', 'litre', 'volume', 1, 0),
+    ('ml', 'millilitre', 'volume', 0.001, 0),
+    ('gal', 'US gallon', 'volume', 3.785411784, 0),
+    ('fl oz', 'US fluid ounce', 'volume', 0.0295735295625, 0),
+    ('c', 'degree Celsius', 'temperature', 1, 0),
+    ('f', 'degree Fahrenheit', 'temperature', 0.5555555555555556, -17.77777777777778),
+    ('k', 'kelvin', 'temperature', 1, -273.15),
+    ('kcal', 'kilocalorie', 'energy', 1, 0),
+    ('kj', 'kilojoule', 'energy', 0.2390057361376673, 0)
+ON CONFLICT DO NOTHING;
+
+CREATE OR REPLACE VIEW public.fact_entry_values AS
+    SELECT e.id, e.uid, e.name, e.units, u.dimension,
+           e.value::DOUBLE PRECISION * u.factor + u.shift AS base_value, e.created_at
+    FROM public.fact_entries e
+    -- The unit the entry was logged in, by symbol or else by name, as UnitRegistry::find matches it
+    CROSS JOIN LATERAL (
+        SELECT * FROM public.units u
+        WHERE (LOWER(u.symbol) = LOWER(TRIM(e.units)) OR LOWER(u.name) = LOWER(TRIM(e.units)))
+        AND (u.uid IS NULL OR u.uid = e.uid)
+        ORDER BY LOWER(u.symbol) = LOWER(TRIM(e.units)) DESC, u.dimension, u.factor, u.symbol
+        LIMIT 1
+    ) u
+    WHERE e.value ~ '^\s*-?[0-9]+(\.[0-9]+)?([eE][-+]?[0-9]+)?\s*$';
+
+CREATE TABLE IF NOT EXISTS public.item_fields (
+    iid UUID NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    tid UUID NOT NULL REFERENCES fact_types(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (iid, tid)
+);
+
+CREATE TABLE IF NOT EXISTS public.item_field_entries (
+    eid UUID NOT NULL PRIMARY KEY REFERENCES fact_entries(id) ON DELETE CASCADE,
+    iid UUID NOT NULL,
+    tid UUID NOT NULL,
+    FOREIGN KEY (iid, tid) REFERENCES item_fields(iid, tid) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS item_field_entries_field ON public.item_field_entries (iid, tid);
+
+CREATE TABLE IF NOT EXISTS public.record_entries (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    rid UUID NOT NULL REFERENCES records(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    content TEXT NOT NULL CHECK (CHAR_LENGTH(content) <= 20000),
+    logged_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS record_entries_logged ON public.record_entries (rid, logged_at);
+
+CREATE TABLE IF NOT EXISTS public.record_entry_facts (
+    eid UUID NOT NULL REFERENCES record_entries(id) ON DELETE CASCADE,
+    fid UUID NOT NULL REFERENCES fact_entries(id) ON DELETE CASCADE,
+    PRIMARY KEY (eid, fid)
+);
+
+CREATE TABLE IF NOT EXISTS public.attributes (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    entity TEXT NOT NULL CHECK (entity IN ('records', 'items', 'groups', 'fact_types')),
+    entity_id UUID NOT NULL,
+    key TEXT NOT NULL CHECK (CHAR_LENGTH(key) < 80),
+    value_type TEXT NOT NULL DEFAULT 'text',
+    value JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (entity, entity_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS attributes_lookup ON public.attributes (uid, entity, key, value);
+
+CREATE TABLE IF NOT EXISTS public.notes (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    entity TEXT NOT NULL CHECK (entity IN ('records', 'items', 'fact_entries', 'groups')),
+    entity_id UUID NOT NULL,
+    content TEXT NOT NULL CHECK (CHAR_LENGTH(content) <= 20000),
+    pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    visibility visibility NOT NULL DEFAULT 'private'::public.visibility,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS notes_entity ON public.notes (entity, entity_id);
+
+CREATE TABLE IF NOT EXISTS public.note_revisions (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    nid UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS public.record_templates (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    name TEXT NOT NULL CHECK (CHAR_LENGTH(name) < 80),
+    version INTEGER NOT NULL DEFAULT 1 CHECK (version > 0),
+    description TEXT,
+    definition JSONB NOT NULL,
+    visibility visibility NOT NULL DEFAULT 'private'::public.visibility,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (uid, name, version)
+);
+
+CREATE TABLE IF NOT EXISTS public.templated_records (
+    rid UUID NOT NULL PRIMARY KEY REFERENCES records(id) ON DELETE CASCADE,
+    tid UUID NOT NULL REFERENCES record_templates(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    migrated_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS public.tags (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    namespace TEXT NOT NULL DEFAULT '' CHECK (CHAR_LENGTH(namespace) < 80),
+    name TEXT NOT NULL CHECK (CHAR_LENGTH(name) BETWEEN 1 AND 79),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (uid, namespace, name)
+);
+
+CREATE TABLE IF NOT EXISTS public.entity_tags (
+    tid UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    entity TEXT NOT NULL CHECK (entity IN ('records', 'items', 'fact_types', 'groups')),
+    entity_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (tid, entity, entity_id)
+);
+
+CREATE INDEX IF NOT EXISTS entity_tags_entity ON public.entity_tags (entity, entity_id);
+
+CREATE TABLE IF NOT EXISTS public.oauth_clients (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    name TEXT NOT NULL CHECK (CHAR_LENGTH(name) < 80),
+    secret TEXT NOT NULL,
+    redirect_uris TEXT[] NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS public.oauth_codes (
+    code TEXT NOT NULL PRIMARY KEY,
+    client_id UUID NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    redirect_uri TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS public.oauth_tokens (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    access_token TEXT NOT NULL UNIQUE,
+    refresh_hash TEXT UNIQUE,
+    client_id UUID NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    scopes TEXT[] NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    refresh_expires_at TIMESTAMPTZ,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Refresh tokens are stored as their SHA-256 only. Ones issued in plain text
+-- before that are dropped, so their clients have to authorize again.
+ALTER TABLE public.oauth_tokens DROP COLUMN IF EXISTS refresh_token;
+ALTER TABLE public.oauth_tokens ADD COLUMN IF NOT EXISTS refresh_hash TEXT UNIQUE;
+ALTER TABLE public.oauth_tokens ADD COLUMN IF NOT EXISTS refresh_expires_at TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS public.user_tokens (
+    token TEXT NOT NULL PRIMARY KEY,
+    uid UUID NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    kind token_kind NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Bootstrap the first administrator with
+-- INSERT INTO user_roles (uid, role) VALUES ('<user id>', 'administrator');
+CREATE TABLE IF NOT EXISTS public.user_roles (
+    uid UUID NOT NULL PRIMARY KEY REFERENCES Users(id) ON DELETE CASCADE,
+    role user_type NOT NULL DEFAULT 'user'::public.user_type,
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS public.admin_actions (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID REFERENCES Users(id) ON DELETE SET NULL,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Rows are only ever inserted. `uid` is the owner of the changed row, so the
+-- entries go when the account does.
+CREATE TABLE IF NOT EXISTS public.audit_log (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL,
+    actor UUID,
+    action TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    before JSONB,
+    after JSONB,
+    diff JSONB NOT NULL DEFAULT '{}',
+    request_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_entity ON public.audit_log (entity, entity_id, created_at);
+
+CREATE TABLE IF NOT EXISTS public.timers (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    uid UUID NOT NULL REFERENCES Users(id),
+    tid UUID NOT NULL REFERENCES fact_types(id) ON DELETE CASCADE,
+    rid UUID REFERENCES records(id) ON DELETE CASCADE,
+    iid UUID REFERENCES items(id) ON DELETE CASCADE,
+    eid UUID REFERENCES fact_entries(id) ON DELETE SET NULL,
+    note TEXT CHECK (CHAR_LENGTH(note) <= 2000),
+    stopped_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS timers_user ON public.timers (uid, created_at);
+
+CREATE TABLE IF NOT EXISTS public.timer_spans (
+    timer UUID NOT NULL REFERENCES timers(id) ON DELETE CASCADE,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMPTZ CHECK (ended_at >= started_at),
+    PRIMARY KEY (timer, started_at)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS timer_spans_open ON public.timer_spans (timer) WHERE ended_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS public.fact_entry_places (
+    eid UUID NOT NULL PRIMARY KEY REFERENCES fact_entries(id) ON DELETE CASCADE,
+    uid UUID NOT NULL REFERENCES Users(id),
+    lat DOUBLE PRECISION NOT NULL CHECK (lat BETWEEN -90 AND 90),
+    lon DOUBLE PRECISION NOT NULL CHECK (lon BETWEEN -180 AND 180),
+    label TEXT CHECK (CHAR_LENGTH(label) <= 500),
+    address TEXT CHECK (CHAR_LENGTH(address) <= 500)
+);
+
+CREATE INDEX IF NOT EXISTS fact_entry_places_coords ON public.fact_entry_places (uid, lat, lon);
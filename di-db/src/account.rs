@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use serde_json::Value;
+use sqlx::Postgres;
+use uuid::Uuid;
+use crate::{db::Db, browse::redact};
+
+/// Tables with a `uid` column pointing at the owning user, listed so that
+/// rows are deleted before anything they reference. `admin_actions` is left
+/// out on purpose: its `uid` is set to NULL, which keeps the audit trail but
+/// drops the link to the person.
+pub const OWNED_TABLES: &[&str] = &[
+    "audit_log",
+    "attributes",
+    "note_revisions",
+    "notes",
+    "record_entries",
+    "timers",
+    "tags",
+    "units",
+    "item_fields",
+    "fact_entry_places",
+    "fact_entries",
+    "fact_types",
+    "items",
+    "records",
+    "record_templates",
+    "groups",
+    "oauth_tokens",
+    "oauth_codes",
+    "oauth_clients",
+    "user_tokens",
+    "user_roles",
+    "user_info",
+];
+
+impl Db {
+
+    /// Every row the user owns, keyed by table, with secrets redacted. The
+    /// account itself is under `users`.
+    pub async fn export_account(&self, uid: Uuid) -> sqlx::Result<BTreeMap<String, Vec<Value>>> {
+        let applied = self.migration_status().await?.applied;
+        let mut tables = BTreeMap::new();
+        let user: Vec<Value> = sqlx::query_scalar::<Postgres, Value>(
+            "SELECT row_to_json(t) FROM public.users t WHERE t.id=$1")
+            .bind(uid)
+            .fetch_all(&self.pool).await?;
+        tables.insert("users".to_string(), user.into_iter().map(without_password).collect());
+        for table in OWNED_TABLES.iter().filter(|t| applied.iter().any(|a| a == *t)) {
+            let rows: Vec<Value> = sqlx::query_scalar::<Postgres, Value>(&format!(
+                "SELECT row_to_json(t) FROM public.\"{}\" t WHERE t.uid=$1", table))
+                .bind(uid)
+                .fetch_all(&self.pool).await?;
+            tables.insert(table.to_string(), rows.into_iter().map(redact).collect());
+        }
+        Ok(tables)
+    }
+
+    /// Deletes the user and everything they own in one transaction, returning
+    /// the number of rows removed per table. `None` if there is no such user.
+    pub async fn delete_account(&self, uid: Uuid) -> sqlx::Result<Option<BTreeMap<String, u64>>> {
+        let applied = self.migration_status().await?.applied;
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = BTreeMap::new();
+        for table in OWNED_TABLES.iter().filter(|t| applied.iter().any(|a| a == *t)) {
+            let n = sqlx::query(&format!("DELETE FROM public.\"{}\" WHERE uid=$1", table))
+                .bind(uid)
+                .execute(&mut tx).await?
+                .rows_affected();
+            deleted.insert(table.to_string(), n);
+        }
+        if applied.iter().any(|a| a == "admin_actions") {
+            sqlx::query("UPDATE public.admin_actions SET uid=NULL WHERE uid=$1")
+                .bind(uid)
+                .execute(&mut tx).await?;
+        }
+        let users = sqlx::query("DELETE FROM public.users WHERE id=$1")
+            .bind(uid)
+            .execute(&mut tx).await?
+            .rows_affected();
+        if users == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+        deleted.insert("users".to_string(), users);
+        tx.commit().await?;
+        Ok(Some(deleted))
+    }
+}
+
+fn without_password(mut row: Value) -> Value {
+    if let Value::Object(map) = &mut row {
+        map.remove("password");
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_tables_exist_in_schema() {
+        let schema = Db::schema_tables();
+        for table in OWNED_TABLES {
+            assert!(schema.iter().any(|t| t == table), "{} is not in up.sql", table);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use sqlx::{Executor, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{
+    db::Db,
+    bulk::{Entity, BulkRow, insert_row},
+    models::{Record, Item, FactType, FactEntry, fact::place::index_entry},
+};
+
+/// One step of a batch, validated and with every reference to an earlier
+/// step already replaced by its id
+pub enum BatchOp {
+    Create(BulkRow),
+    /// Writes the editable columns of the row. With a timestamp, only if the
+    /// row hasn't been updated since; fact rows don't track updates.
+    Update(BulkRow, Option<DateTime<Utc>>),
+    /// Records and items go to the trash, facts are removed
+    Delete(Entity, Uuid),
+    Link { rid: Uuid, iid: Uuid },
+}
+
+pub enum BatchOutcome {
+    Row(BulkRow),
+    Deleted(Entity, Uuid),
+    Linked { rid: Uuid, iid: Uuid },
+}
+
+/// Why a step failed, which rolled back the whole batch
+#[derive(Debug)]
+pub enum BatchFailure {
+    /// The row doesn't exist, isn't the user's, or changed concurrently
+    NotFound,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for BatchFailure {
+    fn from(e: sqlx::Error) -> Self { BatchFailure::Database(e) }
+}
+
+impl Db {
+
+    /// Runs every step for `uid` in one transaction. On failure nothing is
+    /// kept and the index of the failing step is returned with the reason.
+    pub async fn run_batch(&self, uid: Uuid, ops: &[BatchOp])
+        -> Result<Vec<BatchOutcome>, (usize, BatchFailure)>
+    {
+        let mut tx = self.pool.begin().await.map_err(|e| (0, e.into()))?;
+        let mut outcomes = Vec::with_capacity(ops.len());
+        for (i, op) in ops.iter().enumerate() {
+            match run_op(&mut tx, uid, op).await {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(failure) => {
+                    tx.rollback().await.map_err(|e| (i, e.into()))?;
+                    return Err((i, failure));
+                },
+            }
+        }
+        tx.commit().await.map_err(|e| (ops.len(), e.into()))?;
+        Ok(outcomes)
+    }
+
+    /// The user's row of `entity` with this id
+    pub async fn get_row(&self, entity: Entity, uid: Uuid, id: Uuid) -> sqlx::Result<Option<BulkRow>> {
+        fetch_row(&self.pool, entity, uid, id).await
+    }
+}
+
+async fn run_op(tx: &mut Transaction<'_, Postgres>, uid: Uuid, op: &BatchOp)
+    -> Result<BatchOutcome, BatchFailure>
+{
+    match op {
+        BatchOp::Create(row) => {
+            insert_row(&mut *tx, row).await?;
+            let created = fetch_row(&mut *tx, row.entity(), uid, row.id()).await?
+                .ok_or(BatchFailure::NotFound)?;
+            audit(tx, "create", None, Some(&created)).await?;
+            Ok(BatchOutcome::Row(created))
+        },
+        BatchOp::Update(row, since) => {
+            let before = fetch_row(&mut *tx, row.entity(), uid, row.id()).await?;
+            let updated = update_row(tx, uid, row, *since).await?
+                .ok_or(BatchFailure::NotFound)?;
+            audit(tx, "update", before.as_ref(), Some(&updated)).await?;
+            Ok(BatchOutcome::Row(updated))
+        },
+        BatchOp::Delete(entity, id) => {
+            let before = fetch_row(&mut *tx, *entity, uid, *id).await?;
+            let sql = match entity {
+                Entity::Records | Entity::Items => format!(
+                    "UPDATE public.{} SET status='deleted', deleted_at=CURRENT_TIMESTAMP,
+                        updated_at=CURRENT_TIMESTAMP
+                     WHERE id=$1 AND uid=$2 AND status <> 'deleted'", entity.table()),
+                Entity::FactTypes =>
+                    "UPDATE public.fact_types SET status='deleted', deleted_at=CURRENT_TIMESTAMP
+                     WHERE id=$1 AND uid=$2 AND status <> 'deleted'".to_string(),
+                _ => format!("DELETE FROM public.{} WHERE id=$1 AND uid=$2", entity.table()),
+            };
+            let n = sqlx::query(&sql).bind(id).bind(uid).execute(&mut *tx).await?.rows_affected();
+            if n == 0 { return Err(BatchFailure::NotFound) }
+            match entity {
+                Entity::Records | Entity::Items | Entity::FactTypes => {
+                    let after = fetch_row(&mut *tx, *entity, uid, *id).await?;
+                    audit(tx, "trash", before.as_ref(), after.as_ref()).await?;
+                },
+                _ => audit(tx, "delete", before.as_ref(), None).await?,
+            }
+            let dependents: &[&str] = match entity {
+                Entity::FactEntries => &["DELETE FROM public.notes WHERE entity='fact_entries' AND entity_id=$1"],
+                _ => &[],
+            };
+            for sql in dependents {
+                sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+            }
+            Ok(BatchOutcome::Deleted(*entity, *id))
+        },
+        BatchOp::Link { rid, iid } => {
+            let owned: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM public.records r, public.items i
+                 WHERE r.id=$1 AND i.id=$2 AND r.uid=$3 AND i.uid=$3)")
+                .bind(rid).bind(iid).bind(uid)
+                .fetch_one(&mut *tx).await?;
+            if !owned { return Err(BatchFailure::NotFound) }
+            sqlx::query("INSERT INTO public.record_items (rid, iid) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+                .bind(rid).bind(iid)
+                .execute(&mut *tx).await?;
+            Ok(BatchOutcome::Linked { rid: *rid, iid: *iid })
+        },
+    }
+}
+
+async fn audit(tx: &mut Transaction<'_, Postgres>, action: &str, before: Option<&BulkRow>, after: Option<&BulkRow>)
+    -> sqlx::Result<()>
+{
+    match BulkRow::audit_entry(action, before, after) {
+        Some(entry) => entry.insert(tx).await,
+        None => Ok(()),
+    }
+}
+
+async fn fetch_row<'e, E>(ex: E, entity: Entity, uid: Uuid, id: Uuid) -> sqlx::Result<Option<BulkRow>>
+where E: Executor<'e, Database = Postgres>
+{
+    let sql = format!("SELECT * FROM public.{} WHERE id=$1 AND uid=$2", entity.table());
+    let row = match entity {
+        Entity::Records => sqlx::query_as::<Postgres, Record>(&sql).bind(id).bind(uid)
+            .fetch_optional(ex).await?.map(BulkRow::Record),
+        Entity::Items => sqlx::query_as::<Postgres, Item>(&sql).bind(id).bind(uid)
+            .fetch_optional(ex).await?.map(BulkRow::Item),
+        Entity::FactTypes => sqlx::query_as::<Postgres, FactType>(&sql).bind(id).bind(uid)
+            .fetch_optional(ex).await?.map(BulkRow::FactType),
+        Entity::FactEntries => sqlx::query_as::<Postgres, FactEntry>(&sql).bind(id).bind(uid)
+            .fetch_optional(ex).await?.map(BulkRow::FactEntry),
+    };
+    Ok(row)
+}
+
+async fn update_row(
+    tx: &mut Transaction<'_, Postgres>, uid: Uuid, row: &BulkRow, since: Option<DateTime<Utc>>,
+) -> sqlx::Result<Option<BulkRow>> {
+    let res = match row {
+        BulkRow::Record(rec) => sqlx::query_as::<Postgres, Record>(
+            "UPDATE public.records SET name=$3, description=$4, visibility=$5,
+                attributes=$6, notes=$7, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND uid=$2 AND ($8::timestamptz IS NULL OR updated_at=$8) RETURNING *")
+            .bind(rec.id).bind(uid)
+            .bind(&rec.name)
+            .bind(&rec.description)
+            .bind(&rec.visibility)
+            .bind(&rec.attributes)
+            .bind(&rec.notes)
+            .bind(since)
+            .fetch_optional(&mut *tx).await?
+            .map(BulkRow::Record),
+        BulkRow::Item(item) => sqlx::query_as::<Postgres, Item>(
+            "UPDATE public.items SET name=$3, description=$4, visibility=$5,
+                attributes=$6, notes=$7, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND uid=$2 AND ($8::timestamptz IS NULL OR updated_at=$8) RETURNING *")
+            .bind(item.id).bind(uid)
+            .bind(&item.name)
+            .bind(&item.description)
+            .bind(&item.visibility)
+            .bind(&item.attributes)
+            .bind(&item.notes)
+            .bind(since)
+            .fetch_optional(&mut *tx).await?
+            .map(BulkRow::Item),
+        BulkRow::FactType(kind) => sqlx::query_as::<Postgres, FactType>(
+            "UPDATE public.fact_types SET name=$3, description=$4, units=$5, attributes=$6,
+                notes=$7, visibility=$8
+             WHERE id=$1 AND uid=$2 RETURNING *")
+            .bind(kind.id).bind(uid)
+            .bind(&kind.name)
+            .bind(&kind.description)
+            .bind(&kind.units)
+            .bind(&kind.attributes)
+            .bind(&kind.notes)
+            .bind(&kind.visibility)
+            .fetch_optional(&mut *tx).await?
+            .map(BulkRow::FactType),
+        BulkRow::FactEntry(entry) => sqlx::query_as::<Postgres, FactEntry>(
+            "UPDATE public.fact_entries SET value=$3, units=$4, visibility=$5, attributes=$6, notes=$7
+             WHERE id=$1 AND uid=$2 RETURNING *")
+            .bind(entry.id).bind(uid)
+            .bind(&entry.value)
+            .bind(&entry.units)
+            .bind(&entry.visibility)
+            .bind(&entry.attributes)
+            .bind(&entry.notes)
+            .fetch_optional(&mut *tx).await?
+            .map(BulkRow::FactEntry),
+    };
+    if let Some(BulkRow::FactEntry(entry)) = &res {
+        index_entry(tx, entry).await?;
+    }
+    Ok(res)
+}
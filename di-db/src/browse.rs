@@ -0,0 +1,306 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::Postgres;
+use crate::db::Db;
+
+/// Columns whose values never leave the database through the browser
+pub const REDACTED_COLUMNS: &[&str] = &[
+    "password", "secret", "token", "access_token", "refresh_token", "refresh_hash", "code",
+];
+
+/// Name of a table created by `up.sql`. The only way to build one is
+/// [`TableRef::parse`], so anything holding a `TableRef` can safely be
+/// interpolated into SQL as an identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef(String);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableStats {
+    pub table: String,
+    pub rows: i64,
+    pub total_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowPage {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub rows: Vec<Value>,
+    /// Primary key of the last row, before redaction. It stays on the server.
+    #[serde(skip)]
+    pub after: Option<Value>,
+}
+
+/// Rows read after a keyset cursor, see [`Db::table_rows_after`]
+#[derive(Debug, Clone)]
+pub struct RowChunk {
+    pub rows: Vec<Value>,
+    /// Primary key of the last row, before redaction. `None` if there were no rows.
+    pub after: Option<Value>,
+}
+
+impl TableRef {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Db::schema_tables().into_iter()
+            .find(|table| *table == name)
+            .map(TableRef)
+    }
+
+    pub fn all() -> Vec<Self> {
+        Db::schema_tables().into_iter().map(TableRef).collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn ident(&self) -> String {
+        format!("public.\"{}\"", self.0)
+    }
+}
+
+impl Db {
+
+    pub async fn table_columns(&self, table: &TableRef) -> sqlx::Result<Vec<String>> {
+        let res: Vec<String> = sqlx::query_scalar(
+            "SELECT column_name::text FROM information_schema.columns
+             WHERE table_schema='public' AND table_name=$1
+             ORDER BY ordinal_position")
+            .bind(table.name())
+            .fetch_all(&self.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn table_stats(&self, table: &TableRef) -> sqlx::Result<TableStats> {
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", table.ident()))
+            .fetch_one(&self.pool).await?;
+        let total_bytes: i64 = sqlx::query_scalar("SELECT pg_total_relation_size($1::regclass)")
+            .bind(table.ident())
+            .fetch_one(&self.pool).await?;
+        Ok(TableStats { table: table.name().to_string(), rows, total_bytes })
+    }
+
+    /// Stats for every known table that currently exists
+    pub async fn all_table_stats(&self) -> sqlx::Result<Vec<TableStats>> {
+        let status = self.migration_status().await?;
+        let mut stats = Vec::new();
+        for table in TableRef::all().iter().filter(|t| status.applied.contains(&t.0)) {
+            stats.push(self.table_stats(table).await?);
+        }
+        Ok(stats)
+    }
+
+    /// Columns of the table's primary key, in key order. Every table in
+    /// `up.sql` has one.
+    pub async fn primary_key(&self, table: &TableRef) -> sqlx::Result<Vec<String>> {
+        let res: Vec<String> = sqlx::query_scalar(
+            "SELECT a.attname::text FROM pg_index i
+             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+             WHERE i.indrelid = $1::regclass AND i.indisprimary
+             ORDER BY array_position(i.indkey::int2[], a.attnum)")
+            .bind(table.ident())
+            .fetch_all(&self.pool).await?;
+        Ok(res)
+    }
+
+    /// A page of rows as JSON objects in primary key order. Filters are exact
+    /// matches on a column's text value; unknown and redacted columns are
+    /// rejected with [`sqlx::Error::ColumnNotFound`]. The page's `after`
+    /// continues past it through [`Db::table_rows_after`].
+    pub async fn table_rows(
+        &self, table: &TableRef, filters: &[(String, String)], limit: Option<i64>, offset: i64,
+    ) -> sqlx::Result<RowPage> {
+        let columns = self.table_columns(table).await?;
+        let clauses = filter_clauses(&columns, filters)?;
+
+        let count_sql = format!("SELECT count(*) FROM {} t{}", table.ident(), where_clause(&clauses));
+        let mut count = sqlx::query_scalar::<Postgres, i64>(&count_sql);
+        for (_, val) in filters { count = count.bind(val); }
+        let total = count.fetch_one(&self.pool).await?;
+
+        let chunk = self.read_rows(table, clauses, filters, None, limit, offset).await?;
+        Ok(RowPage {
+            table: table.name().to_string(),
+            columns, total, offset,
+            limit: limit.unwrap_or(total),
+            rows: chunk.rows,
+            after: chunk.after,
+        })
+    }
+
+    /// Up to `limit` matching rows whose primary key comes after `after`, the
+    /// cursor of the previous page. The table isn't counted again, so reading
+    /// a whole table this way costs one index scan per page.
+    pub async fn table_rows_after(
+        &self, table: &TableRef, filters: &[(String, String)], after: &Value, limit: i64,
+    ) -> sqlx::Result<RowChunk> {
+        let columns = self.table_columns(table).await?;
+        let clauses = filter_clauses(&columns, filters)?;
+        self.read_rows(table, clauses, filters, Some(after), Some(limit), 0).await
+    }
+
+    async fn read_rows(
+        &self, table: &TableRef, mut clauses: Vec<String>, filters: &[(String, String)],
+        after: Option<&Value>, limit: Option<i64>, offset: i64,
+    ) -> sqlx::Result<RowChunk> {
+        let key = self.primary_key(table).await?;
+        if after.is_some() {
+            clauses.push(after_clause(table, &key, filters.len() + 1));
+        }
+        let page = match limit {
+            Some(limit) => format!(" LIMIT {} OFFSET {}", limit, offset),
+            None => format!(" OFFSET {}", offset),
+        };
+        let rows_sql = format!("SELECT row_to_json(t) FROM {} t{} ORDER BY {}{}",
+            table.ident(), where_clause(&clauses), key_columns("t", &key), page);
+        let mut rows = sqlx::query_scalar::<Postgres, Value>(&rows_sql);
+        for (_, val) in filters { rows = rows.bind(val); }
+        if let Some(after) = after { rows = rows.bind(after); }
+        let rows = rows.fetch_all(&self.pool).await?;
+        Ok(RowChunk {
+            after: rows.last().map(|row| key_cursor(&key, row)),
+            rows: rows.into_iter().map(redact).collect(),
+        })
+    }
+
+    pub async fn clear_table(&self, table: &TableRef) -> sqlx::Result<u64> {
+        let res = sqlx::query(&format!("DELETE FROM {}", table.ident()))
+            .execute(&self.pool).await?
+            .rows_affected();
+        Ok(res)
+    }
+
+    /// Empties the table and restarts its sequences. Tables with foreign keys
+    /// into it are emptied along with it.
+    pub async fn reset_table(&self, table: &TableRef) -> sqlx::Result<()> {
+        sqlx::query(&format!("TRUNCATE {} RESTART IDENTITY CASCADE", table.ident()))
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn drop_table(&self, table: &TableRef) -> sqlx::Result<()> {
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", table.ident()))
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+/// Exact text matches on the filtered columns, binding `$1` onwards
+fn filter_clauses(columns: &[String], filters: &[(String, String)]) -> sqlx::Result<Vec<String>> {
+    let mut clauses = Vec::new();
+    for (i, (col, _)) in filters.iter().enumerate() {
+        if !columns.contains(col) || REDACTED_COLUMNS.contains(&col.as_str()) {
+            return Err(sqlx::Error::ColumnNotFound(col.clone()));
+        }
+        clauses.push(format!("t.\"{}\"::text = ${}", col, i + 1));
+    }
+    Ok(clauses)
+}
+
+fn where_clause(clauses: &[String]) -> String {
+    if clauses.is_empty() { String::new() }
+    else { format!(" WHERE {}", clauses.join(" AND ")) }
+}
+
+fn key_columns(alias: &str, key: &[String]) -> String {
+    key.iter().map(|col| format!("{}.\"{}\"", alias, col)).collect::<Vec<_>>().join(", ")
+}
+
+/// Rows whose primary key sorts after the cursor bound to `$param`. The
+/// cursor is read back into the table's row type, so each key column is
+/// compared with its own type and the primary key index can be used.
+fn after_clause(table: &TableRef, key: &[String], param: usize) -> String {
+    format!("({}) > (SELECT {} FROM jsonb_populate_record(NULL::{}, ${}::jsonb) c)",
+        key_columns("t", key), key_columns("c", key), table.ident(), param)
+}
+
+/// The primary key columns of a row, as the cursor for the rows after it
+fn key_cursor(key: &[String], row: &Value) -> Value {
+    Value::Object(key.iter()
+        .map(|col| (col.clone(), row.get(col).cloned().unwrap_or(Value::Null)))
+        .collect())
+}
+
+pub(crate) fn redact(mut row: Value) -> Value {
+    if let Value::Object(map) = &mut row {
+        for col in REDACTED_COLUMNS {
+            if let Some(val) = map.get_mut(*col) {
+                if !val.is_null() { *val = Value::String("[redacted]".into()); }
+            }
+        }
+    }
+    row
+}
+
+fn csv_escape(field: String) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else { field }
+}
+
+/// Renders rows as CSV with a header line, in the given column order
+pub fn to_csv(columns: &[String], rows: &[Value]) -> String {
+    let mut out = columns.iter().cloned().map(csv_escape).collect::<Vec<_>>().join(",");
+    out.push('\n');
+    out.push_str(&csv_rows(columns, rows));
+    out
+}
+
+/// Renders rows as CSV lines without a header, so a long export can be
+/// written a page at a time
+pub fn csv_rows(columns: &[String], rows: &[Value]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let line = columns.iter()
+            .map(|col| match row.get(col) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+            })
+            .map(csv_escape)
+            .collect::<Vec<_>>()
+            .join(",");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_schema_tables_parse() {
+        assert_eq!(TableRef::parse("Users").map(|t| t.ident()), Some("public.\"users\"".into()));
+        assert!(TableRef::parse("users; DROP TABLE users").is_none());
+        assert!(TableRef::parse("pg_catalog.pg_user").is_none());
+    }
+
+    #[test]
+    fn csv_escapes_and_redacts() {
+        let cols = vec!["username".to_string(), "password".to_string(), "bio".to_string()];
+        let rows = vec![redact(json!({ "username": "a", "password": "hash", "bio": "x, \"y\"" }))];
+        assert_eq!(to_csv(&cols, &rows),
+            "username,password,bio\na,[redacted],\"x, \"\"y\"\"\"\n");
+    }
+
+    #[test]
+    fn keyset_cursor_holds_the_unredacted_key() {
+        let table = TableRef::parse("user_tokens").unwrap();
+        let key = vec!["token".to_string()];
+        let row = json!({ "token": "abc", "uid": "u", "expires_at": null });
+        assert_eq!(key_cursor(&key, &row), json!({ "token": "abc" }));
+        assert_eq!(after_clause(&table, &key, 2),
+            "(t.\"token\") > (SELECT c.\"token\" FROM jsonb_populate_record(NULL::public.\"user_tokens\", $2::jsonb) c)");
+        let spans = TableRef::parse("timer_spans").unwrap();
+        let key = vec!["timer".to_string(), "started_at".to_string()];
+        assert!(after_clause(&spans, &key, 1).starts_with("(t.\"timer\", t.\"started_at\") > (SELECT c.\"timer\", c.\"started_at\""));
+    }
+}
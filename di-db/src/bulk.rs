@@ -0,0 +1,264 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use crate::{
+    db::Db,
+    models::{Record, Item, FactType, FactEntry, AuditEntry, fact::place::index_entry},
+};
+
+/// Kinds of rows that can be imported and exported in bulk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Entity {
+    Records,
+    Items,
+    FactTypes,
+    FactEntries,
+}
+
+/// Which rows a listing shows. The default matches the list endpoints, which
+/// leave out archived rows and anything in the trash.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Listing {
+    Active,
+    Archived,
+    Deleted,
+    All,
+}
+
+impl Default for Listing {
+    fn default() -> Self { Listing::Active }
+}
+
+/// One row of a bulk import or export
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+pub enum BulkRow {
+    Record(Record),
+    Item(Item),
+    FactType(FactType),
+    FactEntry(FactEntry),
+}
+
+impl Entity {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "records" => Some(Entity::Records),
+            "items" => Some(Entity::Items),
+            "fact_types" => Some(Entity::FactTypes),
+            "fact_entries" => Some(Entity::FactEntries),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            Entity::Records => "records",
+            Entity::Items => "items",
+            Entity::FactTypes => "fact_types",
+            Entity::FactEntries => "fact_entries",
+        }
+    }
+
+    /// Whether a user's rows have distinct names. Fact entries don't, since
+    /// a field of an item is logged under the same name again and again.
+    pub fn unique_names(&self) -> bool {
+        !matches!(self, Entity::FactEntries)
+    }
+
+    /// Condition selecting the rows of a listing. Fact entries can be
+    /// neither archived nor trashed.
+    fn filter(&self, listing: Listing) -> &'static str {
+        match (self, listing) {
+            (_, Listing::All) | (Entity::FactEntries, _) => "TRUE",
+            (_, Listing::Active) => "status <> 'archived' AND deleted_at IS NULL",
+            (_, Listing::Archived) => "status = 'archived' AND deleted_at IS NULL",
+            (_, Listing::Deleted) => "deleted_at IS NOT NULL",
+        }
+    }
+}
+
+impl Db {
+
+    /// Inserts every row in one transaction, so either all of them are
+    /// stored or none are. Returns the number of rows inserted.
+    pub async fn import_rows(&self, rows: &[BulkRow]) -> sqlx::Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        for row in rows {
+            insert_row(&mut tx, row).await?;
+            if let Some(entry) = BulkRow::audit_entry("import", None, Some(row)) {
+                entry.insert(&mut tx).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(rows.len() as u64)
+    }
+
+    /// Which of `names` the user already has a row for
+    pub async fn taken_names(&self, entity: Entity, uid: Uuid, names: &[String])
+        -> sqlx::Result<Vec<String>>
+    {
+        let res: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT name FROM public.{} WHERE uid=$1 AND name = ANY($2)", entity.table()))
+            .bind(uid)
+            .bind(names)
+            .fetch_all(&self.pool).await?;
+        Ok(res)
+    }
+
+    /// Up to `limit` of the user's rows ordered by id, starting after the
+    /// id of the last row of the previous page
+    pub async fn export_rows(
+        &self, entity: Entity, uid: Uuid, listing: Listing, after: Option<Uuid>, limit: i64,
+    ) -> sqlx::Result<Vec<BulkRow>> {
+        let sql = format!(
+            "SELECT * FROM public.{} WHERE uid=$1 AND ($2::uuid IS NULL OR id > $2) AND {}
+             ORDER BY id LIMIT $3",
+            entity.table(), entity.filter(listing));
+        let rows = match entity {
+            Entity::Records => sqlx::query_as::<Postgres, Record>(&sql)
+                .bind(uid).bind(after).bind(limit)
+                .fetch_all(&self.pool).await?
+                .into_iter().map(BulkRow::Record).collect(),
+            Entity::Items => sqlx::query_as::<Postgres, Item>(&sql)
+                .bind(uid).bind(after).bind(limit)
+                .fetch_all(&self.pool).await?
+                .into_iter().map(BulkRow::Item).collect(),
+            Entity::FactTypes => sqlx::query_as::<Postgres, FactType>(&sql)
+                .bind(uid).bind(after).bind(limit)
+                .fetch_all(&self.pool).await?
+                .into_iter().map(BulkRow::FactType).collect(),
+            Entity::FactEntries => sqlx::query_as::<Postgres, FactEntry>(&sql)
+                .bind(uid).bind(after).bind(limit)
+                .fetch_all(&self.pool).await?
+                .into_iter().map(BulkRow::FactEntry).collect(),
+        };
+        Ok(rows)
+    }
+}
+
+impl BulkRow {
+
+    pub fn entity(&self) -> Entity {
+        match self {
+            BulkRow::Record(_) => Entity::Records,
+            BulkRow::Item(_) => Entity::Items,
+            BulkRow::FactType(_) => Entity::FactTypes,
+            BulkRow::FactEntry(_) => Entity::FactEntries,
+        }
+    }
+
+    pub fn uid(&self) -> Uuid {
+        match self {
+            BulkRow::Record(rec) => rec.uid,
+            BulkRow::Item(item) => item.uid,
+            BulkRow::FactType(kind) => kind.uid,
+            BulkRow::FactEntry(entry) => entry.uid,
+        }
+    }
+
+    /// The audit entry for a change of this row from `before` to `after`
+    pub fn audit_entry(action: &str, before: Option<&BulkRow>, after: Option<&BulkRow>) -> Option<AuditEntry> {
+        let row = after.or(before)?;
+        Some(AuditEntry::new(action, row.entity(), row.uid(), row.id(), before, after))
+    }
+
+    pub fn id(&self) -> Uuid {
+        match self {
+            BulkRow::Record(rec) => rec.id,
+            BulkRow::Item(item) => item.id,
+            BulkRow::FactType(kind) => kind.id,
+            BulkRow::FactEntry(entry) => entry.id,
+        }
+    }
+}
+
+pub(crate) async fn insert_row(tx: &mut Transaction<'_, Postgres>, row: &BulkRow) -> sqlx::Result<()> {
+    match row {
+        BulkRow::Record(rec) => sqlx::query(
+            "INSERT INTO public.records
+             (id, uid, name, description, status, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
+            .bind(&rec.id)
+            .bind(&rec.uid)
+            .bind(&rec.name)
+            .bind(&rec.description)
+            .bind(&rec.status)
+            .bind(&rec.visibility)
+            .bind(&rec.attributes)
+            .bind(&rec.notes)
+            .bind(&rec.created_at)
+            .execute(&mut *tx).await?,
+        BulkRow::Item(item) => sqlx::query(
+            "INSERT INTO public.items
+             (id, uid, name, description, status, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
+            .bind(&item.id)
+            .bind(&item.uid)
+            .bind(&item.name)
+            .bind(&item.description)
+            .bind(&item.status)
+            .bind(&item.visibility)
+            .bind(&item.attributes)
+            .bind(&item.notes)
+            .bind(&item.created_at)
+            .execute(&mut *tx).await?,
+        BulkRow::FactType(kind) => sqlx::query(
+            "INSERT INTO public.fact_types
+             (id, uid, name, description, value_type, units, status, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)")
+            .bind(&kind.id)
+            .bind(&kind.uid)
+            .bind(&kind.name)
+            .bind(&kind.description)
+            .bind(&kind.value_type)
+            .bind(&kind.units)
+            .bind(&kind.status)
+            .bind(&kind.visibility)
+            .bind(&kind.attributes)
+            .bind(&kind.notes)
+            .bind(&kind.created_at)
+            .execute(&mut *tx).await?,
+        BulkRow::FactEntry(entry) => sqlx::query(
+            "INSERT INTO public.fact_entries
+             (id, uid, name, value, units, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
+            .bind(&entry.id)
+            .bind(&entry.uid)
+            .bind(&entry.name)
+            .bind(&entry.value)
+            .bind(&entry.units)
+            .bind(&entry.visibility)
+            .bind(&entry.attributes)
+            .bind(&entry.notes)
+            .bind(&entry.created_at)
+            .execute(&mut *tx).await?,
+    };
+    if let BulkRow::FactEntry(entry) = row {
+        index_entry(tx, entry).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entity_tables_exist_in_schema() {
+        let schema = Db::schema_tables();
+        for entity in &[Entity::Records, Entity::Items, Entity::FactTypes, Entity::FactEntries] {
+            assert_eq!(Entity::parse(entity.table()), Some(*entity));
+            assert!(schema.iter().any(|t| t == entity.table()));
+        }
+    }
+
+    #[test]
+    fn trashed_fact_types_are_listed_with_the_trash() {
+        assert_eq!(Entity::FactTypes.filter(Listing::Deleted), Entity::Records.filter(Listing::Deleted));
+        assert!(Entity::FactTypes.filter(Listing::Active).contains("deleted_at IS NULL"));
+        assert_eq!(Entity::FactEntries.filter(Listing::Deleted), "TRUE");
+    }
+}
@@ -71,18 +71,30 @@ impl Db {
         Ok(self)
     }
 
-    pub async fn exec(self, query: &str) -> sqlx::Result<()> {
-        sqlx::query(query).execute(&self.pool).await?;
-        Ok(())
+    /// Compares the tables declared in `up.sql` with those present in the
+    /// database, so admins can tell whether [`Db::init`] still needs to run
+    pub async fn migration_status(&self) -> sqlx::Result<MigrationStatus> {
+        let existing: Vec<String> = sqlx::query_scalar(
+            "SELECT table_name::text FROM information_schema.tables WHERE table_schema='public'")
+            .fetch_all(&self.pool).await?;
+        let (applied, pending): (Vec<String>, Vec<String>) = Self::schema_tables()
+            .into_iter()
+            .partition(|table| existing.contains(table));
+        Ok(MigrationStatus { up_to_date: pending.is_empty(), applied, pending })
     }
 
-    pub async fn clear_table(self, table: &str) -> sqlx::Result<()> {
-        sqlx::query("DELETE FROM ?;").bind(table).execute(&self.pool).await?;
-        Ok(())
+    /// Names of the tables created by `up.sql`, in declaration order
+    pub fn schema_tables() -> Vec<String> {
+        include_str!("../sql/up.sql").lines()
+            .filter_map(|line| line.trim()
+                .strip_prefix("CREATE TABLE IF NOT EXISTS public."))
+            .filter_map(|rest| rest.split(|c: char| c == ' ' || c == '(').next())
+            .map(|table| table.to_lowercase())
+            .collect()
     }
 
-    pub async fn drop_table(self, table: &str) -> sqlx::Result<()> {
-        sqlx::query("DROP TABLE IF EXISTS ?;").bind(table).execute(&self.pool).await?;
+    pub async fn exec(self, query: &str) -> sqlx::Result<()> {
+        sqlx::query(query).execute(&self.pool).await?;
         Ok(())
     }
 
@@ -97,6 +109,13 @@ impl Db {
     //}
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MigrationStatus {
+    pub up_to_date: bool,
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
 /*
 pub async fn add_user(pool: &PgPool, user: User) -> sqlx::Result<i32> {
     let res = sqlx::query(
@@ -124,7 +143,17 @@ pub async fn get_all_users(pool: &PgPool) -> sqlx::Result<Vec<User>> {
 #[cfg(test)]
 pub mod tests {
 
+    use super::Db;
+
     pub fn can_connect() {
 
     }
+
+    #[test]
+    fn lists_schema_tables() {
+        let tables = Db::schema_tables();
+        assert!(tables.contains(&"users".to_string()));
+        assert!(tables.contains(&"user_roles".to_string()));
+        assert!(!tables.iter().any(|t| t.contains('(')));
+    }
 }
@@ -7,6 +7,11 @@ pub mod util;
 pub mod models;
 pub mod migrate;
 pub mod types;
+pub mod browse;
+pub mod validate;
+pub mod account;
+pub mod bulk;
+pub mod batch;
 
 pub use db::*;
 pub use query::*;
@@ -14,6 +19,10 @@ pub use util::*;
 pub use models::*;
 pub use migrate::*;
 pub use types::*;
+pub use browse::{TableRef, TableStats, RowPage, RowChunk};
+pub use validate::InvalidField;
+pub use bulk::{Entity, Listing, BulkRow};
+pub use batch::{BatchOp, BatchOutcome, BatchFailure};
 
 pub use sqlx::{
     self,
@@ -6,6 +6,17 @@ pub mod item;
 pub mod group;
 pub mod relation;
 pub mod link;
+pub mod oauth;
+pub mod token;
+pub mod role;
+pub mod audit;
+pub mod attribute;
+pub mod note;
+pub mod journal;
+pub mod tag;
+pub mod field;
+pub mod template;
+pub mod timer;
 
 pub use user::User;
 pub use userinfo::UserInfo;
@@ -13,7 +24,20 @@ pub use record::Record;
 pub use item::Item;
 pub use group::Group;
 pub use link::Link;
-pub use fact::{FactType, FactEntry};
+pub use fact::{
+    FactType, FactEntry, Choices, FactOption, FactRange, OptionCount,
+    Unit, UnitRegistry, UnitStats, Place, BBox, PlacedEntry,
+};
+pub use oauth::{OAuthClient, OAuthCode, OAuthToken, Scope};
+pub use token::{UserToken, TokenKind};
+pub use role::{UserRole, Permission, AdminAction};
+pub use audit::{AuditEntry, AuditContext};
+pub use note::{Note, NoteRevision, NoteOwner};
+pub use journal::RecordEntry;
+pub use tag::{Tag, TagCount, TagExpr, TagOwner};
+pub use field::{ItemField, ItemFieldValue};
+pub use template::{RecordTemplate, TemplateDefinition};
+pub use timer::{Timer, TimerSpan, TimerState, TimerTotal, TimerScope, TotalPeriod};
 
 pub use dynomite::{Attribute, Attributes, AttributeValue};
 
@@ -1 +1,265 @@
-pub struct Attribute {}
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, postgres::PgRow};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{Db, models::fact::kind::ValueType};
+
+/// A typed key/value pair attached to one record, item, group or fact type.
+/// Keys are unique per row.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct Attribute {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub key: String,
+    pub value_type: ValueType,
+    pub value: Value,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Kinds of rows that carry attributes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeOwner {
+    Records,
+    Items,
+    Groups,
+    FactTypes,
+}
+
+impl AttributeOwner {
+
+    pub const ALL: [AttributeOwner; 4] = [
+        AttributeOwner::Records, AttributeOwner::Items, AttributeOwner::Groups, AttributeOwner::FactTypes,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "record" | "records" => Some(AttributeOwner::Records),
+            "item" | "items" => Some(AttributeOwner::Items),
+            "group" | "groups" => Some(AttributeOwner::Groups),
+            "fact_type" | "fact_types" => Some(AttributeOwner::FactTypes),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            AttributeOwner::Records => "records",
+            AttributeOwner::Items => "items",
+            AttributeOwner::Groups => "groups",
+            AttributeOwner::FactTypes => "fact_types",
+        }
+    }
+}
+
+impl Attribute {
+
+    /// Checks `value` against `value_type`, or infers the type from the value
+    pub fn new(uid: Uuid, owner: AttributeOwner, entity_id: Uuid, key: String, value_type: Option<ValueType>, value: Value)
+        -> Result<Self, String>
+    {
+        let value_type = value_type.unwrap_or_else(|| ValueType::infer(&value));
+        value_type.check(&value)?;
+        Ok(Self {
+            id: Uuid::new_v4(),
+            uid,
+            entity: owner.table().into(),
+            entity_id,
+            key,
+            value_type,
+            value,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Owner of the row the attributes would be attached to, `None` if the
+    /// row doesn't exist
+    pub async fn owner(db: &Db, owner: AttributeOwner, entity_id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT uid FROM public.{} WHERE id=$1", owner.table()))
+            .bind(entity_id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_all(db: &Db, owner: AttributeOwner, entity_id: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM attributes WHERE entity=$1 AND entity_id=$2 ORDER BY key")
+            .bind(owner.table())
+            .bind(entity_id)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get(db: &Db, owner: AttributeOwner, entity_id: Uuid, key: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM attributes WHERE entity=$1 AND entity_id=$2 AND key=$3")
+            .bind(owner.table())
+            .bind(entity_id)
+            .bind(key)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Adds the attribute, or replaces the value and type of the one with
+    /// the same key
+    pub async fn set(&self, db: &Db) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO attributes (id, uid, entity, entity_id, key, value_type, value, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+             ON CONFLICT (entity, entity_id, key) DO UPDATE
+             SET value_type=EXCLUDED.value_type, value=EXCLUDED.value, updated_at=EXCLUDED.updated_at
+             RETURNING *")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.entity)
+            .bind(&self.entity_id)
+            .bind(&self.key)
+            .bind(&self.value_type)
+            .bind(&self.value)
+            .bind(Utc::now())
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn delete(db: &Db, owner: AttributeOwner, entity_id: Uuid, key: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "DELETE FROM attributes WHERE entity=$1 AND entity_id=$2 AND key=$3 RETURNING *")
+            .bind(owner.table())
+            .bind(entity_id)
+            .bind(key)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Removes the attributes of rows that are gone for good
+    pub async fn delete_all(db: &Db, owner: AttributeOwner, entity_ids: &[Uuid]) -> sqlx::Result<u64> {
+        let res = sqlx::query("DELETE FROM attributes WHERE entity=$1 AND entity_id = ANY($2)")
+            .bind(owner.table())
+            .bind(entity_ids)
+            .execute(&db.pool).await?;
+        Ok(res.rows_affected())
+    }
+
+    /// The user's rows of one kind with an attribute `key` equal to `value`,
+    /// e.g. every item whose color is red
+    pub async fn find<T>(db: &Db, uid: Uuid, owner: AttributeOwner, key: &str, value: &Value) -> sqlx::Result<Vec<T>>
+    where T: for<'r> FromRow<'r, PgRow> + Send + Unpin
+    {
+        let res: Vec<T> = sqlx::query_as::<Postgres, T>(&format!(
+            "SELECT t.* FROM public.{} t JOIN attributes a ON a.entity_id = t.id
+             WHERE a.entity=$1 AND a.uid=$2 AND a.key=$3 AND a.value=$4
+             ORDER BY t.created_at", owner.table()))
+            .bind(owner.table())
+            .bind(uid)
+            .bind(key)
+            .bind(value)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Copies the legacy `attributes` text arrays into typed attributes,
+    /// counting the ones added per table. Existing keys are left alone, so it
+    /// can be run again safely; the arrays themselves are kept.
+    pub async fn migrate_legacy(db: &Db) -> sqlx::Result<BTreeMap<String, u64>> {
+        let mut added = BTreeMap::new();
+        for owner in AttributeOwner::ALL.iter() {
+            let rows: Vec<(Uuid, Uuid, Option<Vec<String>>)> = sqlx::query_as(&format!(
+                "SELECT id, uid, attributes FROM public.{} WHERE cardinality(attributes) > 0", owner.table()))
+                .fetch_all(&db.pool).await?;
+            let mut count = 0;
+            for (id, uid, attributes) in rows {
+                for text in attributes.unwrap_or_default() {
+                    let (key, value_type, value) = match parse_legacy(&text) { Some(kv) => kv, None => continue };
+                    count += sqlx::query(
+                        "INSERT INTO attributes (uid, entity, entity_id, key, value_type, value)
+                         VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING")
+                        .bind(uid)
+                        .bind(owner.table())
+                        .bind(id)
+                        .bind(key)
+                        .bind(value_type)
+                        .bind(value)
+                        .execute(&db.pool).await?
+                        .rows_affected();
+                }
+            }
+            added.insert(owner.table().to_string(), count);
+        }
+        Ok(added)
+    }
+}
+
+/// Reads `key=value` or `key: value` with the narrowest type the value fits.
+/// A bare word becomes a boolean flag set to true.
+pub fn parse_legacy(text: &str) -> Option<(String, ValueType, Value)> {
+    let (key, raw) = match text.find(|c| c == '=' || c == ':') {
+        Some(i) => (text[..i].trim(), Some(text[i + 1..].trim())),
+        None => (text.trim(), None),
+    };
+    if key.is_empty() || key.chars().count() >= 80 {
+        return None;
+    }
+    let (value_type, value) = match raw {
+        None => (ValueType::Boolean, Value::Bool(true)),
+        Some(raw) => legacy_value(raw),
+    };
+    Some((key.to_string(), value_type, value))
+}
+
+fn legacy_value(raw: &str) -> (ValueType, Value) {
+    if let Ok(n) = raw.parse::<i64>() {
+        return (ValueType::Integer, n.into());
+    }
+    if let Some(n) = raw.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+        return (ValueType::Decimal, Value::Number(n));
+    }
+    if let Ok(b) = raw.parse::<bool>() {
+        return (ValueType::Boolean, b.into());
+    }
+    let value = Value::String(raw.to_string());
+    for value_type in [ValueType::Date, ValueType::Datetime].iter() {
+        if value_type.check(&value).is_ok() {
+            return (*value_type, value);
+        }
+    }
+    (ValueType::Text, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn legacy_attributes_get_the_narrowest_type() {
+        assert_eq!(parse_legacy("color=red"), Some(("color".into(), ValueType::Text, json!("red"))));
+        assert_eq!(parse_legacy("count: 3"), Some(("count".into(), ValueType::Integer, json!(3))));
+        assert_eq!(parse_legacy("weight=2.5"), Some(("weight".into(), ValueType::Decimal, json!(2.5))));
+        assert_eq!(parse_legacy("done=false"), Some(("done".into(), ValueType::Boolean, json!(false))));
+        assert_eq!(parse_legacy("due=2021-03-01"), Some(("due".into(), ValueType::Date, json!("2021-03-01"))));
+        assert_eq!(parse_legacy("favorite"), Some(("favorite".into(), ValueType::Boolean, json!(true))));
+        assert_eq!(parse_legacy(" =x"), None);
+    }
+
+    #[test]
+    fn values_are_checked_against_their_type() {
+        assert!(Attribute::new(Uuid::nil(), AttributeOwner::Items, Uuid::nil(), "n".into(),
+            Some(ValueType::Integer), json!("three")).is_err());
+        assert!(Attribute::new(Uuid::nil(), AttributeOwner::Items, Uuid::nil(), "d".into(),
+            Some(ValueType::Date), json!("2021-02-30")).is_err());
+        let attr = Attribute::new(Uuid::nil(), AttributeOwner::Items, Uuid::nil(), "n".into(), None, json!(1.5))
+            .unwrap();
+        assert_eq!(attr.value_type, ValueType::Decimal);
+        assert_eq!(attr.entity, "items");
+    }
+}
@@ -0,0 +1,253 @@
+use std::{cell::RefCell, future::Future, pin::Pin, task::{Context, Poll}};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use sqlx::{Executor, FromRow, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{Db, bulk::Entity, models::{Record, Item, FactType, FactEntry}};
+
+/// Append-only record of one change to a record, item or fact
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct AuditEntry {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    /// Owner of the changed row
+    pub uid: Uuid,
+    /// Who made the change, when it came through a signed-in request
+    pub actor: Option<Uuid>,
+    pub action: String,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    /// `{ field: { "from": .., "to": .. } }` for every field that changed
+    pub diff: Value,
+    pub request_id: Option<String>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Who is acting, for the entries written while serving one request
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AuditContext {
+    pub actor: Option<Uuid>,
+    pub request_id: Option<String>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<AuditContext>> = RefCell::new(None);
+}
+
+/// Runs `fut` with `ctx` as the audit context of every entry it writes
+pub fn scope<F: Future>(ctx: AuditContext, fut: F) -> Scoped<F> {
+    Scoped { ctx, fut: Box::pin(fut) }
+}
+
+/// The context set by the enclosing [`scope`], if any
+pub fn current() -> AuditContext {
+    CURRENT.with(|c| c.borrow().clone()).unwrap_or_default()
+}
+
+pub struct Scoped<F: Future> {
+    ctx: AuditContext,
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    /// The context is only set while the inner future is being polled, so it
+    /// never leaks into other requests served by the same thread
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let prev = CURRENT.with(|c| c.replace(Some(this.ctx.clone())));
+        let res = this.fut.as_mut().poll(cx);
+        CURRENT.with(|c| *c.borrow_mut() = prev);
+        res
+    }
+}
+
+/// Fields whose value differs between two rows, with both values
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let fields = |row: Option<&Value>| row.and_then(Value::as_object).cloned().unwrap_or_else(|| empty.clone());
+    let (before, after) = (fields(before), fields(after));
+    let mut changed = Map::new();
+    for key in before.keys().chain(after.keys().filter(|k| !before.contains_key(*k))) {
+        let (from, to) = (before.get(key).unwrap_or(&Value::Null), after.get(key).unwrap_or(&Value::Null));
+        if from != to {
+            changed.insert(key.clone(), serde_json::json!({ "from": from, "to": to }));
+        }
+    }
+    Value::Object(changed)
+}
+
+impl AuditEntry {
+
+    /// An entry for `action` on the row, attributed to the current context.
+    /// `before` is `None` for a creation and `after` for a removal.
+    pub fn new<T: Serialize>(
+        action: &str, entity: Entity, uid: Uuid, entity_id: Uuid, before: Option<&T>, after: Option<&T>,
+    ) -> Self {
+        let before = before.and_then(|row| serde_json::to_value(row).ok());
+        let after = after.and_then(|row| serde_json::to_value(row).ok());
+        let ctx = current();
+        Self {
+            id: Uuid::new_v4(),
+            uid,
+            actor: ctx.actor,
+            action: action.into(),
+            entity: entity.table().into(),
+            entity_id,
+            diff: diff(before.as_ref(), after.as_ref()),
+            before, after,
+            request_id: ctx.request_id,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert<'e, E>(&self, ex: E) -> sqlx::Result<()>
+    where E: Executor<'e, Database = Postgres>
+    {
+        sqlx::query(
+            "INSERT INTO audit_log
+             (id, uid, actor, action, entity, entity_id, before, after, diff, request_id, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.actor)
+            .bind(&self.action)
+            .bind(&self.entity)
+            .bind(&self.entity_id)
+            .bind(&self.before)
+            .bind(&self.after)
+            .bind(&self.diff)
+            .bind(&self.request_id)
+            .bind(&self.created_at)
+            .execute(ex).await?;
+        Ok(())
+    }
+
+    /// Changes to the row, oldest first
+    pub async fn history(db: &Db, entity: Entity, entity_id: Uuid, limit: i64, offset: i64)
+        -> sqlx::Result<Vec<Self>>
+    {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM audit_log WHERE entity=$1 AND entity_id=$2
+             ORDER BY created_at, id LIMIT $3 OFFSET $4")
+            .bind(entity.table())
+            .bind(entity_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The row as it stood at `at`: the state left by the last change made
+    /// by then. `None` if it didn't exist yet or had been removed.
+    pub async fn as_of(db: &Db, entity: Entity, entity_id: Uuid, at: DateTime<Utc>)
+        -> sqlx::Result<Option<Value>>
+    {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM audit_log WHERE entity=$1 AND entity_id=$2 AND created_at <= $3
+             ORDER BY created_at DESC, id DESC LIMIT 1")
+            .bind(entity.table())
+            .bind(entity_id)
+            .bind(at)
+            .fetch_optional(&db.pool).await?;
+        Ok(res.and_then(|entry| entry.after))
+    }
+
+    /// Owner of the row, from its earliest entry, so history stays readable
+    /// after the row itself is purged
+    pub async fn owner(db: &Db, entity: Entity, entity_id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(
+            "SELECT uid FROM audit_log WHERE entity=$1 AND entity_id=$2
+             ORDER BY created_at LIMIT 1")
+            .bind(entity.table())
+            .bind(entity_id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+/// Rows whose changes go in the audit log
+pub trait Audited: Serialize {
+    const ENTITY: Entity;
+    fn owner(&self) -> Uuid;
+    fn row_id(&self) -> Uuid;
+}
+
+macro_rules! audited {
+    ($model:ty, $entity:expr) => {
+        impl Audited for $model {
+            const ENTITY: Entity = $entity;
+            fn owner(&self) -> Uuid { self.uid }
+            fn row_id(&self) -> Uuid { self.id }
+        }
+    };
+}
+
+audited!(Record, Entity::Records);
+audited!(Item, Entity::Items);
+audited!(FactType, Entity::FactTypes);
+audited!(FactEntry, Entity::FactEntries);
+
+/// The entry for a change from `before` to `after`, or `None` if neither
+/// exists, meaning nothing changed
+pub fn entry<T: Audited>(action: &str, before: Option<&T>, after: Option<&T>) -> Option<AuditEntry> {
+    let row = after.or(before)?;
+    Some(AuditEntry::new(action, T::ENTITY, row.owner(), row.row_id(), before, after))
+}
+
+/// Records a change in the transaction that made it, so the entry is only
+/// kept if the change is
+pub async fn log<T: Audited>(
+    tx: &mut Transaction<'_, Postgres>, action: &str, before: Option<&T>, after: Option<&T>,
+) -> sqlx::Result<()> {
+    match entry(action, before, after) {
+        Some(entry) => entry.insert(&mut *tx).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_lists_changed_fields_only() {
+        let before = json!({ "name": "a", "notes": ["x"], "gone": 1 });
+        let after = json!({ "name": "b", "notes": ["x"], "new": true });
+        assert_eq!(diff(Some(&before), Some(&after)), json!({
+            "name": { "from": "a", "to": "b" },
+            "gone": { "from": 1, "to": null },
+            "new": { "from": null, "to": true },
+        }));
+        assert_eq!(diff(None, Some(&json!({ "name": "a" }))), json!({ "name": { "from": null, "to": "a" } }));
+    }
+
+    #[test]
+    fn entries_describe_the_row_they_change() {
+        let before = Record::new(Uuid::new_v4(), "reading");
+        let after = Record { name: "books".into(), ..before.clone() };
+        let update = entry("update", Some(&before), Some(&after)).unwrap();
+        assert_eq!((update.entity.as_str(), update.entity_id, update.uid), ("records", before.id, before.uid));
+        assert_eq!(update.diff, json!({ "name": { "from": "reading", "to": "books" } }));
+        let create = entry("create", None, Some(&after)).unwrap();
+        assert!(create.before.is_none() && create.after.is_some());
+        let purge = entry("purge", Some(&before), None).unwrap();
+        assert_eq!(purge.entity_id, before.id);
+        assert!(purge.after.is_none());
+        assert!(entry::<Record>("update", None, None).is_none());
+    }
+
+    #[test]
+    fn context_is_scoped_to_the_future() {
+        let ctx = AuditContext { actor: Some(Uuid::nil()), request_id: Some("r1".into()) };
+        let seen = futures::executor::block_on(scope(ctx.clone(), async { current() }));
+        assert_eq!(seen, ctx);
+        assert_eq!(current(), AuditContext::default());
+    }
+}
@@ -1,6 +1,12 @@
 pub mod kind;
 pub mod entry;
 pub mod value;
+pub mod choice;
+pub mod unit;
+pub mod place;
 
 pub use kind::FactType;
 pub use entry::FactEntry;
+pub use choice::{Choices, FactOption, FactRange, OptionCount};
+pub use unit::{Unit, UnitRegistry, UnitStats};
+pub use place::{Place, BBox, PlacedEntry};
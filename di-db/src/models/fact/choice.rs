@@ -0,0 +1,438 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{
+    Db,
+    bulk::{insert_row, BulkRow, Entity},
+    models::{AuditEntry, fact::{kind::{FactType, ValueType}, entry::FactEntry, place::{Place, link_place}}},
+};
+
+/// One option of a select fact type. Entries refer to options by id, so
+/// an option can be renamed without touching them. Options that were used
+/// are retired rather than deleted, and can't be chosen for new entries.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct FactOption {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub tid: Uuid,
+    pub label: String,
+    #[serde(default)]
+    pub position: i32,
+    #[serde(default)]
+    pub retired_at: Option<DateTime<Utc>>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Bounds of a range fact type, both inclusive. With a `step`, values must
+/// be `min` plus a whole number of steps.
+#[derive(Serialize, Deserialize, FromRow, Clone, Copy, Debug, PartialEq)]
+pub struct FactRange {
+    pub min: f64,
+    pub max: f64,
+    #[serde(default)]
+    pub step: Option<f64>,
+}
+
+/// How many entries chose an option
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct OptionCount {
+    pub id: Uuid,
+    pub label: String,
+    pub retired: bool,
+    pub count: i64,
+}
+
+/// A value as it is stored, with the options it chose or the place it
+/// names
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub value: String,
+    pub options: Vec<Uuid>,
+    pub place: Option<Place>,
+}
+
+/// What values of a fact type may be
+pub enum Choices {
+    Options(ValueType, Vec<FactOption>),
+    Range(Option<FactRange>),
+    Any(ValueType),
+}
+
+impl Resolved {
+    /// A value stored as it was given
+    pub fn plain(value: String) -> Self {
+        Self { value, options: Vec::new(), place: None }
+    }
+}
+
+impl FactRange {
+
+    pub fn check(&self) -> Result<(), String> {
+        if !self.min.is_finite() || !self.max.is_finite() || self.min > self.max {
+            return Err("min must not be above max".into());
+        }
+        match self.step {
+            Some(step) if !(step > 0.0) => Err("step must be above 0".into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains(&self, v: f64) -> bool {
+        if v < self.min || v > self.max {
+            return false;
+        }
+        match self.step {
+            Some(step) => {
+                let steps = (v - self.min) / step;
+                (steps - steps.round()).abs() < 1e-9
+            },
+            None => true,
+        }
+    }
+}
+
+impl Choices {
+
+    pub async fn load(db: &Db, kind: &FactType) -> sqlx::Result<Self> {
+        Ok(match kind.value_type {
+            ValueType::SelectOne | ValueType::SelectMany =>
+                Choices::Options(kind.value_type, FactOption::get_all(db, kind.id).await?),
+            ValueType::Range => Choices::Range(FactOption::range(db, kind.id).await?),
+            other => Choices::Any(other),
+        })
+    }
+
+    /// Checks a submitted value and turns it into what is stored. Options
+    /// are given by label or id, several of them as a JSON array, and are
+    /// stored as a JSON array of ids. Places with coordinates are stored as
+    /// the [`Place`] read from them, without any other keys.
+    pub fn resolve(&self, value: &str) -> Result<Resolved, String> {
+        let json = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        match self {
+            Choices::Any(ValueType::Text) => Ok(Resolved::plain(value.to_string())),
+            Choices::Any(ValueType::Place) => {
+                ValueType::Place.check(&json)?;
+                match Place::from_json(&json)? {
+                    Some(place) => Ok(Resolved {
+                        value: serde_json::to_string(&place).unwrap_or_default(),
+                        place: Some(place),
+                        ..Resolved::plain(String::new())
+                    }),
+                    None => Ok(Resolved::plain(value.to_string())),
+                }
+            },
+            Choices::Any(value_type) => value_type.check(&json)
+                .map(|_| Resolved::plain(value.to_string())),
+            Choices::Range(None) => Err("has no range set yet".into()),
+            Choices::Range(Some(range)) => match json.as_f64() {
+                Some(v) if range.contains(v) => Ok(Resolved::plain(v.to_string())),
+                Some(_) => Err(match range.step {
+                    Some(step) => format!("must be between {} and {} in steps of {}", range.min, range.max, step),
+                    None => format!("must be between {} and {}", range.min, range.max),
+                }),
+                None => Err("must be a number".into()),
+            },
+            Choices::Options(value_type, options) => {
+                let picked = match (value_type, &json) {
+                    (ValueType::SelectMany, Value::Array(all)) => all.iter()
+                        .map(|v| v.as_str().ok_or_else(|| "must be a list of options".to_string()))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    (ValueType::SelectMany, Value::String(s)) | (ValueType::SelectOne, Value::String(s)) => vec![s.as_str()],
+                    (ValueType::SelectOne, _) => return Err("must be one option".into()),
+                    _ => return Err("must be a list of options".into()),
+                };
+                if picked.is_empty() {
+                    return Err("must choose an option".into());
+                }
+                let mut ids = Vec::new();
+                for pick in picked {
+                    let option = options.iter()
+                        .filter(|o| o.retired_at.is_none())
+                        .find(|o| o.label == pick || o.id.to_string() == pick)
+                        .ok_or_else(|| format!("'{}' is not an option", pick))?;
+                    if ids.contains(&option.id) {
+                        return Err(format!("'{}' is chosen twice", pick));
+                    }
+                    ids.push(option.id);
+                }
+                Ok(Resolved { value: serde_json::to_string(&ids).unwrap_or_default(), options: ids, place: None })
+            },
+        }
+    }
+}
+
+impl FactOption {
+
+    /// Creates a select or range fact type with its options or bounds, in
+    /// one transaction
+    pub async fn create_type(db: &Db, kind: &FactType, options: &[String], range: Option<FactRange>) -> sqlx::Result<Vec<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let row = BulkRow::FactType(kind.clone());
+        insert_row(&mut tx, &row).await?;
+        if let Some(audit) = BulkRow::audit_entry("create", None, Some(&row)) {
+            audit.insert(&mut tx).await?;
+        }
+        let mut res = Vec::new();
+        for (position, label) in options.iter().enumerate() {
+            res.push(sqlx::query_as::<Postgres, Self>(
+                "INSERT INTO fact_type_options (tid, label, position) VALUES ($1, $2, $3) RETURNING *")
+                .bind(kind.id)
+                .bind(label)
+                .bind(position as i32)
+                .fetch_one(&mut tx).await?);
+        }
+        if let Some(range) = range {
+            sqlx::query("INSERT INTO fact_type_ranges (tid, min, max, step) VALUES ($1, $2, $3, $4)")
+                .bind(kind.id)
+                .bind(range.min)
+                .bind(range.max)
+                .bind(range.step)
+                .execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Stores an entry of a fact type along with the options it chose or
+    /// the place it names
+    pub async fn log(db: &Db, entry: &FactEntry, value: &Resolved) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
+        let row = BulkRow::FactEntry(entry.clone());
+        insert_row(&mut tx, &row).await?;
+        if let Some(audit) = BulkRow::audit_entry("create", None, Some(&row)) {
+            audit.insert(&mut tx).await?;
+        }
+        link_value(&mut tx, entry, value).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The type's options in order, retired ones included
+    pub async fn get_all(db: &Db, tid: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM fact_type_options WHERE tid=$1 ORDER BY position, created_at")
+            .bind(tid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_id(db: &Db, tid: Uuid, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM fact_type_options WHERE tid=$1 AND id=$2")
+            .bind(tid)
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Adds an option after the existing ones
+    pub async fn add(db: &Db, tid: Uuid, label: &str) -> sqlx::Result<Self> {
+        let mut tx = db.pool.begin().await?;
+        let uid: Uuid = sqlx::query_scalar("SELECT uid FROM fact_types WHERE id=$1")
+            .bind(tid)
+            .fetch_one(&mut tx).await?;
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO fact_type_options (tid, label, position)
+             SELECT $1, $2, COALESCE(MAX(position) + 1, 0) FROM fact_type_options WHERE tid=$1
+             RETURNING *")
+            .bind(tid)
+            .bind(label)
+            .fetch_one(&mut tx).await?;
+        res.audit_entry("create", uid, None, Some(&res)).insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Writes the label and position. Entries keep pointing at the option.
+    pub async fn update(&self, db: &Db) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let (before, uid) = match Self::lock(&mut tx, self.tid, self.id).await? {
+            Some(locked) => locked,
+            None => return Ok(None),
+        };
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "UPDATE fact_type_options SET label=$3, position=$4 WHERE tid=$1 AND id=$2 RETURNING *")
+            .bind(&self.tid)
+            .bind(&self.id)
+            .bind(&self.label)
+            .bind(&self.position)
+            .fetch_one(&mut tx).await?;
+        self.audit_entry("update", uid, Some(&before), Some(&res)).insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(Some(res))
+    }
+
+    /// Deletes the option if no entry chose it, and otherwise retires it so
+    /// those entries still resolve. Returns whether it was retired.
+    pub async fn remove(&self, db: &Db) -> sqlx::Result<bool> {
+        let mut tx = db.pool.begin().await?;
+        let (before, uid) = match Self::lock(&mut tx, self.tid, self.id).await? {
+            Some(locked) => locked,
+            None => return Ok(false),
+        };
+        let deleted = sqlx::query(
+            "DELETE FROM fact_type_options o WHERE o.id=$1
+             AND NOT EXISTS (SELECT 1 FROM fact_entry_options l WHERE l.oid = o.id)")
+            .bind(&self.id)
+            .execute(&mut tx).await?
+            .rows_affected();
+        if deleted > 0 {
+            self.audit_entry("delete", uid, Some(&before), None).insert(&mut tx).await?;
+            tx.commit().await?;
+            return Ok(false);
+        }
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "UPDATE fact_type_options SET retired_at=COALESCE(retired_at, CURRENT_TIMESTAMP) WHERE id=$1 RETURNING *")
+            .bind(&self.id)
+            .fetch_one(&mut tx).await?;
+        self.audit_entry("retire", uid, Some(&before), Some(&res)).insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Makes a retired option available again
+    pub async fn restore(&self, db: &Db) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
+        let (before, uid) = match Self::lock(&mut tx, self.tid, self.id).await? {
+            Some(locked) => locked,
+            None => return Ok(()),
+        };
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "UPDATE fact_type_options SET retired_at=NULL WHERE id=$1 RETURNING *")
+            .bind(&self.id)
+            .fetch_one(&mut tx).await?;
+        self.audit_entry("restore", uid, Some(&before), Some(&res)).insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The option as it is before a change, locked until the transaction
+    /// ends, along with the owner of its type
+    async fn lock(tx: &mut Transaction<'_, Postgres>, tid: Uuid, id: Uuid) -> sqlx::Result<Option<(Self, Uuid)>> {
+        let option: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM fact_type_options WHERE tid=$1 AND id=$2 FOR UPDATE")
+            .bind(tid)
+            .bind(id)
+            .fetch_optional(&mut *tx).await?;
+        let option = match option { Some(option) => option, None => return Ok(None) };
+        let uid: Uuid = sqlx::query_scalar("SELECT uid FROM fact_types WHERE id=$1")
+            .bind(tid)
+            .fetch_one(&mut *tx).await?;
+        Ok(Some((option, uid)))
+    }
+
+    /// Options are audited as part of their fact type: the entries are
+    /// filed under `fact_types` with the option's own id, so they show up
+    /// in the option's history without standing in for the type's state
+    fn audit_entry(&self, action: &str, uid: Uuid, before: Option<&Self>, after: Option<&Self>) -> AuditEntry {
+        AuditEntry::new(action, Entity::FactTypes, uid, self.id, before, after)
+    }
+
+    /// How many entries chose each of the type's options, in option order
+    pub async fn counts(db: &Db, tid: Uuid) -> sqlx::Result<Vec<OptionCount>> {
+        let res: Vec<OptionCount> = sqlx::query_as::<Postgres, OptionCount>(
+            "SELECT o.id, o.label, o.retired_at IS NOT NULL AS retired, COUNT(l.eid) AS count
+             FROM fact_type_options o LEFT JOIN fact_entry_options l ON l.oid = o.id
+             WHERE o.tid=$1 GROUP BY o.id ORDER BY o.position, o.created_at")
+            .bind(tid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn range(db: &Db, tid: Uuid) -> sqlx::Result<Option<FactRange>> {
+        let res: Option<FactRange> = sqlx::query_as::<Postgres, FactRange>(
+            "SELECT min, max, step FROM fact_type_ranges WHERE tid=$1")
+            .bind(tid)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Sets the bounds of a range type. Entries logged under earlier bounds
+    /// are kept as they are.
+    pub async fn set_range(db: &Db, tid: Uuid, range: FactRange) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO fact_type_ranges (tid, min, max, step) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (tid) DO UPDATE SET min=EXCLUDED.min, max=EXCLUDED.max, step=EXCLUDED.step")
+            .bind(tid)
+            .bind(range.min)
+            .bind(range.max)
+            .bind(range.step)
+            .execute(&db.pool).await?;
+        Ok(())
+    }
+}
+
+/// Records which options an entry chose, or where the place it names is,
+/// inside the entry's transaction
+pub async fn link_value(tx: &mut Transaction<'_, Postgres>, entry: &FactEntry, value: &Resolved) -> sqlx::Result<()> {
+    if !value.options.is_empty() {
+        sqlx::query(
+            "INSERT INTO fact_entry_options (eid, oid) SELECT $1, o FROM unnest($2::uuid[]) AS o
+             ON CONFLICT DO NOTHING")
+            .bind(&entry.id)
+            .bind(&value.options)
+            .execute(&mut *tx).await?;
+    }
+    if let Some(place) = &value.place {
+        link_place(tx, entry.id, entry.uid, place).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(label: &str, retired: bool) -> FactOption {
+        FactOption {
+            id: Uuid::new_v4(),
+            tid: Uuid::nil(),
+            label: label.into(),
+            position: 0,
+            retired_at: if retired { Some(Utc::now()) } else { None },
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn options_resolve_by_label_or_id() {
+        let options = vec![option("red", false), option("green", false), option("blue", true)];
+        let red = options[0].id;
+        let one = Choices::Options(ValueType::SelectOne, options.clone());
+        assert_eq!(one.resolve("red").unwrap().options, vec![red]);
+        assert_eq!(one.resolve(&red.to_string()).unwrap().options, vec![red]);
+        assert!(one.resolve("blue").is_err());
+        assert!(one.resolve(r#"["red","green"]"#).is_err());
+        let many = Choices::Options(ValueType::SelectMany, options);
+        assert_eq!(many.resolve(r#"["red","green"]"#).unwrap().options.len(), 2);
+        assert!(many.resolve(r#"["red","red"]"#).is_err());
+        assert!(many.resolve("[]").is_err());
+    }
+
+    #[test]
+    fn ranges_check_bounds_and_steps() {
+        let range = FactRange { min: 1.0, max: 5.0, step: Some(0.5) };
+        let choices = Choices::Range(Some(range));
+        assert_eq!(choices.resolve("3.5").unwrap().value, "3.5");
+        assert!(choices.resolve("3.3").is_err());
+        assert!(choices.resolve("6").is_err());
+        assert!(choices.resolve("high").is_err());
+        assert!(FactRange { min: 2.0, max: 1.0, step: None }.check().is_err());
+        assert!(FactRange { min: 0.0, max: 1.0, step: Some(0.0) }.check().is_err());
+    }
+
+    #[test]
+    fn option_changes_are_audited_under_the_option() {
+        let uid = Uuid::new_v4();
+        let before = option("red", false);
+        let after = FactOption { label: "crimson".into(), ..before.clone() };
+        let entry = before.audit_entry("update", uid, Some(&before), Some(&after));
+        assert_eq!((entry.entity.as_str(), entry.entity_id, entry.uid), ("fact_types", before.id, uid));
+        assert_eq!(entry.diff, serde_json::json!({ "label": { "from": "red", "to": "crimson" } }));
+        let entry = before.audit_entry("delete", uid, Some(&before), None);
+        assert!(entry.before.is_some() && entry.after.is_none());
+    }
+}
@@ -56,17 +56,30 @@ impl FactEntry {
     }
 
     pub async fn insert(&self, db: &Db) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
         sqlx::query(
-            "INSERT INTO FactEntries (id, name, value, visibility, attributes, notes, created_at)
-             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id")
+            "INSERT INTO fact_entries (id, uid, name, value, units, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
             .bind(&self.id)
+            .bind(&self.uid)
             .bind(&self.name)
             .bind(&self.value)
+            .bind(&self.units)
             .bind(&self.visibility)
             .bind(&self.attributes)
             .bind(&self.notes)
             .bind(&self.created_at)
-            .fetch_one(&db.pool).await?;
+            .execute(&mut tx).await?;
+        crate::models::audit::log(&mut tx, "create", None, Some(self)).await?;
+        tx.commit().await?;
         Ok(())
     }
+
+    pub async fn get_all_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<FactEntry> = sqlx::query_as::<Postgres, FactEntry>(
+            "SELECT * FROM fact_entries WHERE uid=$1")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
 }
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
 
-#[derive(FromRow, Serialize, Deserialize, Clone,)]
+#[derive(FromRow, Serialize, Deserialize, Clone)]
 pub struct FactType {
     #[serde(default = "uuid::Uuid::new_v4")]
     pub id: uuid::Uuid,
@@ -25,6 +25,9 @@ pub struct FactType {
     pub status: Status,
     #[serde(default = "Utc::now")]
     pub created_at: DateTime<Utc>,
+    /// When the fact type was moved to the trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Default for FactType {
@@ -41,6 +44,7 @@ impl Default for FactType {
             notes: Vec::new(),
             units: Vec::new(),
             created_at: Utc::now(),
+            deleted_at: None,
         }
     }
 }
@@ -55,12 +59,15 @@ impl Default for FactType {
 //
 //
 //
-#[derive(Serialize, Deserialize, sqlx::Type, Clone, Debug)]
+/// Stored as text, named like the variants in snake case
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq)]
 #[serde(rename_all = "snake_case")]
+#[sqlx(rename = "text", rename_all = "snake_case")]
 pub enum ValueType {
     Text,
     Integer,
     Decimal,
+    Boolean,
     Date,
     Datetime,
     Duration,
@@ -68,6 +75,12 @@ pub enum ValueType {
     Place,
     Object,
     Event,
+    /// One of the type's options
+    SelectOne,
+    /// One or more of the type's options
+    SelectMany,
+    /// A number within the type's range
+    Range,
 }
 
 impl From<String> for ValueType {
@@ -76,11 +89,87 @@ impl From<String> for ValueType {
             "text" => Self::Text,
             "integer" | "int" => Self::Integer,
             "decimal" => Self::Decimal,
+            "boolean" | "bool" => Self::Boolean,
+            "date" => Self::Date,
+            "datetime" => Self::Datetime,
+            "duration" => Self::Duration,
+            "person" => Self::Person,
+            "place" => Self::Place,
+            "object" => Self::Object,
+            "event" => Self::Event,
+            "select_one" | "enum_select_one" | "dropdown" => Self::SelectOne,
+            "select_many" | "enum_select_mul" => Self::SelectMany,
+            "range" => Self::Range,
             &_ => Self::Text,
         }
     }
 }
 
+impl ValueType {
+
+    /// The type a value is stored as when none is given
+    pub fn infer(value: &serde_json::Value) -> Self {
+        use serde_json::Value;
+        match value {
+            Value::Bool(_) => Self::Boolean,
+            Value::Number(n) if n.is_i64() || n.is_u64() => Self::Integer,
+            Value::Number(_) => Self::Decimal,
+            Value::Object(_) | Value::Array(_) => Self::Object,
+            Value::String(_) | Value::Null => Self::Text,
+        }
+    }
+
+    /// Whether `value` holds a value of this type. Dates are `YYYY-MM-DD`,
+    /// datetimes RFC 3339 and durations a whole number of seconds; people,
+    /// places, objects and events are a name or an object describing them.
+    /// This only checks the shape of select, range and place values; whether
+    /// they fit the type's options or bounds, or hold valid coordinates, is
+    /// up to [`super::choice::Choices`].
+    pub fn check(&self, value: &serde_json::Value) -> Result<(), String> {
+        use serde_json::Value;
+        let ok = match (self, value) {
+            (Self::Text, Value::String(_)) => true,
+            (Self::Integer, Value::Number(n)) => n.is_i64(),
+            (Self::Decimal, Value::Number(_)) => true,
+            (Self::Boolean, Value::Bool(_)) => true,
+            (Self::Date, Value::String(s)) => chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok(),
+            (Self::Datetime, Value::String(s)) => DateTime::parse_from_rfc3339(s).is_ok(),
+            (Self::Duration, Value::Number(n)) => n.as_u64().is_some(),
+            (Self::Person | Self::Place | Self::Object | Self::Event, Value::String(s)) => !s.is_empty(),
+            (Self::Person | Self::Place | Self::Object | Self::Event, Value::Object(_)) => true,
+            (Self::SelectOne, Value::String(s)) => !s.is_empty(),
+            (Self::SelectMany, Value::Array(all)) => !all.is_empty() && all.iter().all(Value::is_string),
+            (Self::Range, Value::Number(_)) => true,
+            _ => false,
+        };
+        if ok { Ok(()) } else { Err(format!("is not a valid {} value", self.as_str())) }
+    }
+
+    /// Whether values must fit options or bounds set on the fact type
+    pub fn is_choice(&self) -> bool {
+        matches!(self, Self::SelectOne | Self::SelectMany | Self::Range)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Integer => "integer",
+            Self::Decimal => "decimal",
+            Self::Boolean => "boolean",
+            Self::Date => "date",
+            Self::Datetime => "datetime",
+            Self::Duration => "duration",
+            Self::Person => "person",
+            Self::Place => "place",
+            Self::Object => "object",
+            Self::Event => "event",
+            Self::SelectOne => "select_one",
+            Self::SelectMany => "select_many",
+            Self::Range => "range",
+        }
+    }
+}
+
 impl Default for ValueType {
     fn default() -> Self {
         Self::Text
@@ -94,9 +183,10 @@ impl FactType {
     }
 
     pub async fn insert(&self, db: &crate::db::Db) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
         sqlx::query(
-            "INSERT INTO FactTypes (id, uid, name, value_type, units, visibility, attributes, notes, created_at)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id")
+            "INSERT INTO fact_types (id, uid, name, value_type, units, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
             .bind(&self.id)
             .bind(&self.uid)
             .bind(&self.name)
@@ -106,9 +196,101 @@ impl FactType {
             .bind(&self.attributes)
             .bind(&self.notes)
             .bind(&self.created_at)
-            .fetch_one(&db.pool).await?;
+            .execute(&mut tx).await?;
+        crate::models::audit::log(&mut tx, "create", None, Some(self)).await?;
+        tx.commit().await?;
         Ok(())
     }
+
+    pub async fn get_by_id(db: &crate::db::Db, id: uuid::Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>("SELECT * FROM fact_types WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Fact type names are unique per user
+    pub async fn get_by_name(db: &crate::db::Db, uid: uuid::Uuid, name: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>("SELECT * FROM fact_types WHERE uid=$1 AND name=$2")
+            .bind(uid)
+            .bind(name)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's fact types with the given status, most recently trashed first
+    pub async fn get_by_status(db: &crate::db::Db, uid: uuid::Uuid, status: Status) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM fact_types WHERE uid=$1 AND status=$2
+             ORDER BY COALESCE(deleted_at, created_at) DESC")
+            .bind(uid)
+            .bind(status)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Moves the fact type to the trash. `None` if it is missing or already there.
+    pub async fn soft_delete(db: &crate::db::Db, id: uuid::Uuid) -> sqlx::Result<Option<Self>> {
+        Self::set_status(db, id, "trash",
+            "UPDATE fact_types SET status='deleted', deleted_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status <> 'deleted' RETURNING *").await
+    }
+
+    /// Takes the fact type back out of the trash as active
+    pub async fn restore(db: &crate::db::Db, id: uuid::Uuid) -> sqlx::Result<Option<Self>> {
+        Self::set_status(db, id, "restore",
+            "UPDATE fact_types SET status='active', deleted_at=NULL
+             WHERE id=$1 AND status='deleted' RETURNING *").await
+    }
+
+    /// Archives or unarchives the fact type. Trashed ones have to be restored first.
+    pub async fn set_archived(db: &crate::db::Db, id: uuid::Uuid, archived: bool) -> sqlx::Result<Option<Self>> {
+        if archived {
+            Self::set_status(db, id, "archive",
+                "UPDATE fact_types SET status='archived'
+                 WHERE id=$1 AND status NOT IN ('archived', 'deleted') RETURNING *").await
+        } else {
+            Self::set_status(db, id, "unarchive",
+                "UPDATE fact_types SET status='active'
+                 WHERE id=$1 AND status='archived' RETURNING *").await
+        }
+    }
+
+    /// Runs a status change on the locked row and audits it as `action`
+    async fn set_status(db: &crate::db::Db, id: uuid::Uuid, action: &str, sql: &str) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM fact_types WHERE id=$1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(sql)
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() {
+            crate::models::audit::log(&mut tx, action, before.as_ref(), res.as_ref()).await?;
+        }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Permanently removes fact types trashed before `cutoff`. Their entries
+    /// and options go with them.
+    pub async fn purge_deleted(db: &crate::db::Db, cutoff: DateTime<Utc>) -> sqlx::Result<u64> {
+        use crate::models::{Tag, TagOwner, attribute::{Attribute, AttributeOwner}};
+        let mut tx = db.pool.begin().await?;
+        let purged: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "DELETE FROM fact_types WHERE status='deleted' AND deleted_at < $1 RETURNING *")
+            .bind(cutoff)
+            .fetch_all(&mut tx).await?;
+        for kind in &purged {
+            crate::models::audit::log(&mut tx, "purge", Some(kind), None).await?;
+        }
+        tx.commit().await?;
+        let ids = purged.iter().map(|kind| kind.id).collect::<Vec<_>>();
+        Attribute::delete_all(db, AttributeOwner::FactTypes, &ids).await?;
+        Tag::detach_all(db, TagOwner::FactTypes, &ids).await?;
+        Ok(purged.len() as u64)
+    }
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -209,6 +391,7 @@ impl FactTypeBuilder {
             visibility: self.visibility.unwrap_or_default(),
             notes: self.notes.unwrap_or_default(),
             created_at: Utc::now(),
+            deleted_at: None,
         }
     }
 }
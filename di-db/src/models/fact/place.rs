@@ -0,0 +1,283 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{Db, models::fact::{FactEntry, Choices, kind::ValueType}};
+
+/// Mean radius of the earth, in km
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Km per degree of latitude
+const KM_PER_DEGREE: f64 = 111.195;
+
+/// A place value with coordinates. Place entries are stored as this in
+/// JSON, and their coordinates are also kept in `fact_entry_places` so
+/// they can be searched by distance.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Place {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// An area between two latitudes and two longitudes. `min_lon` above
+/// `max_lon` means the box crosses the antimeridian.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+/// A place entry of the user, with how far it is from the point searched
+/// from, if any
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct PlacedEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub label: Option<String>,
+    pub address: Option<String>,
+    pub distance_km: Option<f64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Place {
+
+    /// The place in a submitted value. `None` for places given only by
+    /// name, or by an object without coordinates. Keys other than `lat`,
+    /// `lon`, `label` and `address` are not part of the place and are
+    /// dropped when it is stored.
+    pub fn from_json(value: &Value) -> Result<Option<Self>, String> {
+        let obj = match value {
+            Value::Object(obj) if obj.contains_key("lat") || obj.contains_key("lon") => obj,
+            _ => return Ok(None),
+        };
+        let place: Self = serde_json::from_value(Value::Object(obj.clone()))
+            .map_err(|_| "must have a numeric lat and lon".to_string())?;
+        place.check()?;
+        Ok(Some(place))
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.lat) {
+            return Err("lat must be between -90 and 90".into());
+        }
+        if !(-180.0..=180.0).contains(&self.lon) {
+            return Err("lon must be between -180 and 180".into());
+        }
+        match (&self.label, &self.address) {
+            (Some(s), _) | (_, Some(s)) if s.chars().count() > 500 => Err("label and address must be at most 500 characters".into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Great-circle distance to another point, in km
+    pub fn distance_km(&self, lat: f64, lon: f64) -> f64 {
+        let (p1, p2) = (self.lat.to_radians(), lat.to_radians());
+        let dp = (lat - self.lat).to_radians();
+        let dl = (lon - self.lon).to_radians();
+        let a = (dp / 2.0).sin().powi(2) + p1.cos() * p2.cos() * (dl / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+
+    /// Every place entry of the user, oldest first
+    pub async fn get_all(db: &Db, uid: Uuid) -> sqlx::Result<Vec<PlacedEntry>> {
+        let res: Vec<PlacedEntry> = sqlx::query_as::<Postgres, PlacedEntry>(
+            "SELECT e.id, e.name, p.lat, p.lon, p.label, p.address, NULL::float8 AS distance_km, e.created_at
+             FROM fact_entry_places p JOIN fact_entries e ON e.id = p.eid
+             WHERE p.uid=$1 ORDER BY e.created_at")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's place entries within `km` of the point, nearest first.
+    /// Candidates are narrowed to a bounding box on the indexed columns
+    /// before the exact distance is worked out.
+    pub async fn within(db: &Db, uid: Uuid, lat: f64, lon: f64, km: f64, limit: i64) -> sqlx::Result<Vec<PlacedEntry>> {
+        let bbox = BBox::around(lat, lon, km);
+        let res: Vec<PlacedEntry> = sqlx::query_as::<Postgres, PlacedEntry>(
+            "SELECT * FROM (
+                 SELECT e.id, e.name, p.lat, p.lon, p.label, p.address, e.created_at,
+                        2 * $7 * ASIN(LEAST(1, SQRT(
+                            POWER(SIN(RADIANS(p.lat - $8) / 2), 2)
+                            + COS(RADIANS($8)) * COS(RADIANS(p.lat)) * POWER(SIN(RADIANS(p.lon - $9) / 2), 2)
+                        ))) AS distance_km
+                 FROM fact_entry_places p JOIN fact_entries e ON e.id = p.eid
+                 WHERE p.uid=$1 AND p.lat BETWEEN $3 AND $5
+                 AND (CASE WHEN $2 <= $4 THEN p.lon BETWEEN $2 AND $4 ELSE p.lon >= $2 OR p.lon <= $4 END)
+             ) d WHERE d.distance_km <= $6 ORDER BY d.distance_km LIMIT $10")
+            .bind(uid)
+            .bind(bbox.min_lon)
+            .bind(bbox.min_lat)
+            .bind(bbox.max_lon)
+            .bind(bbox.max_lat)
+            .bind(km)
+            .bind(EARTH_RADIUS_KM)
+            .bind(lat)
+            .bind(lon)
+            .bind(limit)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's place entries inside the box, newest first
+    pub async fn in_bbox(db: &Db, uid: Uuid, bbox: BBox, limit: i64) -> sqlx::Result<Vec<PlacedEntry>> {
+        let res: Vec<PlacedEntry> = sqlx::query_as::<Postgres, PlacedEntry>(
+            "SELECT e.id, e.name, p.lat, p.lon, p.label, p.address, NULL::float8 AS distance_km, e.created_at
+             FROM fact_entry_places p JOIN fact_entries e ON e.id = p.eid
+             WHERE p.uid=$1 AND p.lat BETWEEN $3 AND $5
+             AND (CASE WHEN $2 <= $4 THEN p.lon BETWEEN $2 AND $4 ELSE p.lon >= $2 OR p.lon <= $4 END)
+             ORDER BY e.created_at DESC LIMIT $6")
+            .bind(uid)
+            .bind(bbox.min_lon)
+            .bind(bbox.min_lat)
+            .bind(bbox.max_lon)
+            .bind(bbox.max_lat)
+            .bind(limit)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+impl BBox {
+
+    /// Parses `min_lon,min_lat,max_lon,max_lat`, the order GeoJSON uses
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let n: Vec<f64> = s.split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| "must be min_lon,min_lat,max_lon,max_lat".to_string())?;
+        let bbox = match n[..] {
+            [min_lon, min_lat, max_lon, max_lat] => Self { min_lon, min_lat, max_lon, max_lat },
+            _ => return Err("must be min_lon,min_lat,max_lon,max_lat".into()),
+        };
+        if bbox.min_lat > bbox.max_lat {
+            return Err("min_lat must not be above max_lat".into());
+        }
+        let lats = [bbox.min_lat, bbox.max_lat];
+        let lons = [bbox.min_lon, bbox.max_lon];
+        if lats.iter().any(|v| !(-90.0..=90.0).contains(v)) || lons.iter().any(|v| !(-180.0..=180.0).contains(v)) {
+            return Err("must be within -180..180 longitude and -90..90 latitude".into());
+        }
+        Ok(bbox)
+    }
+
+    /// A box holding every point within `km` of the given one. Near the
+    /// poles, or when it would wrap past the antimeridian, it spans every
+    /// longitude.
+    pub fn around(lat: f64, lon: f64, km: f64) -> Self {
+        let dlat = km / KM_PER_DEGREE;
+        let (min_lat, max_lat) = ((lat - dlat).max(-90.0), (lat + dlat).min(90.0));
+        let cos = lat.to_radians().cos().min(min_lat.to_radians().cos()).min(max_lat.to_radians().cos());
+        let dlon = if cos > 1e-6 { km / (KM_PER_DEGREE * cos) } else { 360.0 };
+        if dlon >= 180.0 || min_lat <= -90.0 || max_lat >= 90.0 {
+            return Self { min_lon: -180.0, min_lat, max_lon: 180.0, max_lat };
+        }
+        let wrap = |v: f64| if v < -180.0 { v + 360.0 } else if v > 180.0 { v - 360.0 } else { v };
+        Self { min_lon: wrap(lon - dlon), min_lat, max_lon: wrap(lon + dlon), max_lat }
+    }
+}
+
+/// Keeps the coordinates of a place entry, inside the entry's transaction.
+/// Writing them again replaces what was kept before.
+pub async fn link_place(tx: &mut Transaction<'_, Postgres>, eid: Uuid, uid: Uuid, place: &Place) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO fact_entry_places (eid, uid, lat, lon, label, address) VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (eid) DO UPDATE
+         SET lat=EXCLUDED.lat, lon=EXCLUDED.lon, label=EXCLUDED.label, address=EXCLUDED.address")
+        .bind(eid)
+        .bind(uid)
+        .bind(place.lat)
+        .bind(place.lon)
+        .bind(&place.label)
+        .bind(&place.address)
+        .execute(&mut *tx).await?;
+    Ok(())
+}
+
+/// Forgets the coordinates of an entry whose value no longer has any
+pub async fn unlink_place(tx: &mut Transaction<'_, Postgres>, eid: Uuid) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM fact_entry_places WHERE eid=$1")
+        .bind(eid)
+        .execute(&mut *tx).await?;
+    Ok(())
+}
+
+/// Brings the kept coordinates of an entry written by a batch, an import or
+/// a template in line with its value, resolved the way a logged place is
+pub(crate) async fn index_entry(tx: &mut Transaction<'_, Postgres>, entry: &FactEntry) -> sqlx::Result<()> {
+    let value_type: Option<ValueType> = sqlx::query_scalar::<Postgres, ValueType>(
+        "SELECT value_type FROM fact_types WHERE uid=$1 AND name=$2")
+        .bind(entry.uid)
+        .bind(&entry.name)
+        .fetch_optional(&mut *tx).await?;
+    match indexed_place(value_type, &entry.value) {
+        Some(place) => link_place(tx, entry.id, entry.uid, &place).await,
+        None => unlink_place(tx, entry.id).await,
+    }
+}
+
+/// The coordinates kept for an entry of a type with `value_type`: those of
+/// a place value, and none for other types or for places without them
+fn indexed_place(value_type: Option<ValueType>, value: &str) -> Option<Place> {
+    match value_type {
+        Some(ValueType::Place) => Choices::Any(ValueType::Place).resolve(value).ok()
+            .and_then(|resolved| resolved.place),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn places_need_valid_coordinates() {
+        assert_eq!(Place::from_json(&json!("home")).unwrap(), None);
+        assert_eq!(Place::from_json(&json!({ "name": "home" })).unwrap(), None);
+        let place = Place::from_json(&json!({ "lat": 51.5, "lon": -0.12, "label": "London" })).unwrap().unwrap();
+        assert_eq!(place.label.as_deref(), Some("London"));
+        assert!(Place::from_json(&json!({ "lat": 91, "lon": 0 })).is_err());
+        assert!(Place::from_json(&json!({ "lat": 10 })).is_err());
+        assert!(Place::from_json(&json!({ "lat": "north", "lon": 0 })).is_err());
+    }
+
+    #[test]
+    fn boxes_hold_the_radius() {
+        let london = Place { lat: 51.5074, lon: -0.1278, label: None, address: None };
+        let paris = (48.8566, 2.3522);
+        let km = london.distance_km(paris.0, paris.1);
+        assert!((km - 343.5).abs() < 1.0);
+        let bbox = BBox::around(london.lat, london.lon, km + 1.0);
+        assert!(bbox.min_lat <= paris.0 && paris.0 <= bbox.max_lat);
+        assert!(bbox.min_lon <= paris.1 && paris.1 <= bbox.max_lon);
+        let fiji = BBox::around(-17.7, 179.9, 50.0);
+        assert!(fiji.min_lon > fiji.max_lon);
+        assert_eq!(BBox::around(89.9, 0.0, 50.0).min_lon, -180.0);
+        assert!(BBox::parse("-1,51,1,52").is_ok());
+        assert!(BBox::parse("-1,52,1,51").is_err());
+        assert!(BBox::parse("a,b").is_err());
+        let pacific = BBox::parse("170,-20,-170,-10").unwrap();
+        assert!(pacific.min_lon > pacific.max_lon);
+    }
+
+    #[test]
+    fn only_place_values_with_coordinates_are_indexed() {
+        let value = r#"{"lat":51.5,"lon":-0.12,"label":"London"}"#;
+        let place = indexed_place(Some(ValueType::Place), value).unwrap();
+        assert_eq!((place.lat, place.lon, place.label.as_deref()), (51.5, -0.12, Some("London")));
+        assert_eq!(indexed_place(Some(ValueType::Place), "home"), None);
+        assert_eq!(indexed_place(Some(ValueType::Place), r#"{"lat":95,"lon":0}"#), None);
+        assert_eq!(indexed_place(Some(ValueType::Text), value), None);
+        assert_eq!(indexed_place(None, value), None);
+    }
+}
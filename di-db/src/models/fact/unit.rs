@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::Db;
+
+/// A unit of measure. Built-in units have no `uid`; users may add their
+/// own. A value `v` in this unit is `v * factor + shift` in the canonical
+/// unit of its dimension, which is the one with a factor of 1 and no
+/// shift.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, PartialEq)]
+pub struct Unit {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    #[serde(default)]
+    pub uid: Option<Uuid>,
+    pub symbol: String,
+    pub name: String,
+    pub dimension: String,
+    pub factor: f64,
+    #[serde(default)]
+    pub shift: f64,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sums and bounds of a fact type's numeric entries, in the canonical unit
+/// of their dimension
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, PartialEq)]
+pub struct UnitStats {
+    pub dimension: String,
+    pub count: i64,
+    pub sum: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+}
+
+/// The units one user can use: the built-in ones and their own
+#[derive(Clone, Debug, Default)]
+pub struct UnitRegistry {
+    pub units: Vec<Unit>,
+}
+
+impl Unit {
+
+    pub fn new(uid: Uuid, symbol: String, name: String, dimension: String, factor: f64, shift: f64) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            uid: Some(uid),
+            symbol,
+            name,
+            dimension,
+            factor,
+            shift,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        self.factor == 1.0 && self.shift == 0.0
+    }
+
+    pub fn to_canonical(&self, v: f64) -> f64 {
+        v * self.factor + self.shift
+    }
+
+    pub fn from_canonical(&self, v: f64) -> f64 {
+        (v - self.shift) / self.factor
+    }
+
+    /// The built-in units and the user's own, by dimension and symbol
+    pub async fn get_all(db: &Db, uid: Option<Uuid>) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM units WHERE uid IS NULL OR uid=$1 ORDER BY dimension, factor, symbol")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Adds a user's unit. Returns `None` if its symbol is taken, by a
+    /// built-in unit or one of theirs.
+    pub async fn insert(&self, db: &Db) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO units (id, uid, symbol, name, dimension, factor, shift, created_at)
+             SELECT $1, $2, $3, $4, $5, $6, $7, $8
+             WHERE NOT EXISTS (SELECT 1 FROM units u
+                 WHERE LOWER(u.symbol) = LOWER($3) AND (u.uid IS NULL OR u.uid = $2))
+             RETURNING *")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.symbol)
+            .bind(&self.name)
+            .bind(&self.dimension)
+            .bind(&self.factor)
+            .bind(&self.shift)
+            .bind(&self.created_at)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Deletes one of the user's units. Entries logged in it keep their
+    /// unit as text.
+    pub async fn delete(db: &Db, uid: Uuid, id: Uuid) -> sqlx::Result<bool> {
+        let res = sqlx::query("DELETE FROM units WHERE id=$1 AND uid=$2")
+            .bind(id)
+            .bind(uid)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res > 0)
+    }
+
+    /// Count, sum and bounds of the user's numeric entries named `name`, per
+    /// dimension, converted to canonical units. Units are matched by symbol
+    /// or name like [`UnitRegistry::find`]; entries in unknown units are
+    /// left out.
+    pub async fn stats(db: &Db, uid: Uuid, name: &str) -> sqlx::Result<Vec<UnitStats>> {
+        let res: Vec<UnitStats> = sqlx::query_as::<Postgres, UnitStats>(
+            "SELECT dimension, COUNT(*) AS count, SUM(base_value) AS sum, MIN(base_value) AS min,
+                    MAX(base_value) AS max, AVG(base_value) AS avg
+             FROM fact_entry_values WHERE uid=$1 AND name=$2 GROUP BY dimension ORDER BY dimension")
+            .bind(uid)
+            .bind(name)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+impl UnitRegistry {
+
+    pub async fn load(db: &Db, uid: Option<Uuid>) -> sqlx::Result<Self> {
+        Ok(Self { units: Unit::get_all(db, uid).await? })
+    }
+
+    /// The unit with the symbol, or else the name, ignoring case
+    pub fn find(&self, unit: &str) -> Option<&Unit> {
+        let unit = unit.trim();
+        self.units.iter().find(|u| u.symbol.eq_ignore_ascii_case(unit))
+            .or_else(|| self.units.iter().find(|u| u.name.eq_ignore_ascii_case(unit)))
+    }
+
+    pub fn canonical(&self, dimension: &str) -> Option<&Unit> {
+        self.units.iter().find(|u| u.dimension == dimension && u.is_canonical())
+    }
+
+    pub fn convert(&self, v: f64, from: &str, to: &str) -> Result<f64, String> {
+        let from = self.find(from).ok_or_else(|| format!("'{}' is not a known unit", from))?;
+        let to = self.find(to).ok_or_else(|| format!("'{}' is not a known unit", to))?;
+        if from.dimension != to.dimension {
+            return Err(format!("can't convert {} to {}", from.dimension, to.dimension));
+        }
+        Ok(to.from_canonical(from.to_canonical(v)))
+    }
+
+    /// Whether an entry may be logged in `unit` for a type listing
+    /// `allowed`. Types without units take any; otherwise the unit must be
+    /// listed, or share a dimension with a listed known unit.
+    pub fn check(&self, allowed: &[String], unit: &str) -> Result<(), String> {
+        if allowed.is_empty() || allowed.iter().any(|a| a == unit) {
+            return Ok(());
+        }
+        let dimensions: Vec<&str> = allowed.iter()
+            .filter_map(|a| self.find(a))
+            .map(|u| u.dimension.as_str())
+            .collect();
+        match self.find(unit) {
+            Some(u) if dimensions.contains(&u.dimension.as_str()) => Ok(()),
+            _ if dimensions.is_empty() => Err(format!("must be one of {}", allowed.join(", "))),
+            _ => Err(format!("must be a unit of {}", dimensions.join(" or "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(symbol: &str, dimension: &str, factor: f64, shift: f64) -> Unit {
+        Unit { uid: None, ..Unit::new(Uuid::nil(), symbol.into(), symbol.into(), dimension.into(), factor, shift) }
+    }
+
+    fn registry() -> UnitRegistry {
+        UnitRegistry { units: vec![
+            unit("kg", "mass", 1.0, 0.0),
+            unit("lb", "mass", 0.45359237, 0.0),
+            unit("km", "length", 1000.0, 0.0),
+            unit("mi", "length", 1609.344, 0.0),
+            unit("m", "length", 1.0, 0.0),
+            unit("c", "temperature", 1.0, 0.0),
+            unit("f", "temperature", 5.0 / 9.0, -160.0 / 9.0),
+        ]}
+    }
+
+    #[test]
+    fn units_convert_within_a_dimension() {
+        let units = registry();
+        assert!((units.convert(10.0, "lb", "kg").unwrap() - 4.5359237).abs() < 1e-9);
+        assert!((units.convert(26.2, "MI", "km").unwrap() - 42.1648128).abs() < 1e-9);
+        assert!((units.convert(212.0, "f", "c").unwrap() - 100.0).abs() < 1e-9);
+        assert!(units.convert(1.0, "kg", "km").is_err());
+        assert!(units.convert(1.0, "stone", "kg").is_err());
+        assert_eq!(units.canonical("length").map(|u| u.symbol.as_str()), Some("m"));
+    }
+
+    #[test]
+    fn entry_units_must_fit_the_type() {
+        let units = registry();
+        assert!(units.check(&[], "anything").is_ok());
+        assert!(units.check(&["kg".into()], "lb").is_ok());
+        assert!(units.check(&["kg".into()], "km").is_err());
+        assert!(units.check(&["reps".into()], "reps").is_ok());
+        assert!(units.check(&["reps".into()], "sets").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{
+    Db,
+    bulk::{insert_row, BulkRow},
+    models::{FactType, FactEntry, fact::choice::{link_value, Resolved}},
+};
+
+/// A field an item declares, backed by one of its owner's fact types.
+/// Values logged for the field are fact entries linked to the item and the
+/// type through `item_field_entries`.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct ItemField {
+    pub iid: Uuid,
+    pub tid: Uuid,
+    pub uid: Uuid,
+    pub position: i32,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// One of an item's fields with its newest value and how many values were
+/// logged for it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ItemFieldValue {
+    pub field: FactType,
+    pub latest: Option<FactEntry>,
+    pub count: i64,
+}
+
+impl ItemField {
+
+    /// Declares the fact type as a field of the item, after its existing
+    /// fields. Returns 0 if it already was one.
+    pub async fn add(db: &Db, iid: Uuid, kind: &FactType) -> sqlx::Result<u64> {
+        let res = sqlx::query(
+            "INSERT INTO item_fields (iid, tid, uid, position)
+             SELECT $1, $2, $3, COALESCE(MAX(position) + 1, 0) FROM item_fields WHERE iid=$1
+             ON CONFLICT DO NOTHING")
+            .bind(iid)
+            .bind(&kind.id)
+            .bind(&kind.uid)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res)
+    }
+
+    /// Removes the field along with the links to its values. The fact
+    /// entries themselves are kept.
+    pub async fn remove(db: &Db, iid: Uuid, tid: Uuid) -> sqlx::Result<bool> {
+        let n = sqlx::query("DELETE FROM item_fields WHERE iid=$1 AND tid=$2")
+            .bind(iid)
+            .bind(tid)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(n > 0)
+    }
+
+    /// The fact type behind one of the item's fields
+    pub async fn get(db: &Db, iid: Uuid, tid: Uuid) -> sqlx::Result<Option<FactType>> {
+        let res: Option<FactType> = sqlx::query_as::<Postgres, FactType>(
+            "SELECT t.* FROM fact_types t JOIN item_fields f ON f.tid = t.id WHERE f.iid=$1 AND f.tid=$2")
+            .bind(iid)
+            .bind(tid)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_name(db: &Db, iid: Uuid, name: &str) -> sqlx::Result<Option<FactType>> {
+        let res: Option<FactType> = sqlx::query_as::<Postgres, FactType>(
+            "SELECT t.* FROM fact_types t JOIN item_fields f ON f.tid = t.id WHERE f.iid=$1 AND t.name=$2")
+            .bind(iid)
+            .bind(name)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The item's fields in the order they were added, each with its newest
+    /// value. Fields whose fact type is in the trash are left out.
+    pub async fn get_all(db: &Db, iid: Uuid) -> sqlx::Result<Vec<ItemFieldValue>> {
+        let fields: Vec<FactType> = sqlx::query_as::<Postgres, FactType>(
+            "SELECT t.* FROM fact_types t JOIN item_fields f ON f.tid = t.id
+             WHERE f.iid=$1 AND t.deleted_at IS NULL ORDER BY f.position, f.created_at")
+            .bind(iid)
+            .fetch_all(&db.pool).await?;
+        let counts: HashMap<Uuid, i64> = sqlx::query_as::<Postgres, (Uuid, i64)>(
+            "SELECT tid, COUNT(*) FROM item_field_entries WHERE iid=$1 GROUP BY tid")
+            .bind(iid)
+            .fetch_all(&db.pool).await?
+            .into_iter().collect();
+        let newest: Vec<(Uuid, Uuid)> = sqlx::query_as::<Postgres, (Uuid, Uuid)>(
+            "SELECT DISTINCT ON (l.tid) l.tid, l.eid
+             FROM item_field_entries l JOIN fact_entries e ON e.id = l.eid
+             WHERE l.iid=$1 ORDER BY l.tid, e.created_at DESC")
+            .bind(iid)
+            .fetch_all(&db.pool).await?;
+        let eids = newest.iter().map(|(_, eid)| *eid).collect::<Vec<_>>();
+        let mut entries: HashMap<Uuid, FactEntry> = sqlx::query_as::<Postgres, FactEntry>(
+            "SELECT * FROM fact_entries WHERE id = ANY($1)")
+            .bind(&eids)
+            .fetch_all(&db.pool).await?
+            .into_iter().map(|e| (e.id, e)).collect();
+        let mut latest: HashMap<Uuid, FactEntry> = newest.into_iter()
+            .filter_map(|(tid, eid)| entries.remove(&eid).map(|e| (tid, e)))
+            .collect();
+        Ok(fields.into_iter()
+            .map(|field| ItemFieldValue {
+                count: counts.get(&field.id).copied().unwrap_or(0),
+                latest: latest.remove(&field.id),
+                field,
+            })
+            .collect())
+    }
+
+    /// Values logged for one of the item's fields, newest first
+    pub async fn entries(db: &Db, iid: Uuid, tid: Uuid, limit: i64, offset: i64) -> sqlx::Result<Vec<FactEntry>> {
+        let res: Vec<FactEntry> = sqlx::query_as::<Postgres, FactEntry>(
+            "SELECT e.* FROM fact_entries e JOIN item_field_entries l ON l.eid = e.id
+             WHERE l.iid=$1 AND l.tid=$2 ORDER BY e.created_at DESC LIMIT $3 OFFSET $4")
+            .bind(iid)
+            .bind(tid)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Stores the entry as a value of the item's field, in one transaction
+    pub async fn log(db: &Db, iid: Uuid, tid: Uuid, entry: &FactEntry, value: &Resolved) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
+        let row = BulkRow::FactEntry(entry.clone());
+        insert_row(&mut tx, &row).await?;
+        if let Some(audit) = BulkRow::audit_entry("create", None, Some(&row)) {
+            audit.insert(&mut tx).await?;
+        }
+        sqlx::query("INSERT INTO item_field_entries (eid, iid, tid) VALUES ($1, $2, $3)")
+            .bind(&entry.id)
+            .bind(iid)
+            .bind(tid)
+            .execute(&mut tx).await?;
+        link_value(&mut tx, entry, value).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{Db, Visibility, Status, models::{
-    Model, User, Record,   Item,
+    Model, User, Record,   Item, attribute::{Attribute, AttributeOwner}, Tag, TagOwner,
 }};
 use serde::{Serialize, Deserialize};
 use sqlx::{
@@ -23,6 +23,9 @@ pub struct Group {
     pub attributes: Vec<String>,
     #[serde(default="Utc::now")]
     pub created_at: DateTime<Utc>,
+    /// When the group was moved to the trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Group {
@@ -44,15 +47,59 @@ impl Group {
         Ok(res)
     }
 
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Group> = sqlx::query_as::<Postgres, Group>("SELECT * FROM Groups WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's groups with the given status, most recently trashed first
+    pub async fn get_by_status(db: &Db, uid: Uuid, status: Status) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Group> = sqlx::query_as::<Postgres, Group>(
+            "SELECT * FROM Groups WHERE uid=$1 AND status=$2
+             ORDER BY COALESCE(deleted_at, created_at) DESC")
+            .bind(uid)
+            .bind(status)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Takes the group back out of the trash as active
+    pub async fn restore(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Group> = sqlx::query_as::<Postgres, Group>(
+            "UPDATE Groups SET status='active', deleted_at=NULL
+             WHERE id=$1 AND status='deleted' RETURNING *")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Permanently removes groups trashed before `cutoff`
+    pub async fn purge_deleted(db: &Db, cutoff: DateTime<Utc>) -> sqlx::Result<u64> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            "DELETE FROM Groups WHERE status='deleted' AND deleted_at < $1 RETURNING id")
+            .bind(cutoff)
+            .fetch_all(&db.pool).await?;
+        Attribute::delete_all(db, AttributeOwner::Groups, &ids).await?;
+        Tag::detach_all(db, TagOwner::Groups, &ids).await?;
+        Ok(ids.len() as u64)
+    }
+
 }
 
 impl Default for Group {
     fn default() -> Self {
         Self {
+            id: Uuid::new_v4(),
+            uid: Uuid::new_v4(),
+            name: String::new(),
             status: Status::Active,
             description: None,
             visibility: Visibility::Public,
-            created_at: Utc::now(), ..Default::default()
+            attributes: Vec::new(),
+            created_at: Utc::now(),
+            deleted_at: None,
         }
     }
 }
@@ -71,10 +118,26 @@ impl From<&'static PgRow> for Group {
     }
 }
 
+#[async_trait::async_trait]
 impl Model for Group {
     fn table() -> String { String::from("Groups") }
     fn foreign_id() -> String {
        String::from("gid")
     }
     fn id(self) -> Uuid { self.id }
+
+    async fn delete(self, db: &Db) -> sqlx::Result<Uuid> {
+        Self::delete_from_id(db, self.id).await
+    }
+
+    /// Moves the group to the trash. Its attributes and tags stay until the
+    /// trash is purged, so a restore brings them back.
+    async fn delete_from_id(db: &Db, id: Uuid) -> sqlx::Result<Uuid> {
+        let res: Uuid = sqlx::query_scalar(
+            "UPDATE Groups SET status='deleted', deleted_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status <> 'deleted' RETURNING id")
+            .bind(id)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
 }
@@ -3,11 +3,11 @@ use sqlx::{ prelude::*,
     types::{
         chrono::{Utc, DateTime, NaiveDate, NaiveDateTime}, uuid::{Uuid, Variant},
     },
-    FromRow, Type, postgres::{Postgres, PgRow}, Decode
+    FromRow, Type, Transaction, postgres::{Postgres, PgRow}, Decode
 };
 use crate::{
     Db,
-    models::{Model, User, Record, Group, fact::{FactType, FactEntry}},
+    models::{Model, User, Record, Group, audit, attribute::{Attribute, AttributeOwner}, Note, NoteOwner, Tag, TagOwner, ItemField, fact::{FactType, FactEntry, Choices, choice::Resolved}},
     Visibility, Status,
 };
 
@@ -28,6 +28,10 @@ pub struct Item {
     pub notes: Vec<String>,
     #[serde(default="Utc::now")]
     pub created_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Item {
@@ -66,26 +70,58 @@ impl Item {
         Ok(res)
     }
 
+    /// The row, locked until the transaction ends so the audit entry's
+    /// `before` is the state the change was made to
+    async fn lock(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Item> = sqlx::query_as::<Postgres, Item>(
+            "SELECT * FROM Items WHERE id=$1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx).await?;
+        Ok(res)
+    }
+
     pub async fn delete_by_id(db: &Db, id: Uuid) -> sqlx::Result<Uuid> {
-        let res = sqlx::query(
-            "DELETE FROM Items WHERE id=$1 RETURNING id")
+        let mut tx = db.pool.begin().await?;
+        let res: Item = sqlx::query_as::<Postgres, Item>(
+            "DELETE FROM Items WHERE id=$1 RETURNING *")
             .bind(&id)
-            .fetch_one(&db.pool).await?;
-        Ok(res.get("id"))
+            .fetch_one(&mut tx).await?;
+        audit::log(&mut tx, "delete", Some(&res), None).await?;
+        tx.commit().await?;
+        Attribute::delete_all(db, AttributeOwner::Items, &[res.id]).await?;
+        Note::delete_all(db, NoteOwner::Items, &[res.id]).await?;
+        Tag::detach_all(db, TagOwner::Items, &[res.id]).await?;
+        Ok(res.id)
     }
 
-    pub async fn update_by_id(db: &Db, id: Uuid, item: Item) -> sqlx::Result<Option<Self>> {
+    /// Same contract as [`Record::update_by_id`]
+    pub async fn update_by_id(
+        db: &Db, id: Uuid, item: Item, unmodified_since: DateTime<Utc>,
+    ) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
         let res: Option<Item> = sqlx::query_as::<Postgres, Item>(
-            "SELECT * FROM Items WHERE id=$1")
+            "UPDATE Items SET name=$2, description=$3, visibility=$4,
+                attributes=$5, notes=$6, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND updated_at=$7 RETURNING *")
             .bind(id)
-            .fetch_optional(&db.pool).await?;
+            .bind(&item.name)
+            .bind(&item.description)
+            .bind(&item.visibility)
+            .bind(&item.attributes)
+            .bind(&item.notes)
+            .bind(unmodified_since)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "update", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
         Ok(res)
     }
 
-    pub async fn insert(&self, db: &Db) -> sqlx::Result<()> {
-        let res: Uuid = sqlx::query(
+    pub async fn insert(&self, db: &Db) -> sqlx::Result<Self> {
+        let mut tx = db.pool.begin().await?;
+        let res: Item = sqlx::query_as::<Postgres, Item>(
             "INSERT INTO Items (uid, name, description, status, visibility, attributes, notes, created_at)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id")
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *")
             .bind(&self.uid)
             .bind(&self.name)
             .bind(&self.description)
@@ -94,36 +130,139 @@ impl Item {
             .bind(&self.attributes)
             .bind(&self.notes)
             .bind(&self.created_at)
-            .fetch_one(&db.pool).await?
-            .get("id");
-        Ok(())
+            .fetch_one(&mut tx).await?;
+        audit::log(&mut tx, "create", None, Some(&res)).await?;
+        tx.commit().await?;
+        Ok(res)
     }
 
-    pub async fn add_new_fact(&self, db: &Db, fact: String, val: String) -> sqlx::Result<()>
+    /// Logs `val` for the item's field named `fact`. `None` if the item has
+    /// no such field, or `val` isn't one of a select field's options or is
+    /// out of a range field's bounds.
+    pub async fn add_new_fact(&self, db: &Db, fact: String, val: String) -> sqlx::Result<Option<FactEntry>>
     {
-        let fact: FactEntry = FactEntry::new(self.id, fact, val);
-        Ok(())
+        let kind = match ItemField::get_by_name(db, self.id, &fact).await? {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+        let val = match Choices::load(db, &kind).await?.resolve(&val) {
+            Ok(resolved) => resolved,
+            Err(_) if !kind.value_type.is_choice() => Resolved::plain(val),
+            Err(_) => return Ok(None),
+        };
+        let entry = FactEntry::new(self.uid, fact, val.value.clone());
+        ItemField::log(db, self.id, kind.id, &entry, &val).await?;
+        Ok(Some(entry))
     }
 
-    pub async fn add_existing_field(db: &Db, iid: Uuid, field: FactEntry) -> sqlx::Result<()> {
-        Ok(())
+    /// Declares the fact type as a field of the item. Returns 0 if it
+    /// already was one.
+    pub async fn add_existing_field(db: &Db, iid: Uuid, field: &FactType) -> sqlx::Result<u64> {
+        ItemField::add(db, iid, field).await
     }
 
-    pub async fn add_to_record(self, db: &Db, rid: Uuid) -> sqlx::Result<u32> {
-        Ok(0)
+    /// Links the item to a record. Returns 0 if it was already linked.
+    pub async fn add_to_record(self, db: &Db, rid: Uuid) -> sqlx::Result<u64> {
+        let res = sqlx::query(
+            "INSERT INTO record_items (rid, iid) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(rid)
+            .bind(self.id)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res)
     }
 
+    /// The user's items, leaving out archived ones and the trash
     pub async fn get_all_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Vec<Item>> {
         let res: Vec<Item> = sqlx::query_as::<Postgres, Item>(
-            "SELECT * FROM Items i WHERE i.uid=$1")
+            "SELECT * FROM Items i WHERE i.uid=$1 AND i.status NOT IN ('archived', 'deleted')")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_status(db: &Db, uid: Uuid, status: Status) -> sqlx::Result<Vec<Item>> {
+        let res: Vec<Item> = sqlx::query_as::<Postgres, Item>(
+            "SELECT * FROM Items i WHERE i.uid=$1 AND i.status=$2
+             ORDER BY COALESCE(i.deleted_at, i.updated_at) DESC")
             .bind(uid)
+            .bind(status)
             .fetch_all(&db.pool).await?;
         Ok(res)
     }
 
+    /// Moves the item to the trash. `None` if it is missing or already there.
+    pub async fn soft_delete(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let res: Option<Item> = sqlx::query_as::<Postgres, Item>(
+            "UPDATE Items SET status='deleted', deleted_at=CURRENT_TIMESTAMP,
+                updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status <> 'deleted' RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "trash", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn restore(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let res: Option<Item> = sqlx::query_as::<Postgres, Item>(
+            "UPDATE Items SET status='active', deleted_at=NULL, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status='deleted' RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "restore", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn set_archived(db: &Db, id: Uuid, archived: bool) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let sql = if archived {
+            "UPDATE Items SET status='archived', updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status NOT IN ('archived', 'deleted') RETURNING *"
+        } else {
+            "UPDATE Items SET status='active', updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status='archived' RETURNING *"
+        };
+        let res: Option<Item> = sqlx::query_as::<Postgres, Item>(sql)
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() {
+            let action = if archived { "archive" } else { "unarchive" };
+            audit::log(&mut tx, action, before.as_ref(), res.as_ref()).await?;
+        }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Permanently removes items trashed before `cutoff`
+    pub async fn purge_deleted(db: &Db, cutoff: DateTime<Utc>) -> sqlx::Result<u64> {
+        let mut tx = db.pool.begin().await?;
+        let purged: Vec<Item> = sqlx::query_as::<Postgres, Item>(
+            "DELETE FROM Items WHERE status='deleted' AND deleted_at < $1 RETURNING *")
+            .bind(cutoff)
+            .fetch_all(&mut tx).await?;
+        for item in &purged {
+            audit::log(&mut tx, "purge", Some(item), None).await?;
+        }
+        tx.commit().await?;
+        let ids = purged.iter().map(|item| item.id).collect::<Vec<_>>();
+        Attribute::delete_all(db, AttributeOwner::Items, &ids).await?;
+        Note::delete_all(db, NoteOwner::Items, &ids).await?;
+        Tag::detach_all(db, TagOwner::Items, &ids).await?;
+        Ok(purged.len() as u64)
+    }
+
     pub async fn get_all_from_record(db: &Db, rid: Uuid) -> sqlx::Result<Vec<Item>> {
         let res: Vec<Item> = sqlx::query_as::<Postgres, Item>(
-            "SELECT * FROM Items i WHERE i.uid=$1") //IMPLEMENT
+            "SELECT i.* FROM Items i JOIN record_items ri ON ri.iid = i.id
+             WHERE ri.rid=$1 AND i.deleted_at IS NULL
+             ORDER BY ri.created_at")
             .bind(rid)
             .fetch_all(&db.pool).await?;
         Ok(res)
@@ -146,6 +285,8 @@ impl Default for Item {
             attributes: Vec::new(),
             notes: Vec::new(),
             created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
         }
     }
 }
@@ -0,0 +1,150 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::Db;
+
+/// One timestamped entry in a record's journal, with the fact entries that
+/// were logged along with it
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct RecordEntry {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub rid: Uuid,
+    pub uid: Uuid,
+    pub content: String,
+    #[serde(default)]
+    pub facts: Vec<Uuid>,
+    /// When the entry happened, which may be earlier than when it was written
+    #[serde(default="Utc::now")]
+    pub logged_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+const SELECT_ENTRY: &str =
+    "SELECT e.*, ARRAY(SELECT f.fid FROM record_entry_facts f WHERE f.eid = e.id) AS facts
+     FROM record_entries e";
+
+impl RecordEntry {
+
+    pub fn new(rid: Uuid, uid: Uuid, content: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            rid,
+            uid,
+            content,
+            facts: Vec::new(),
+            logged_at: Utc::now(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert(&self, db: &Db) -> sqlx::Result<Self> {
+        let mut tx = db.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO record_entries (id, rid, uid, content, logged_at, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $6)")
+            .bind(&self.id)
+            .bind(&self.rid)
+            .bind(&self.uid)
+            .bind(&self.content)
+            .bind(&self.logged_at)
+            .bind(&self.created_at)
+            .execute(&mut tx).await?;
+        link_facts(&mut tx, self.id, &self.facts).await?;
+        let res = fetch(&mut tx, self.id).await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(&format!("{} WHERE e.id=$1", SELECT_ENTRY))
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The record's entries logged in `[from, to)`, oldest first. Either end
+    /// may be left open.
+    pub async fn get_range(
+        db: &Db, rid: Uuid, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, limit: i64, offset: i64,
+    ) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(&format!(
+            "{} WHERE e.rid=$1
+             AND ($2::timestamptz IS NULL OR e.logged_at >= $2)
+             AND ($3::timestamptz IS NULL OR e.logged_at < $3)
+             ORDER BY e.logged_at, e.created_at LIMIT $4 OFFSET $5", SELECT_ENTRY))
+            .bind(rid)
+            .bind(from)
+            .bind(to)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Writes the content, time and linked facts of the entry
+    pub async fn update(&self, db: &Db) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let n = sqlx::query(
+            "UPDATE record_entries SET content=$2, logged_at=$3, updated_at=CURRENT_TIMESTAMP WHERE id=$1")
+            .bind(&self.id)
+            .bind(&self.content)
+            .bind(&self.logged_at)
+            .execute(&mut tx).await?
+            .rows_affected();
+        if n == 0 {
+            return Ok(None);
+        }
+        sqlx::query("DELETE FROM record_entry_facts WHERE eid=$1")
+            .bind(&self.id)
+            .execute(&mut tx).await?;
+        link_facts(&mut tx, self.id, &self.facts).await?;
+        let res = fetch(&mut tx, self.id).await?;
+        tx.commit().await?;
+        Ok(Some(res))
+    }
+
+    pub async fn delete_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar("DELETE FROM record_entries WHERE id=$1 RETURNING id")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Whether every one of the fact entries belongs to the user
+    pub async fn owns_facts(db: &Db, uid: Uuid, facts: &[Uuid]) -> sqlx::Result<bool> {
+        if facts.is_empty() {
+            return Ok(true);
+        }
+        let owned: i64 = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT id) FROM fact_entries WHERE id = ANY($1) AND uid=$2")
+            .bind(facts)
+            .bind(uid)
+            .fetch_one(&db.pool).await?;
+        let mut distinct = facts.to_vec();
+        distinct.sort();
+        distinct.dedup();
+        Ok(owned as usize == distinct.len())
+    }
+}
+
+async fn link_facts(tx: &mut Transaction<'_, Postgres>, eid: Uuid, facts: &[Uuid]) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO record_entry_facts (eid, fid) SELECT $1, f FROM unnest($2::uuid[]) AS f
+         ON CONFLICT DO NOTHING")
+        .bind(eid)
+        .bind(facts)
+        .execute(&mut *tx).await?;
+    Ok(())
+}
+
+async fn fetch(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> sqlx::Result<RecordEntry> {
+    sqlx::query_as::<Postgres, RecordEntry>(&format!("{} WHERE e.id=$1", SELECT_ENTRY))
+        .bind(id)
+        .fetch_one(&mut *tx).await
+}
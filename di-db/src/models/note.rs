@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{Db, Visibility};
+
+/// A markdown note attached to one record, item, fact entry or group. Each
+/// edit keeps the content it replaced as a [`NoteRevision`].
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct Note {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub content: String,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default="Visibility::default")]
+    pub visibility: Visibility,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Content of a note before one of its edits
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct NoteRevision {
+    pub id: Uuid,
+    pub nid: Uuid,
+    pub uid: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Kinds of rows that notes attach to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteOwner {
+    Records,
+    Items,
+    FactEntries,
+    Groups,
+}
+
+impl NoteOwner {
+
+    pub const ALL: [NoteOwner; 4] = [NoteOwner::Records, NoteOwner::Items, NoteOwner::FactEntries, NoteOwner::Groups];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "record" | "records" => Some(NoteOwner::Records),
+            "item" | "items" => Some(NoteOwner::Items),
+            "fact_entry" | "fact_entries" => Some(NoteOwner::FactEntries),
+            "group" | "groups" => Some(NoteOwner::Groups),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            NoteOwner::Records => "records",
+            NoteOwner::Items => "items",
+            NoteOwner::FactEntries => "fact_entries",
+            NoteOwner::Groups => "groups",
+        }
+    }
+
+    /// Owner of the row notes would be attached to, `None` if there's no
+    /// such row
+    pub async fn row_owner(&self, db: &Db, entity_id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT uid FROM public.{} WHERE id=$1", self.table()))
+            .bind(entity_id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Owner of the row notes would be attached to, `None` if there's no
+    /// such row or `viewer` can't see it: only its owner sees a row that
+    /// isn't public
+    pub async fn visible_row_owner(&self, db: &Db, entity_id: Uuid, viewer: Option<Uuid>) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT uid FROM public.{} WHERE id=$1 AND (uid=$2 OR visibility='public')", self.table()))
+            .bind(entity_id)
+            .bind(viewer)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+impl Note {
+
+    pub fn new(uid: Uuid, owner: NoteOwner, entity_id: Uuid, content: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            uid,
+            entity: owner.table().into(),
+            entity_id,
+            content,
+            pinned: false,
+            visibility: Visibility::default(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert(&self, db: &Db) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO notes (id, uid, entity, entity_id, content, pinned, visibility, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8) RETURNING *")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.entity)
+            .bind(&self.entity_id)
+            .bind(&self.content)
+            .bind(&self.pinned)
+            .bind(&self.visibility)
+            .bind(&self.created_at)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>("SELECT * FROM notes WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Notes on the row, pinned ones first and then newest first. With
+    /// `public_only`, notes the author kept to themselves are left out.
+    pub async fn get_all(db: &Db, owner: NoteOwner, entity_id: Uuid, public_only: bool) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM notes WHERE entity=$1 AND entity_id=$2 AND (NOT $3 OR visibility='public')
+             ORDER BY pinned DESC, created_at DESC")
+            .bind(owner.table())
+            .bind(entity_id)
+            .bind(public_only)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Writes the note's content, pin and visibility. When the content
+    /// changed, the old content is kept as a revision in the same transaction
+    /// and `updated_at` moves; otherwise it's left as it was.
+    pub async fn update(&self, db: &Db) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let previous: Option<String> = sqlx::query_scalar("SELECT content FROM notes WHERE id=$1 FOR UPDATE")
+            .bind(&self.id)
+            .fetch_optional(&mut tx).await?;
+        let previous = match previous { Some(content) => content, None => return Ok(None) };
+        let edited = previous != self.content;
+        if edited {
+            sqlx::query("INSERT INTO note_revisions (nid, uid, content) VALUES ($1, $2, $3)")
+                .bind(&self.id)
+                .bind(&self.uid)
+                .bind(&previous)
+                .execute(&mut tx).await?;
+        }
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "UPDATE notes SET content=$2, pinned=$3, visibility=$4,
+                updated_at=CASE WHEN $5 THEN CURRENT_TIMESTAMP ELSE updated_at END
+             WHERE id=$1 RETURNING *")
+            .bind(&self.id)
+            .bind(&self.content)
+            .bind(&self.pinned)
+            .bind(&self.visibility)
+            .bind(edited)
+            .fetch_optional(&mut tx).await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Pins or unpins the note. Unlike [`Note::update`] this leaves
+    /// `updated_at` alone, so pinning doesn't mark a note as edited.
+    pub async fn set_pinned(db: &Db, id: Uuid, pinned: bool) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "UPDATE notes SET pinned=$2 WHERE id=$1 RETURNING *")
+            .bind(id)
+            .bind(pinned)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn delete_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar("DELETE FROM notes WHERE id=$1 RETURNING id")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Removes the notes of rows that are gone for good
+    pub async fn delete_all(db: &Db, owner: NoteOwner, entity_ids: &[Uuid]) -> sqlx::Result<u64> {
+        let res = sqlx::query("DELETE FROM notes WHERE entity=$1 AND entity_id = ANY($2)")
+            .bind(owner.table())
+            .bind(entity_ids)
+            .execute(&db.pool).await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Earlier versions of the note, newest first
+    pub async fn revisions(db: &Db, id: Uuid) -> sqlx::Result<Vec<NoteRevision>> {
+        let res: Vec<NoteRevision> = sqlx::query_as::<Postgres, NoteRevision>(
+            "SELECT * FROM note_revisions WHERE nid=$1 ORDER BY created_at DESC")
+            .bind(id)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Copies the legacy `notes` text arrays into notes of their own, private
+    /// and dated like the row, counting the ones added per table. Rows that
+    /// already have notes are skipped, so it can be run again safely.
+    pub async fn migrate_legacy(db: &Db) -> sqlx::Result<BTreeMap<String, u64>> {
+        let mut added = BTreeMap::new();
+        for owner in NoteOwner::ALL.iter().filter(|o| **o != NoteOwner::Groups) {
+            let res = sqlx::query(&format!(
+                "INSERT INTO notes (uid, entity, entity_id, content, created_at, updated_at)
+                 SELECT t.uid, $1, t.id, n.content,
+                    COALESCE(t.created_at, CURRENT_TIMESTAMP), COALESCE(t.created_at, CURRENT_TIMESTAMP)
+                 FROM public.{} t, unnest(t.notes) AS n(content)
+                 WHERE TRIM(n.content) <> ''
+                 AND NOT EXISTS (SELECT 1 FROM notes x WHERE x.entity=$1 AND x.entity_id=t.id)",
+                owner.table()))
+                .bind(owner.table())
+                .execute(&db.pool).await?;
+            added.insert(owner.table().to_string(), res.rows_affected());
+        }
+        Ok(added)
+    }
+}
@@ -0,0 +1,388 @@
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    types::{chrono::{Utc, DateTime, Duration}, uuid::Uuid},
+    FromRow, postgres::Postgres, prelude::*,
+};
+use dynomite::AttributeError;
+use div_com::DError;
+use crate::{Db, models::Model};
+
+/// A third-party application registered by a user to request access
+/// to other users' data through the authorization code grant
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct OAuthClient {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A short-lived, single-use authorization code handed to the client
+/// after the resource owner has consented
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct OAuthCode {
+    pub code: String,
+    pub client_id: Uuid,
+    pub uid: Uuid,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// An access/refresh token pair issued to a client on behalf of a user
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct OAuthToken {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub access_token: String,
+    /// The refresh token as handed to the client. Only its hash is stored,
+    /// so this is `None` on tokens read back from the database.
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub refresh_token: Option<String>,
+    /// Hex encoded SHA-256 of the refresh token
+    #[serde(skip)]
+    pub refresh_hash: Option<String>,
+    pub client_id: Uuid,
+    pub uid: Uuid,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+    pub refresh_expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Scope {
+    #[serde(rename="profile")]
+    Profile,
+    #[serde(rename="records:read")]
+    RecordsRead,
+    #[serde(rename="records:write")]
+    RecordsWrite,
+    #[serde(rename="items:read")]
+    ItemsRead,
+    #[serde(rename="items:write")]
+    ItemsWrite,
+    #[serde(rename="facts:read")]
+    FactsRead,
+    #[serde(rename="facts:write")]
+    FactsWrite,
+}
+
+/// Generates an opaque random token suitable for codes, secrets and bearer tokens
+pub fn random_token() -> String {
+    format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple())
+}
+
+impl OAuthClient {
+
+    pub fn new<T: Into<String>>(
+        uid: Uuid, name: T, secret: String, redirect_uris: Vec<String>, scopes: Vec<Scope>
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            uid,
+            name: name.into(),
+            secret,
+            redirect_uris,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn allows_redirect(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+
+    /// True if every requested scope was granted to the client on registration
+    pub fn allows_scopes(&self, scopes: &[Scope]) -> bool {
+        scopes.iter().all(|s| self.scopes.contains(&s.to_string()))
+    }
+
+    pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
+        let res: Uuid = sqlx::query_scalar(
+            "INSERT INTO oauth_clients (id, uid, name, secret, redirect_uris, scopes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.name)
+            .bind(&self.secret)
+            .bind(&self.redirect_uris)
+            .bind(&self.scopes)
+            .bind(&self.created_at)
+            .fetch_one(&db.pool).await?;
+        Ok(Self { id: res, ..self })
+    }
+
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM oauth_clients WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_all_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM oauth_clients WHERE uid=$1")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn delete_by_id(db: &Db, uid: Uuid, id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(
+            "DELETE FROM oauth_clients WHERE id=$1 AND uid=$2 RETURNING id")
+            .bind(id)
+            .bind(uid)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+impl OAuthCode {
+
+    pub fn new(client_id: Uuid, uid: Uuid, redirect_uri: String, scopes: &[Scope], ttl: Duration) -> Self {
+        Self {
+            code: random_token(),
+            client_id, uid, redirect_uri,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            expires_at: Utc::now() + ttl,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
+        sqlx::query(
+            "INSERT INTO oauth_codes (code, client_id, uid, redirect_uri, scopes, expires_at, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(&self.code)
+            .bind(&self.client_id)
+            .bind(&self.uid)
+            .bind(&self.redirect_uri)
+            .bind(&self.scopes)
+            .bind(&self.expires_at)
+            .bind(&self.created_at)
+            .execute(&db.pool).await?;
+        Ok(self)
+    }
+
+    /// Removes and returns the code, so that each code can be exchanged at most once
+    pub async fn take(db: &Db, code: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "DELETE FROM oauth_codes WHERE code=$1 RETURNING *")
+            .bind(code)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
+    }
+}
+
+impl OAuthToken {
+
+    pub fn issue(client_id: Uuid, uid: Uuid, scopes: Vec<String>, ttl: Duration, refresh_ttl: Duration) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            access_token: random_token(),
+            refresh_token: Some(random_token()),
+            refresh_hash: None,
+            client_id, uid, scopes,
+            expires_at: Utc::now() + ttl,
+            refresh_expires_at: Some(Utc::now() + refresh_ttl),
+            revoked: false,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.revoked && self.expires_at > Utc::now()
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope.to_string())
+    }
+
+    pub fn expires_in(&self) -> i64 {
+        (self.expires_at - Utc::now()).num_seconds().max(0)
+    }
+
+    /// Stores the token, with the refresh token replaced by its hash
+    pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
+        sqlx::query(
+            "INSERT INTO oauth_tokens (id, access_token, refresh_hash, client_id, uid, scopes,
+                expires_at, refresh_expires_at, revoked, created_at)
+             VALUES ($1, $2, encode(sha256(convert_to($3, 'UTF8')), 'hex'), $4, $5, $6, $7, $8, $9, $10)")
+            .bind(&self.id)
+            .bind(&self.access_token)
+            .bind(&self.refresh_token)
+            .bind(&self.client_id)
+            .bind(&self.uid)
+            .bind(&self.scopes)
+            .bind(&self.expires_at)
+            .bind(&self.refresh_expires_at)
+            .bind(&self.revoked)
+            .bind(&self.created_at)
+            .execute(&db.pool).await?;
+        Ok(self)
+    }
+
+    pub async fn get_by_access_token(db: &Db, token: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM oauth_tokens WHERE access_token=$1")
+            .bind(token)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Revokes and returns the pair the client's refresh token belongs to,
+    /// unless it was already used or has expired. Revoking and reading in one
+    /// statement means concurrent requests can't both redeem the same token.
+    pub async fn redeem_refresh_token(db: &Db, client_id: Uuid, token: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "UPDATE oauth_tokens SET revoked=TRUE
+             WHERE refresh_hash=encode(sha256(convert_to($1, 'UTF8')), 'hex') AND client_id=$2
+               AND NOT revoked AND refresh_expires_at > CURRENT_TIMESTAMP
+             RETURNING *")
+            .bind(token)
+            .bind(client_id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn revoke(db: &Db, id: Uuid) -> sqlx::Result<()> {
+        sqlx::query("UPDATE oauth_tokens SET revoked=TRUE WHERE id=$1")
+            .bind(id)
+            .execute(&db.pool).await?;
+        Ok(())
+    }
+}
+
+impl Scope {
+
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::Profile,
+            Self::RecordsRead, Self::RecordsWrite,
+            Self::ItemsRead, Self::ItemsWrite,
+            Self::FactsRead, Self::FactsWrite,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Profile => "profile",
+            Self::RecordsRead => "records:read",
+            Self::RecordsWrite => "records:write",
+            Self::ItemsRead => "items:read",
+            Self::ItemsWrite => "items:write",
+            Self::FactsRead => "facts:read",
+            Self::FactsWrite => "facts:write",
+        }
+    }
+
+    /// Human readable description shown on the consent page
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Profile => "View your username and email",
+            Self::RecordsRead => "View your records",
+            Self::RecordsWrite => "Create and modify your records",
+            Self::ItemsRead => "View your items",
+            Self::ItemsWrite => "Create and modify your items",
+            Self::FactsRead => "View your facts and fact entries",
+            Self::FactsWrite => "Log new fact entries",
+        }
+    }
+
+    /// Parses a space-delimited scope parameter, as sent in OAuth2 requests
+    pub fn parse_list(scopes: &str) -> Result<Vec<Self>, DError> {
+        scopes.split_whitespace()
+            .map(Self::from_str)
+            .collect()
+    }
+
+    pub fn join(scopes: &[String]) -> String {
+        scopes.join(" ")
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = DError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::all().into_iter()
+            .find(|s| s.as_str() == value)
+            .ok_or(DError::AttError(AttributeError::InvalidFormat))
+    }
+}
+
+impl ToString for Scope {
+    fn to_string(&self) -> String {
+        self.as_str().to_string()
+    }
+}
+
+impl Default for OAuthClient {
+    fn default() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            uid: Uuid::nil(),
+            name: String::new(),
+            secret: String::new(),
+            redirect_uris: Vec::new(),
+            scopes: Vec::new(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+impl Model for OAuthClient {
+    fn table() -> String { String::from("oauth_clients") }
+    fn foreign_id() -> String { String::from("client_id") }
+    fn id(self) -> Uuid { self.id }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scope_list() {
+        let scopes = Scope::parse_list("records:read  facts:write").unwrap();
+        assert_eq!(scopes, vec![Scope::RecordsRead, Scope::FactsWrite]);
+        assert!(Scope::parse_list("records:read admin").is_err());
+    }
+
+    #[test]
+    fn client_checks_redirect_and_scopes() {
+        let client = OAuthClient::new(Uuid::nil(), "app", String::new(),
+            vec!["https://app.example/cb".into()], vec![Scope::RecordsRead]);
+        assert!(client.allows_redirect("https://app.example/cb"));
+        assert!(!client.allows_redirect("https://evil.example/cb"));
+        assert!(client.allows_scopes(&[Scope::RecordsRead]));
+        assert!(!client.allows_scopes(&[Scope::RecordsRead, Scope::FactsWrite]));
+    }
+
+    #[test]
+    fn refresh_tokens_outlive_access_tokens_and_stay_out_of_json() {
+        let tok = OAuthToken::issue(Uuid::nil(), Uuid::nil(), vec!["profile".into()],
+            Duration::hours(1), Duration::days(30));
+        assert!(tok.refresh_expires_at.unwrap() > tok.expires_at);
+        assert!(tok.refresh_token.is_some());
+        let json = serde_json::to_value(&tok).unwrap();
+        assert!(json.get("refresh_token").is_none());
+        assert!(json.get("refresh_hash").is_none());
+    }
+}
@@ -1,14 +1,14 @@
 use serde::{Serialize, Deserialize};
 use sqlx::{
     types::{chrono::{Utc, DateTime, NaiveDate, NaiveDateTime}, Json, uuid::Uuid},
-    FromRow, Type, postgres::{Postgres, PgRow}, Decode, prelude::*,
+    FromRow, Type, Transaction, postgres::{Postgres, PgRow}, Decode, prelude::*,
 };
 use crate::{Db,
     types::{Visibility, Status},
-    models::{Model, User, Item, Group, Link,},
+    models::{Model, User, Item, Group, Link, audit, attribute::{Attribute, AttributeOwner}, Note, NoteOwner, Tag, TagOwner},
 };
 
-//TODO add validation so that user/record name combo is unique
+/// Names are unique per user, which the `(name, uid)` constraint enforces
 #[derive(Serialize, Deserialize, FromRow, Clone)]
 pub struct Record {
     #[serde(default="Uuid::new_v4")]
@@ -28,6 +28,9 @@ pub struct Record {
     pub created_at: DateTime<Utc>,
     #[serde(default="Utc::now")]
     pub updated_at: DateTime<Utc>,
+    /// When the record was moved to the trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Record {
@@ -66,10 +69,15 @@ impl Record {
         self, db: &Db, visibility: T, id: Uuid,
     ) -> sqlx::Result<Self> where T: Into<Visibility>{
         let vis = visibility.into();
-        sqlx::query("UPDATE Records SET visibility=$1 WHERE id=$2")
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let after: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "UPDATE Records SET visibility=$1, updated_at=CURRENT_TIMESTAMP WHERE id=$2 RETURNING *")
                 .bind(&vis)
                 .bind(id)
-                .execute(&db.pool).await?;
+                .fetch_optional(&mut tx).await?;
+        audit::log(&mut tx, "update", before.as_ref(), after.as_ref()).await?;
+        tx.commit().await?;
         Ok ( Self { visibility: vis, ..self } )
     }
 
@@ -77,28 +85,115 @@ impl Record {
         self, db: &Db, status: T, id: Uuid,
     ) -> sqlx::Result<Self> where T: Into<Status>{
         let stat = status.into();
-        sqlx::query("UPDATE Records SET visibility=$1 WHERE id=$2")
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let after: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "UPDATE Records SET status=$1, updated_at=CURRENT_TIMESTAMP WHERE id=$2 RETURNING *")
                 .bind(&stat)
                 .bind(id)
-                .execute(&db.pool).await?;
+                .fetch_optional(&mut tx).await?;
+        audit::log(&mut tx, "update", before.as_ref(), after.as_ref()).await?;
+        tx.commit().await?;
         Ok ( Self { status: stat, ..self } )
     }
 
+    /// Every record that is neither archived nor in the trash
     pub async fn get_all(db: &Db) -> sqlx::Result<Vec<Self>> {
         let res: Vec<Record> = sqlx::query_as::<Postgres, Record>(
-            "SELECT * FROM Records")
+            "SELECT * FROM Records WHERE status NOT IN ('archived', 'deleted')")
             .fetch_all(&db.pool).await?;
         Ok(res)
     }
 
+    /// The user's records, leaving out archived ones and the trash
     pub async fn get_all_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Vec<Self>> {
         let res: Vec<Record> = sqlx::query_as::<Postgres, Record>(
-            "SELECT * FROM Records r WHERE r.uid=$1")
+            "SELECT * FROM Records r WHERE r.uid=$1 AND r.status NOT IN ('archived', 'deleted')")
             .bind(uid)
             .fetch_all(&db.pool).await?;
         Ok(res)
     }
 
+    /// The user's records with the given status, most recently changed first
+    pub async fn get_by_status(db: &Db, uid: Uuid, status: Status) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Record> = sqlx::query_as::<Postgres, Record>(
+            "SELECT * FROM Records r WHERE r.uid=$1 AND r.status=$2
+             ORDER BY COALESCE(r.deleted_at, r.updated_at) DESC")
+            .bind(uid)
+            .bind(status)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Moves the record to the trash. `None` if it is missing or already there.
+    pub async fn soft_delete(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "UPDATE Records SET status='deleted', deleted_at=CURRENT_TIMESTAMP,
+                updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status <> 'deleted' RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "trash", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Takes the record back out of the trash as active
+    pub async fn restore(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "UPDATE Records SET status='active', deleted_at=NULL, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status='deleted' RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "restore", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Archives or unarchives the record. Trashed records have to be restored first.
+    pub async fn set_archived(db: &Db, id: Uuid, archived: bool) -> sqlx::Result<Option<Self>> {
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let sql = if archived {
+            "UPDATE Records SET status='archived', updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status NOT IN ('archived', 'deleted') RETURNING *"
+        } else {
+            "UPDATE Records SET status='active', updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND status='archived' RETURNING *"
+        };
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(sql)
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() {
+            let action = if archived { "archive" } else { "unarchive" };
+            audit::log(&mut tx, action, before.as_ref(), res.as_ref()).await?;
+        }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Permanently removes records trashed before `cutoff`
+    pub async fn purge_deleted(db: &Db, cutoff: DateTime<Utc>) -> sqlx::Result<u64> {
+        let mut tx = db.pool.begin().await?;
+        let purged: Vec<Record> = sqlx::query_as::<Postgres, Record>(
+            "DELETE FROM Records WHERE status='deleted' AND deleted_at < $1 RETURNING *")
+            .bind(cutoff)
+            .fetch_all(&mut tx).await?;
+        for rec in &purged {
+            audit::log(&mut tx, "purge", Some(rec), None).await?;
+        }
+        tx.commit().await?;
+        let ids = purged.iter().map(|rec| rec.id).collect::<Vec<_>>();
+        Attribute::delete_all(db, AttributeOwner::Records, &ids).await?;
+        Note::delete_all(db, NoteOwner::Records, &ids).await?;
+        Tag::detach_all(db, TagOwner::Records, &ids).await?;
+        Ok(purged.len() as u64)
+    }
+
     // implemented in model trait -- remove?
     pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
         let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
@@ -108,6 +203,16 @@ impl Record {
         Ok(res)
     }
 
+    /// The row, locked until the transaction ends so the audit entry's
+    /// `before` is the state the change was made to
+    async fn lock(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "SELECT * FROM Records WHERE id=$1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx).await?;
+        Ok(res)
+    }
+
     pub async fn get_by_username_and_name(db: &Db, username: String, name: String)
         -> sqlx::Result<Option<Self>>
     {
@@ -122,17 +227,22 @@ impl Record {
     }
 
     pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
-        let res: Uuid = sqlx::query(
-            "INSERT INTO Records (uid, name, status, visibility, created_at)
-             VALUES ($1, $2, $3, $4, $5) RETURNING id")
+        let mut tx = db.pool.begin().await?;
+        let res: Record = sqlx::query_as::<Postgres, Record>(
+            "INSERT INTO Records (uid, name, description, status, visibility, attributes, notes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *")
             .bind(&self.uid)
             .bind(&self.name)
+            .bind(&self.description)
             .bind(&self.status)
             .bind(&self.visibility)
+            .bind(&self.attributes)
+            .bind(&self.notes)
             .bind(&self.created_at)
-            .fetch_one(&db.pool).await?
-            .get("id");
-        Ok( Self { id: res, ..self })
+            .fetch_one(&mut tx).await?;
+        audit::log(&mut tx, "create", None, Some(&res)).await?;
+        tx.commit().await?;
+        Ok(res)
     }
 
     pub async fn add_new_item<T: Into<String>>
@@ -144,23 +254,58 @@ impl Record {
     }
 
     pub async fn delete_by_id<I: Into<Uuid>>(db: &Db, id:  I) -> sqlx::Result<Uuid> {
-        let res = sqlx::query(
-            "DELETE FROM Records WHERE id=$1 RETURNING id")
+        let mut tx = db.pool.begin().await?;
+        let res: Record = sqlx::query_as::<Postgres, Record>(
+            "DELETE FROM Records WHERE id=$1 RETURNING *")
             .bind(id.into())
-            .fetch_one(&db.pool).await?;
-        Ok( res.get("id") )
+            .fetch_one(&mut tx).await?;
+        audit::log(&mut tx, "delete", Some(&res), None).await?;
+        tx.commit().await?;
+        Attribute::delete_all(db, AttributeOwner::Records, &[res.id]).await?;
+        Note::delete_all(db, NoteOwner::Records, &[res.id]).await?;
+        Tag::detach_all(db, TagOwner::Records, &[res.id]).await?;
+        Ok(res.id)
     }
 
-    pub async fn update_by_id<I, R>(db: &Db, id: I, record: R) -> sqlx::Result<Uuid>
+    /// Writes every client-editable column of `record` to the row `id` and
+    /// bumps `updated_at`, unless the row has changed since `unmodified_since`.
+    /// The status is left alone: it only changes through archive, trash and
+    /// restore.
+    /// `None` means the record is gone or was modified concurrently.
+    pub async fn update_by_id<I, R>(
+        db: &Db, id: I, record: R, unmodified_since: DateTime<Utc>,
+    ) -> sqlx::Result<Option<Self>>
     where
         I: Into<Uuid>,
         R: Into<Record>
     {
-        let res = sqlx::query(
-            "UPDATE FROM Records WHERE id=$1 RETURNING id")
-            .bind(id.into())
-            .fetch_one(&db.pool).await?;
-        Ok( res.get("id") )
+        let (id, rec): (Uuid, Record) = (id.into(), record.into());
+        let mut tx = db.pool.begin().await?;
+        let before = Self::lock(&mut tx, id).await?;
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "UPDATE Records SET name=$2, description=$3, visibility=$4,
+                attributes=$5, notes=$6, updated_at=CURRENT_TIMESTAMP
+             WHERE id=$1 AND updated_at=$7 RETURNING *")
+            .bind(id)
+            .bind(&rec.name)
+            .bind(&rec.description)
+            .bind(&rec.visibility)
+            .bind(&rec.attributes)
+            .bind(&rec.notes)
+            .bind(unmodified_since)
+            .fetch_optional(&mut tx).await?;
+        if res.is_some() { audit::log(&mut tx, "update", before.as_ref(), res.as_ref()).await?; }
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_name(db: &Db, uid: Uuid, name: String) -> sqlx::Result<Option<Self>> {
+        let res: Option<Record> = sqlx::query_as::<Postgres, Record>(
+            "SELECT * FROM Records WHERE uid=$1 AND name=$2")
+            .bind(uid)
+            .bind(name)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
     }
 }
 
@@ -177,6 +322,7 @@ impl Default for Record {
             notes: Vec::new(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            deleted_at: None,
         }
     }
 }
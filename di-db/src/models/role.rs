@@ -0,0 +1,213 @@
+use dynomite::AttributeError;
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    types::{chrono::{Utc, DateTime}, uuid::Uuid},
+    FromRow, postgres::Postgres,
+};
+use div_com::DError;
+use crate::{Db, models::userinfo::UserType};
+
+/// A single capability checked before an admin route is served
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Permission {
+    #[serde(rename="admin:read")]
+    AdminRead,
+    #[serde(rename="db:manage")]
+    DbManage,
+    #[serde(rename="server:manage")]
+    ServerManage,
+    #[serde(rename="users:manage")]
+    UsersManage,
+    #[serde(rename="audit:read")]
+    AuditRead,
+}
+
+/// The role a user holds, plus any permissions granted to them on top
+/// of what the role already implies. Users without a row are plain users.
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct UserRole {
+    pub uid: Uuid,
+    pub role: UserType,
+    pub permissions: Vec<String>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Append-only record of a request made against the admin API
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct AdminAction {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Option<Uuid>,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserType {
+
+    /// Permissions every holder of the role has
+    pub fn permissions(&self) -> Vec<Permission> {
+        match self {
+            Self::Administrator => Permission::all(),
+            Self::Moderator => vec![
+                Permission::AdminRead, Permission::UsersManage, Permission::AuditRead
+            ],
+            Self::Associate => vec![Permission::AdminRead],
+            Self::User => Vec::new(),
+        }
+    }
+}
+
+impl Permission {
+
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::AdminRead, Self::DbManage, Self::ServerManage,
+            Self::UsersManage, Self::AuditRead,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AdminRead => "admin:read",
+            Self::DbManage => "db:manage",
+            Self::ServerManage => "server:manage",
+            Self::UsersManage => "users:manage",
+            Self::AuditRead => "audit:read",
+        }
+    }
+}
+
+impl std::str::FromStr for Permission {
+    type Err = DError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::all().into_iter()
+            .find(|p| p.as_str() == value)
+            .ok_or(DError::AttError(AttributeError::InvalidFormat))
+    }
+}
+
+impl ToString for Permission {
+    fn to_string(&self) -> String {
+        self.as_str().to_string()
+    }
+}
+
+impl UserRole {
+
+    pub fn new(uid: Uuid, role: UserType, permissions: &[Permission]) -> Self {
+        Self {
+            uid, role,
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission)
+            || self.permissions.contains(&permission.to_string())
+    }
+
+    /// Whether `other` holds a permission this role doesn't, by role or grant
+    pub fn is_outranked_by(&self, other: &UserRole) -> bool {
+        Permission::all().into_iter().any(|p| other.has_permission(p) && !self.has_permission(p))
+    }
+
+    /// Falls back to a plain user role for users that were never granted one
+    pub async fn get_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Self> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM user_roles WHERE uid=$1")
+            .bind(uid)
+            .fetch_optional(&db.pool).await?;
+        Ok(res.unwrap_or_else(|| Self::new(uid, UserType::User, &[])))
+    }
+
+    /// Inserts or replaces the role held by the user
+    pub async fn upsert(self, db: &Db) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO user_roles (uid, role, permissions, updated_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (uid) DO UPDATE
+             SET role=EXCLUDED.role, permissions=EXCLUDED.permissions, updated_at=EXCLUDED.updated_at
+             RETURNING *")
+            .bind(&self.uid)
+            .bind(&self.role)
+            .bind(&self.permissions)
+            .bind(Utc::now())
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+impl AdminAction {
+
+    pub fn new<T, U>(uid: Option<Uuid>, method: T, path: U, status: u16) -> Self
+    where T: Into<String>, U: Into<String> {
+        Self {
+            id: Uuid::new_v4(),
+            uid,
+            method: method.into(),
+            path: path.into(),
+            status: status as i32,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
+        sqlx::query(
+            "INSERT INTO admin_actions (id, uid, method, path, status, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.method)
+            .bind(&self.path)
+            .bind(&self.status)
+            .bind(&self.created_at)
+            .execute(&db.pool).await?;
+        Ok(self)
+    }
+
+    /// Most recent actions first, optionally only those taken by one user
+    pub async fn get_recent(db: &Db, uid: Option<Uuid>, limit: i64, offset: i64)
+        -> sqlx::Result<Vec<Self>>
+    {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM admin_actions
+             WHERE ($1::uuid IS NULL OR uid=$1)
+             ORDER BY created_at DESC LIMIT $2 OFFSET $3")
+            .bind(uid)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn role_and_grants_both_confer_permissions() {
+        let role = UserRole::new(Uuid::nil(), UserType::Associate, &[Permission::AuditRead]);
+        assert!(role.has_permission(Permission::AdminRead));
+        assert!(role.has_permission(Permission::AuditRead));
+        assert!(!role.has_permission(Permission::DbManage));
+        let admin = UserRole::new(Uuid::nil(), UserType::Administrator, &[]);
+        assert!(Permission::all().into_iter().all(|p| admin.has_permission(p)));
+    }
+
+    #[test]
+    fn extra_grants_count_towards_rank() {
+        let moderator = UserRole::new(Uuid::nil(), UserType::Moderator, &[]);
+        let admin = UserRole::new(Uuid::nil(), UserType::Administrator, &[]);
+        let granted = UserRole::new(Uuid::nil(), UserType::Associate, &[Permission::DbManage]);
+        assert!(moderator.is_outranked_by(&admin));
+        assert!(!admin.is_outranked_by(&moderator));
+        assert!(moderator.is_outranked_by(&granted));
+        assert!(!moderator.is_outranked_by(&moderator.clone()));
+    }
+}
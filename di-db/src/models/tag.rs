@@ -0,0 +1,492 @@
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::Db;
+
+/// A user's tag, written `namespace:name` or just `name`. Names are
+/// normalized to lowercase with dashes for spaces.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, PartialEq)]
+pub struct Tag {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    #[serde(default)]
+    pub namespace: String,
+    pub name: String,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A tag with the number of rows carrying it
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct TagCount {
+    pub id: Uuid,
+    pub namespace: String,
+    pub name: String,
+    pub count: i64,
+}
+
+/// A public row carrying a tag, for the public tag pages
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct TaggedRow {
+    pub entity: String,
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub username: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Kinds of rows that can be tagged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TagOwner {
+    Records,
+    Items,
+    FactTypes,
+    Groups,
+}
+
+/// Most tags one filter expression may name
+pub const MAX_EXPR_TAGS: usize = 20;
+
+/// Deepest nesting of parentheses and `NOT` one filter expression may have
+pub const MAX_EXPR_DEPTH: usize = 32;
+
+impl TagOwner {
+
+    pub const ALL: [TagOwner; 4] = [TagOwner::Records, TagOwner::Items, TagOwner::FactTypes, TagOwner::Groups];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "record" | "records" => Some(TagOwner::Records),
+            "item" | "items" => Some(TagOwner::Items),
+            "fact_type" | "fact_types" => Some(TagOwner::FactTypes),
+            "group" | "groups" => Some(TagOwner::Groups),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            TagOwner::Records => "records",
+            TagOwner::Items => "items",
+            TagOwner::FactTypes => "fact_types",
+            TagOwner::Groups => "groups",
+        }
+    }
+
+    pub async fn row_owner(&self, db: &Db, entity_id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT uid FROM public.{} WHERE id=$1", self.table()))
+            .bind(entity_id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+/// Splits `namespace:name` and normalizes both parts, `None` if the label
+/// isn't a valid tag
+pub fn parse_label(label: &str) -> Option<(String, String)> {
+    let label = label.trim().to_lowercase();
+    let (namespace, name) = match label.find(':') {
+        Some(i) => (label[..i].trim(), label[i + 1..].trim()),
+        None => ("", label.as_str()),
+    };
+    let normalize = |part: &str| part.split_whitespace().collect::<Vec<_>>().join("-");
+    let (namespace, name) = (normalize(namespace), normalize(name));
+    let valid = |part: &str| part.chars().count() < 80
+        && part.chars().all(|c| c.is_alphanumeric() || "-_.".contains(c));
+    if name.is_empty() || !valid(&name) || !valid(&namespace) {
+        return None;
+    }
+    Some((namespace, name))
+}
+
+/// The written form of a tag, the inverse of [`parse_label`]
+pub fn label(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() { name.to_string() } else { format!("{}:{}", namespace, name) }
+}
+
+impl TagCount {
+
+    pub fn label(&self) -> String {
+        label(&self.namespace, &self.name)
+    }
+}
+
+impl Tag {
+
+    pub fn label(&self) -> String {
+        label(&self.namespace, &self.name)
+    }
+
+    /// The user's tag with this label, created if it doesn't exist yet
+    pub async fn get_or_create(db: &Db, uid: Uuid, namespace: &str, name: &str) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO tags (uid, namespace, name) VALUES ($1, $2, $3)
+             ON CONFLICT (uid, namespace, name) DO UPDATE SET name=EXCLUDED.name
+             RETURNING *")
+            .bind(uid)
+            .bind(namespace)
+            .bind(name)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get(db: &Db, uid: Uuid, namespace: &str, name: &str) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM tags WHERE uid=$1 AND namespace=$2 AND name=$3")
+            .bind(uid)
+            .bind(namespace)
+            .bind(name)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Tags on the row, by label
+    pub async fn get_for(db: &Db, owner: TagOwner, entity_id: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT t.* FROM tags t JOIN entity_tags et ON et.tid = t.id
+             WHERE et.entity=$1 AND et.entity_id=$2 ORDER BY t.namespace, t.name")
+            .bind(owner.table())
+            .bind(entity_id)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn attach(&self, db: &Db, owner: TagOwner, entity_id: Uuid) -> sqlx::Result<()> {
+        sqlx::query("INSERT INTO entity_tags (tid, entity, entity_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+            .bind(&self.id)
+            .bind(owner.table())
+            .bind(entity_id)
+            .execute(&db.pool).await?;
+        Ok(())
+    }
+
+    pub async fn detach(&self, db: &Db, owner: TagOwner, entity_id: Uuid) -> sqlx::Result<bool> {
+        let n = sqlx::query("DELETE FROM entity_tags WHERE tid=$1 AND entity=$2 AND entity_id=$3")
+            .bind(&self.id)
+            .bind(owner.table())
+            .bind(entity_id)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(n > 0)
+    }
+
+    /// Removes the tags of rows that are gone for good
+    pub async fn detach_all(db: &Db, owner: TagOwner, entity_ids: &[Uuid]) -> sqlx::Result<u64> {
+        let res = sqlx::query("DELETE FROM entity_tags WHERE entity=$1 AND entity_id = ANY($2)")
+            .bind(owner.table())
+            .bind(entity_ids)
+            .execute(&db.pool).await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Every tag of the user with how often it's used, most used first
+    pub async fn counts(db: &Db, uid: Uuid) -> sqlx::Result<Vec<TagCount>> {
+        let res: Vec<TagCount> = sqlx::query_as::<Postgres, TagCount>(
+            "SELECT t.id, t.namespace, t.name, COUNT(et.tid) AS count
+             FROM tags t LEFT JOIN entity_tags et ON et.tid = t.id
+             WHERE t.uid=$1 GROUP BY t.id ORDER BY count DESC, t.namespace, t.name")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's tags whose name, or `namespace:name`, starts with `prefix`,
+    /// most used first
+    pub async fn autocomplete(db: &Db, uid: Uuid, prefix: &str, limit: i64) -> sqlx::Result<Vec<TagCount>> {
+        let pattern = format!("{}%", prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let res: Vec<TagCount> = sqlx::query_as::<Postgres, TagCount>(
+            "SELECT t.id, t.namespace, t.name, COUNT(et.tid) AS count
+             FROM tags t LEFT JOIN entity_tags et ON et.tid = t.id
+             WHERE t.uid=$1 AND (t.name LIKE $2 OR (t.namespace || ':' || t.name) LIKE $2)
+             GROUP BY t.id ORDER BY count DESC, t.name LIMIT $3")
+            .bind(uid)
+            .bind(pattern)
+            .bind(limit)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Gives the tag a new label. If the user already has a tag with that
+    /// label, this one is merged into it instead. Returns the surviving tag
+    /// with its new count.
+    pub async fn rename(&self, db: &Db, namespace: &str, name: &str) -> sqlx::Result<TagCount> {
+        let mut tx = db.pool.begin().await?;
+        let existing: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM tags WHERE uid=$1 AND namespace=$2 AND name=$3 AND id <> $4")
+            .bind(&self.uid)
+            .bind(namespace)
+            .bind(name)
+            .bind(&self.id)
+            .fetch_optional(&mut tx).await?;
+        let survivor = match existing {
+            Some(target) => {
+                sqlx::query(
+                    "INSERT INTO entity_tags (tid, entity, entity_id, created_at)
+                     SELECT $2, entity, entity_id, created_at FROM entity_tags WHERE tid=$1
+                     ON CONFLICT DO NOTHING")
+                    .bind(&self.id)
+                    .bind(target)
+                    .execute(&mut tx).await?;
+                sqlx::query("DELETE FROM tags WHERE id=$1")
+                    .bind(&self.id)
+                    .execute(&mut tx).await?;
+                target
+            },
+            None => {
+                sqlx::query("UPDATE tags SET namespace=$2, name=$3 WHERE id=$1")
+                    .bind(&self.id)
+                    .bind(namespace)
+                    .bind(name)
+                    .execute(&mut tx).await?;
+                self.id
+            },
+        };
+        let res: TagCount = sqlx::query_as::<Postgres, TagCount>(
+            "SELECT t.id, t.namespace, t.name, COUNT(et.tid) AS count
+             FROM tags t LEFT JOIN entity_tags et ON et.tid = t.id
+             WHERE t.id=$1 GROUP BY t.id")
+            .bind(survivor)
+            .fetch_one(&mut tx).await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn delete(&self, db: &Db) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM tags WHERE id=$1")
+            .bind(&self.id)
+            .execute(&db.pool).await?;
+        Ok(())
+    }
+
+    /// Which of `ids` match the expression
+    pub async fn matching(db: &Db, owner: TagOwner, expr: &TagExpr, ids: &[Uuid]) -> sqlx::Result<HashSet<Uuid>> {
+        let mut binds = Vec::new();
+        let cond = expr.sql("x", &mut binds, 3);
+        let sql = format!("SELECT x FROM unnest($2::uuid[]) AS x WHERE {}", cond);
+        let mut query = sqlx::query_scalar::<Postgres, Uuid>(&sql)
+            .bind(owner.table())
+            .bind(ids);
+        for bind in binds {
+            query = query.bind(bind);
+        }
+        Ok(query.fetch_all(&db.pool).await?.into_iter().collect())
+    }
+
+    /// Public records, items, fact types and groups carrying a tag with this
+    /// label, whoever they belong to. Trashed rows are left out.
+    pub async fn public_rows(db: &Db, namespace: &str, name: &str, limit: i64) -> sqlx::Result<Vec<TaggedRow>> {
+        let selects = TagOwner::ALL.iter()
+            .map(|owner| format!(
+                "SELECT '{table}' AS entity, x.id, x.uid, x.name, u.username, x.created_at
+                 FROM public.{table} x JOIN users u ON u.id = x.uid
+                 JOIN entity_tags et ON et.entity = '{table}' AND et.entity_id = x.id
+                 JOIN tags t ON t.id = et.tid
+                 WHERE x.visibility = 'public' AND t.namespace = $1 AND t.name = $2
+                   AND x.deleted_at IS NULL",
+                table = owner.table()))
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+        let res: Vec<TaggedRow> = sqlx::query_as::<Postgres, TaggedRow>(&format!(
+            "{} ORDER BY created_at DESC NULLS LAST LIMIT $3", selects))
+            .bind(namespace)
+            .bind(name)
+            .bind(limit)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+/// A filter over tags such as `work AND (urgent OR blocked) AND NOT done`.
+/// `NOT` binds tightest, then `AND`, then `OR`; tags side by side are
+/// joined with `AND`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpr {
+    Tag(String, String),
+    Not(Box<TagExpr>),
+    And(Vec<TagExpr>),
+    Or(Vec<TagExpr>),
+}
+
+impl TagExpr {
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let spaced = input.replace('(', " ( ").replace(')', " ) ");
+        let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+        if tokens.is_empty() {
+            return Err("is empty".into());
+        }
+        let mut parser = ExprParser { tokens, pos: 0, tags: 0, depth: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+        }
+    }
+
+    /// SQL condition on the id column `id`. Labels are appended to `binds`
+    /// and numbered from `first`; the entity table name is expected at `$1`.
+    pub fn sql(&self, id: &str, binds: &mut Vec<String>, first: usize) -> String {
+        match self {
+            TagExpr::Tag(namespace, name) => {
+                let n = first + binds.len();
+                binds.push(namespace.clone());
+                binds.push(name.clone());
+                format!(
+                    "EXISTS (SELECT 1 FROM entity_tags et JOIN tags t ON t.id = et.tid
+                     WHERE et.entity = $1 AND et.entity_id = {} AND t.namespace = ${} AND t.name = ${})",
+                    id, n, n + 1)
+            },
+            TagExpr::Not(inner) => format!("NOT ({})", inner.sql(id, binds, first)),
+            TagExpr::And(all) => format!("({})", all.iter()
+                .map(|e| e.sql(id, binds, first)).collect::<Vec<_>>().join(" AND ")),
+            TagExpr::Or(any) => format!("({})", any.iter()
+                .map(|e| e.sql(id, binds, first)).collect::<Vec<_>>().join(" OR ")),
+        }
+    }
+}
+
+struct ExprParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    tags: usize,
+    depth: usize,
+}
+
+impl<'a> ExprParser<'a> {
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn is(&self, keyword: &str) -> bool {
+        self.peek().map_or(false, |t| t.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<TagExpr, String> {
+        let mut any = vec![self.and()?];
+        while self.is("or") {
+            self.pos += 1;
+            any.push(self.and()?);
+        }
+        Ok(if any.len() == 1 { any.remove(0) } else { TagExpr::Or(any) })
+    }
+
+    fn and(&mut self) -> Result<TagExpr, String> {
+        let mut all = vec![self.unary()?];
+        loop {
+            if self.is("and") {
+                self.pos += 1;
+            } else if self.peek().map_or(true, |t| t == ")" || t.eq_ignore_ascii_case("or")) {
+                break;
+            }
+            all.push(self.unary()?);
+        }
+        Ok(if all.len() == 1 { all.remove(0) } else { TagExpr::And(all) })
+    }
+
+    fn unary(&mut self) -> Result<TagExpr, String> {
+        let token = self.peek().ok_or("ends too early")?;
+        self.pos += 1;
+        if token.eq_ignore_ascii_case("not") || token == "(" {
+            self.depth += 1;
+            if self.depth > MAX_EXPR_DEPTH {
+                return Err(format!("nests deeper than {} levels", MAX_EXPR_DEPTH));
+            }
+            let inner = self.nested(token)?;
+            self.depth -= 1;
+            return Ok(inner);
+        }
+        if token == ")" || token.eq_ignore_ascii_case("and") || token.eq_ignore_ascii_case("or") {
+            return Err(format!("unexpected '{}'", token));
+        }
+        self.tags += 1;
+        if self.tags > MAX_EXPR_TAGS {
+            return Err(format!("names more than {} tags", MAX_EXPR_TAGS));
+        }
+        let (namespace, name) = parse_label(token).ok_or_else(|| format!("'{}' is not a valid tag", token))?;
+        Ok(TagExpr::Tag(namespace, name))
+    }
+
+    /// What follows a `NOT` or an opening parenthesis
+    fn nested(&mut self, opener: &str) -> Result<TagExpr, String> {
+        if opener != "(" {
+            return Ok(TagExpr::Not(Box::new(self.unary()?)));
+        }
+        let inner = self.or()?;
+        if self.peek() != Some(")") {
+            return Err("is missing a ')'".into());
+        }
+        self.pos += 1;
+        Ok(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(label: &str) -> TagExpr {
+        let (ns, name) = parse_label(label).unwrap();
+        TagExpr::Tag(ns, name)
+    }
+
+    #[test]
+    fn labels_are_normalized() {
+        assert_eq!(parse_label(" Work : Deep Focus "), Some(("work".into(), "deep-focus".into())));
+        assert_eq!(parse_label("urgent"), Some(("".into(), "urgent".into())));
+        assert_eq!(parse_label("proj:"), None);
+        assert_eq!(parse_label("a'b"), None);
+        assert_eq!(parse_label("a/b"), None);
+    }
+
+    #[test]
+    fn expressions_follow_precedence() {
+        assert_eq!(TagExpr::parse("a b OR NOT c").unwrap(), TagExpr::Or(vec![
+            TagExpr::And(vec![tag("a"), tag("b")]),
+            TagExpr::Not(Box::new(tag("c"))),
+        ]));
+        assert_eq!(TagExpr::parse("work AND (x:urgent OR blocked)").unwrap(), TagExpr::And(vec![
+            tag("work"),
+            TagExpr::Or(vec![tag("x:urgent"), tag("blocked")]),
+        ]));
+        assert!(TagExpr::parse("a AND").is_err());
+        assert!(TagExpr::parse("(a OR b").is_err());
+        assert!(TagExpr::parse("a ) b").is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |n: usize| format!("{}a{}", "( ".repeat(n), " )".repeat(n));
+        assert!(TagExpr::parse(&nested(MAX_EXPR_DEPTH)).is_ok());
+        assert!(TagExpr::parse(&nested(MAX_EXPR_DEPTH + 1)).is_err());
+        assert!(TagExpr::parse(&"NOT ".repeat(10_000)).is_err());
+        assert!(TagExpr::parse(&"(".repeat(10_000)).is_err());
+    }
+
+    #[test]
+    fn sql_numbers_binds_in_order() {
+        let mut binds = Vec::new();
+        let sql = TagExpr::parse("a OR ns:b").unwrap().sql("x", &mut binds, 3);
+        assert_eq!(binds, vec!["", "a", "ns", "b"]);
+        assert!(sql.contains("t.namespace = $3 AND t.name = $4"));
+        assert!(sql.contains("t.namespace = $5 AND t.name = $6"));
+    }
+
+    #[test]
+    fn sql_keeps_the_expression_structure() {
+        let mut binds = Vec::new();
+        let sql = TagExpr::parse("a AND NOT (b OR c)").unwrap().sql("x.id", &mut binds, 2);
+        let exists = |n: usize| format!(
+            "EXISTS (SELECT 1 FROM entity_tags et JOIN tags t ON t.id = et.tid
+                     WHERE et.entity = $1 AND et.entity_id = x.id AND t.namespace = ${} AND t.name = ${})",
+            n, n + 1);
+        assert_eq!(sql, format!("({} AND NOT (({} OR {})))", exists(2), exists(4), exists(6)));
+        assert_eq!(binds, vec!["", "a", "", "b", "", "c"]);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres, Transaction, types::Json};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{
+    Db, Visibility, Status, InvalidField,
+    bulk::{insert_row, BulkRow},
+    models::{Record, Item, FactType, fact::kind::ValueType},
+};
+
+/// One version of a record template. Versions are never edited: a change
+/// is saved as a new row with the next `version` under the same owner and
+/// name.
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct RecordTemplate {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    #[serde(default="first_version")]
+    pub version: i32,
+    pub description: Option<String>,
+    pub definition: Json<TemplateDefinition>,
+    #[serde(default="Visibility::default")]
+    pub visibility: Visibility,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+fn first_version() -> i32 { 1 }
+
+/// What a record made from a template starts with
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TemplateDefinition {
+    /// Description given to new records
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub status: Status,
+    /// Fact types the records use, created for the user when they don't
+    /// have one of that name yet
+    #[serde(default)]
+    pub fact_types: Vec<TemplateFactType>,
+    #[serde(default)]
+    pub items: Vec<TemplateItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateFactType {
+    pub name: String,
+    #[serde(default)]
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub units: Vec<String>,
+}
+
+/// An item linked to every record of the template. The user's item of the
+/// same name is reused, since item names are unique per user.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateItem {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Names of the template's fact types this item has as fields
+    #[serde(default)]
+    pub fields: Vec<String>,
+}
+
+impl TemplateDefinition {
+
+    /// Names must be valid and distinct, and item fields must name one of
+    /// the template's fact types
+    pub fn check(&self) -> Vec<InvalidField> {
+        let mut errors = Vec::new();
+        let valid_name = |name: &str| !name.trim().is_empty() && name.chars().count() < 80;
+        let mut kinds = HashSet::new();
+        for (i, kind) in self.fact_types.iter().enumerate() {
+            if !valid_name(&kind.name) {
+                errors.push(InvalidField::new(format!("fact_types[{}].name", i), "must be between 1 and 79 characters"));
+            } else if !kinds.insert(kind.name.as_str()) {
+                errors.push(InvalidField::new(format!("fact_types[{}].name", i), "is repeated"));
+            }
+        }
+        let mut items = HashSet::new();
+        for (i, item) in self.items.iter().enumerate() {
+            if !valid_name(&item.name) {
+                errors.push(InvalidField::new(format!("items[{}].name", i), "must be between 1 and 79 characters"));
+            } else if !items.insert(item.name.as_str()) {
+                errors.push(InvalidField::new(format!("items[{}].name", i), "is repeated"));
+            }
+            for field in item.fields.iter().filter(|f| !kinds.contains(f.as_str())) {
+                errors.push(InvalidField::new(
+                    format!("items[{}].fields", i), format!("'{}' is not one of the template's fact_types", field)));
+            }
+        }
+        errors
+    }
+}
+
+impl RecordTemplate {
+
+    pub fn new(uid: Uuid, name: String, description: Option<String>, definition: TemplateDefinition) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            uid,
+            name,
+            version: 1,
+            description,
+            definition: Json(definition),
+            visibility: Visibility::default(),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn insert(&self, db: &Db) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO record_templates (id, uid, name, version, description, definition, visibility, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.name)
+            .bind(&self.version)
+            .bind(&self.description)
+            .bind(&self.definition)
+            .bind(&self.visibility)
+            .bind(&self.created_at)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>("SELECT * FROM record_templates WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The newest version of each of the user's templates
+    pub async fn get_all_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT DISTINCT ON (name) * FROM record_templates WHERE uid=$1 ORDER BY name, version DESC")
+            .bind(uid)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The newest version of every template whose newest version is public
+    pub async fn get_public(db: &Db, limit: i64, offset: i64) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM (SELECT DISTINCT ON (uid, name) * FROM record_templates ORDER BY uid, name, version DESC) t
+             WHERE t.visibility='public' ORDER BY t.created_at DESC LIMIT $1 OFFSET $2")
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Every version of this template, newest first
+    pub async fn versions(&self, db: &Db) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM record_templates WHERE uid=$1 AND name=$2 ORDER BY version DESC")
+            .bind(&self.uid)
+            .bind(&self.name)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Saves a new version after the newest one. Of two saves racing for
+    /// the same number, the second fails on the `(uid, name, version)` key.
+    pub async fn new_version(
+        &self, db: &Db, description: Option<String>, definition: TemplateDefinition, visibility: Visibility,
+    ) -> sqlx::Result<Self> {
+        let res: Self = sqlx::query_as::<Postgres, Self>(
+            "INSERT INTO record_templates (uid, name, version, description, definition, visibility)
+             SELECT $1, $2, MAX(version) + 1, $3, $4, $5 FROM record_templates WHERE uid=$1 AND name=$2
+             RETURNING *")
+            .bind(&self.uid)
+            .bind(&self.name)
+            .bind(description)
+            .bind(Json(definition))
+            .bind(visibility)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Creates a record for `uid` named `name`, with the template's items,
+    /// fact types and fields, all in one transaction
+    pub async fn instantiate(&self, db: &Db, uid: Uuid, name: String) -> sqlx::Result<Record> {
+        let def = &self.definition.0;
+        let rec = Record {
+            description: def.description.clone(),
+            visibility: def.visibility,
+            status: def.status,
+            ..Record::new(uid, name)
+        };
+        let mut tx = db.pool.begin().await?;
+        create(&mut tx, BulkRow::Record(rec.clone())).await?;
+        populate(&mut tx, uid, rec.id, def).await?;
+        sqlx::query("INSERT INTO templated_records (rid, tid) VALUES ($1, $2)")
+            .bind(&rec.id)
+            .bind(&self.id)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(rec)
+    }
+
+    /// Brings the user's records made from older versions of this template
+    /// up to this version, or only `only` of them when given. Items, fact
+    /// types and fields the new version adds are created; nothing the
+    /// records already have is removed. Returns the ids of the migrated
+    /// records.
+    pub async fn migrate(&self, db: &Db, uid: Uuid, only: Option<&[Uuid]>) -> sqlx::Result<Vec<Uuid>> {
+        let mut tx = db.pool.begin().await?;
+        let rids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT tr.rid FROM templated_records tr
+             JOIN record_templates t ON t.id = tr.tid
+             JOIN records r ON r.id = tr.rid
+             WHERE r.uid=$1 AND t.uid=$2 AND t.name=$3 AND t.version < $4
+             AND ($5::uuid[] IS NULL OR tr.rid = ANY($5))
+             FOR UPDATE OF tr")
+            .bind(uid)
+            .bind(&self.uid)
+            .bind(&self.name)
+            .bind(&self.version)
+            .bind(only)
+            .fetch_all(&mut tx).await?;
+        for rid in &rids {
+            populate(&mut tx, uid, *rid, &self.definition.0).await?;
+        }
+        sqlx::query("UPDATE templated_records SET tid=$1, migrated_at=CURRENT_TIMESTAMP WHERE rid = ANY($2)")
+            .bind(&self.id)
+            .bind(&rids)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(rids)
+    }
+
+    /// The template version the record was made from or last migrated to
+    pub async fn for_record(db: &Db, rid: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT t.* FROM record_templates t JOIN templated_records tr ON tr.tid = t.id WHERE tr.rid=$1")
+            .bind(rid)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+async fn create(tx: &mut Transaction<'_, Postgres>, row: BulkRow) -> sqlx::Result<()> {
+    insert_row(tx, &row).await?;
+    if let Some(entry) = BulkRow::audit_entry("create", None, Some(&row)) {
+        entry.insert(&mut *tx).await?;
+    }
+    Ok(())
+}
+
+/// Gives the record the definition's items, and the items their fields,
+/// reusing the user's fact types and items of the same names
+async fn populate(tx: &mut Transaction<'_, Postgres>, uid: Uuid, rid: Uuid, def: &TemplateDefinition) -> sqlx::Result<()> {
+    let mut kinds = HashMap::new();
+    for kind in &def.fact_types {
+        let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM fact_types WHERE uid=$1 AND name=$2")
+            .bind(uid)
+            .bind(&kind.name)
+            .fetch_optional(&mut *tx).await?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let new = FactType {
+                    uid,
+                    name: kind.name.clone(),
+                    value_type: kind.value_type,
+                    units: kind.units.clone(),
+                    ..FactType::default()
+                };
+                let id = new.id;
+                create(tx, BulkRow::FactType(new)).await?;
+                id
+            },
+        };
+        kinds.insert(kind.name.as_str(), id);
+    }
+    for item in &def.items {
+        let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM items WHERE uid=$1 AND name=$2")
+            .bind(uid)
+            .bind(&item.name)
+            .fetch_optional(&mut *tx).await?;
+        let iid = match existing {
+            Some(id) => id,
+            None => {
+                let new = Item { description: item.description.clone(), ..Item::new(uid, item.name.clone()) };
+                let id = new.id;
+                create(tx, BulkRow::Item(new)).await?;
+                id
+            },
+        };
+        sqlx::query("INSERT INTO record_items (rid, iid) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(rid)
+            .bind(iid)
+            .execute(&mut *tx).await?;
+        for tid in item.fields.iter().filter_map(|f| kinds.get(f.as_str())) {
+            sqlx::query(
+                "INSERT INTO item_fields (iid, tid, uid, position)
+                 SELECT $1, $2, $3, COALESCE(MAX(position) + 1, 0) FROM item_fields WHERE iid=$1
+                 ON CONFLICT DO NOTHING")
+                .bind(iid)
+                .bind(tid)
+                .bind(uid)
+                .execute(&mut *tx).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(name: &str) -> TemplateFactType {
+        TemplateFactType { name: name.into(), value_type: ValueType::Decimal, units: vec!["km".into()] }
+    }
+
+    #[test]
+    fn fields_must_name_declared_fact_types() {
+        let def = TemplateDefinition {
+            fact_types: vec![kind("distance")],
+            items: vec![TemplateItem { name: "Shoes".into(), description: None, fields: vec!["distance".into(), "pace".into()] }],
+            ..TemplateDefinition::default()
+        };
+        let errors = def.check();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "items[0].fields");
+    }
+
+    #[test]
+    fn names_must_be_distinct() {
+        let def = TemplateDefinition {
+            fact_types: vec![kind("distance"), kind("distance")],
+            ..TemplateDefinition::default()
+        };
+        assert_eq!(def.check()[0].field, "fact_types[1].name");
+    }
+}
@@ -0,0 +1,381 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use uuid::Uuid;
+use crate::{
+    Db,
+    bulk::{insert_row, BulkRow},
+    models::{FactType, FactEntry},
+};
+
+/// A running, paused or stopped timer of one of the user's duration fact
+/// types, optionally for a record or an item. The time it ran is the sum
+/// of its spans; pausing closes the open span and resuming opens another.
+/// Stopping logs the total as a `Duration` fact entry in seconds.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct Timer {
+    #[serde(default="Uuid::new_v4")]
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub tid: Uuid,
+    #[serde(default)]
+    pub rid: Option<Uuid>,
+    #[serde(default)]
+    pub iid: Option<Uuid>,
+    #[serde(default)]
+    pub eid: Option<Uuid>,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub stopped_at: Option<DateTime<Utc>>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// One stretch of time a timer ran. `ended_at` is `None` while it runs.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, PartialEq)]
+pub struct TimerSpan {
+    pub timer: Uuid,
+    pub started_at: DateTime<Utc>,
+    #[serde(default)]
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// Seconds timed in one day or week, starting on `period`
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, PartialEq)]
+pub struct TimerTotal {
+    pub period: NaiveDate,
+    pub seconds: i64,
+}
+
+/// What a total is grouped by
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TotalPeriod {
+    Day,
+    Week,
+}
+
+/// What timers a total is over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimerScope {
+    User,
+    Record(Uuid),
+    Item(Uuid),
+}
+
+impl TimerState {
+    pub fn of(timer: &Timer, spans: &[TimerSpan]) -> Self {
+        if timer.stopped_at.is_some() {
+            Self::Stopped
+        } else if spans.iter().any(|s| s.ended_at.is_none()) {
+            Self::Running
+        } else {
+            Self::Paused
+        }
+    }
+}
+
+impl TotalPeriod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+        }
+    }
+}
+
+impl TimerSpan {
+    pub fn seconds(&self, now: DateTime<Utc>) -> i64 {
+        (self.ended_at.unwrap_or(now) - self.started_at).num_seconds().max(0)
+    }
+
+    /// Whether the span ran at some point between `from` and `to`. Open
+    /// spans, and a `to` of `None`, reach on indefinitely; spans that only
+    /// touch the window at one end don't count. This is the condition
+    /// [`Timer::overlapping`] puts to the database.
+    pub fn overlaps(&self, from: DateTime<Utc>, to: Option<DateTime<Utc>>) -> bool {
+        to.map_or(true, |to| self.started_at < to) && self.ended_at.map_or(true, |end| end > from)
+    }
+}
+
+/// Whole seconds covered by the spans, counting open ones up to `now`
+pub fn total_seconds(spans: &[TimerSpan], now: DateTime<Utc>) -> i64 {
+    spans.iter().map(|s| s.seconds(now)).sum()
+}
+
+impl Timer {
+
+    pub fn new(uid: Uuid, tid: Uuid, rid: Option<Uuid>, iid: Option<Uuid>, note: Option<String>) -> Self {
+        Self { id: Uuid::new_v4(), uid, tid, rid, iid, eid: None, note, stopped_at: None, created_at: Utc::now() }
+    }
+
+    pub async fn get_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>("SELECT * FROM timers WHERE id=$1")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// The user's timers, newest first. Stopped ones are left out unless
+    /// `stopped` is set.
+    pub async fn get_all_by_user(db: &Db, uid: Uuid, stopped: bool, limit: i64, offset: i64) -> sqlx::Result<Vec<Self>> {
+        let res: Vec<Self> = sqlx::query_as::<Postgres, Self>(
+            "SELECT * FROM timers WHERE uid=$1 AND ($2 OR stopped_at IS NULL)
+             ORDER BY created_at DESC LIMIT $3 OFFSET $4")
+            .bind(uid)
+            .bind(stopped)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    pub async fn spans(&self, db: &Db) -> sqlx::Result<Vec<TimerSpan>> {
+        let res: Vec<TimerSpan> = sqlx::query_as::<Postgres, TimerSpan>(
+            "SELECT timer, started_at, ended_at FROM timer_spans WHERE timer=$1 ORDER BY started_at")
+            .bind(&self.id)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Timers of the same user and fact type, other than this one, with a
+    /// span that [overlaps](TimerSpan::overlaps) `from` to `to`
+    pub async fn overlapping(&self, db: &Db, from: DateTime<Utc>, to: Option<DateTime<Utc>>) -> sqlx::Result<Vec<Uuid>> {
+        let res: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT DISTINCT t.id FROM timers t JOIN timer_spans s ON s.timer = t.id
+             WHERE t.uid=$1 AND t.tid=$2 AND t.id <> $3
+               AND s.started_at < COALESCE($5::timestamptz, 'infinity'::timestamptz)
+               AND COALESCE(s.ended_at, 'infinity'::timestamptz) > $4")
+            .bind(&self.uid)
+            .bind(&self.tid)
+            .bind(&self.id)
+            .bind(from)
+            .bind(to)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Saves the timer and starts its first span
+    pub async fn start(&self, db: &Db) -> sqlx::Result<TimerSpan> {
+        let mut tx = db.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO timers (id, uid, tid, rid, iid, note, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(&self.id)
+            .bind(&self.uid)
+            .bind(&self.tid)
+            .bind(&self.rid)
+            .bind(&self.iid)
+            .bind(&self.note)
+            .bind(&self.created_at)
+            .execute(&mut tx).await?;
+        let span = open_span(&mut tx, self.id).await?;
+        tx.commit().await?;
+        Ok(span)
+    }
+
+    /// Closes the open span. Returns whether the timer was running.
+    pub async fn pause(&self, db: &Db) -> sqlx::Result<bool> {
+        let res = sqlx::query(
+            "UPDATE timer_spans SET ended_at=CURRENT_TIMESTAMP WHERE timer=$1 AND ended_at IS NULL")
+            .bind(&self.id)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res > 0)
+    }
+
+    /// Opens a new span. Returns `None` if the timer was already running.
+    pub async fn resume(&self, db: &Db) -> sqlx::Result<Option<TimerSpan>> {
+        let mut tx = db.pool.begin().await?;
+        let running: bool = sqlx::query_scalar::<Postgres, bool>(
+            "SELECT EXISTS (SELECT 1 FROM timer_spans WHERE timer=$1 AND ended_at IS NULL)")
+            .bind(&self.id)
+            .fetch_one(&mut tx).await?;
+        if running {
+            return Ok(None);
+        }
+        let span = open_span(&mut tx, self.id).await?;
+        tx.commit().await?;
+        Ok(Some(span))
+    }
+
+    /// Closes the open span, if any, and logs the time the timer ran as an
+    /// entry of its fact type, in one transaction. If the timer is for an
+    /// item with a field of that type, the entry is a value of the field.
+    pub async fn stop(&self, db: &Db, kind: &FactType) -> sqlx::Result<FactEntry> {
+        let mut tx = db.pool.begin().await?;
+        sqlx::query("UPDATE timer_spans SET ended_at=CURRENT_TIMESTAMP WHERE timer=$1 AND ended_at IS NULL")
+            .bind(&self.id)
+            .execute(&mut tx).await?;
+        let spans: Vec<TimerSpan> = sqlx::query_as::<Postgres, TimerSpan>(
+            "SELECT timer, started_at, ended_at FROM timer_spans WHERE timer=$1")
+            .bind(&self.id)
+            .fetch_all(&mut tx).await?;
+        let entry = FactEntry {
+            units: Some("s".into()),
+            visibility: kind.visibility,
+            ..FactEntry::new(self.uid, kind.name.clone(), total_seconds(&spans, Utc::now()).to_string())
+        };
+        let row = BulkRow::FactEntry(entry.clone());
+        insert_row(&mut tx, &row).await?;
+        if let Some(audit) = BulkRow::audit_entry("create", None, Some(&row)) {
+            audit.insert(&mut tx).await?;
+        }
+        if let Some(iid) = self.iid {
+            sqlx::query(
+                "INSERT INTO item_field_entries (eid, iid, tid)
+                 SELECT $1, iid, tid FROM item_fields WHERE iid=$2 AND tid=$3")
+                .bind(&entry.id)
+                .bind(iid)
+                .bind(&self.tid)
+                .execute(&mut tx).await?;
+        }
+        sqlx::query("UPDATE timers SET stopped_at=CURRENT_TIMESTAMP, eid=$2 WHERE id=$1")
+            .bind(&self.id)
+            .bind(&entry.id)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(entry)
+    }
+
+    /// Replaces the timer's spans with one from `started_at` to `ended_at`.
+    /// A stopped timer needs an end, and its entry is updated to the new
+    /// length.
+    pub async fn set_times(&self, db: &Db, started_at: DateTime<Utc>, ended_at: Option<DateTime<Utc>>) -> sqlx::Result<()> {
+        let mut tx = db.pool.begin().await?;
+        sqlx::query("DELETE FROM timer_spans WHERE timer=$1")
+            .bind(&self.id)
+            .execute(&mut tx).await?;
+        sqlx::query("INSERT INTO timer_spans (timer, started_at, ended_at) VALUES ($1, $2, $3)")
+            .bind(&self.id)
+            .bind(started_at)
+            .bind(ended_at)
+            .execute(&mut tx).await?;
+        if let (Some(eid), Some(ended_at)) = (self.eid, ended_at) {
+            let seconds = (ended_at - started_at).num_seconds().max(0);
+            let before = sqlx::query_as::<Postgres, FactEntry>("SELECT * FROM fact_entries WHERE id=$1 FOR UPDATE")
+                .bind(eid)
+                .fetch_optional(&mut tx).await?
+                .map(BulkRow::FactEntry);
+            let after = sqlx::query_as::<Postgres, FactEntry>("UPDATE fact_entries SET value=$2 WHERE id=$1 RETURNING *")
+                .bind(eid)
+                .bind(seconds.to_string())
+                .fetch_optional(&mut tx).await?
+                .map(BulkRow::FactEntry);
+            if let Some(audit) = BulkRow::audit_entry("update", before.as_ref(), after.as_ref()) {
+                audit.insert(&mut tx).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes the timer. The entry it logged, if any, is kept.
+    pub async fn delete(db: &Db, uid: Uuid, id: Uuid) -> sqlx::Result<bool> {
+        let res = sqlx::query("DELETE FROM timers WHERE id=$1 AND uid=$2")
+            .bind(id)
+            .bind(uid)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res > 0)
+    }
+
+    /// Seconds the user's timers ran per day or week from `from` up to
+    /// `to`, optionally only those of a record or an item. A span counts
+    /// toward the period it started in; running spans count up to now.
+    pub async fn totals(
+        db: &Db,
+        uid: Uuid,
+        scope: TimerScope,
+        period: TotalPeriod,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<TimerTotal>> {
+        let (rid, iid) = match scope {
+            TimerScope::User => (None, None),
+            TimerScope::Record(rid) => (Some(rid), None),
+            TimerScope::Item(iid) => (None, Some(iid)),
+        };
+        let res: Vec<TimerTotal> = sqlx::query_as::<Postgres, TimerTotal>(
+            "SELECT DATE_TRUNC($2, s.started_at)::date AS period,
+                    SUM(EXTRACT(EPOCH FROM COALESCE(s.ended_at, CURRENT_TIMESTAMP) - s.started_at))::bigint AS seconds
+             FROM timers t JOIN timer_spans s ON s.timer = t.id
+             WHERE t.uid=$1 AND ($3::uuid IS NULL OR t.rid=$3) AND ($4::uuid IS NULL OR t.iid=$4)
+             AND s.started_at >= $5 AND s.started_at < $6
+             GROUP BY period ORDER BY period")
+            .bind(uid)
+            .bind(period.as_str())
+            .bind(rid)
+            .bind(iid)
+            .bind(from)
+            .bind(to)
+            .fetch_all(&db.pool).await?;
+        Ok(res)
+    }
+}
+
+async fn open_span(tx: &mut Transaction<'_, Postgres>, timer: Uuid) -> sqlx::Result<TimerSpan> {
+    sqlx::query_as::<Postgres, TimerSpan>(
+        "INSERT INTO timer_spans (timer, started_at) VALUES ($1, CURRENT_TIMESTAMP)
+         RETURNING timer, started_at, ended_at")
+        .bind(timer)
+        .fetch_one(&mut *tx).await
+}
+
+/// The default window of totals: the last `n` days or weeks up to now
+pub fn default_window(period: TotalPeriod, n: i64) -> (DateTime<Utc>, DateTime<Utc>) {
+    let now = Utc::now();
+    let span = match period {
+        TotalPeriod::Day => Duration::days(n),
+        TotalPeriod::Week => Duration::weeks(n),
+    };
+    (now - span, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn span(start: i64, end: Option<i64>) -> TimerSpan {
+        let at = |s: i64| Utc.timestamp_opt(s, 0).unwrap();
+        TimerSpan { timer: Uuid::nil(), started_at: at(start), ended_at: end.map(at) }
+    }
+
+    #[test]
+    fn spans_add_up_with_open_ones_counted_to_now() {
+        let now = span(1_000, None).started_at + Duration::seconds(30);
+        let spans = vec![span(0, Some(600)), span(700, Some(760)), span(1_000, None)];
+        assert_eq!(total_seconds(&spans, now), 690);
+        assert_eq!(total_seconds(&[span(10, Some(5))], now), 0);
+    }
+
+    #[test]
+    fn spans_overlap_unless_they_only_touch() {
+        let at = |s: i64| Utc.timestamp_opt(s, 0).unwrap();
+        assert!(span(0, Some(100)).overlaps(at(50), Some(at(150))));
+        assert!(span(0, Some(100)).overlaps(at(20), Some(at(30))));
+        assert!(!span(0, Some(100)).overlaps(at(100), Some(at(200))));
+        assert!(!span(100, Some(200)).overlaps(at(0), Some(at(100))));
+        assert!(span(0, None).overlaps(at(1_000), Some(at(2_000))));
+        assert!(span(500, Some(600)).overlaps(at(0), None));
+        assert!(!span(0, Some(100)).overlaps(at(200), None));
+    }
+
+    #[test]
+    fn state_follows_spans() {
+        let timer = Timer::new(Uuid::nil(), Uuid::nil(), None, None, None);
+        assert!(TimerState::of(&timer, &[span(0, None)]) == TimerState::Running);
+        assert!(TimerState::of(&timer, &[span(0, Some(5))]) == TimerState::Paused);
+        let stopped = Timer { stopped_at: Some(Utc::now()), ..timer };
+        assert!(TimerState::of(&stopped, &[span(0, Some(5))]) == TimerState::Stopped);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    types::{chrono::{Utc, DateTime, Duration}, uuid::Uuid},
+    FromRow, postgres::Postgres,
+};
+use crate::{Db, models::oauth::random_token};
+
+/// What a [`UserToken`] may be redeemed for
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[sqlx(rename="token_kind", rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum TokenKind {
+    PasswordReset,
+    EmailVerification,
+}
+
+/// Expiring, single-use token mailed to a user to prove ownership of
+/// their email address
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct UserToken {
+    pub token: String,
+    pub uid: Uuid,
+    pub kind: TokenKind,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserToken {
+
+    pub fn new(uid: Uuid, kind: TokenKind, ttl: Duration) -> Self {
+        Self {
+            token: random_token(),
+            uid, kind,
+            expires_at: Utc::now() + ttl,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
+    }
+
+    pub async fn insert(self, db: &Db) -> sqlx::Result<Self> {
+        sqlx::query(
+            "INSERT INTO user_tokens (token, uid, kind, expires_at, created_at)
+             VALUES ($1, $2, $3, $4, $5)")
+            .bind(&self.token)
+            .bind(&self.uid)
+            .bind(&self.kind)
+            .bind(&self.expires_at)
+            .bind(&self.created_at)
+            .execute(&db.pool).await?;
+        Ok(self)
+    }
+
+    /// Removes and returns the token if it exists and is of the given kind,
+    /// so that each token can be redeemed at most once
+    pub async fn take(db: &Db, token: &str, kind: TokenKind) -> sqlx::Result<Option<Self>> {
+        let res: Option<Self> = sqlx::query_as::<Postgres, Self>(
+            "DELETE FROM user_tokens WHERE token=$1 AND kind=$2 RETURNING *")
+            .bind(token)
+            .bind(kind)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Invalidates every outstanding token of a kind for a user
+    pub async fn delete_all(db: &Db, uid: Uuid, kind: TokenKind) -> sqlx::Result<u64> {
+        let res = sqlx::query(
+            "DELETE FROM user_tokens WHERE uid=$1 AND kind=$2")
+            .bind(uid)
+            .bind(kind)
+            .execute(&db.pool).await?
+            .rows_affected();
+        Ok(res)
+    }
+}
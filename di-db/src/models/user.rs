@@ -16,19 +16,6 @@ use crate::{
 use sqlx::Postgres;
 use div_cloud::dynamo::DynamoClient;
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct UserLogin {
-    pub username: String,
-    pub password: String
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct UserRegister {
-    pub email: String,
-    pub username: String,
-    pub password: String
-}
-
 #[derive(DItem, Serialize, Deserialize, FromRow, Clone)]
 pub struct User {
     #[dynomite(partition_key)]
@@ -36,10 +23,15 @@ pub struct User {
     pub id: Uuid,
     pub email: String,
     pub username: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Argon2 hash; never serialized
+    #[serde(skip_serializing)]
     pub password: Option<String>,
+    #[serde(default)]
+    pub confirmed: bool,
     #[serde(default="Utc::now")]
     pub created_at: DateTime<Utc>,
+    #[serde(default="Utc::now")]
+    pub updated_at: DateTime<Utc>,
 }
 
 impl User {
@@ -57,16 +49,17 @@ impl User {
 
     pub async fn insert_db(self, db: &Db) -> sqlx::Result<Self> {
         let res: Uuid = sqlx::query
-            ("INSERT INTO Users (email, username, password, created_at)
-              VALUES ($1, $2, $3, $4) RETURNING id")
+            ("INSERT INTO Users (email, username, password, confirmed, created_at)
+              VALUES ($1, $2, $3, $4, $5) RETURNING id")
             .bind(&self.email)
             .bind(&self.username)
             .bind(&self.password)
+            .bind(&self.confirmed)
             .bind(&self.created_at)
             .fetch_one(&db.pool).await?
             .get("id");
         let user_with_id = User { id: res, ..self };
-        //UserInfo::from(user_with_id.clone()).insert(db).await?;
+        UserInfo::insert_empty(db, res).await?;
         Ok(user_with_id)
     }
 
@@ -74,20 +67,17 @@ impl User {
         db.insert("diuser".into(), self).await
     }
 
+    /// Deletes the user along with every row they own, see [`Db::delete_account`]
     pub async fn delete_by_username(db: &Db, username: String) -> sqlx::Result<Uuid> {
-        let res: Uuid = sqlx::query_scalar
-            ("DELETE FROM Users WHERE username=$1 RETURNING id")
-            .bind(username)
-            .fetch_one(&db.pool).await?;
-        Ok(res as Uuid)
+        match Self::get_by_username(db, username).await? {
+            Some(user) => Self::delete_by_id(db, user.id).await?.ok_or(sqlx::Error::RowNotFound),
+            None => Err(sqlx::Error::RowNotFound),
+        }
     }
 
+    /// Deletes the user along with every row they own, see [`Db::delete_account`]
     pub async fn delete_by_id(db: &Db, id: Uuid) -> sqlx::Result<Option<Uuid>> {
-        let res: Option<Uuid> = sqlx::query_scalar
-            ("DELETE FROM Users WHERE id=$1 RETURNING id")
-            .bind(id)
-            .fetch_optional(&db.pool).await?;
-        Ok(res)
+        Ok(db.delete_account(id).await?.map(|_| id))
     }
 
     pub async fn get_all(db: &Db) -> sqlx::Result<Vec<User>> {
@@ -117,10 +107,54 @@ impl User {
         Ok(res)
     }
 
+    pub async fn get_by_email(db: &Db, email: String) -> sqlx::Result<Option<User>> {
+        let res: Option<User> = sqlx::query_as::<Postgres, User>
+            ("SELECT * FROM Users WHERE email=$1")
+            .bind(email)
+            .fetch_optional(&db.pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Mark a pending account as having a verified email address
+    pub async fn confirm(db: &Db, id: Uuid) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar
+            ("UPDATE Users SET confirmed=TRUE WHERE id=$1 RETURNING id")
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Writes the user's email, username and confirmation flag, unless the
+    /// row has changed since `unmodified_since`. `None` means the user is
+    /// gone or was modified concurrently.
+    pub async fn update(self, db: &Db, unmodified_since: DateTime<Utc>) -> sqlx::Result<Option<Self>> {
+        let res: Option<User> = sqlx::query_as::<Postgres, User>
+            ("UPDATE Users SET email=$2, username=$3, confirmed=$4, updated_at=CURRENT_TIMESTAMP
+              WHERE id=$1 AND updated_at=$5 RETURNING *")
+            .bind(self.id)
+            .bind(&self.email)
+            .bind(&self.username)
+            .bind(self.confirmed)
+            .bind(unmodified_since)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
+    /// Replace the stored password hash for a user
+    pub async fn update_password(db: &Db, id: Uuid, hash: String) -> sqlx::Result<Option<Uuid>> {
+        let res: Option<Uuid> = sqlx::query_scalar
+            ("UPDATE Users SET password=$1 WHERE id=$2 RETURNING id")
+            .bind(hash)
+            .bind(id)
+            .fetch_optional(&db.pool).await?;
+        Ok(res)
+    }
+
     // Get all records created by user
     pub async fn get_all_records(db: &Db, id: Uuid) -> sqlx::Result<Vec<Record>> {
         let res: Vec<Record> = sqlx::query_as::<Postgres, Record>
-            ("SELECT * FROM Records r WHERE r.uid = $1")
+            ("SELECT * FROM Records r WHERE r.uid = $1 AND r.status NOT IN ('archived', 'deleted')")
             .bind(id)
             .fetch_all(&db.pool).await?;
         Ok(res)
@@ -138,22 +172,25 @@ impl User {
         Ok(res)
     }
 
+    /// Moves the user's item with this name to the trash
     pub async fn delete_item_by_name(
         db: &Db, uid: Uuid, item_name: String
         ) -> sqlx::Result<Uuid>
     {
-        let res: Uuid = sqlx::query(
-            "DELETE FROM Items i WHERE i.uid = $1 AND i.name = $2 RETURNING id")
+        let before: Item = sqlx::query_as::<Postgres, Item>(
+            "SELECT * FROM Items WHERE uid = $1 AND name = $2 AND status <> 'deleted'")
             .bind(uid)
             .bind(item_name)
-            .fetch_one(&db.pool).await?
-            .get("id");
-        Ok(res)
+            .fetch_one(&db.pool).await?;
+        match Item::soft_delete(db, before.id).await? {
+            Some(item) => Ok(item.id),
+            None => Err(sqlx::Error::RowNotFound),
+        }
     }
 
     pub async fn get_all_items(db: &Db, id: Uuid) -> sqlx::Result<Vec<Item>> {
         let res: Vec<Item> = sqlx::query_as::<Postgres, Item>
-            ("SELECT * FROM Items i WHERE i.uid = $1")
+            ("SELECT * FROM Items i WHERE i.uid = $1 AND i.status NOT IN ('archived', 'deleted')")
             .bind(id)
             .fetch_all(&db.pool).await?;
         Ok(res)
@@ -217,9 +254,7 @@ impl User {
     pub async fn add_new_item(
         db: &Db, uid: Uuid, item_name: String,
     ) -> sqlx::Result<Item> {
-        let item = Item::new(uid, item_name);
-        item.insert(db).await?;
-        Ok(item)
+        Item::new(uid, item_name).insert(db).await
     }
 
     pub async fn add_existing_item(db: &Db, uid: Uuid, item: Item)
@@ -237,7 +272,9 @@ impl Default for User {
             username: String::from(""),
             email: String::from(""),
             password: None,
+            confirmed: false,
             created_at: Utc::now(),
+            updated_at: Utc::now(),
         }
     }
 }
@@ -253,7 +290,7 @@ impl Model for User {
     fn table() -> String { String::from("Users") }
     fn foreign_id() -> String { String::from("uid") }
     fn fields() ->  Vec<String> {
-        let fields = vec!["id", "uid", "username", "password", "email", "created_at"];
+        let fields = vec!["id", "uid", "username", "password", "email", "confirmed", "created_at"];
         fields.into_iter()
             .map(|field| field.to_string())
             .collect::<Vec<String>>()
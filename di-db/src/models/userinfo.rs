@@ -1,26 +1,35 @@
 use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
-use dynomite::{self, Item as DItem, Attribute, FromAttributes, attr_map, AttributeValue, AttributeError};
-use div_cloud::dynamo::DynamoClient;
+use serde::{Serialize, Deserialize, Deserializer};
 use sqlx::{
-    types::{chrono::{Utc, DateTime, NaiveDate, NaiveDateTime}, Json, uuid::Uuid},
-    FromRow, Type, postgres::{Postgres, PgRow}, Decode, prelude::*,
+    types::{chrono::{Utc, DateTime, NaiveDate}, Json, uuid::Uuid},
+    FromRow, Type, postgres::{Postgres, PgRow},
 };
 use crate::{ Db,
-    models::{Model, User}
+    models::{Model, User},
+    validate::InvalidField,
 };
 
+/// ISO 3166-1 alpha-2 country codes accepted for [`UserInfo::country`]
+const COUNTRY_CODES: &str = "\
+    AD AE AF AG AI AL AM AO AQ AR AS AT AU AW AX AZ BA BB BD BE BF BG BH BI BJ BL BM BN BO \
+    BQ BR BS BT BV BW BY BZ CA CC CD CF CG CH CI CK CL CM CN CO CR CU CV CW CX CY CZ DE DJ \
+    DK DM DO DZ EC EE EG EH ER ES ET FI FJ FK FM FO FR GA GB GD GE GF GG GH GI GL GM GN GP \
+    GQ GR GS GT GU GW GY HK HM HN HR HT HU ID IE IL IM IN IO IQ IR IS IT JE JM JO JP KE KG \
+    KH KI KM KN KP KR KW KY KZ LA LB LC LI LK LR LS LT LU LV LY MA MC MD ME MF MG MH MK ML \
+    MM MN MO MP MQ MR MS MT MU MV MW MX MY MZ NA NC NE NF NG NI NL NO NP NR NU NZ OM PA PE \
+    PF PG PH PK PL PM PN PR PS PT PW PY QA RE RO RS RU RW SA SB SC SD SE SG SH SI SJ SK SL \
+    SM SN SO SR SS ST SV SX SY SZ TC TD TF TG TH TJ TK TL TM TN TO TR TT TV TW TZ UA UG UM \
+    US UY UZ VA VC VE VG VI VN VU WF WS YE YT ZA ZM ZW";
+
 #[serde(rename_all="camelCase")]
 #[derive(Serialize, Deserialize, FromRow, Clone, PartialEq)]
 pub struct UserInfo {
-    // #[dynomite(default)]
     #[serde(default="Uuid::new_v4")]
     pub id: Uuid,
-    // #[dynomite(partition_key)]
     pub uid: Uuid,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
-    pub mid_initial: Option<i8>,
+    pub mid_initial: Option<String>,
     pub phone_number: Option<String>,
     pub occupation: Option<String>,
     pub bio: Option<String>,
@@ -30,7 +39,7 @@ pub struct UserInfo {
     pub city: Option<String>,
     pub zip_code: Option<String>,
     pub state: Option<String>,
-    pub country: String,
+    pub country: Option<String>,
     pub social_links: Option<Json<SocialLinks>>,
     pub experience: i32,
     pub user_type: UserType,
@@ -38,72 +47,166 @@ pub struct UserInfo {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Partial update of a profile. A missing field is left untouched while an
+/// explicit `null` clears it.
+#[serde(rename_all="camelCase")]
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct UserInfoPatch {
+    #[serde(default, deserialize_with="nullable")]
+    pub first_name: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub last_name: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub mid_initial: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub phone_number: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub occupation: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub bio: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub gender: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub birth_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub city: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub zip_code: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub state: Option<Option<String>>,
+    #[serde(default, deserialize_with="nullable")]
+    pub country: Option<Option<String>>,
+}
+
+fn nullable<'de, T, D>(de: D) -> Result<Option<Option<T>>, D::Error>
+where T: Deserialize<'de>, D: Deserializer<'de> {
+    Option::<T>::deserialize(de).map(Some)
+}
+
 impl UserInfo {
 
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn update<T, U>(mut self, field: T, val: U) -> Self
-        where T: Into<String> {
-        let field: String = field.into();
-        match field.as_str() {
-            "first_name" => (),
-            "last_name" => (),
-            "mid_initial" => (),
-            "phone_number" => (),
-            "occupation" => (),
-            "bio" => (),
-            "img_path" => (),
-            "gender" => (),
-            "birth_date" => (),
-            "city" => (),
-            "zip_code" => (),
-            "state" => (),
-            "country" => (),
-            "social_links" => (),
-            "experience" => (),
-            _ => (),
-        }
+    pub fn new(uid: Uuid) -> Self {
+        Self { uid, ..Self::default() }
+    }
+
+    /// Applies the fields present in the patch, normalizing phone numbers
+    /// and country codes, then validates the result
+    pub fn apply(mut self, patch: UserInfoPatch) -> Result<Self, Vec<InvalidField>> {
+        let trim = |v: Option<String>| v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        if let Some(v) = patch.first_name { self.first_name = trim(v); }
+        if let Some(v) = patch.last_name { self.last_name = trim(v); }
+        if let Some(v) = patch.mid_initial { self.mid_initial = trim(v); }
+        if let Some(v) = patch.phone_number { self.phone_number = trim(v).map(|p| normalize_phone(&p)); }
+        if let Some(v) = patch.occupation { self.occupation = trim(v); }
+        if let Some(v) = patch.bio { self.bio = trim(v); }
+        if let Some(v) = patch.gender { self.gender = trim(v); }
+        if let Some(v) = patch.birth_date { self.birth_date = v; }
+        if let Some(v) = patch.city { self.city = trim(v); }
+        if let Some(v) = patch.zip_code { self.zip_code = trim(v); }
+        if let Some(v) = patch.state { self.state = trim(v); }
+        if let Some(v) = patch.country { self.country = trim(v).map(|c| c.to_uppercase()); }
+        self.validate()?;
         self.updated_at = Utc::now();
-        self.to_owned()
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<InvalidField>> {
+        let mut errors = Vec::new();
+        let mut invalid = |field: &str, reason: &str| errors.push(InvalidField {
+            field: field.to_string(), reason: reason.to_string()
+        });
+        for (field, val) in [("firstName", &self.first_name), ("lastName", &self.last_name)].iter() {
+            if val.as_ref().map(|v| v.chars().count() >= 80).unwrap_or(false) {
+                invalid(field, "must be shorter than 80 characters");
+            }
+        }
+        if let Some(initial) = &self.mid_initial {
+            if initial.chars().count() != 1 || !initial.chars().all(char::is_alphabetic) {
+                invalid("midInitial", "must be a single letter");
+            }
+        }
+        if let Some(phone) = &self.phone_number {
+            let digits = phone.trim_start_matches('+');
+            if !digits.chars().all(|c| c.is_ascii_digit()) || digits.len() < 7 || digits.len() > 15 {
+                invalid("phoneNumber", "must be 7 to 15 digits, optionally starting with +");
+            }
+        }
+        if let Some(date) = self.birth_date {
+            if date > Utc::now().naive_utc().date() {
+                invalid("birthDate", "cannot be in the future");
+            } else if date < NaiveDate::from_ymd(1900, 1, 1) {
+                invalid("birthDate", "cannot be before 1900");
+            }
+        }
+        if let Some(country) = &self.country {
+            if country.len() != 2 || !COUNTRY_CODES.split_whitespace().any(|c| c == country) {
+                invalid("country", "must be an ISO 3166-1 alpha-2 country code");
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub fn links(&self) -> SocialLinks {
+        self.social_links.as_ref().map(|l| l.0.clone()).unwrap_or_default()
     }
 
     pub async fn get_all(db: &Db) -> sqlx::Result<Vec<Self>> {
         let res: Vec<UserInfo> = sqlx::query_as::<Postgres, UserInfo>
-            ("SELECT * FROM UserInfo")
+            ("SELECT * FROM user_info")
             .fetch_all(&db.pool)
             .await?;
         Ok(res)
     }
 
-    // pub async fn insert_dynamo(self, db: &DynamoClient) -> Result<(), String> {
-    //     db.insert("diuser", self).await
-    // }
+    pub async fn get_by_user(db: &Db, uid: Uuid) -> sqlx::Result<Option<Self>> {
+        let res: Option<UserInfo> = sqlx::query_as::<Postgres, UserInfo>
+            ("SELECT * FROM user_info WHERE uid=$1")
+            .bind(uid)
+            .fetch_optional(&db.pool)
+            .await?;
+        Ok(res)
+    }
 
-    pub async fn insert_empty(db: &Db, uid: i32) -> sqlx::Result<i32> {
-        let res = sqlx::query("INSERT INTO UserInfo (
-            uid, first_name, mid_initial, last_name, phone_number,
-            occupation, bio, img_path, gender, birth_date, city,
-            zip_code, state, country, social_links, experience,
-            user_type, updated_at ) VALUES ($1, $2, $3, $4, $5, $6, $7)")
-            .bind(vec![-1_i32])
+    /// Creates the blank profile for a user if they don't have one yet,
+    /// returning whichever row now exists
+    pub async fn insert_empty(db: &Db, uid: Uuid) -> sqlx::Result<Self> {
+        sqlx::query("INSERT INTO user_info (uid) VALUES ($1) ON CONFLICT (uid) DO NOTHING")
+            .bind(uid)
             .execute(&db.pool).await?;
-        Ok(0)
+        let res: UserInfo = sqlx::query_as::<Postgres, UserInfo>
+            ("SELECT * FROM user_info WHERE uid=$1")
+            .bind(uid)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
     }
 
-    pub async fn insert_field<T: Into<String>>(self, db: &Db, field: &str, value: T) -> sqlx::Result<i32> {
-        let str_fields = vec!["first_name, last_name, phone_number, occupation, bio,
-            img_path, gender, birth_date, city, zip_code, state, country, experience,
-            user_type"];
-        if str_fields.contains(&field) {
-            let res = sqlx::query("INSERT INTO UserInfo $1 VALUES $2 RETURNING id")
-                .bind(value.into())
-                .execute(&db.pool)
-                .await?
-                .rows_affected();
-            Ok(res as i32)
-        } else { Err(sqlx::Error::ColumnNotFound(field.to_string())) }
+    /// Writes every editable field of the profile back to the database
+    pub async fn update(self, db: &Db) -> sqlx::Result<Self> {
+        let res: UserInfo = sqlx::query_as::<Postgres, UserInfo>(
+            "UPDATE user_info SET
+                first_name=$1, last_name=$2, mid_initial=$3, phone_number=$4,
+                occupation=$5, bio=$6, img_path=$7, gender=$8, birth_date=$9,
+                city=$10, zip_code=$11, state=$12, country=$13, social_links=$14,
+                updated_at=$15
+             WHERE uid=$16 RETURNING *")
+            .bind(&self.first_name)
+            .bind(&self.last_name)
+            .bind(&self.mid_initial)
+            .bind(&self.phone_number)
+            .bind(&self.occupation)
+            .bind(&self.bio)
+            .bind(&self.img_path)
+            .bind(&self.gender)
+            .bind(&self.birth_date)
+            .bind(&self.city)
+            .bind(&self.zip_code)
+            .bind(&self.state)
+            .bind(&self.country)
+            .bind(&self.social_links)
+            .bind(Utc::now())
+            .bind(&self.uid)
+            .fetch_one(&db.pool).await?;
+        Ok(res)
     }
 }
 
@@ -111,11 +214,24 @@ impl Default for UserInfo {
     fn default() -> Self {
         Self {
             id: Uuid::new_v4(),
+            uid: Uuid::nil(),
+            first_name: None,
+            last_name: None,
+            mid_initial: None,
+            phone_number: None,
+            occupation: None,
+            bio: None,
+            img_path: None,
+            gender: None,
+            birth_date: None,
+            city: None,
+            zip_code: None,
+            state: None,
+            country: None,
+            social_links: None,
+            experience: 0_i32,
             user_type: UserType::default(),
             updated_at: Utc::now(),
-            experience: 0_i32,
-            country: String::new(),
-            ..Default::default()
         }
     }
 }
@@ -126,15 +242,34 @@ impl From<User> for UserInfo {
     }
 }
 
-#[derive(Serialize, Deserialize, Type, PartialEq, Clone, Default)]
-pub struct SocialLinks(HashMap<String, String>);
+/// Strips the separators people commonly type into phone numbers
+fn normalize_phone(phone: &str) -> String {
+    phone.chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct SocialLinks(pub HashMap<String, String>);
+
+impl SocialLinks {
+
+    pub fn set(&mut self, provider: SocialProvider, url: String) {
+        self.0.insert(provider.as_str().to_string(), url);
+    }
+
+    pub fn remove(&mut self, provider: SocialProvider) -> Option<String> {
+        self.0.remove(provider.as_str())
+    }
+}
 
 pub struct SocialLink {
     pub label: String,
     pub url: String,
 }
 
-#[derive(Type, Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Type, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all="lowercase")]
 pub enum SocialProvider {
     Twitter,
     Facebook,
@@ -142,11 +277,25 @@ pub enum SocialProvider {
     Personal,
 }
 
+impl SocialProvider {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Twitter => "twitter",
+            Self::Facebook => "facebook",
+            Self::LinkedIn => "linkedin",
+            Self::Personal => "personal",
+        }
+    }
+}
+
 impl Default for SocialProvider {
     fn default() -> Self { SocialProvider::Personal }
 }
 
-#[derive(Type, Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Type, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[sqlx(rename="user_type", rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
 pub enum UserType {
     Administrator,
     Associate,
@@ -154,10 +303,6 @@ pub enum UserType {
     User,
 }
 
-impl UserType {
-
-}
-
 impl Into<String> for UserType {
     fn into(self) -> String {
         match self {
@@ -180,9 +325,35 @@ impl Default for UserType {
 }
 
 impl Model for UserInfo {
-    fn table() -> String { "UserInfo".to_string() }
+    fn table() -> String { "user_info".to_string() }
     fn foreign_id() -> String {
         "uiid".to_string()
     }
     fn id(self) -> Uuid { self.id }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_only_touches_present_fields() {
+        let info = UserInfo { city: Some("Oslo".into()), bio: Some("hi".into()), ..UserInfo::new(Uuid::nil()) };
+        let patch: UserInfoPatch = serde_json::from_str(
+            r#"{ "bio": null, "country": "no", "phoneNumber": "+47 (22) 33-44-55" }"#).unwrap();
+        let info = info.apply(patch).unwrap();
+        assert_eq!(info.city.as_deref(), Some("Oslo"));
+        assert_eq!(info.bio, None);
+        assert_eq!(info.country.as_deref(), Some("NO"));
+        assert_eq!(info.phone_number.as_deref(), Some("+4722334455"));
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let patch: UserInfoPatch = serde_json::from_str(
+            r#"{ "country": "XX", "phoneNumber": "call me", "birthDate": "2999-01-01" }"#).unwrap();
+        let errors = UserInfo::new(Uuid::nil()).apply(patch).unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["phoneNumber", "birthDate", "country"]);
+    }
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+use serde::{Serialize, Deserialize};
+use validator::{ValidationError, ValidationErrors};
+
+/// A field that failed validation, and why
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InvalidField {
+    pub field: String,
+    pub reason: String,
+}
+
+impl InvalidField {
+
+    pub fn new<T: Into<String>, U: Into<String>>(field: T, reason: U) -> Self {
+        Self { field: field.into(), reason: reason.into() }
+    }
+
+    /// Flattens the errors reported by a `#[derive(Validate)]` struct, using
+    /// each rule's message when it has one and its code otherwise
+    pub fn from_errors(errors: &ValidationErrors) -> Vec<Self> {
+        let mut fields = errors.field_errors().into_iter()
+            .flat_map(|(field, errs)| errs.iter().map(move |e| Self::new(field,
+                e.message.as_ref().map(|m| m.to_string()).unwrap_or_else(|| e.code.to_string()))))
+            .collect::<Vec<Self>>();
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        fields
+    }
+}
+
+/// Usernames end up in URLs, so they are limited to ASCII letters, digits,
+/// `_`, `-` and `.`
+pub fn valid_username(username: &str) -> Result<(), ValidationError> {
+    if username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("username_charset");
+        err.message = Some(Cow::from("may only contain letters, digits, '_', '-' and '.'"));
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_are_url_safe() {
+        assert!(valid_username("jane.doe_1-a").is_ok());
+        assert!(valid_username("no spaces").is_err());
+        assert!(valid_username("ünïcode").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, io::{self, Cursor, Write}, path::PathBuf};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use zip::{ZipWriter, write::FileOptions};
+use div_cloud::{s3, cognito::CognitoClient, dynamo::DynamoClient};
+use div_db::{Db, AttributeValue, models::{User, UserInfo}};
+use crate::config::AppConfig;
+
+/// DynamoDB tables that mirror Postgres rows
+const DYNAMO_USER_TABLE: &str = "diuser";
+const DYNAMO_FACT_TABLE: &str = "difact";
+
+/// What an account deletion removed
+#[derive(Serialize, Deserialize)]
+pub struct AccountDeletion {
+    pub uid: Uuid,
+    pub rows: BTreeMap<String, u64>,
+    pub files: usize,
+    /// Cleanup outside Postgres that failed. The account is gone either way;
+    /// these need to be finished by hand.
+    pub warnings: Vec<String>,
+}
+
+/// Removes the user's rows in one transaction, then their files, their
+/// Cognito identity and their DynamoDB mirror rows. `None` if the user
+/// doesn't exist.
+pub async fn delete_account(db: &Db, cognito: &CognitoClient, user: &User)
+    -> div_db::sqlx::Result<Option<AccountDeletion>>
+{
+    let avatar = UserInfo::get_by_user(db, user.id).await?.and_then(|info| info.img_path);
+    let rows = match db.delete_account(user.id).await? {
+        Some(rows) => rows,
+        None => return Ok(None),
+    };
+    let mut deletion = AccountDeletion { uid: user.id, rows, files: 0, warnings: Vec::new() };
+
+    if let Some(avatar) = avatar {
+        match std::fs::remove_file(PathBuf::from(AppConfig::upload_dir()).join(&avatar)) {
+            Ok(_) => deletion.files += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => deletion.warnings.push(format!("avatar {}: {}", avatar, e)),
+        }
+    }
+    if let Some(bucket) = AppConfig::upload_bucket() {
+        let client = s3::Client::new();
+        match client.list_objects(&bucket, Some(format!("{}/", user.id))).await {
+            Ok(objects) => for obj in objects {
+                let key = obj.key.unwrap_or_default();
+                match client.delete(&bucket, &key).await {
+                    Ok(_) => deletion.files += 1,
+                    Err(e) => deletion.warnings.push(format!("s3 {}/{}: {}", bucket, key, e)),
+                }
+            },
+            Err(e) => deletion.warnings.push(format!("s3 {}/{}/: listing failed: {}", bucket, user.id, e)),
+        }
+    }
+    if AppConfig::aws_configured() {
+        if dotenv::var("AWS_COGNITO_USER_POOL_ID").is_ok() {
+            if let Err(e) = cognito.delete_user(user.username.clone()).await {
+                if !e.contains("UserNotFound") {
+                    deletion.warnings.push(format!("cognito {}: {}", user.username, e));
+                }
+            }
+        }
+        match DynamoClient::new() {
+            Ok(dynamo) => {
+                let id = AttributeValue { s: Some(user.id.to_string()), ..Default::default() };
+                let key = vec![("id".to_string(), id.clone())].into_iter().collect();
+                if let Err(e) = dynamo.delete_item(DYNAMO_USER_TABLE, key).await {
+                    deletion.warnings.push(format!("dynamo {}: {}", DYNAMO_USER_TABLE, e));
+                }
+                if let Err(e) = dynamo.delete_partition(DYNAMO_FACT_TABLE, "uid", "id", id).await {
+                    deletion.warnings.push(format!("dynamo {}: {}", DYNAMO_FACT_TABLE, e));
+                }
+            },
+            Err(e) => deletion.warnings.push(format!("dynamo: {}", e)),
+        }
+    }
+    for warning in &deletion.warnings {
+        log::error!("Incomplete cleanup deleting account {}: {}", user.id, warning);
+    }
+    Ok(Some(deletion))
+}
+
+/// Builds a ZIP with one JSON file per table the user has rows in, plus
+/// their avatar and any attachments under `attachments/`
+pub async fn export_account(db: &Db, user: &User) -> io::Result<Vec<u8>> {
+    let tables = db.export_account(user.id).await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let avatar = tables.get("user_info")
+        .and_then(|rows| rows.first())
+        .and_then(|info| info.get("img_path"))
+        .and_then(|path| path.as_str())
+        .map(String::from);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let opts = FileOptions::default();
+    for (table, rows) in tables.iter().filter(|(_, rows)| !rows.is_empty()) {
+        zip.start_file(format!("{}.json", table), opts)?;
+        zip.write_all(&serde_json::to_vec_pretty(rows)?)?;
+    }
+    if let Some(avatar) = avatar {
+        if let Ok(bytes) = std::fs::read(PathBuf::from(AppConfig::upload_dir()).join(&avatar)) {
+            zip.start_file(format!("attachments/{}", avatar), opts)?;
+            zip.write_all(&bytes)?;
+        }
+    }
+    if let Some(bucket) = AppConfig::upload_bucket() {
+        let client = s3::Client::new();
+        let objects = client.list_objects(&bucket, Some(format!("{}/", user.id))).await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        for obj in objects {
+            let key = obj.key.unwrap_or_default();
+            let bytes = client.get_object(&bucket, &key).await?;
+            zip.start_file(format!("attachments/{}", key), opts)?;
+            zip.write_all(&bytes)?;
+        }
+    }
+    Ok(zip.finish()?.into_inner())
+}
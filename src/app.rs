@@ -1,6 +1,7 @@
 use super::config::AppConfig;
 use std::collections::HashMap;
-use crate::{handlers, middleware, state};
+use crate::{handlers, jobs, middleware, state};
+use actix::Actor;
 use actix_web_prom::PrometheusMetrics;
 use actix_service::ServiceFactory;
 use actix_web::{body, dev, get,  web, App, Error, HttpRequest, HttpResponse, HttpServer};
@@ -14,18 +15,24 @@ pub async fn run_api() -> std::io::Result<()> {
     let mut labels = HashMap::new();
     labels.insert("label1".to_string(), "value1".to_string());
     let prometheus = PrometheusMetrics::new("", Some("/metrics"), Some(labels));
+    let handle = st.server.clone();
+    jobs::TrashPurger::new(st.db.lock().unwrap().clone()).start();
     let srv = HttpServer::new(move || {
         App::new()
             .data(st.clone())
+            .app_data(middleware::validated_json())
             .wrap(middleware::cors())
             .wrap(middleware::logger())
             .wrap(prometheus.clone())
+            .wrap(middleware::audit::AuditContextMiddleware)
             .wrap(middleware::redis_session(&AppConfig::session_key()))
             .configure(handlers::public::routes)
             .service(handlers::api::routes("/api"))
         });
-    srv.bind(&config.clone().address())?
-        .run().await?;
+    let srv = srv.bind(&config.clone().address())?
+        .run();
+    handle.set(srv.clone());
+    srv.await?;
     Ok(())
 }
 
@@ -63,7 +70,9 @@ pub fn create_app() -> App<
     let st = state::State::new_blocking();
     App::new()
         .data(st.clone())
+        .app_data(middleware::validated_json())
         .wrap(middleware::cors())
+        .wrap(middleware::audit::AuditContextMiddleware)
         .wrap(middleware::redis_session(&AppConfig::session_key()))
         .configure(handlers::public::routes)
         .service(handlers::api::routes("/api"))
@@ -18,29 +18,66 @@ pub(crate) struct State {
     pub until: Option<i64>,
 }
 
+/// Argon2id cost parameters. Changing any of these causes existing hashes
+/// to be transparently rehashed the next time their owner logs in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashParams {
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+    pub hash_length: u32,
+}
+
+#[derive(Clone)]
 pub struct PwVerifier {
+    secret: Vec<u8>,
+    ad: Vec<u8>,
+    params: HashParams,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        Self { mem_cost: 65536, time_cost: 3, lanes: 4, hash_length: 32 }
+    }
+}
+
+impl HashParams {
+
+    /// Reads ARGON2_MEM_COST, ARGON2_TIME_COST, ARGON2_LANES and
+    /// ARGON2_HASH_LENGTH, falling back to the defaults for any unset value
+    pub fn from_env() -> Self {
+        let var = |key: &str, default: u32| dotenv::var(key).ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(default);
+        let d = Self::default();
+        Self {
+            mem_cost: var("ARGON2_MEM_COST", d.mem_cost),
+            time_cost: var("ARGON2_TIME_COST", d.time_cost),
+            lanes: var("ARGON2_LANES", d.lanes),
+            hash_length: var("ARGON2_HASH_LENGTH", d.hash_length),
+        }
+    }
 }
 
 impl PwVerifier {
+
     pub fn new() -> Self {
-        let _cf = argon2::Config {
-            variant: argon2::Variant::Argon2i,
-            version: argon2::Version::Version13,
-            secret: Self::sk().expect("HASH_SECRET_KEY not set").as_bytes(),
-            ad: Self::ad().expect("AD NOT SET").as_bytes(),
-            time_cost: 10,
-            mem_cost: 65536,
-            lanes: 4,
-            hash_length: 32,
-            thread_mode: argon2::ThreadMode::Parallel,
-        };
-        Self {  }
+        let secret = Self::sk().unwrap_or_else(|_| {
+            log::warn!("HASH_SECRET_KEY not set, hashing passwords without a secret");
+            String::new()
+        });
+        let ad = Self::ad().unwrap_or_default();
+        Self::with_params(secret.as_bytes(), ad.as_bytes(), HashParams::from_env())
+    }
+
+    pub fn with_params(secret: &[u8], ad: &[u8], params: HashParams) -> Self {
+        Self { secret: secret.to_vec(), ad: ad.to_vec(), params }
     }
 
     pub fn ad() -> Result<String, dotenv::Error> {
-        match option_env!("HASH_SECRET_KEY") {
+        match option_env!("HASH_AD") {
             Some(ev) => Ok(ev.to_string()),
-            None => Ok(dotenv::var("HASH_SECRET_KEY")?),
+            None => Ok(dotenv::var("HASH_AD")?),
         }
     }
 
@@ -51,34 +88,48 @@ impl PwVerifier {
         }
     }
 
-    pub fn hash(&self, pw: &str) -> argon2::Result<String> {
-        let sk = Self::sk().expect("HASH_SECRET_KEY not set");
-        let ad = Self::ad().expect("AD not set");
-        let cf = argon2::Config {
-            variant: argon2::Variant::Argon2i,
+    fn config(&self) -> argon2::Config {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
             version: argon2::Version::Version13,
-            secret: sk.as_bytes(),
-            ad: ad.as_bytes(),
-            time_cost: 10,
-            mem_cost: 65536,
-            lanes: 4,
-            hash_length: 32,
+            secret: &self.secret,
+            ad: &self.ad,
+            time_cost: self.params.time_cost,
+            mem_cost: self.params.mem_cost,
+            lanes: self.params.lanes,
+            hash_length: self.params.hash_length,
             thread_mode: argon2::ThreadMode::Parallel,
-        };
-        let salt = &[];
-        let hash = argon2::hash_encoded(pw.as_bytes(), salt, &cf)?;
-        return Ok(hash);
+        }
     }
 
-    pub fn verify(&self, pw: &str, hash: &str) -> argon2::Result<bool> {
+    /// Hashes a password with a fresh random 16 byte salt
+    pub fn hash(&self, pw: &str) -> argon2::Result<String> {
+        let salt = uuid::Uuid::new_v4();
+        argon2::hash_encoded(pw.as_bytes(), salt.as_bytes(), &self.config())
+    }
+
+    pub fn verify<H: AsRef<str>>(&self, pw: &str, hash: H) -> argon2::Result<bool> {
         argon2::verify_encoded_ext(
-            &hash,
-            &pw.as_bytes(),
-            Self::sk().unwrap().as_bytes(),
-            Self::ad().unwrap().as_bytes(),
+            hash.as_ref(),
+            pw.as_bytes(),
+            &self.secret,
+            &self.ad,
         )
     }
 
+    /// True if the encoded hash was produced with a different variant or
+    /// different cost parameters than the ones currently configured
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let parts: Vec<&str> = hash.split('$').collect();
+        if parts.len() != 6 || parts[1] != "argon2id" || parts[2] != "v=19" {
+            return true;
+        }
+        let expected = format!("m={},t={},p={}",
+            self.params.mem_cost, self.params.time_cost, self.params.lanes);
+        let hash_len = parts[5].len() * 3 / 4;
+        parts[3] != expected || hash_len != self.params.hash_length as usize
+    }
+
 }
 
 impl State {
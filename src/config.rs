@@ -42,6 +42,62 @@ impl AppConfig {
             .map(|k| k.as_bytes().to_vec())
     }
 
+    /// Base URL used when building links sent to users, e.g. in emails
+    pub fn public_url() -> String {
+        dotenv::var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:7777".to_string())
+    }
+
+    pub fn mail_from() -> String {
+        dotenv::var("MAIL_FROM").unwrap_or_else(|_| "noreply@div.is".to_string())
+    }
+
+    pub fn mail_dir() -> Option<String> {
+        dotenv::var("MAIL_DIR").ok()
+    }
+
+    /// Local directory user uploads such as avatars are written to
+    pub fn upload_dir() -> String {
+        dotenv::var("UPLOAD_DIR").unwrap_or_else(|_| "./uploads".to_string())
+    }
+
+    /// S3 bucket holding user attachments, stored under `{uid}/`
+    pub fn upload_bucket() -> Option<String> {
+        dotenv::var("UPLOAD_BUCKET").ok()
+    }
+
+    /// Whether AWS credentials are configured, so Cognito and DynamoDB can
+    /// be reached at all
+    pub fn aws_configured() -> bool {
+        dotenv::var("AWS_ACCESS_KEY_ID").is_ok() || dotenv::var("AWS_PROFILE").is_ok()
+    }
+
+    /// Days a trashed record or item is kept before it is purged for good
+    pub fn trash_retention_days() -> i64 {
+        dotenv::var("TRASH_RETENTION_DAYS").ok()
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(30)
+    }
+
+    /// Settings worth showing to admins. Secrets are only reported as set
+    /// or unset, and the password is stripped from the database URL.
+    pub fn summary(&self) -> ConfigSummary {
+        let redact = |key: &str| dotenv::var(key).ok().map(|_| "[redacted]".to_string());
+        ConfigSummary {
+            address: self.address(),
+            session_type: self.session_type.clone(),
+            public_url: Self::public_url(),
+            database_url: Self::database_url().map(|u| redact_url_password(&u)),
+            mail_from: Self::mail_from(),
+            mail_dir: Self::mail_dir(),
+            upload_dir: Self::upload_dir(),
+            upload_bucket: Self::upload_bucket(),
+            trash_retention_days: Self::trash_retention_days(),
+            session_key: redact("SESSION_KEY"),
+            jwt_secret: redact("JWT_SECRET"),
+            hash_secret_key: redact("HASH_SECRET_KEY"),
+        }
+    }
+
     pub fn from_file() -> Self {
         let c = include_str!("../Config.toml");
         let conf: Self = toml::from_str(c)
@@ -56,3 +112,31 @@ pub enum SessionType {
     Cookie,
     None,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSummary {
+    pub address: String,
+    pub session_type: SessionType,
+    pub public_url: String,
+    pub database_url: Option<String>,
+    pub mail_from: String,
+    pub mail_dir: Option<String>,
+    pub upload_dir: String,
+    pub upload_bucket: Option<String>,
+    pub trash_retention_days: i64,
+    pub session_key: Option<String>,
+    pub jwt_secret: Option<String>,
+    pub hash_secret_key: Option<String>,
+}
+
+fn redact_url_password(raw: &str) -> String {
+    match url::Url::parse(raw) {
+        Ok(mut url) => {
+            if url.password().is_some() {
+                let _ = url.set_password(Some("redacted"));
+            }
+            url.to_string()
+        },
+        Err(_) => "[redacted]".to_string(),
+    }
+}
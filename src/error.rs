@@ -2,7 +2,7 @@ use std::{fmt, convert::Infallible};
 use derive_more::Display;
 use actix_web::http::StatusCode;
 use div_com::error::DError;
-use div_db::sqlx::error::Error as SqlxError;
+use div_db::{InvalidField, sqlx::error::Error as SqlxError};
 use actix_web::{ResponseError, HttpResponse};
 
 pub type AResult<T> = Result<T, ApiError>;
@@ -31,27 +31,38 @@ pub enum ApiError {
 
 #[derive(Debug, Display)]
 pub enum UserError {
-    #[display(fmt = "Validation error on field: {}", field)]
-    ValidationError { field: String },
+    #[display(fmt = "Validation failed on {} field(s)", "errors.len()")]
+    ValidationError { errors: Vec<InvalidField> },
     #[display(fmt = "No user with {} = {}", field, val)]
     NoSuchUser { field: String, val: String },
+    #[display(fmt = "A user with {} = {} already exists", field, val)]
+    AlreadyExists { field: String, val: String },
+    #[display(fmt = "Account {} has not been verified", username)]
+    NotVerified { username: String },
 }
 
 impl actix_web::error::ResponseError for UserError {
 
     fn error_response(&self) -> HttpResponse {
         match self {
-            Self::ValidationError { field } => HttpResponse::BadRequest()
-                .body(format!("No field {}", field)),
+            Self::ValidationError { errors } => HttpResponse::UnprocessableEntity()
+                .json(serde_json::json!({ "errors": errors })),
             Self::NoSuchUser { field, val } => HttpResponse::NotFound()
-                .body(format!("No user with {} {}", field, val))
+                .body(format!("No user with {} {}", field, val)),
+            Self::AlreadyExists { field, val } => HttpResponse::Conflict()
+                .json(serde_json::json!({ "field": field, "value": val,
+                    "error": format!("{} already taken", field) })),
+            Self::NotVerified { username } => HttpResponse::Forbidden()
+                .body(format!("Account {} has not been verified, check your email", username)),
         }
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
-            UserError::ValidationError { .. } => StatusCode::BAD_REQUEST,
+            UserError::ValidationError { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             UserError::NoSuchUser { .. } => StatusCode::BAD_REQUEST,
+            UserError::AlreadyExists { .. } => StatusCode::CONFLICT,
+            UserError::NotVerified { .. } => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -115,3 +126,29 @@ impl From<SqlxError> for ApiError {
 
 
 
+
+impl From<&validator::ValidationErrors> for UserError {
+    fn from(e: &validator::ValidationErrors) -> Self {
+        Self::ValidationError { errors: InvalidField::from_errors(e) }
+    }
+}
+
+/// Maps rejected request bodies to field-level errors. Bodies that don't
+/// deserialize at all have no field to blame, so they're reported under `body`.
+pub fn validation_error(err: actix_web_validator::Error) -> actix_web::Error {
+    let user_err = match &err {
+        actix_web_validator::Error::Validate(e) => UserError::from(e),
+        other => UserError::ValidationError {
+            errors: vec![InvalidField::new("body", other.to_string())]
+        },
+    };
+    actix_web::error::InternalError::from_response(err, user_err.error_response()).into()
+}
+
+/// True if the query failed on a unique constraint
+pub fn is_unique_violation(e: &SqlxError) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .map(|code| code == "23505")
+        .unwrap_or(false)
+}
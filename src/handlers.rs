@@ -8,7 +8,21 @@ pub mod group;
 pub mod upload;
 pub mod public;
 pub mod fact;
+pub mod choice;
+pub mod unit;
+pub mod place;
 pub mod feed;
+pub mod trash;
+pub mod transfer;
+pub mod batch;
+pub mod history;
+pub mod attribute;
+pub mod note;
+pub mod journal;
+pub mod tag;
+pub mod field;
+pub mod template;
+pub mod timer;
 
 use crate::state::State;
 use serde::{Deserialize, Serialize};
@@ -29,6 +43,16 @@ pub mod api {
             .service(admin::routes("/admin"))
             .service(auth::routes("/auth"))
             .service(feed::routes("/feed"))
+            .service(trash::routes("/trash"))
+            .service(transfer::import_routes("/import"))
+            .service(transfer::export_routes("/export"))
+            .service(batch::routes("/batch"))
+            .service(attribute::routes("/attributes"))
+            .service(note::routes("/notes"))
+            .service(tag::routes("/tags"))
+            .service(template::routes("/templates"))
+            .service(unit::routes("/units"))
+            .service(timer::routes("/timers"))
     }
 }
 
@@ -2,7 +2,13 @@ pub mod db;
 pub mod server;
 pub mod user;
 
-use crate::{models::{Response, UserIn}, state::State, handlers::{user::*, auth::validate}};
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+use div_db::models::{AdminAction, Permission, UserRole};
+use crate::{
+    models::{Response, UserIn}, state::State, handlers::{user::*, auth::validate},
+    middleware::rbac::{AuditAdmin, RequirePermission},
+};
 use actix_session::Session;
 use actix_web::{ Error,
     web::{self, delete, get, post, put, resource, scope, ServiceConfig},
@@ -10,25 +16,51 @@ use actix_web::{ Error,
 };
 
 
-// pub struct AdminRouteGuard;
-
-// impl Guard for AdminRouteGuard {
-//     fn check(&self, request: &RequestHead, session: Session) -> bool {
-//         session.get::<usize>("uid").unwrap() == uuid::Uuid::new_v4()
-//     }
-// }
-
+/// Every admin request is audited, including those rejected for lacking
+/// permissions. Each sub-scope then requires its own permission on top of
+/// the [`Permission::AdminRead`] needed to reach the admin API at all.
 pub fn routes(base: &str) -> actix_web::Scope {
     scope(base)
         .route("", get().to(check_auth))
-        .service(self::db::routes("/db"))
-        .service(self::server::routes("/server"))
-        .service(self::user::routes("/user"))
+        .service(resource("/audit")
+            .wrap(RequirePermission(Permission::AuditRead))
+            .route(get().to(get_audit_log)))
+        .service(self::db::routes("/db")
+            .wrap(RequirePermission(Permission::DbManage)))
+        .service(self::server::routes("/server")
+            .wrap(RequirePermission(Permission::ServerManage)))
+        .service(self::user::routes("/user")
+            .wrap(RequirePermission(Permission::UsersManage)))
+        .wrap(RequirePermission(Permission::AdminRead))
+        .wrap(AuditAdmin)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub uid: Option<Uuid>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
 }
 
 pub async fn check_auth(session: Session, data: web::Data<State>) -> Result<HttpResponse, Error> {
     let user = validate(&session)?;
-    Ok(HttpResponse::Ok().json(user))
+    let db = data.db.lock().unwrap();
+    let role = UserRole::get_by_user(&db, user.id).await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(role))
+}
+
+pub async fn get_audit_log(
+    query: web::Query<AuditQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match AdminAction::get_recent(&db, query.uid, limit, offset).await {
+        Ok(actions) => HttpResponse::Ok().json(actions),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
 
 
@@ -1,22 +1,84 @@
-use crate::{models::{Response, UserIn}, state::State, handlers::{user::*, auth::validate}};
-use actix_session::Session;
+use std::collections::HashMap;
+use futures::{stream, StreamExt};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use div_db::{browse, RowChunk, TableRef};
+use crate::state::State;
 use actix_web::{ Error,
-    web::{self, delete, get, post, put, resource, scope, ServiceConfig},
+    web::{self, delete, get, post, put, resource, scope, Bytes, ServiceConfig},
     HttpRequest, HttpResponse,
 };
 
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Rows read per query while streaming an export
+const EXPORT_PAGE_SIZE: i64 = 500;
+
 pub fn routes(base: &str) -> actix_web::Scope {
     scope(base)
+        .route("", get().to(get_all_tables))
         .route("/up", get().to(db_up))
         .route("/down", get().to(db_down))
         .service(table_routes("/{table}"))
 }
 
 pub fn table_routes(base: &str) -> actix_web::Scope {
-    scope("/{table}")
+    scope(base)
         .route("", get().to(get_all_table))
-        .route("/down", get().to(table_down))
-        .route("/up", get().to(table_up))
+        .route("/stats", get().to(table_stats))
+        .route("/export", get().to(table_export))
+        .route("/truncate", post().to(table_truncate))
+        .route("/reset", post().to(table_reset))
+}
+
+/// Destructive table actions must repeat the table name back
+#[derive(Serialize, Deserialize)]
+pub struct TableConfirm {
+    pub confirm: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all="lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self { ExportFormat::Json }
+}
+
+impl ExportFormat {
+
+    fn head(self, columns: &[String]) -> String {
+        match self {
+            ExportFormat::Csv => browse::to_csv(columns, &[]),
+            ExportFormat::Json => "[".into(),
+        }
+    }
+
+    fn page(self, columns: &[String], rows: &[Value], first: bool) -> String {
+        match self {
+            ExportFormat::Csv => browse::csv_rows(columns, rows),
+            ExportFormat::Json => {
+                let rows = rows.iter().map(Value::to_string).collect::<Vec<_>>().join(",");
+                if first { rows } else { format!(",{}", rows) }
+            },
+        }
+    }
+
+    fn tail(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "",
+            ExportFormat::Json => "]",
+        }
+    }
+}
+
+/// How many rows to read at `at`, stopping at `end` if the export has a limit
+fn export_page_size(end: Option<i64>, at: i64) -> i64 {
+    end.map_or(EXPORT_PAGE_SIZE, |end| (end - at).min(EXPORT_PAGE_SIZE))
 }
 
 pub async fn db_up(data: web::Data<State>) -> Result<HttpResponse, Error> {
@@ -46,14 +108,167 @@ pub async fn db_reset(data: web::Data<State>) -> HttpResponse {
     }
 }
 
-pub async fn get_all_table(path: web::Path<String>) -> HttpResponse {
-    HttpResponse::Ok().body("")
+fn known_table(path: web::Path<String>) -> Result<TableRef, HttpResponse> {
+    TableRef::parse(&path.into_inner())
+        .ok_or_else(|| HttpResponse::NotFound().body("Unknown table"))
+}
+
+/// Splits the paging parameters from the column filters in a query string
+fn paging(mut query: HashMap<String, String>) -> Result<(Option<i64>, i64, Vec<(String, String)>), HttpResponse> {
+    let mut num = |key: &str| query.remove(key)
+        .map(|v| v.parse::<i64>().map_err(|_| HttpResponse::BadRequest()
+            .body(format!("{} must be a number", key))))
+        .transpose();
+    let limit = num("limit")?;
+    let offset = num("offset")?.unwrap_or(0).max(0);
+    query.remove("format");
+    Ok((limit, offset, query.into_iter().collect()))
+}
+
+fn db_error(e: div_db::sqlx::Error) -> HttpResponse {
+    match e {
+        div_db::sqlx::Error::ColumnNotFound(col) => HttpResponse::BadRequest()
+            .body(format!("Cannot filter on column {}", col)),
+        e => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_all_tables(data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match db.all_table_stats().await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => db_error(e),
+    }
 }
 
-pub async fn table_up(path: web::Path<String>) -> HttpResponse {
-    HttpResponse::Ok().body("")
+pub async fn get_all_table(
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let table = match known_table(path) { Ok(t) => t, Err(resp) => return resp };
+    let (limit, offset, filters) = match paging(query.into_inner()) {
+        Ok(p) => p, Err(resp) => return resp
+    };
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let db = data.db.lock().unwrap();
+    match db.table_rows(&table, &filters, Some(limit), offset).await {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) => db_error(e),
+    }
 }
 
-pub async fn table_down(path: web::Path<String>) -> HttpResponse {
-    HttpResponse::Ok().body("")
+pub async fn table_stats(path: web::Path<String>, data: web::Data<State>) -> HttpResponse {
+    let table = match known_table(path) { Ok(t) => t, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match db.table_stats(&table).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => db_error(e),
+    }
+}
+
+/// Exports every matching row, as a JSON array or a CSV file with a header.
+/// Rows are read and sent a page at a time in primary key order, each page
+/// continuing after the key of the last one, so large tables never sit in
+/// memory whole and rows aren't skipped or repeated as the table changes.
+pub async fn table_export(
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let table = match known_table(path) { Ok(t) => t, Err(resp) => return resp };
+    let query = query.into_inner();
+    let format = match query.get("format").map(String::as_str) {
+        None | Some("json") => ExportFormat::Json,
+        Some("csv") => ExportFormat::Csv,
+        Some(other) => return HttpResponse::BadRequest()
+            .body(format!("Unsupported export format {}", other)),
+    };
+    let (limit, offset, filters) = match paging(query) { Ok(p) => p, Err(resp) => return resp };
+    let end = limit.map(|limit| offset + limit.max(0));
+    let db = data.db.lock().unwrap().clone();
+    // The first page is read up front so bad filters are still a 400
+    let first = match db.table_rows(&table, &filters, Some(export_page_size(end, offset)), offset).await {
+        Ok(page) => page,
+        Err(e) => return db_error(e),
+    };
+    let head = format.head(&first.columns);
+    let filename = format!("{}.{}", first.table, if format == ExportFormat::Csv { "csv" } else { "json" });
+    let columns = first.columns.clone();
+    let first = RowChunk { rows: first.rows, after: first.after };
+
+    let pages = stream::unfold(Some((offset, None::<Value>, Some(first))), move |cursor| {
+        let (db, table, filters, columns) = (db.clone(), table.clone(), filters.clone(), columns.clone());
+        async move {
+            let (at, after, fetched) = cursor?;
+            let size = export_page_size(end, at);
+            if size <= 0 {
+                return None;
+            }
+            let chunk = match (fetched, after) {
+                (Some(chunk), _) => chunk,
+                (None, Some(after)) => match db.table_rows_after(&table, &filters, &after, size).await {
+                    Ok(chunk) => chunk,
+                    Err(e) => return Some((Err(actix_web::error::ErrorInternalServerError(e)), None)),
+                },
+                (None, None) => return None,
+            };
+            if chunk.rows.is_empty() {
+                return None;
+            }
+            let read = chunk.rows.len() as i64;
+            let next = if read < size { None } else { Some((at + read, chunk.after, None)) };
+            Some((Ok(Bytes::from(format.page(&columns, &chunk.rows, at == offset))), next))
+        }
+    });
+    let body = stream::once(async move { Ok::<_, Error>(Bytes::from(head)) })
+        .chain(pages)
+        .chain(stream::once(async move { Ok(Bytes::from_static(format.tail().as_bytes())) }));
+
+    HttpResponse::Ok()
+        .content_type(if format == ExportFormat::Csv { "text/csv" } else { "application/json" })
+        .set_header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+        .streaming(body)
+}
+
+pub async fn table_truncate(
+    path: web::Path<String>,
+    body: web::Json<TableConfirm>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let table = match known_table(path) { Ok(t) => t, Err(resp) => return resp };
+    if body.confirm != table.name() {
+        return HttpResponse::PreconditionFailed()
+            .body(format!("Set confirm to \"{}\" to truncate this table", table.name()));
+    }
+    let db = data.db.lock().unwrap();
+    match db.clear_table(&table).await {
+        Ok(deleted) => {
+            log::warn!("Admin truncated table {} ({} rows)", table.name(), deleted);
+            HttpResponse::Ok().json(deleted)
+        },
+        Err(e) => db_error(e),
+    }
+}
+
+/// Truncates the table, restarting its sequences and cascading to the tables
+/// that reference it
+pub async fn table_reset(
+    path: web::Path<String>,
+    body: web::Json<TableConfirm>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let table = match known_table(path) { Ok(t) => t, Err(resp) => return resp };
+    if body.confirm != table.name() {
+        return HttpResponse::PreconditionFailed()
+            .body(format!("Set confirm to \"{}\" to reset this table", table.name()));
+    }
+    let db = data.db.lock().unwrap();
+    match db.reset_table(&table).await {
+        Ok(_) => {
+            log::warn!("Admin reset table {}", table.name());
+            HttpResponse::Ok().json(true)
+        },
+        Err(e) => db_error(e),
+    }
 }
@@ -1,43 +1,141 @@
-use crate::models::Response;
+use chrono::{DateTime, Utc};
 use actix_web::{
     HttpResponse,
     web::{self, scope, resource, get, post},
 };
 use serde::{Deserialize, Serialize};
+use div_db::models::{Note, attribute::Attribute};
+use crate::{config::{AppConfig, ConfigSummary}, state::State};
 
 pub fn routes(base: &str) -> actix_web::Scope {
     scope(base)
         .route("", get().to(server_info))
-        .route("/up", post().to(server_up))
-        .route("/down", post().to(server_down))
+        .route("/drain", post().to(server_drain))
+        .route("/shutdown", post().to(server_shutdown))
+        .route("/templates/reload", post().to(templates_reload))
+        .route("/migrations", get().to(migration_status))
+        .route("/migrations/attributes", post().to(migrate_attributes))
+        .route("/migrations/notes", post().to(migrate_notes))
 }
 
-pub async fn server_info() -> HttpResponse {
-    HttpResponse::Ok().body("")
+#[derive(Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: String,
+    pub build: BuildInfo,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: i64,
+    pub stopping: bool,
+    pub config: ConfigSummary,
 }
 
-pub async fn server_up() -> HttpResponse {
-    HttpResponse::Ok().body("")
+#[derive(Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub profile: String,
+    pub git_commit: Option<String>,
+    pub built_at: Option<String>,
 }
 
-pub async fn server_down() -> HttpResponse {
-    HttpResponse::Ok().body("")
+#[derive(Serialize, Deserialize)]
+pub struct ServerState {
+    pub stopping: bool,
+    /// Whether in-flight requests are let finish before the server exits
+    pub graceful: bool,
 }
 
-pub async fn run_cmd(cmd: web::Json<Cmd>) -> HttpResponse {
-    use std::process::Command;
-    let proc = Command::new("sh")
-        .arg(&cmd.cmd)
-        .status()
-        .expect("Failed to execute cmd");
-    if proc.success() {
-        HttpResponse::Ok().json(Response::ok())
-    } else {
-        HttpResponse::Ok().json(Response::fail())
+impl BuildInfo {
+    /// Commit and timestamp are only known when the build script exports
+    /// GIT_COMMIT and BUILD_TIMESTAMP
+    pub fn current() -> Self {
+        Self {
+            profile: if cfg!(debug_assertions) { "debug" } else { "release" }.to_string(),
+            git_commit: option_env!("GIT_COMMIT").map(String::from),
+            built_at: option_env!("BUILD_TIMESTAMP").map(String::from),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Cmd {
-    cmd: String,
+pub async fn server_info(data: web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(ServerInfo {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build: BuildInfo::current(),
+        started_at: data.started_at,
+        uptime_secs: (Utc::now() - data.started_at).num_seconds(),
+        stopping: data.server.is_stopping(),
+        config: AppConfig::default().summary(),
+    })
+}
+
+/// Stops accepting new connections, lets in-flight requests finish and then
+/// exits. This is terminal: the admin API goes down with the server, so a
+/// drained instance is brought back by starting it again.
+pub async fn server_drain(data: web::Data<State>) -> HttpResponse {
+    stop_server(&data, true)
+}
+
+/// Stops the server right away, dropping requests still in flight
+pub async fn server_shutdown(data: web::Data<State>) -> HttpResponse {
+    stop_server(&data, false)
+}
+
+/// The stop is spawned rather than awaited, since a graceful stop waits on
+/// in-flight requests, this one included.
+fn stop_server(data: &State, graceful: bool) -> HttpResponse {
+    match data.server.get() {
+        Some(srv) => {
+            data.server.set_stopping();
+            if graceful {
+                log::warn!("Server draining through admin API, exiting once in-flight requests finish");
+            } else {
+                log::warn!("Server shutdown requested through admin API");
+            }
+            actix_rt::spawn(async move { srv.stop(graceful).await });
+            HttpResponse::Accepted().json(ServerState { stopping: true, graceful })
+        },
+        None => HttpResponse::ServiceUnavailable().body("Server handle not available"),
+    }
+}
+
+/// Reloads the compiled templates from disk
+pub async fn templates_reload(data: web::Data<State>) -> HttpResponse {
+    let mut tera = data.tera.write().unwrap();
+    match tera.full_reload() {
+        Ok(_) => HttpResponse::Ok().json(tera.get_template_names().count()),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Could not reload templates: {}", e)),
+    }
+}
+
+pub async fn migration_status(data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match db.migration_status().await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Copies the old free-text attributes of every row into typed attributes.
+/// Safe to repeat: keys that already exist are skipped.
+pub async fn migrate_attributes(data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match Attribute::migrate_legacy(&db).await {
+        Ok(added) => {
+            log::warn!("Migrated legacy attributes: {:?}", added);
+            HttpResponse::Ok().json(added)
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Turns the old free-text notes of records, items and fact entries into
+/// private notes. Rows that already have notes are skipped.
+pub async fn migrate_notes(data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match Note::migrate_legacy(&db).await {
+        Ok(added) => {
+            log::warn!("Migrated legacy notes: {:?}", added);
+            HttpResponse::Ok().json(added)
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
@@ -1,10 +1,15 @@
+use actix_session::Session;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use div_db::models::{User, UserRole, Permission, userinfo::UserType};
+use crate::{account, state::State, handlers::{auth::validate, admin::db::TableConfirm}};
 use actix_web::{ Error,
     web::{self, delete, get, post, put, resource, scope, ServiceConfig},
     HttpRequest, HttpResponse,
 };
 
 pub fn routes(base: &str) -> actix_web::Scope {
-    scope("/user")
+    scope(base)
         .service(uid_routes("/{uid}"))
 }
 
@@ -12,9 +17,91 @@ pub fn uid_routes(base: &str) -> actix_web::Scope {
     scope(base)
         .route("", delete().to(delete_user_by_id))
         .route("", post().to(|| HttpResponse::Ok().finish()))
+        .route("/role", get().to(get_user_role))
+        .route("/role", put().to(set_user_role))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RoleUpdate {
+    pub role: UserType,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+/// Deletes a user and everything they own. The body must repeat their username.
+pub async fn delete_user_by_id(
+    path: web::Path<Uuid>,
+    body: web::Json<TableConfirm>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let user = match User::get_by_id(&db, path.into_inner()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("No user found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if body.confirm != user.username {
+        return HttpResponse::PreconditionFailed()
+            .body(format!("Set confirm to \"{}\" to delete this user", user.username));
+    }
+    match account::delete_account(&db, &data.cognito, &user).await {
+        Ok(Some(deletion)) => {
+            log::warn!("Admin deleted user {} ({})", user.username, user.id);
+            HttpResponse::Ok().json(deletion)
+        },
+        Ok(None) => HttpResponse::NotFound().body("No user found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
 
+pub async fn get_user_role(path: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match UserRole::get_by_user(&db, path.into_inner()).await {
+        Ok(role) => HttpResponse::Ok().json(role),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
 
-pub async fn delete_user_by_id(path: web::Path<String>) -> HttpResponse {
-    HttpResponse::Ok().body("")
+/// Assigns a role and extra grants to a user. Admins can only hand out
+/// permissions they hold themselves, so a moderator can't mint an administrator,
+/// and can't change the role of anyone holding a permission they lack.
+pub async fn set_user_role(
+    session: Session,
+    path: web::Path<Uuid>,
+    body: web::Json<RoleUpdate>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let granter = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let uid = path.into_inner();
+    let body = body.into_inner();
+    let db = data.db.lock().unwrap();
+    let granter_role = match UserRole::get_by_user(&db, granter.id).await {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let escalates = body.role.permissions().iter()
+        .chain(body.permissions.iter())
+        .find(|p| !granter_role.has_permission(**p));
+    if let Some(p) = escalates {
+        return HttpResponse::Forbidden()
+            .body(format!("Cannot grant permission {} you don't hold", p.as_str()));
+    }
+    match User::get_by_id(&db, uid).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return HttpResponse::NotFound().body("No user found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match UserRole::get_by_user(&db, uid).await {
+        Ok(current) if granter_role.is_outranked_by(&current) => return HttpResponse::Forbidden()
+            .body("Cannot change the role of a user holding permissions you don't"),
+        Ok(_) => (),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match UserRole::new(uid, body.role, &body.permissions).upsert(&db).await {
+        Ok(role) => HttpResponse::Ok().json(role),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
@@ -0,0 +1,142 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::auth::validate,
+    models::{AttributeQuery, AttributeView, SetAttribute, RecordView, ItemView, GroupView, FactTypeView, Resp},
+};
+use actix_web::{
+    web::{self, delete, get, put, resource, scope},
+    HttpResponse, ResponseError, Scope,
+};
+use div_db::{
+    Db, InvalidField,
+    models::{Record, Item, Group, FactType, attribute::{Attribute, AttributeOwner}},
+};
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("/{entity}", get().to(find_by_attribute))
+        .route("/{entity}/{id}", get().to(get_attributes))
+        .service(resource("/{entity}/{id}/{key}")
+            .route(get().to(get_attribute))
+            .route(put().to(set_attribute))
+            .route(delete().to(delete_attribute)))
+}
+
+fn known_owner(name: &str) -> Result<AttributeOwner, HttpResponse> {
+    AttributeOwner::parse(name)
+        .ok_or_else(|| HttpResponse::NotFound().body("Attributes belong to records, items, groups or fact_types"))
+}
+
+/// Resolves the path to a row of the session user, answering 404 for rows
+/// that don't exist or belong to someone else
+async fn owned_row(db: &Db, session: &Session, entity: &str, id: Uuid)
+    -> Result<(Uuid, AttributeOwner), HttpResponse>
+{
+    let user = validate(session)?;
+    let owner = known_owner(entity)?;
+    match Attribute::owner(db, owner, id).await {
+        Ok(Some(uid)) if uid == user.id => Ok((uid, owner)),
+        Ok(_) => Err(HttpResponse::NotFound().body("No such row")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+pub async fn get_attributes(
+    session: Session,
+    path: web::Path<(String, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (_, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    match Attribute::get_all(&db, owner, id).await {
+        Ok(attrs) => Resp::<Vec<AttributeView>>::views(attrs).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_attribute(
+    session: Session,
+    path: web::Path<(String, Uuid, String)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id, key) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (_, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    match Attribute::get(&db, owner, id, &key).await {
+        Ok(Some(attr)) => Resp::<AttributeView>::view(attr).into(),
+        Ok(None) => HttpResponse::NotFound().body("No attribute with this key"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Adds the attribute or replaces its value
+pub async fn set_attribute(
+    session: Session,
+    path: web::Path<(String, Uuid, String)>,
+    body: web::Json<SetAttribute>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id, key) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (uid, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    if key.trim().is_empty() || key.chars().count() >= 80 {
+        return invalid("key", "must be between 1 and 79 characters");
+    }
+    let SetAttribute { value_type, value } = body.into_inner();
+    let attr = match Attribute::new(uid, owner, id, key, value_type, value) {
+        Ok(attr) => attr,
+        Err(reason) => return invalid("value", reason),
+    };
+    match attr.set(&db).await {
+        Ok(attr) => Resp::<AttributeView>::view(attr).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn delete_attribute(
+    session: Session,
+    path: web::Path<(String, Uuid, String)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id, key) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (_, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    match Attribute::delete(&db, owner, id, &key).await {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().body("No attribute with this key"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The session user's rows of one kind whose attribute `key` equals `value`,
+/// as in `GET /api/attributes/items?key=color&value=red`
+pub async fn find_by_attribute(
+    session: Session,
+    path: web::Path<String>,
+    query: web::Query<AttributeQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let owner = match known_owner(&path.into_inner()) { Ok(o) => o, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let value = query.json_value();
+    let res: div_db::sqlx::Result<HttpResponse> = match owner {
+        AttributeOwner::Records => Attribute::find::<Record>(&db, uid, owner, &query.key, &value).await
+            .map(|rows| Resp::<Vec<RecordView>>::views(rows).into()),
+        AttributeOwner::Items => Attribute::find::<Item>(&db, uid, owner, &query.key, &value).await
+            .map(|rows| Resp::<Vec<ItemView>>::views(rows).into()),
+        AttributeOwner::Groups => Attribute::find::<Group>(&db, uid, owner, &query.key, &value).await
+            .map(|rows| Resp::<Vec<GroupView>>::views(rows).into()),
+        AttributeOwner::FactTypes => Attribute::find::<FactType>(&db, uid, owner, &query.key, &value).await
+            .map(|rows| Resp::<Vec<FactTypeView>>::views(rows).into()),
+    };
+    res.unwrap_or_else(|e| HttpResponse::InternalServerError().body(e.to_string()))
+}
+
+fn invalid<R: Into<String>>(field: &str, reason: R) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
@@ -2,10 +2,13 @@ pub mod site;
 pub mod cognito;
 pub mod jwt;
 pub mod session;
+pub mod oauth;
+pub mod password;
+pub mod verify;
 
 use actix_session::Session;
 use serde::{Serialize, Deserialize};
-use crate::{state::State, models::UserIn};
+use crate::{state::State, models::{UserIn, UserView, UserLogin, UserRegister}};
 use actix_web::{ Error, cookie::Cookie,
     get, post, put,
     web::{self, delete, get, post, put, resource, scope, ServiceConfig},
@@ -22,6 +25,9 @@ pub fn routes(base: &str) -> Scope {
         .service(self::cognito::routes("/cg"))
         .service(self::jwt::routes("/jwt"))
         .service(self::session::routes("/sess"))
+        .service(self::oauth::routes("/ext"))
+        .service(self::password::routes("/password"))
+        .service(self::verify::routes("/verify"))
 }
 
 pub async fn check_session(
@@ -45,12 +51,14 @@ pub async fn register_user(
 ) -> actix_web::Result<HttpResponse> {
     let db = data.db.lock().unwrap();
     let user = user.into_inner();
-    let hash = crate::auth::PwVerifier::new()
-        .hash(user.password.as_str())
-        .expect("Could not hash password");
+    let hash = match crate::auth::PwVerifier::new().hash(user.password.as_str()) {
+        Ok(hash) => hash,
+        Err(e) => return Ok(HttpResponse::InternalServerError()
+            .body(format!("Could not hash password: {}", e))),
+    };
     let user = User::new(user.email, user.username, Some(hash));
     match user.insert_db(&db).await {
-        Ok(user) => Ok(HttpResponse::Created().json(&user)),
+        Ok(user) => Ok(HttpResponse::Created().json(&UserView::from(user))),
         Err(e) =>Ok(HttpResponse::NotModified()
             .body(format!("User already exists, or other error... {}", e)))
     }
@@ -67,7 +75,7 @@ pub async fn signin_user(
         Ok(Some(duser)) => match ver.verify(user.password.as_str(), &duser.clone().password.unwrap()) {
             Ok(_) => return Ok(HttpResponse::Accepted()
                 .set_header("auth", "false")
-                .json(&duser)),
+                .json(&UserView::from(duser))),
             Err(e) => return Ok(HttpResponse::Unauthorized()
                 .set_header("auth", "false")
                 .body(format!("Username or password incorrect: {}", e)))
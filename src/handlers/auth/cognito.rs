@@ -41,13 +41,6 @@ pub fn cognito_user_attributes(base: &str) -> actix_web::Resource {
         .route(put().to(set_attribute))
 }
 
-pub fn ext_cognito_routes() -> Scope {
-    scope("/ext")
-        .service(resource("/authorize").route(post().to(cognito_authorize)))
-        .service(resource("/token").route(post().to(cognito_token)))
-        .service(resource("/userinfo").route(post().to(cognito_userinfo)))
-}
-
 pub async fn authorize_user(
     (req,  data, body): (HttpRequest,web::Data<State>, web::Json<CognitoIn>) ) -> HttpResponse
 {
@@ -211,15 +204,3 @@ pub async fn delete_attribute(
         Err(_) => HttpResponse::NotFound().finish()
     }
 }
-
-pub async fn cognito_authorize() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
-
-pub async fn cognito_token() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
-
-pub async fn cognito_userinfo() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
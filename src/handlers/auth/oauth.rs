@@ -0,0 +1,490 @@
+use url::Url;
+use uuid::Uuid;
+use chrono::Duration;
+use actix_session::Session;
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Serialize, Deserialize};
+use crate::{state::State, handlers::auth::validate, auth::PwVerifier};
+use crate::models::{CreateFactEntry, FactEntryView, RecordView, Resp};
+use actix_web::{
+    web::{self, delete, get, post, resource, scope},
+    HttpRequest, HttpResponse, Scope as ActixScope,
+};
+use div_db::{
+    Db,
+    models::{User, Record, FactEntry, OAuthClient, OAuthCode, OAuthToken, Scope},
+    models::oauth::random_token,
+};
+
+/// Lifetime of an authorization code before it must be exchanged
+const CODE_TTL_MINUTES: i64 = 10;
+/// Lifetime of an issued access token
+const ACCESS_TTL_SECONDS: i64 = 3600;
+/// Lifetime of a refresh token, which can be redeemed once
+const REFRESH_TTL_DAYS: i64 = 30;
+
+pub fn routes(base: &str) -> ActixScope {
+    scope(base)
+        .service(resource("/authorize")
+            .route(get().to(authorize))
+            .route(post().to(authorize_consent)))
+        .route("/token", post().to(token))
+        .route("/introspect", post().to(introspect))
+        .service(resource("/userinfo")
+            .route(get().to(userinfo))
+            .route(post().to(userinfo)))
+        .service(resource("/clients")
+            .route(get().to(get_clients))
+            .route(post().to(register_client)))
+        .route("/clients/{id}", delete().to(delete_client))
+        .route("/records", get().to(get_records))
+        .service(resource("/facts")
+            .route(get().to(get_facts))
+            .route(post().to(new_fact)))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuthorizeQuery {
+    pub response_type: String,
+    pub client_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConsentForm {
+    pub client_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: Option<String>,
+    pub csrf: String,
+    pub decision: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Uuid,
+    pub client_secret: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: Option<String>,
+    pub scope: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IntrospectRequest {
+    pub token: String,
+    pub client_id: Uuid,
+    pub client_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct IntrospectResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientRegister {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientCredentials {
+    pub client_id: Uuid,
+    pub client_secret: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl From<OAuthToken> for TokenResponse {
+    fn from(tok: OAuthToken) -> Self {
+        Self {
+            expires_in: tok.expires_in(),
+            scope: Scope::join(&tok.scopes),
+            access_token: tok.access_token,
+            token_type: "bearer".to_string(),
+            refresh_token: tok.refresh_token,
+        }
+    }
+}
+
+/// Renders the consent page for a logged in resource owner, or sends them
+/// to the login page first
+pub async fn authorize(
+    session: Session,
+    req: HttpRequest,
+    query: web::Query<AuthorizeQuery>,
+    data: web::Data<State>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let db = data.db.lock().unwrap();
+    let client = match OAuthClient::get_by_id(&db, query.client_id).await {
+        Ok(Some(client)) if client.allows_redirect(&query.redirect_uri) => client,
+        Ok(_) => return Ok(oauth_error("invalid_client", "Unknown client or redirect_uri")),
+        Err(_) => return Ok(HttpResponse::InternalServerError().finish()),
+    };
+    let state = query.state.clone().unwrap_or_default();
+    if query.response_type != "code" {
+        return Ok(redirect_with(&query.redirect_uri, &[
+            ("error", "unsupported_response_type"), ("state", &state)]));
+    }
+    let scopes = match Scope::parse_list(query.scope.as_deref().unwrap_or("profile")) {
+        Ok(scopes) if client.allows_scopes(&scopes) => scopes,
+        _ => return Ok(redirect_with(&query.redirect_uri, &[
+            ("error", "invalid_scope"), ("state", &state)])),
+    };
+    let user = match validate(&session) {
+        Ok(user) => user,
+        Err(_) => {
+            let next: String = url::form_urlencoded::byte_serialize(req.uri().to_string().as_bytes()).collect();
+            return Ok(HttpResponse::Found()
+                .set_header("Location", format!("/login?next={}", next))
+                .finish())
+        }
+    };
+    let csrf = random_token();
+    session.set("oauth_csrf", &csrf)?;
+    let requested = scopes.iter()
+        .map(|s| (s.as_str(), s.description()))
+        .collect::<Vec<(&str, &str)>>();
+    let mut ctx = tera::Context::new();
+    ctx.insert("username", &user.username);
+    ctx.insert("client_name", &client.name);
+    ctx.insert("client_id", &client.id.to_string());
+    ctx.insert("redirect_uri", &query.redirect_uri);
+    ctx.insert("scope", &scopes.iter().map(|s| s.to_string()).collect::<Vec<String>>().join(" "));
+    ctx.insert("scopes", &requested);
+    ctx.insert("state", &state);
+    ctx.insert("csrf", &csrf);
+    let s = data.tera.read().unwrap().render("oauth/consent.html", &ctx)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+/// Handles the resource owner's decision on the consent page
+pub async fn authorize_consent(
+    session: Session,
+    form: web::Form<ConsentForm>,
+    data: web::Data<State>,
+) -> actix_web::Result<HttpResponse> {
+    let form = form.into_inner();
+    let user = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let csrf: Option<String> = session.get("oauth_csrf")?;
+    session.remove("oauth_csrf");
+    if csrf.as_deref() != Some(form.csrf.as_str()) {
+        return Ok(oauth_error("invalid_request", "Consent form expired, please try again"));
+    }
+    let db = data.db.lock().unwrap();
+    let client = match OAuthClient::get_by_id(&db, form.client_id).await {
+        Ok(Some(client)) if client.allows_redirect(&form.redirect_uri) => client,
+        Ok(_) => return Ok(oauth_error("invalid_client", "Unknown client or redirect_uri")),
+        Err(_) => return Ok(HttpResponse::InternalServerError().finish()),
+    };
+    let state = form.state.clone().unwrap_or_default();
+    if form.decision != "allow" {
+        return Ok(redirect_with(&form.redirect_uri, &[
+            ("error", "access_denied"), ("state", &state)]));
+    }
+    let scopes = match Scope::parse_list(&form.scope) {
+        Ok(scopes) if client.allows_scopes(&scopes) => scopes,
+        _ => return Ok(redirect_with(&form.redirect_uri, &[
+            ("error", "invalid_scope"), ("state", &state)])),
+    };
+    let code = OAuthCode::new(client.id, user.id, form.redirect_uri.clone(),
+        &scopes, Duration::minutes(CODE_TTL_MINUTES));
+    match code.insert(&db).await {
+        Ok(code) => Ok(redirect_with(&form.redirect_uri, &[
+            ("code", &code.code), ("state", &state)])),
+        Err(_) => Ok(redirect_with(&form.redirect_uri, &[
+            ("error", "server_error"), ("state", &state)])),
+    }
+}
+
+/// Token endpoint supporting the authorization_code and refresh_token grants
+pub async fn token(
+    form: web::Form<TokenRequest>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let req = form.into_inner();
+    let db = data.db.lock().unwrap();
+    let client = match authenticate_client(&db, req.client_id, &req.client_secret).await {
+        Ok(client) => client,
+        Err(resp) => return resp,
+    };
+    let (ttl, refresh_ttl) = (Duration::seconds(ACCESS_TTL_SECONDS), Duration::days(REFRESH_TTL_DAYS));
+    let token = match req.grant_type.as_str() {
+        "authorization_code" => {
+            let code = match OAuthCode::take(&db, req.code.as_deref().unwrap_or_default()).await {
+                Ok(Some(code)) => code,
+                Ok(None) => return oauth_error("invalid_grant", "Unknown or already used code"),
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            if code.is_expired()
+                || code.client_id != client.id
+                || req.redirect_uri.as_deref() != Some(code.redirect_uri.as_str())
+            {
+                return oauth_error("invalid_grant", "Code expired or issued to another client");
+            }
+            OAuthToken::issue(client.id, code.uid, code.scopes, ttl, refresh_ttl)
+        },
+        "refresh_token" => {
+            let narrowed = match req.scope.as_deref().map(Scope::parse_list).transpose() {
+                Ok(narrowed) => narrowed,
+                Err(_) => return oauth_error("invalid_scope", "Unknown scope"),
+            };
+            let refresh = req.refresh_token.as_deref().unwrap_or_default();
+            let old = match OAuthToken::redeem_refresh_token(&db, client.id, refresh).await {
+                Ok(Some(old)) => old,
+                Ok(None) => return oauth_error("invalid_grant", "Unknown, expired or already used refresh token"),
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            // The old pair is revoked by now, so asking for more than it was
+            // granted uses up the refresh token like any other misuse would
+            let scopes = match narrowed {
+                None => old.scopes.clone(),
+                Some(narrowed) if narrowed.iter().all(|s| old.has_scope(*s)) =>
+                    narrowed.iter().map(|s| s.to_string()).collect(),
+                Some(_) => return oauth_error("invalid_scope", "Scope exceeds original grant"),
+            };
+            OAuthToken::issue(client.id, old.uid, scopes, ttl, refresh_ttl)
+        },
+        _ => return oauth_error("unsupported_grant_type", "Only authorization_code and refresh_token are supported"),
+    };
+    match token.insert(&db).await {
+        Ok(tok) => HttpResponse::Ok()
+            .set_header("Cache-Control", "no-store")
+            .json(TokenResponse::from(tok)),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// RFC 7662 token introspection, available to the client the token was issued to
+pub async fn introspect(
+    form: web::Form<IntrospectRequest>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let req = form.into_inner();
+    let db = data.db.lock().unwrap();
+    let client = match authenticate_client(&db, req.client_id, &req.client_secret).await {
+        Ok(client) => client,
+        Err(resp) => return resp,
+    };
+    let tok = match OAuthToken::get_by_access_token(&db, &req.token).await {
+        Ok(Some(tok)) if tok.is_active() && tok.client_id == client.id => tok,
+        Ok(_) => return HttpResponse::Ok().json(IntrospectResponse::default()),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let username = match User::get_by_id(&db, tok.uid).await {
+        Ok(Some(user)) => Some(user.username),
+        _ => None,
+    };
+    HttpResponse::Ok().json(IntrospectResponse {
+        active: true,
+        scope: Some(Scope::join(&tok.scopes)),
+        client_id: Some(tok.client_id),
+        username,
+        sub: Some(tok.uid),
+        exp: Some(tok.expires_at.timestamp()),
+    })
+}
+
+pub async fn userinfo(auth: BearerAuth, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let tok = match bearer_token(&db, &auth, Scope::Profile).await {
+        Ok(tok) => tok,
+        Err(resp) => return resp,
+    };
+    match User::get_by_id(&db, tok.uid).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(serde_json::json!({
+            "sub": user.id,
+            "username": user.username,
+            "email": user.email,
+        })),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub async fn register_client(
+    session: Session,
+    body: web::Json<ClientRegister>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let user = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let body = body.into_inner();
+    if body.redirect_uris.is_empty() || body.redirect_uris.iter().any(|uri| Url::parse(uri).is_err()) {
+        return oauth_error("invalid_redirect_uri", "At least one absolute redirect_uri is required");
+    }
+    let secret = random_token();
+    let hash = match PwVerifier::new().hash(&secret) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let db = data.db.lock().unwrap();
+    let client = OAuthClient::new(user.id, body.name, hash, body.redirect_uris, body.scopes);
+    match client.insert(&db).await {
+        Ok(client) => HttpResponse::Created().json(ClientCredentials {
+            client_id: client.id,
+            client_secret: secret,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            scopes: client.scopes,
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn get_clients(session: Session, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match OAuthClient::get_all_by_user(&data.db.lock().unwrap(), user.id).await {
+        Ok(clients) => HttpResponse::Ok().json(&clients),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn delete_client(
+    session: Session,
+    id: web::Path<Uuid>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let user = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match OAuthClient::delete_by_id(&data.db.lock().unwrap(), user.id, *id).await {
+        Ok(Some(id)) => HttpResponse::Ok().json(id),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn get_records(auth: BearerAuth, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let tok = match bearer_token(&db, &auth, Scope::RecordsRead).await {
+        Ok(tok) => tok,
+        Err(resp) => return resp,
+    };
+    match Record::get_all_by_user(&db, tok.uid).await {
+        Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn get_facts(auth: BearerAuth, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let tok = match bearer_token(&db, &auth, Scope::FactsRead).await {
+        Ok(tok) => tok,
+        Err(resp) => return resp,
+    };
+    match FactEntry::get_all_by_user(&db, tok.uid).await {
+        Ok(facts) => Resp::<Vec<FactEntryView>>::views(facts).into(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn new_fact(
+    auth: BearerAuth,
+    fact: actix_web_validator::Json<CreateFactEntry>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let tok = match bearer_token(&db, &auth, Scope::FactsWrite).await {
+        Ok(tok) => tok,
+        Err(resp) => return resp,
+    };
+    let entry = fact.into_inner().into_entry(tok.uid);
+    match entry.insert(&db).await {
+        Ok(_) => Resp::<FactEntryView>::created(entry.into()).into(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Checks the client secret sent to the token and introspection endpoints
+async fn authenticate_client(db: &Db, client_id: Uuid, secret: &str) -> Result<OAuthClient, HttpResponse> {
+    match OAuthClient::get_by_id(db, client_id).await {
+        Ok(Some(client)) => match PwVerifier::new().verify(secret, &client.secret) {
+            Ok(true) => Ok(client),
+            _ => Err(HttpResponse::Unauthorized()
+                .json(OAuthErrorBody::new("invalid_client", "Client authentication failed"))),
+        },
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(OAuthErrorBody::new("invalid_client", "Client authentication failed"))),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+/// Resolves a bearer token and checks that it was granted the given scope
+async fn bearer_token(db: &Db, auth: &BearerAuth, scope: Scope) -> Result<OAuthToken, HttpResponse> {
+    match OAuthToken::get_by_access_token(db, auth.token()).await {
+        Ok(Some(tok)) if tok.is_active() && tok.has_scope(scope) => Ok(tok),
+        Ok(Some(tok)) if tok.is_active() => Err(HttpResponse::Forbidden()
+            .set_header("WWW-Authenticate", format!("Bearer error=\"insufficient_scope\", scope=\"{}\"", scope.as_str()))
+            .json(OAuthErrorBody::new("insufficient_scope", scope.as_str()))),
+        Ok(_) => Err(HttpResponse::Unauthorized()
+            .set_header("WWW-Authenticate", "Bearer error=\"invalid_token\"")
+            .json(OAuthErrorBody::new("invalid_token", "Token expired, revoked or unknown"))),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OAuthErrorBody {
+    pub error: String,
+    pub error_description: String,
+}
+
+impl OAuthErrorBody {
+    pub fn new(error: &str, description: &str) -> Self {
+        Self { error: error.to_string(), error_description: description.to_string() }
+    }
+}
+
+fn oauth_error(error: &str, description: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(OAuthErrorBody::new(error, description))
+}
+
+/// Redirects back to the client with the given query parameters appended
+fn redirect_with(redirect_uri: &str, params: &[(&str, &str)]) -> HttpResponse {
+    match Url::parse(redirect_uri) {
+        Ok(mut url) => {
+            url.query_pairs_mut()
+                .extend_pairs(params.iter().filter(|(_, v)| !v.is_empty()));
+            HttpResponse::Found()
+                .set_header("Location", url.as_str())
+                .finish()
+        },
+        Err(_) => oauth_error("invalid_request", "Malformed redirect_uri"),
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::Duration;
+use actix_session::Session;
+use serde::{Serialize, Deserialize};
+use crate::{
+    state::State, auth::PwVerifier, config::AppConfig,
+    handlers::auth::validate, mail::Mail,
+};
+use actix_web::{
+    web::{self, post, scope},
+    HttpResponse, Scope,
+};
+use div_db::models::{User, UserToken, TokenKind};
+
+/// How long a password reset token stays valid after being mailed
+const RESET_TTL_MINUTES: i64 = 60;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", post().to(change_password))
+        .route("/forgot", post().to(forgot_password))
+        .route("/reset", post().to(reset_password))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordForgot {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: String,
+}
+
+pub async fn change_password(
+    session: Session,
+    body: web::Json<PasswordChange>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let sess_user = match validate(&session) {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let body = body.into_inner();
+    if body.new_password.len() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest()
+            .body(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    let db = data.db.lock().unwrap();
+    let ver = PwVerifier::new();
+    let user = match User::get_by_id(&db, sess_user.id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("No user found"),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match user.password.as_deref().map(|hash| ver.verify(&body.current_password, hash)) {
+        Some(Ok(true)) => (),
+        _ => return HttpResponse::Unauthorized().body("Current password incorrect"),
+    }
+    let hash = match ver.hash(&body.new_password) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match User::update_password(&db, user.id, hash).await {
+        Ok(Some(_)) => {
+            let _ = UserToken::delete_all(&db, user.id, TokenKind::PasswordReset).await;
+            session.renew();
+            HttpResponse::Ok().json(true)
+        },
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Mails a reset token if an account exists for the address. Always answers
+/// 202 so the endpoint can't be used to discover registered emails.
+pub async fn forgot_password(
+    body: web::Json<PasswordForgot>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    if let Ok(Some(user)) = User::get_by_email(&db, body.into_inner().email).await {
+        let token = UserToken::new(user.id, TokenKind::PasswordReset,
+            Duration::minutes(RESET_TTL_MINUTES));
+        match token.insert(&db).await {
+            Ok(token) => {
+                let mail = Mail::new(user.email, "Reset your div.is password", format!(
+                    "Hi {},\n\nUse the link below to choose a new password. \
+                     It expires in {} minutes and can only be used once.\n\n\
+                     {}/reset?token={}\n\n\
+                     If you didn't ask for this you can ignore this email.",
+                    user.username, RESET_TTL_MINUTES, AppConfig::public_url(), token.token));
+                if let Err(e) = data.mailer.send(mail).await {
+                    log::error!("Could not send password reset mail: {}", e);
+                }
+            },
+            Err(e) => log::error!("Could not create password reset token: {}", e),
+        }
+    }
+    HttpResponse::Accepted().json(true)
+}
+
+pub async fn reset_password(
+    body: web::Json<PasswordReset>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    if body.new_password.len() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest()
+            .body(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    let db = data.db.lock().unwrap();
+    let token = match UserToken::take(&db, &body.token, TokenKind::PasswordReset).await {
+        Ok(Some(token)) if !token.is_expired() => token,
+        Ok(_) => return HttpResponse::Gone().body("Reset token is invalid or has expired"),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let hash = match PwVerifier::new().hash(&body.new_password) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match User::update_password(&db, token.uid, hash).await {
+        Ok(Some(_)) => {
+            let _ = UserToken::delete_all(&db, token.uid, TokenKind::PasswordReset).await;
+            HttpResponse::Ok().json(true)
+        },
+        Ok(None) => HttpResponse::NotFound().body("No user found"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -1,10 +1,12 @@
 use actix_session::Session;
-use crate::{state::State, models::UserIn};
+use crate::{state::State, models::{UserIn, UserView, UserLogin, UserRegister}};
 use actix_web::{ Error,
     web::{self, delete, get, post, put, resource, scope},
     HttpRequest, HttpResponse, Scope,
 };
-use div_db::models::user::*;
+use div_db::{Db, models::user::*};
+use crate::error::{UserError, is_unique_violation};
+use super::verify;
 
 pub fn routes(base: &str) -> Scope {
     scope(base)
@@ -14,34 +16,80 @@ pub fn routes(base: &str) -> Scope {
 }
 
 pub async fn register_user(
-    user: web::Json<UserRegister>,
+    user: actix_web_validator::Json<UserRegister>,
     data: web::Data<State>,
 ) -> actix_web::Result<HttpResponse> {
     let db = data.db.lock().unwrap();
     let user = user.into_inner();
-    let hash = crate::auth::PwVerifier::new()
-        .hash(user.password.as_str())
-        .expect("Could not hash password");
-    let user = User::new(user.email, user.username, Some(hash));
-    match user.insert_db(&db).await {
-        Ok(user) => Ok(HttpResponse::Created().json(&user)),
-        Err(e) =>Ok(HttpResponse::NotModified()
-            .body(format!("User already exists, or other error... {}", e)))
+    if let Some(field) = taken_field(&db, &user).await? {
+        return Err(field.into());
     }
+    let hash = match crate::auth::PwVerifier::new().hash(user.password.as_str()) {
+        Ok(hash) => hash,
+        Err(e) => return Ok(HttpResponse::InternalServerError()
+            .body(format!("Could not hash password: {}", e))),
+    };
+    let new_user = User::new(user.email.as_str(), user.username.as_str(), Some(hash));
+    match new_user.insert_db(&db).await {
+        Ok(duser) => {
+            verify::send_verification(&db, data.mailer.as_ref(), &duser).await
+                .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+            Ok(HttpResponse::Created().json(&UserIn::from(duser)))
+        },
+        // Lost a race with a concurrent signup for the same username or email
+        Err(e) if is_unique_violation(&e) => match taken_field(&db, &user).await? {
+            Some(field) => Err(field.into()),
+            None => Err(UserError::AlreadyExists {
+                field: "username".into(), val: user.username }.into()),
+        },
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .body(format!("Could not create user: {}", e)))
+    }
+}
+
+/// Finds whichever of the unique username/email fields is already registered
+async fn taken_field(db: &Db, user: &UserRegister) -> actix_web::Result<Option<UserError>> {
+    let by_name = User::get_by_username(db, user.username.clone()).await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    if by_name.is_some() {
+        return Ok(Some(UserError::AlreadyExists { field: "username".into(), val: user.username.clone() }));
+    }
+    let by_email = User::get_by_email(db, user.email.clone()).await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    if by_email.is_some() {
+        return Ok(Some(UserError::AlreadyExists { field: "email".into(), val: user.email.clone() }));
+    }
+    Ok(None)
 }
 
 pub async fn signin_user(
-    user: web::Json<UserLogin>,
+    session: Session,
+    user: actix_web_validator::Json<UserLogin>,
     data: web::Data<State>,
 ) -> actix_web::Result<HttpResponse> {
     let db = data.db.lock().unwrap();
     let user = user.into_inner();
     let ver = crate::auth::PwVerifier::new();
     match User::get_by_username(&db, user.username).await {
-        Ok(Some(duser)) => match ver.verify(user.password.as_str(), &duser.clone().password.unwrap()) {
-            Ok(_) => return Ok(HttpResponse::Accepted()
+        Ok(Some(duser)) => match ver.verify(user.password.as_str(), duser.password.as_deref().unwrap_or_default()) {
+            Ok(true) if !duser.confirmed => {
+                return Err(UserError::NotVerified { username: duser.username }.into())
+            },
+            Ok(true) => {
+                if ver.needs_rehash(duser.password.as_deref().unwrap_or_default()) {
+                    if let Ok(hash) = ver.hash(user.password.as_str()) {
+                        let _ = User::update_password(&db, duser.id, hash).await;
+                    }
+                }
+                session.set("uid", UserIn::from(duser.clone()))?;
+                session.renew();
+                return Ok(HttpResponse::Accepted()
+                    .set_header("auth", "true")
+                    .json(&UserView::from(duser)))
+            },
+            Ok(false) => return Ok(HttpResponse::Unauthorized()
                 .set_header("auth", "false")
-                .json(&duser)),
+                .body("Username or password incorrect")),
             Err(e) => return Ok(HttpResponse::Unauthorized()
                 .set_header("auth", "false")
                 .body(format!("Username or password incorrect: {}", e)))
@@ -0,0 +1,95 @@
+use chrono::Duration;
+use serde::{Serialize, Deserialize};
+use crate::{state::State, config::AppConfig, mail::{Mail, Mailer}};
+use actix_web::{
+    web::{self, get, post, scope},
+    HttpResponse, Scope,
+};
+use div_db::{Db, models::{User, UserToken, TokenKind}};
+
+/// How long a verification link stays valid before a new one must be requested
+const VERIFY_TTL_HOURS: i64 = 24;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(verify_link))
+        .route("", post().to(verify_email))
+        .route("/resend", post().to(resend_verification))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerifyToken {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerifyResend {
+    pub email: String,
+}
+
+/// Creates a fresh verification token for a pending account, replacing any
+/// outstanding ones, and mails it to the user
+pub async fn send_verification(db: &Db, mailer: &dyn Mailer, user: &User) -> sqlx::Result<()> {
+    UserToken::delete_all(db, user.id, TokenKind::EmailVerification).await?;
+    let token = UserToken::new(user.id, TokenKind::EmailVerification,
+        Duration::hours(VERIFY_TTL_HOURS))
+        .insert(db).await?;
+    let mail = Mail::new(user.email.as_str(), "Verify your div.is account", format!(
+        "Hi {},\n\nPlease confirm your email address to activate your account. \
+         The link expires in {} hours.\n\n\
+         {}/api/auth/verify?token={}\n",
+        user.username, VERIFY_TTL_HOURS, AppConfig::public_url(), token.token));
+    if let Err(e) = mailer.send(mail).await {
+        log::error!("Could not send verification mail to {}: {}", user.email, e);
+    }
+    Ok(())
+}
+
+/// Target of the link in the verification mail
+pub async fn verify_link(
+    query: web::Query<VerifyToken>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    confirm(&data, &query.token).await
+}
+
+pub async fn verify_email(
+    body: web::Json<VerifyToken>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    confirm(&data, &body.token).await
+}
+
+/// Sends a new verification mail to a pending account. Always answers 202
+/// so the endpoint can't be used to discover registered emails.
+pub async fn resend_verification(
+    body: web::Json<VerifyResend>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match User::get_by_email(&db, body.into_inner().email).await {
+        Ok(Some(user)) if !user.confirmed => {
+            if let Err(e) = send_verification(&db, data.mailer.as_ref(), &user).await {
+                log::error!("Could not create verification token: {}", e);
+            }
+        },
+        _ => (),
+    }
+    HttpResponse::Accepted().json(true)
+}
+
+async fn confirm(data: &web::Data<State>, token: &str) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let token = match UserToken::take(&db, token, TokenKind::EmailVerification).await {
+        Ok(Some(token)) if !token.is_expired() => token,
+        Ok(Some(_)) => return HttpResponse::Gone()
+            .body("Verification link has expired, request a new one"),
+        Ok(None) => return HttpResponse::NotFound().body("Unknown verification token"),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match User::confirm(&db, token.uid).await {
+        Ok(Some(uid)) => HttpResponse::Ok().json(uid),
+        Ok(None) => HttpResponse::NotFound().body("No user found"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
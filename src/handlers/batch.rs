@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use actix_session::Session;
+use uuid::Uuid;
+use div_db::{BatchOp, BatchFailure, BulkRow};
+use crate::{
+    state::State,
+    error::is_unique_violation,
+    handlers::auth::validate,
+    models::{etag, transfer,
+        batch::{BatchRequest, BatchRequestOp, BatchRefs, BatchResult, BatchError, apply_update, MAX_BATCH_OPS},
+    },
+};
+use actix_web::{
+    web::{self, post, resource},
+    HttpResponse, Resource,
+};
+
+pub fn routes(base: &str) -> Resource {
+    resource(base).route(post().to(run_batch))
+}
+
+/// Runs the operations in order in one transaction. Every operation is
+/// validated and its references resolved before anything is written, and a
+/// failure part way through rolls back the ones before it.
+pub async fn run_batch(
+    session: Session,
+    body: web::Json<BatchRequest>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let ops = body.into_inner().operations;
+    if ops.is_empty() || ops.len() > MAX_BATCH_OPS {
+        return HttpResponse::UnprocessableEntity().json(BatchError::new(0, "operations",
+            format!("must hold between 1 and {} operations", MAX_BATCH_OPS)));
+    }
+
+    let db = data.db.lock().unwrap();
+    let mut refs = BatchRefs::default();
+    // Rows as earlier operations in the batch leave them
+    let mut pending: HashMap<Uuid, BulkRow> = HashMap::new();
+    let mut planned = Vec::with_capacity(ops.len());
+    for (i, op) in ops.iter().enumerate() {
+        let fail = |errors| HttpResponse::UnprocessableEntity()
+            .json(BatchError { index: i, errors, rolled_back: true });
+        match op {
+            BatchRequestOp::Create { entity, alias, body } => {
+                let row = match transfer::bulk_row(*entity, uid, body.clone()) {
+                    Ok(row) => row,
+                    Err(errors) => return fail(errors),
+                };
+                refs.push(alias.as_ref(), Some(row.id()));
+                pending.insert(row.id(), row.clone());
+                planned.push(BatchOp::Create(row));
+            },
+            BatchRequestOp::Update { entity, id, alias, body, if_match } => {
+                let id = match refs.resolve("id", id) { Ok(id) => id, Err(e) => return fail(vec![e]) };
+                let (current, since) = match pending.get(&id) {
+                    Some(row) => (row.clone(), None),
+                    None => match db.get_row(*entity, uid, id).await {
+                        Ok(Some(row)) => {
+                            let since = updated_at(&row);
+                            (row, since)
+                        },
+                        Ok(None) => return HttpResponse::NotFound()
+                            .json(BatchError::new(i, "id", "not found")),
+                        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+                    },
+                };
+                if current.entity() != *entity {
+                    return fail(vec![div_db::InvalidField::new("id", "refers to a different kind of row")]);
+                }
+                if let (Some(tag), Some(since)) = (if_match, since) {
+                    if tag.trim_start_matches("W/") != etag(since) && tag != "*" {
+                        return HttpResponse::PreconditionFailed()
+                            .json(BatchError::new(i, "if_match", "the row was modified since it was last fetched"));
+                    }
+                }
+                let row = match apply_update(current, body.clone()) {
+                    Ok(row) => row,
+                    Err(errors) => return fail(errors),
+                };
+                refs.push(alias.as_ref(), Some(id));
+                pending.insert(id, row.clone());
+                planned.push(BatchOp::Update(row, since));
+            },
+            BatchRequestOp::Delete { entity, id } => {
+                let id = match refs.resolve("id", id) { Ok(id) => id, Err(e) => return fail(vec![e]) };
+                refs.push(None, Some(id));
+                pending.remove(&id);
+                planned.push(BatchOp::Delete(*entity, id));
+            },
+            BatchRequestOp::Link { record, item } => {
+                let rid = match refs.resolve("record", record) { Ok(id) => id, Err(e) => return fail(vec![e]) };
+                let iid = match refs.resolve("item", item) { Ok(id) => id, Err(e) => return fail(vec![e]) };
+                refs.push(None, None);
+                planned.push(BatchOp::Link { rid, iid });
+            },
+        }
+    }
+
+    match db.run_batch(uid, &planned).await {
+        Ok(outcomes) => HttpResponse::Ok().json(outcomes.into_iter().zip(ops.iter()).enumerate()
+            .map(|(i, (outcome, op))| BatchResult::new(i, outcome, op.name() == "create"))
+            .collect::<Vec<_>>()),
+        Err((i, BatchFailure::NotFound)) => HttpResponse::NotFound()
+            .json(BatchError::new(i, "id", "not found, or changed while the batch ran")),
+        Err((i, BatchFailure::Database(e))) if is_unique_violation(&e) => HttpResponse::Conflict()
+            .json(BatchError::new(i, "name", "already exists")),
+        Err((i, BatchFailure::Database(e))) => HttpResponse::InternalServerError()
+            .json(BatchError::new(i, "database", e.to_string())),
+    }
+}
+
+/// Fact rows don't track updates, so only records and items can be guarded
+fn updated_at(row: &BulkRow) -> Option<chrono::DateTime<chrono::Utc>> {
+    match row {
+        BulkRow::Record(rec) => Some(rec.updated_at),
+        BulkRow::Item(item) => Some(item.updated_at),
+        _ => None,
+    }
+}
@@ -0,0 +1,230 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::{auth::validate, unit::{unit_registry, check_unit}},
+    models::{
+        CreateChoiceType, AddFactOption, UpdateFactOption, FactOptionView, ChoiceTypeView,
+        OptionCounts, LogFieldValue, FactEntryView, Resp,
+    },
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use div_db::{Db, InvalidField, Visibility, models::{FactType, FactOption, FactRange, Choices, fact::kind::ValueType}};
+
+fn invalid(field: &str, reason: &str) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
+
+async fn load_type(db: &Db, id: Uuid) -> Result<FactType, HttpResponse> {
+    match FactType::get_by_id(db, id).await {
+        Ok(Some(kind)) => Ok(kind),
+        Ok(None) => Err(HttpResponse::NotFound().body("No such fact type")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The fact type, if the session user may read it: their own, or anyone's
+/// public type
+async fn readable_type(db: &Db, session: &Session, id: Uuid) -> Result<FactType, HttpResponse> {
+    let kind = load_type(db, id).await?;
+    let is_owner = validate(session).map(|user| user.id == kind.uid).unwrap_or(false);
+    if !is_owner && kind.visibility != Visibility::Public {
+        return Err(HttpResponse::NotFound().body("No such fact type"));
+    }
+    Ok(kind)
+}
+
+pub(crate) async fn own_type(db: &Db, session: &Session, id: Uuid) -> Result<FactType, HttpResponse> {
+    let user = validate(session)?;
+    let kind = load_type(db, id).await?;
+    if kind.uid != user.id {
+        return Err(HttpResponse::Forbidden().body("Not your fact type"));
+    }
+    Ok(kind)
+}
+
+async fn load_option(db: &Db, tid: Uuid, oid: Uuid) -> Result<FactOption, HttpResponse> {
+    match FactOption::get_by_id(db, tid, oid).await {
+        Ok(Some(option)) => Ok(option),
+        Ok(None) => Err(HttpResponse::NotFound().body("The fact type has no such option")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Creates a select or range fact type of the session user along with its
+/// options or bounds
+pub async fn create_choice_type(
+    session: Session,
+    body: actix_web_validator::Json<CreateChoiceType>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    if let Err((field, reason)) = body.check() {
+        return invalid(field, &reason);
+    }
+    let (kind, options, range) = body.into_inner().into_fact_type(uid);
+    let db = data.db.lock().unwrap();
+    match FactOption::create_type(&db, &kind, &options, range).await {
+        Ok(options) => Resp::created(ChoiceTypeView {
+            kind: kind.into(),
+            options: options.into_iter().map(FactOptionView::from).collect(),
+            range,
+        }).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: kind.name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The fact type with its options, retired ones included, or its range
+pub async fn get_choices(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match readable_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    let (options, range) = match Choices::load(&db, &kind).await {
+        Ok(Choices::Options(_, options)) => (options, None),
+        Ok(Choices::Range(range)) => (Vec::new(), range),
+        Ok(Choices::Any(_)) => (Vec::new(), None),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    Resp::ok(ChoiceTypeView {
+        kind: kind.into(),
+        options: options.into_iter().map(FactOptionView::from).collect(),
+        range,
+    }).into()
+}
+
+pub async fn add_option(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: actix_web_validator::Json<AddFactOption>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    if !matches!(kind.value_type, ValueType::SelectOne | ValueType::SelectMany) {
+        return invalid("label", "only select fact types have options");
+    }
+    let label = body.into_inner().label;
+    match FactOption::add(&db, kind.id, &label).await {
+        Ok(option) => Resp::created(FactOptionView::from(option)).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "label".into(), val: label }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Renames, moves, retires or restores an option. Entries that chose it
+/// keep pointing at it either way.
+pub async fn update_option(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    body: actix_web_validator::Json<UpdateFactOption>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (tid, oid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = own_type(&db, &session, tid).await { return resp }
+    let option = match load_option(&db, tid, oid).await { Ok(option) => option, Err(resp) => return resp };
+    let label = body.label.clone().unwrap_or_default();
+    let option = match body.apply(option).update(&db).await {
+        Ok(Some(option)) => option,
+        Ok(None) => return HttpResponse::NotFound().body("The fact type has no such option"),
+        Err(e) if is_unique_violation(&e) => return UserError::AlreadyExists {
+            field: "label".into(), val: label }.error_response(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let res = match body.retired {
+        Some(true) => option.remove(&db).await.map(|_| ()),
+        Some(false) => option.restore(&db).await,
+        None => Ok(()),
+    };
+    if let Err(e) = res {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    match load_option(&db, tid, oid).await {
+        Ok(option) => Resp::<FactOptionView>::view(option).into(),
+        // Retiring an option no entry chose deletes it
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+/// Deletes an option no entry chose, and retires one that was chosen
+pub async fn remove_option(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (tid, oid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = own_type(&db, &session, tid).await { return resp }
+    let option = match load_option(&db, tid, oid).await { Ok(option) => option, Err(resp) => return resp };
+    match option.remove(&db).await {
+        Ok(false) => HttpResponse::NoContent().finish(),
+        Ok(true) => match load_option(&db, tid, oid).await {
+            Ok(option) => Resp::<FactOptionView>::view(option).into(),
+            Err(resp) => resp,
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Sets the bounds of a range fact type. Earlier entries are kept even if
+/// they fall outside the new bounds.
+pub async fn set_range(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: web::Json<FactRange>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    if kind.value_type != ValueType::Range {
+        return invalid("range", "only range fact types have bounds");
+    }
+    if let Err(reason) = body.check() {
+        return invalid("range", &reason);
+    }
+    match FactOption::set_range(&db, kind.id, *body).await {
+        Ok(()) => Resp::ok(*body).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// How many entries chose each option of a select fact type
+pub async fn get_option_counts(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match readable_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    match FactOption::counts(&db, kind.id).await {
+        Ok(options) => Resp::ok(OptionCounts { tid: kind.id, options }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Logs an entry of the session user's fact type. Select values name
+/// options by label or id, range values must be within the bounds, and
+/// place values with `lat` and `lon` are indexed for proximity search.
+pub async fn log_entry(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: actix_web_validator::Json<LogFieldValue>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    let resolved = match Choices::load(&db, &kind).await {
+        Ok(choices) => match choices.resolve(&body.value) {
+            Ok(resolved) => resolved,
+            Err(reason) => return invalid("value", &reason),
+        },
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let units = match unit_registry(&db, Some(kind.uid)).await { Ok(units) => units, Err(resp) => return resp };
+    if let Err(resp) = check_unit(&units, &kind, body.units.as_deref()) { return resp }
+    let body = LogFieldValue { value: resolved.value.clone(), ..body.into_inner() };
+    let entry = body.into_entry(kind.uid, kind.name);
+    match FactOption::log(&db, &entry, &resolved).await {
+        Ok(()) => Resp::created(FactEntryView::from(entry)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
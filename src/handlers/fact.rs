@@ -2,18 +2,39 @@ use actix_multipart::Multipart;
 use tokio::io::AsyncWriteExt;
 use futures::{StreamExt, TryStreamExt};
 use uuid::Uuid;
-use crate::state::State;
+use crate::{
+    state::State,
+    handlers::{choice::own_type, tag::filter_tagged, unit::{unit_registry, convert_entries}},
+    models::{FactEntryView, FactTypeView, Resp, TagFilter, UnitQuery},
+};
 use actix_web::{Scope,
-    web::{self, delete, get, post, put, resource, scope, ServiceConfig},
+    web::{self, delete, get, patch, post, put, resource, scope, ServiceConfig},
     HttpResponse, HttpRequest
 };
-use div_db::models::{User, FactType, FactEntry};
+use div_db::models::{User, FactType, FactEntry, TagOwner};
 use div_db::sqlx::{self, Postgres, query_as};
 
 pub fn routes(base: &str) -> actix_web::Scope {
     scope(base)
         .service(resource("").route(get().to(get_all_types)))
         .service(resource("/entries").route(get().to(get_all_entries)))
+        .route("/types", post().to(super::choice::create_choice_type))
+        .route("/types/{id}", delete().to(trash_type))
+        .route("/types/{id}/archive", post().to(archive_type))
+        .route("/types/{id}/unarchive", post().to(unarchive_type))
+        .route("/types/{id}/restore", post().to(restore_type))
+        .route("/types/{id}/options", get().to(super::choice::get_choices))
+        .route("/types/{id}/options", post().to(super::choice::add_option))
+        .route("/types/{id}/options/{oid}", patch().to(super::choice::update_option))
+        .route("/types/{id}/options/{oid}", delete().to(super::choice::remove_option))
+        .route("/types/{id}/range", put().to(super::choice::set_range))
+        .route("/types/{id}/counts", get().to(super::choice::get_option_counts))
+        .route("/types/{id}/entries", post().to(super::choice::log_entry))
+        .route("/types/{id}/stats", get().to(super::unit::get_fact_stats))
+        .route("/places", get().to(super::place::get_places))
+        .route("/places.geojson", get().to(super::place::export_geojson))
+        .route("/types/{id}/history", get().to(super::history::fact_type_history))
+        .route("/entries/{id}/history", get().to(super::history::fact_entry_history))
         .service(scope("/{uid}")
             .service(resource("")
                 .route(get().to(get_by_uid))
@@ -28,32 +49,79 @@ pub fn with_name() -> actix_web::Scope {
 
 pub async fn get_all_entries(
     id: actix_session::Session,
+    query: web::Query<UnitQuery>,
     data: web::Data<State>,) -> HttpResponse {
     let db = data.db.lock().unwrap();
-    let res = sqlx::query_as::<Postgres, FactEntry>("SELECT * FROM FactEntries")
+    let res = sqlx::query_as::<Postgres, FactEntry>("SELECT * FROM fact_entries")
         .fetch_all(&db.pool).await.unwrap();
-    HttpResponse::Ok()
-        .json(&res)
+    let uid = crate::handlers::auth::validate(&id).map(|user| user.id).ok();
+    let units = match unit_registry(&db, uid).await { Ok(units) => units, Err(resp) => return resp };
+    match convert_entries(&units, &query, res.into_iter().map(FactEntryView::from).collect()) {
+        Ok(res) => Resp::ok(res).into(),
+        Err(resp) => resp,
+    }
+}
+
+/// Moves the fact type to the trash, from where it can be restored until it
+/// is purged. Its entries stay until then.
+pub async fn trash_type(session: actix_session::Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    status_changed(FactType::soft_delete(&db, kind.id).await, "Fact type is already in the trash")
+}
+
+pub async fn archive_type(session: actix_session::Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    status_changed(FactType::set_archived(&db, kind.id, true).await,
+        "Fact type is already archived or in the trash")
+}
+
+pub async fn unarchive_type(session: actix_session::Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    status_changed(FactType::set_archived(&db, kind.id, false).await, "Fact type is not archived")
+}
+
+pub async fn restore_type(session: actix_session::Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let kind = match own_type(&db, &session, *id).await { Ok(kind) => kind, Err(resp) => return resp };
+    status_changed(FactType::restore(&db, kind.id).await, "Fact type is not in the trash")
+}
+
+fn status_changed(res: sqlx::Result<Option<FactType>>, conflict: &str) -> HttpResponse {
+    match res {
+        Ok(Some(kind)) => Resp::<FactTypeView>::view(kind).into(),
+        Ok(None) => HttpResponse::Conflict().body(conflict.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
 
+/// Every fact type outside the trash, narrowed by `?tags=`
 pub async fn get_all_types(
     id: actix_session::Session,
+    query: web::Query<TagFilter>,
     data: web::Data<State>,) -> HttpResponse {
     let db = data.db.lock().unwrap();
-    let res = sqlx::query_as::<Postgres, FactType>("SELECT * FROM FactTypes")
+    let res = sqlx::query_as::<Postgres, FactType>("SELECT * FROM fact_types WHERE deleted_at IS NULL")
         .fetch_all(&db.pool).await.unwrap();
-    HttpResponse::Ok()
-        .json(&res)
+    match filter_tagged(&db, TagOwner::FactTypes, &query, res, |t| t.id).await {
+        Ok(res) => Resp::<Vec<FactTypeView>>::views(res).into(),
+        Err(resp) => resp,
+    }
 }
 
 pub async fn get_by_uid(
     id: actix_session::Session,
     data: web::Data<State>,
-    uid: web::Path<Uuid>,) -> HttpResponse {
+    uid: web::Path<Uuid>,
+    query: web::Query<TagFilter>,) -> HttpResponse {
     let db = data.db.lock().unwrap();
-    let res = sqlx::query_as::<Postgres, FactType>("SELECT * FROM FactTypes WHERE uid = ?")
-        .bind(&uid.to_string())
+    let res = sqlx::query_as::<Postgres, FactType>("SELECT * FROM fact_types WHERE uid = $1 AND deleted_at IS NULL")
+        .bind(*uid)
         .fetch_all(&db.pool).await.unwrap();
-    HttpResponse::Ok()
-        .json(&res)
+    match filter_tagged(&db, TagOwner::FactTypes, &query, res, |t| t.id).await {
+        Ok(res) => Resp::<Vec<FactTypeView>>::views(res).into(),
+        Err(resp) => resp,
+    }
 }
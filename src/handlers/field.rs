@@ -0,0 +1,185 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::{auth::validate, unit::{unit_registry, check_unit, convert_entries}},
+    models::{
+        AddItemField, LogFieldValue, FieldEntriesQuery, ItemFieldView, ItemWithFields,
+        FactEntryView, ItemView, UnitQuery, Resp,
+    },
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use div_db::{Db, InvalidField, Visibility, models::{Item, ItemField, FactType, Choices}};
+
+/// Most values returned in one page of a field's history
+const MAX_FIELD_PAGE: i64 = 500;
+
+fn invalid(field: &str, reason: &str) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
+
+async fn load_item(db: &Db, iid: Uuid) -> Result<Item, HttpResponse> {
+    match Item::get_by_id(db, iid).await {
+        Ok(Some(item)) => Ok(item),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The item, if the session user may read its fields: their own, or
+/// anyone's public item
+async fn readable_item(db: &Db, session: &Session, iid: Uuid) -> Result<Item, HttpResponse> {
+    let item = load_item(db, iid).await?;
+    let is_owner = validate(session).map(|user| user.id == item.uid).unwrap_or(false);
+    if !is_owner && item.visibility != Visibility::Public {
+        return Err(HttpResponse::NotFound().finish());
+    }
+    Ok(item)
+}
+
+async fn own_item(db: &Db, session: &Session, iid: Uuid) -> Result<Item, HttpResponse> {
+    let user = validate(session)?;
+    let item = load_item(db, iid).await?;
+    if item.uid != user.id {
+        return Err(HttpResponse::Forbidden().body("Not your item"));
+    }
+    Ok(item)
+}
+
+async fn load_field(db: &Db, iid: Uuid, tid: Uuid) -> Result<FactType, HttpResponse> {
+    match ItemField::get(db, iid, tid).await {
+        Ok(Some(kind)) => Ok(kind),
+        Ok(None) => Err(HttpResponse::NotFound().body("The item has no such field")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The fact type a new field is backed by: the given one, which must be
+/// the user's, or the user's type of the given name, created if needed
+async fn field_type(db: &Db, uid: Uuid, body: AddItemField) -> Result<FactType, HttpResponse> {
+    let found = match (body.tid, &body.name) {
+        (Some(tid), _) => FactType::get_by_id(db, tid).await.map(|k| k.filter(|k| k.uid == uid)),
+        (None, Some(name)) => FactType::get_by_name(db, uid, name).await,
+        (None, None) => return Err(invalid("tid", "either tid or name is required")),
+    };
+    match found {
+        Ok(Some(kind)) => return Ok(kind),
+        Ok(None) if body.tid.is_some() => return Err(invalid("tid", "must be a fact type of yours")),
+        Ok(None) => {},
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+    let kind = FactType {
+        uid,
+        name: body.name.unwrap_or_default(),
+        value_type: body.value_type,
+        units: body.units,
+        ..FactType::default()
+    };
+    match kind.insert(db).await {
+        Ok(_) => Ok(kind),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The item with its fields and each field's newest value
+pub async fn get_fields(session: Session, iid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let item = match readable_item(&db, &session, *iid).await { Ok(item) => item, Err(resp) => return resp };
+    match ItemField::get_all(&db, item.id).await {
+        Ok(fields) => Resp::ok(ItemWithFields {
+            item: ItemView::from(item),
+            fields: fields.into_iter().map(ItemFieldView::from).collect(),
+        }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn add_field(
+    session: Session,
+    iid: web::Path<Uuid>,
+    body: actix_web_validator::Json<AddItemField>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let item = match own_item(&db, &session, *iid).await { Ok(item) => item, Err(resp) => return resp };
+    let kind = match field_type(&db, item.uid, body.into_inner()).await { Ok(kind) => kind, Err(resp) => return resp };
+    match Item::add_existing_field(&db, item.id, &kind).await {
+        Ok(0) => HttpResponse::Conflict().body("The item already has this field"),
+        Ok(_) => Resp::created(ItemFieldView { field: kind.into(), latest: None, count: 0 }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Removes the field from the item. Values logged for it stay as fact
+/// entries.
+pub async fn remove_field(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (iid, tid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = own_item(&db, &session, iid).await { return resp }
+    match ItemField::remove(&db, iid, tid).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("The item has no such field"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Values logged for the field, newest first, converted to `?unit=` if
+/// given
+pub async fn get_field_entries(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    query: web::Query<FieldEntriesQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (iid, tid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let item = match readable_item(&db, &session, iid).await { Ok(item) => item, Err(resp) => return resp };
+    if let Err(resp) = load_field(&db, iid, tid).await { return resp }
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_FIELD_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let entries = match ItemField::entries(&db, iid, tid, limit, offset).await {
+        Ok(entries) => entries.into_iter().map(FactEntryView::from).collect(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let units = match unit_registry(&db, Some(item.uid)).await { Ok(units) => units, Err(resp) => return resp };
+    match convert_entries(&units, &UnitQuery { unit: query.unit.clone() }, entries) {
+        Ok(entries) => Resp::ok(entries).into(),
+        Err(resp) => resp,
+    }
+}
+
+/// Logs a value for the field. It must fit the field's type, including a
+/// select field's options, a range field's bounds or a place field's
+/// coordinates, and its unit, if
+/// given, must be one the type lists or convertible to one.
+pub async fn log_field_value(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    body: actix_web_validator::Json<LogFieldValue>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (iid, tid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let item = match own_item(&db, &session, iid).await { Ok(item) => item, Err(resp) => return resp };
+    let kind = match load_field(&db, iid, tid).await { Ok(kind) => kind, Err(resp) => return resp };
+    let resolved = match Choices::load(&db, &kind).await {
+        Ok(choices) => match choices.resolve(&body.value) {
+            Ok(resolved) => resolved,
+            Err(reason) => return invalid("value", &reason),
+        },
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let units = match unit_registry(&db, Some(item.uid)).await { Ok(units) => units, Err(resp) => return resp };
+    if let Err(resp) = check_unit(&units, &kind, body.units.as_deref()) { return resp }
+    let body = LogFieldValue { value: resolved.value.clone(), ..body.into_inner() };
+    let entry = body.into_entry(item.uid, kind.name);
+    match ItemField::log(&db, iid, tid, &entry, &resolved).await {
+        Ok(_) => Resp::created(FactEntryView::from(entry)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,76 @@
+use actix_session::Session;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use uuid::Uuid;
+use crate::{state::State, handlers::auth::validate};
+use actix_web::{web, HttpResponse};
+use div_db::{Entity, models::AuditEntry};
+
+/// Most changes returned in one page of history
+const MAX_HISTORY_PAGE: i64 = 200;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct HistoryQuery {
+    /// Returns the row as it stood at this time instead of its changes
+    pub as_of: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PointInTime {
+    pub id: Uuid,
+    pub as_of: DateTime<Utc>,
+    pub state: Value,
+}
+
+pub async fn record_history(session: Session, id: web::Path<Uuid>, query: web::Query<HistoryQuery>, data: web::Data<State>)
+    -> HttpResponse
+{
+    history(Entity::Records, session, *id, query.into_inner(), data).await
+}
+
+pub async fn item_history(session: Session, id: web::Path<Uuid>, query: web::Query<HistoryQuery>, data: web::Data<State>)
+    -> HttpResponse
+{
+    history(Entity::Items, session, *id, query.into_inner(), data).await
+}
+
+pub async fn fact_type_history(session: Session, id: web::Path<Uuid>, query: web::Query<HistoryQuery>, data: web::Data<State>)
+    -> HttpResponse
+{
+    history(Entity::FactTypes, session, *id, query.into_inner(), data).await
+}
+
+pub async fn fact_entry_history(session: Session, id: web::Path<Uuid>, query: web::Query<HistoryQuery>, data: web::Data<State>)
+    -> HttpResponse
+{
+    history(Entity::FactEntries, session, *id, query.into_inner(), data).await
+}
+
+/// Changes to one of the session user's rows, oldest first, or with
+/// `as_of` the row as it stood then. History outlives the row itself.
+async fn history(entity: Entity, session: Session, id: Uuid, query: HistoryQuery, data: web::Data<State>)
+    -> HttpResponse
+{
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match AuditEntry::owner(&db, entity, id).await {
+        Ok(Some(uid)) if uid == user.id => {},
+        Ok(_) => return HttpResponse::NotFound().body("No history for this id"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    if let Some(at) = query.as_of {
+        return match AuditEntry::as_of(&db, entity, id, at).await {
+            Ok(Some(state)) => HttpResponse::Ok().json(PointInTime { id, as_of: at, state }),
+            Ok(None) => HttpResponse::NotFound().body("Didn't exist at that time"),
+            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        };
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_HISTORY_PAGE);
+    match AuditEntry::history(&db, entity, id, limit, query.offset.unwrap_or(0).max(0)).await {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
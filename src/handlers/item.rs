@@ -1,21 +1,40 @@
 use uuid::Uuid;
 use actix_session::Session;
-use crate::state::State;
+use crate::{
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::{auth::validate, tag::filter_tagged},
+    models::{CreateItem, UpdateItem, ItemView, Resp, TagFilter, if_match, patched, stale},
+};
 use actix_web::{
-    get, post, delete, put,
+    get, post, delete, put, patch,
     web::{self, delete, get, post, put, resource, scope, ServiceConfig},
-    HttpRequest, HttpResponse, Scope, Result,
+    HttpRequest, HttpResponse, ResponseError, Scope, Result,
 };
 use div_db::{
-    models::{Item, Record, User},
-    Db,
+    models::{Item, Record, TagOwner, User},
+    Db, Status,
 };
 
 pub fn routes(base: &str) -> Scope {
     scope(base)
+        .service(get_own)
+        .service(get_archived)
         .service(get_by_id)
         .service(delete_by_id)
         .service(update_by_id)
+        .service(patch_by_id)
+        .service(archive_by_id)
+        .service(unarchive_by_id)
+        .service(restore_by_id)
+        .route("/{iid}/history", get().to(super::history::item_history))
+        .service(resource("/{iid}/fields")
+            .route(get().to(super::field::get_fields))
+            .route(post().to(super::field::add_field)))
+        .route("/{iid}/fields/{tid}", delete().to(super::field::remove_field))
+        .service(resource("/{iid}/fields/{tid}/entries")
+            .route(get().to(super::field::get_field_entries))
+            .route(post().to(super::field::log_field_value)))
 }
 
 pub fn user_item_routes() -> Scope {
@@ -51,34 +70,168 @@ pub async fn get_by_id(
 {
     let id: Uuid = Uuid::parse_str(iid.into_inner().as_mut_str()).unwrap();
     match Item::get_by_id(&data.db.lock().unwrap(), id).await {
-        Ok(rec) => Ok(HttpResponse::Ok().json("{}")), //PgRow -> JSon?
-        Err(_) => Ok(HttpResponse::NotFound().json("{}")),
+        Ok(Some(item)) => Ok(Resp::<ItemView>::view(item.clone()).with_etag(item.updated_at).into()),
+        _ => Ok(HttpResponse::NotFound().json("{}")),
     }
 }
 
+/// Moves the item to the trash
 #[delete("/{iid}")]
 pub async fn delete_by_id(
-    id: Session,
-    iid: web::Path<String>,
-    data: web::Data<State>,) -> actix_web::Result<HttpResponse>
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
+    data: web::Data<State>,) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    match owned_item(&db, &session, &req, *iid).await {
+        Ok(item) => status_changed(Item::soft_delete(&db, item.id).await,
+            "Item is already in the trash"),
+        Err(resp) => resp,
+    }
+}
+
+#[post("/{iid}/archive")]
+pub async fn archive_by_id(
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
+    data: web::Data<State>,) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    match owned_item(&db, &session, &req, *iid).await {
+        Ok(item) => status_changed(Item::set_archived(&db, item.id, true).await,
+            "Item is already archived or in the trash"),
+        Err(resp) => resp,
+    }
+}
+
+#[post("/{iid}/unarchive")]
+pub async fn unarchive_by_id(
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
+    data: web::Data<State>,) -> HttpResponse
 {
-    let iid: Uuid = Uuid::parse_str(iid.into_inner().as_mut_str()).unwrap();
-    match Item::delete_by_id(&data.db.lock().unwrap(), iid).await {
-        Ok(rec) => Ok(HttpResponse::Ok().json("{}")), //PgRow -> JSon?
-        Err(_) => Ok(HttpResponse::NotFound().json("{}")),
+    let db = data.db.lock().unwrap();
+    match owned_item(&db, &session, &req, *iid).await {
+        Ok(item) => status_changed(Item::set_archived(&db, item.id, false).await,
+            "Item is not archived"),
+        Err(resp) => resp,
+    }
+}
+
+#[post("/{iid}/restore")]
+pub async fn restore_by_id(
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
+    data: web::Data<State>,) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    match owned_item(&db, &session, &req, *iid).await {
+        Ok(item) => status_changed(Item::restore(&db, item.id).await,
+            "Item is not in the trash"),
+        Err(resp) => resp,
+    }
+}
+
+/// The session user's items, leaving out archived ones and the trash,
+/// narrowed by `?tags=`
+#[get("")]
+pub async fn get_own(session: Session, query: web::Query<TagFilter>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let items = match Item::get_all_by_user(&db, user.id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match filter_tagged(&db, TagOwner::Items, &query, items, |i| i.id).await {
+        Ok(items) => Resp::<Vec<ItemView>>::views(items).into(),
+        Err(resp) => resp,
+    }
+}
+
+#[get("/archived")]
+pub async fn get_archived(session: Session, query: web::Query<TagFilter>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let items = match Item::get_by_status(&db, user.id, Status::Archived).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match filter_tagged(&db, TagOwner::Items, &query, items, |i| i.id).await {
+        Ok(items) => Resp::<Vec<ItemView>>::views(items).into(),
+        Err(resp) => resp,
+    }
+}
+
+fn status_changed(res: div_db::sqlx::Result<Option<Item>>, conflict: &str) -> HttpResponse {
+    match res {
+        Ok(Some(item)) => Resp::<ItemView>::view(item.clone()).with_etag(item.updated_at).into(),
+        Ok(None) => HttpResponse::Conflict().body(conflict.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Loads an item the session user owns, checked against `If-Match`
+async fn owned_item(
+    db: &Db, session: &Session, req: &HttpRequest, iid: Uuid,
+) -> std::result::Result<Item, HttpResponse> {
+    let user = validate(session)?;
+    let item = match Item::get_by_id(db, iid).await {
+        Ok(Some(item)) => item,
+        Ok(None) => return Err(HttpResponse::NotFound().finish()),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    if item.uid != user.id {
+        return Err(HttpResponse::Forbidden().body("Not your item"));
+    }
+    if_match(req, item.updated_at)?;
+    Ok(item)
+}
+
+async fn save_item(db: &Db, current: Item, update: UpdateItem) -> HttpResponse {
+    let name = update.name.clone();
+    let (id, since) = (current.id, current.updated_at);
+    match Item::update_by_id(db, id, update.apply(current), since).await {
+        Ok(Some(item)) => Resp::<ItemView>::view(item.clone()).with_etag(item.updated_at).into(),
+        Ok(None) => stale(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 #[put("/{iid}")]
 pub async fn update_by_id(
-    id: Session,
-    iid: web::Path<String>,
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
     data: web::Data<State>,
-    item: web::Json<Item>,) -> actix_web::Result<HttpResponse> {
-    let iid: Uuid = Uuid::parse_str(iid.into_inner().as_mut_str()).unwrap();
-    match Item::update_by_id(&data.db.lock().unwrap(), iid, item.into_inner()).await {
-        Ok(rec) => Ok(HttpResponse::Ok().json("{}")), //PgRow -> JSon?
-        Err(_) => Ok(HttpResponse::NotFound().json("{}")),
+    item: actix_web_validator::Json<UpdateItem>,) -> actix_web::Result<HttpResponse> {
+    let db = data.db.lock().unwrap();
+    match owned_item(&db, &session, &req, *iid).await {
+        Ok(current) => Ok(save_item(&db, current, item.into_inner()).await),
+        Err(resp) => Ok(resp),
+    }
+}
+
+#[patch("/{iid}")]
+pub async fn patch_by_id(
+    session: Session,
+    req: HttpRequest,
+    iid: web::Path<Uuid>,
+    data: web::Data<State>,
+    body: web::Json<serde_json::Value>,) -> actix_web::Result<HttpResponse> {
+    let db = data.db.lock().unwrap();
+    let current = match owned_item(&db, &session, &req, *iid).await {
+        Ok(current) => current,
+        Err(resp) => return Ok(resp),
+    };
+    match patched(UpdateItem::from(current.clone()), body.into_inner()) {
+        Ok(update) => Ok(save_item(&db, current, update).await),
+        Err(e) => Ok(e.error_response()),
     }
 }
 
@@ -88,16 +241,19 @@ pub async fn get_user_item(
     let (mut uid, item_name) = path.into_inner();
     let uid = Uuid::parse_str(uid.as_mut_str()).unwrap();
     match User::get_item_by_name(&data.db.lock().unwrap(), uid, item_name).await {
-        Ok(Some(item)) => HttpResponse::Ok().json(&item),
+        Ok(Some(item)) => Resp::<ItemView>::view(item).into(),
         _ => HttpResponse::NotFound().json("{}"),
     }
 }
 
 pub async fn delete_user_item(
-    id: Session, path: web::Path<(String, String)>, data: web::Data<State>,
+    session: Session, path: web::Path<(Uuid, String)>, data: web::Data<State>,
 ) -> HttpResponse {
-    let (mut uid, item_name) = path.into_inner();
-    let uid = Uuid::parse_str(uid.as_mut_str()).unwrap();
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let (uid, item_name) = path.into_inner();
+    if user.id != uid {
+        return HttpResponse::Forbidden().body("Can only delete your own items");
+    }
     match User::delete_item_by_name(&data.db.lock().unwrap(), uid, item_name).await {
         Ok(iid) => HttpResponse::Ok().json(&iid),
         _ => HttpResponse::NotFound().json("{}"),
@@ -105,36 +261,52 @@ pub async fn delete_user_item(
 }
 
 pub async fn get_user_items(
-    id: Session, uid: web::Path<String>, data: web::Data<State>,
+    id: Session, uid: web::Path<String>, query: web::Query<TagFilter>, data: web::Data<State>,
 ) -> HttpResponse {
     let uid = Uuid::parse_str(uid.into_inner().as_mut_str()).unwrap();
-    match User::get_all_items(&data.db.lock().unwrap(), uid).await {
-        Ok(rec) => HttpResponse::Ok().json("{}"), //PgRow -> JSon?
-        Err(_) => HttpResponse::NotFound().json("{}"),
+    let db = data.db.lock().unwrap();
+    let items = match User::get_all_items(&db, uid).await {
+        Ok(items) => items,
+        Err(_) => return HttpResponse::NotFound().json("{}"),
+    };
+    match filter_tagged(&db, TagOwner::Items, &query, items, |i| i.id).await {
+        Ok(items) => Resp::<Vec<ItemView>>::views(items).into(),
+        Err(resp) => resp,
     }
 }
 
+/// The owner is always the session user, who must also be the `{uid}` in the path
 pub async fn add_item_to_user(
-    id: Session, uid: web::Path<Uuid>, data: web::Data<State>, item: web::Json<Item>,
+    session: Session,
+    uid: web::Path<Uuid>,
+    data: web::Data<State>,
+    item: actix_web_validator::Json<CreateItem>,
 ) -> HttpResponse {
-    match User::add_existing_item(&data.db.lock().unwrap(), uid.into_inner(), item.into_inner()).await {
-        Ok(item) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&item), //PgRow -> JSon?
-        Err(_) => HttpResponse::NotFound().json("{}"),
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    if user.id != uid.into_inner() {
+        return HttpResponse::Forbidden().body("Can only create your own items");
+    }
+    let item = item.into_inner().into_item(user.id);
+    match item.insert(&data.db.lock().unwrap()).await {
+        Ok(item) => Resp::<ItemView>::created(item.into()).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: item.name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 pub async fn add_new_item_to_user(
-    id: Session,
+    session: Session,
     path: web::Path<(Uuid, String)>,
     data: web::Data<State>,
 ) -> HttpResponse {
-    let (mut uid, name) = path.into_inner();
-    match User::add_new_item(&data.db.lock().unwrap(), uid, name).await {
-        Ok(item) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&item), //PgRow -> JSon?
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let (uid, name) = path.into_inner();
+    if user.id != uid {
+        return HttpResponse::Forbidden().body("Can only create your own items");
+    }
+    match User::add_new_item(&data.db.lock().unwrap(), user.id, name).await {
+        Ok(item) => Resp::<ItemView>::created(item.into()).into(),
         Err(_) => HttpResponse::NotFound().json("{}"),
     }
 }
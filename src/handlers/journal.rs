@@ -0,0 +1,138 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::{auth::validate, record::load_record},
+    models::{CreateRecordEntry, UpdateRecordEntry, EntryRangeQuery, RecordEntryView, Resp},
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use div_db::{Db, InvalidField, Visibility, models::{Record, RecordEntry}};
+
+/// Most entries returned in one page of a journal
+const MAX_ENTRIES_PAGE: i64 = 500;
+
+/// The record, if the session user may read its journal: their own, or
+/// anyone's public record
+async fn readable_record(db: &Db, session: &Session, rid: Uuid) -> Result<Record, HttpResponse> {
+    let rec = load_record(db, rid).await?;
+    let is_owner = validate(session).map(|user| user.id == rec.uid).unwrap_or(false);
+    if !is_owner && rec.visibility != Visibility::Public {
+        return Err(HttpResponse::NotFound().finish());
+    }
+    Ok(rec)
+}
+
+async fn own_record(db: &Db, session: &Session, rid: Uuid) -> Result<Record, HttpResponse> {
+    let user = validate(session)?;
+    let rec = load_record(db, rid).await?;
+    if rec.uid != user.id {
+        return Err(HttpResponse::Forbidden().body("Not your record"));
+    }
+    Ok(rec)
+}
+
+async fn load_entry(db: &Db, rid: Uuid, eid: Uuid) -> Result<RecordEntry, HttpResponse> {
+    match RecordEntry::get_by_id(db, eid).await {
+        Ok(Some(entry)) if entry.rid == rid => Ok(entry),
+        Ok(_) => Err(HttpResponse::NotFound().body("No such entry in this record")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Linked facts must be the user's own fact entries
+async fn check_facts(db: &Db, uid: Uuid, facts: &[Uuid]) -> Result<(), HttpResponse> {
+    match RecordEntry::owns_facts(db, uid, facts).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(UserError::ValidationError {
+            errors: vec![InvalidField::new("facts", "must be fact entries of yours")],
+        }.error_response()),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The record's journal in chronological order, optionally limited to a
+/// range of `logged_at`
+pub async fn get_entries(
+    session: Session,
+    rid: web::Path<Uuid>,
+    query: web::Query<EntryRangeQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match readable_record(&db, &session, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    if query.is_inverted() {
+        return UserError::ValidationError {
+            errors: vec![InvalidField::new("to", "must not be before from")],
+        }.error_response();
+    }
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_ENTRIES_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match RecordEntry::get_range(&db, rec.id, query.from, query.to, limit, offset).await {
+        Ok(entries) => Resp::<Vec<RecordEntryView>>::views(entries).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn add_entry(
+    session: Session,
+    rid: web::Path<Uuid>,
+    body: actix_web_validator::Json<CreateRecordEntry>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match own_record(&db, &session, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    if let Err(resp) = check_facts(&db, rec.uid, &body.facts).await { return resp }
+    match body.into_inner().into_entry(rec.id, rec.uid).insert(&db).await {
+        Ok(entry) => Resp::created(RecordEntryView::from(entry)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_entry(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (rid, eid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = readable_record(&db, &session, rid).await { return resp }
+    match load_entry(&db, rid, eid).await {
+        Ok(entry) => Resp::<RecordEntryView>::view(entry).into(),
+        Err(resp) => resp,
+    }
+}
+
+/// Changes the fields that are present. `facts` replaces the linked facts.
+pub async fn update_entry(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    body: actix_web_validator::Json<UpdateRecordEntry>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (rid, eid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let rec = match own_record(&db, &session, rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    let entry = match load_entry(&db, rid, eid).await { Ok(entry) => entry, Err(resp) => return resp };
+    if let Err(resp) = check_facts(&db, rec.uid, body.facts()).await { return resp }
+    match body.into_inner().apply(entry).update(&db).await {
+        Ok(Some(entry)) => Resp::<RecordEntryView>::view(entry).into(),
+        Ok(None) => HttpResponse::NotFound().body("No such entry in this record"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn delete_entry(
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (rid, eid) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = own_record(&db, &session, rid).await { return resp }
+    let entry = match load_entry(&db, rid, eid).await { Ok(entry) => entry, Err(resp) => return resp };
+    match RecordEntry::delete_by_id(&db, entry.id).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,155 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    handlers::auth::validate,
+    models::{CreateNote, UpdateNote, NoteView, NoteRevisionView, Resp},
+};
+use actix_web::{
+    web::{self, delete, get, patch, post, resource, scope},
+    HttpResponse, Scope,
+};
+use div_db::{Db, Visibility, models::{Note, NoteOwner}};
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("/{nid}/revisions", get().to(get_revisions))
+        .service(resource("/{nid}/pin")
+            .route(post().to(pin_note))
+            .route(delete().to(unpin_note)))
+        .service(resource("/{entity}/{id}")
+            .route(get().to(get_notes))
+            .route(post().to(add_note)))
+        .service(resource("/{nid}")
+            .route(get().to(get_note))
+            .route(patch().to(update_note))
+            .route(delete().to(delete_note)))
+}
+
+fn known_owner(name: &str) -> Result<NoteOwner, HttpResponse> {
+    NoteOwner::parse(name)
+        .ok_or_else(|| HttpResponse::NotFound().body("Notes belong to records, items, fact_entries or groups"))
+}
+
+/// The session user's note, answering 404 for anyone else's
+async fn own_note(db: &Db, session: &Session, nid: Uuid) -> Result<Note, HttpResponse> {
+    let user = validate(session)?;
+    match Note::get_by_id(db, nid).await {
+        Ok(Some(note)) if note.uid == user.id => Ok(note),
+        Ok(_) => Err(HttpResponse::NotFound().body("No such note")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Notes on a row. The row's owner sees all of them, anyone else only the
+/// public ones, and only when the row itself is public.
+pub async fn get_notes(
+    session: Session,
+    path: web::Path<(String, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id) = path.into_inner();
+    let owner = match known_owner(&entity) { Ok(o) => o, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let viewer = validate(&session).map(|user| user.id).ok();
+    let row_owner = match owner.visible_row_owner(&db, id, viewer).await {
+        Ok(Some(uid)) => uid,
+        Ok(None) => return HttpResponse::NotFound().body("No such row"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let public_only = viewer != Some(row_owner);
+    match Note::get_all(&db, owner, id, public_only).await {
+        Ok(notes) => Resp::<Vec<NoteView>>::views(notes).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn add_note(
+    session: Session,
+    path: web::Path<(String, Uuid)>,
+    body: actix_web_validator::Json<CreateNote>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let (entity, id) = path.into_inner();
+    let owner = match known_owner(&entity) { Ok(o) => o, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match owner.row_owner(&db, id).await {
+        Ok(Some(row_owner)) if row_owner == uid => {},
+        Ok(_) => return HttpResponse::NotFound().body("No such row"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    let note = body.into_inner().apply(Note::new(uid, owner, id, String::new()));
+    match note.insert(&db).await {
+        Ok(note) => Resp::created(NoteView::from(note)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_note(session: Session, nid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let note = match Note::get_by_id(&db, *nid).await {
+        Ok(Some(note)) => note,
+        Ok(None) => return HttpResponse::NotFound().body("No such note"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let is_author = validate(&session).map(|user| user.id == note.uid).unwrap_or(false);
+    if !is_author && note.visibility != Visibility::Public {
+        return HttpResponse::NotFound().body("No such note");
+    }
+    Resp::<NoteView>::view(note).into()
+}
+
+/// Edits the content, pin or visibility. A content change keeps the old
+/// content in the note's revisions.
+pub async fn update_note(
+    session: Session,
+    nid: web::Path<Uuid>,
+    body: actix_web_validator::Json<UpdateNote>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let note = match own_note(&db, &session, *nid).await { Ok(note) => note, Err(resp) => return resp };
+    saved(body.into_inner().apply(note).update(&db).await)
+}
+
+pub async fn pin_note(session: Session, nid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    set_pinned(session, *nid, true, data).await
+}
+
+pub async fn unpin_note(session: Session, nid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    set_pinned(session, *nid, false, data).await
+}
+
+async fn set_pinned(session: Session, nid: Uuid, pinned: bool, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let note = match own_note(&db, &session, nid).await { Ok(note) => note, Err(resp) => return resp };
+    saved(Note::set_pinned(&db, note.id, pinned).await)
+}
+
+fn saved(res: div_db::sqlx::Result<Option<Note>>) -> HttpResponse {
+    match res {
+        Ok(Some(note)) => Resp::<NoteView>::view(note).into(),
+        Ok(None) => HttpResponse::NotFound().body("No such note"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn delete_note(session: Session, nid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let note = match own_note(&db, &session, *nid).await { Ok(note) => note, Err(resp) => return resp };
+    match Note::delete_by_id(&db, note.id).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Earlier versions of the note, newest first. Only its author sees them.
+pub async fn get_revisions(session: Session, nid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let note = match own_note(&db, &session, *nid).await { Ok(note) => note, Err(resp) => return resp };
+    match Note::revisions(&db, note.id).await {
+        Ok(revs) => Resp::<Vec<NoteRevisionView>>::views(revs).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
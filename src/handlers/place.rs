@@ -0,0 +1,60 @@
+use actix_session::Session;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::auth::validate,
+    models::{PlacesQuery, PlaceEntryView, FeatureCollection, Resp},
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use div_db::{InvalidField, models::{Place, BBox}};
+
+/// Most entries returned by one place search
+const MAX_PLACES_PAGE: i64 = 1000;
+
+/// Largest radius searched, in km: half the earth's circumference
+const MAX_RADIUS_KM: f64 = 20_016.0;
+
+fn invalid(field: &str, reason: &str) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
+
+/// The session user's place entries within `km` of `lat`,`lon`, nearest
+/// first, or inside `bbox`, newest first
+pub async fn get_places(session: Session, query: web::Query<PlacesQuery>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_PLACES_PAGE);
+    let db = data.db.lock().unwrap();
+    let res = match (&query.bbox, query.lat, query.lon, query.km) {
+        (Some(bbox), None, None, None) => match BBox::parse(bbox) {
+            Ok(bbox) => Place::in_bbox(&db, uid, bbox, limit).await,
+            Err(reason) => return invalid("bbox", &reason),
+        },
+        (None, Some(lat), Some(lon), Some(km)) => {
+            let at = Place { lat, lon, label: None, address: None };
+            if let Err(reason) = at.check() {
+                return invalid("lat", &reason);
+            }
+            if !(km > 0.0 && km <= MAX_RADIUS_KM) {
+                return invalid("km", &format!("must be above 0 and at most {}", MAX_RADIUS_KM));
+            }
+            Place::within(&db, uid, lat, lon, km, limit).await
+        },
+        _ => return invalid("bbox", "give either bbox, or lat, lon and km"),
+    };
+    match res {
+        Ok(entries) => Resp::<Vec<PlaceEntryView>>::views(entries).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Every place entry of the session user as a GeoJSON feature collection
+pub async fn export_geojson(session: Session, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match Place::get_all(&db, uid).await {
+        Ok(entries) => HttpResponse::Ok()
+            .content_type("application/geo+json")
+            .json(FeatureCollection::from(entries)),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
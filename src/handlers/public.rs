@@ -9,7 +9,7 @@ use actix_web::{ get,
     web::{self, ServiceConfig},
     HttpResponse,
 };
-use div_db::models::User;
+use div_db::models::{User, Tag, tag::{label, parse_label}};
 
 pub fn routes(cfg: &mut ServiceConfig) {
     cfg
@@ -20,6 +20,7 @@ pub fn routes(cfg: &mut ServiceConfig) {
         .service(contact)
         .service(dash)
         .service(about)
+        .service(tagged)
         .service(self::auth::routes(""))
         .service(self::dashboard::routes("/dashboard"))
         .service(self::admin::routes("/admin"));
@@ -44,7 +45,7 @@ pub async fn index(
     ctx.insert("scheme", req.connection_info().scheme());
     let uid = crate::session::id(&id).unwrap_or(Uuid::nil());
     ctx.insert("uid", &uid.to_string());
-    let s = data.tera.render("index.html", &ctx)
+    let s = data.tera.read().unwrap().render("index.html", &ctx)
         .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))
         .unwrap_or_default();
     HttpResponse::Ok().content_type("text/html").body(s)
@@ -60,7 +61,7 @@ pub async fn about(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("contact.html", &ctx)
+    let s = data.tera.read().unwrap().render("contact.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -74,7 +75,7 @@ pub async fn contact(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("contact.html", &ctx)
+    let s = data.tera.read().unwrap().render("contact.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -88,7 +89,7 @@ pub async fn cover(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("cover.html", &ctx)
+    let s = data.tera.read().unwrap().render("cover.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -104,7 +105,7 @@ pub async fn users(
     let users = User::get_all(&db).await.unwrap_or_default();
     let mut ctx = tera::Context::new();
     ctx.insert("users", &users.to_owned());
-    let s = data.tera.render("users.html", &ctx)
+    let s = data.tera.read().unwrap().render("users.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -122,18 +123,46 @@ pub async fn user(
     let mut ctx = tera::Context::new();
     if let Some(user) = user {
         ctx.insert("user", &user.to_owned());
-        let s = data.tera.render("user.html", &ctx)
+        let s = data.tera.read().unwrap().render("user.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
         Ok(HttpResponse::Ok().content_type("text/html").body(s))
     } else {
         ctx.insert("status_code", &"404".to_string());
         ctx.insert("error", &"No user found".to_string());
-        let s = data.tera.render("error.html", &ctx)
+        let s = data.tera.read().unwrap().render("error.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
         Ok(HttpResponse::Ok().content_type("text/html").body(s))
     }
 }
 
+/// Public records, items, fact types and groups of every user carrying
+/// the tag
+#[get("/tags/{tag}")]
+pub async fn tagged(
+    tag: web::Path<String>,
+    data: web::Data<State>,) -> Result<HttpResponse, actix_web::Error>
+{
+    let db = data.db.lock().unwrap();
+    let mut ctx = tera::Context::new();
+    let (namespace, name) = match parse_label(&tag) {
+        Some(parts) => parts,
+        None => {
+            ctx.insert("status_code", &"404".to_string());
+            ctx.insert("error", &"No such tag".to_string());
+            let s = data.tera.read().unwrap().render("error.html", &ctx)
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
+            return Ok(HttpResponse::NotFound().content_type("text/html").body(s));
+        }
+    };
+    let rows = Tag::public_rows(&db, &namespace, &name, 200).await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    ctx.insert("tag", &label(&namespace, &name));
+    ctx.insert("rows", &rows);
+    let s = data.tera.read().unwrap().render("tag.html", &ctx)
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
 #[get("/dashboard")]
 pub async fn dash(
     _id: actix_session::Session,
@@ -143,7 +172,7 @@ pub async fn dash(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("dashboard.html", &ctx)
+    let s = data.tera.read().unwrap().render("dashboard.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))
             .unwrap_or_default();
     HttpResponse::Ok().content_type("text/html").body(s)
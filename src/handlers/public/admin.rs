@@ -20,7 +20,7 @@ pub async fn dashboard(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("dashboard.html", &ctx)
+    let s = data.tera.read().unwrap().render("dashboard.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))
             .unwrap_or_default();
     HttpResponse::Ok().content_type("text/html").body(s)
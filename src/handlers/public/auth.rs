@@ -20,7 +20,7 @@ pub async fn login(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("login.html", &ctx)
+    let s = data.tera.read().unwrap().render("login.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -34,7 +34,7 @@ pub async fn signup(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("login.html", &ctx)
+    let s = data.tera.read().unwrap().render("login.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))?;
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
-use crate::state::State;
+use uuid::Uuid;
+use crate::{state::State, handlers::auth::validate, models::{RecordView, RecordEntryView, NoteView}};
+use div_db::{Visibility, models::{Record, RecordEntry, Note, NoteOwner}};
 use actix_web::{ get,
     Scope, web::{scope, self},
     HttpResponse,
@@ -8,6 +10,7 @@ use actix_web::{ get,
 pub fn routes(base: &str) -> Scope {
     scope(base)
         .service(dashboard)
+        .service(record_journal)
 }
 
 #[get("/")]
@@ -19,7 +22,42 @@ pub async fn dashboard(
 {
     let _db = data.db.lock().unwrap();
     let mut ctx = tera::Context::new();
-    let s = data.tera.render("dashboard.html", &ctx)
+    let s = data.tera.read().unwrap().render("dashboard.html", &ctx)
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))
+            .unwrap_or_default();
+    HttpResponse::Ok().content_type("text/html").body(s)
+}
+
+/// A record's journal, oldest entry first, below the record's notes, for
+/// its owner or for anyone if the record is public
+#[get("/record/{rid}")]
+pub async fn record_journal(
+    session: actix_session::Session,
+    rid: web::Path<Uuid>,
+    data: web::Data<State>,) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    let rec = match Record::get_by_id(&db, *rid).await {
+        Ok(Some(rec)) => rec,
+        _ => return HttpResponse::NotFound().body("No such record"),
+    };
+    let is_owner = validate(&session).map(|user| user.id == rec.uid).unwrap_or(false);
+    if !is_owner && rec.visibility != Visibility::Public {
+        return HttpResponse::NotFound().body("No such record");
+    }
+    let entries = match RecordEntry::get_range(&db, rec.id, None, None, 1000, 0).await {
+        Ok(entries) => entries.into_iter().map(RecordEntryView::from).collect::<Vec<_>>(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let notes = match Note::get_all(&db, NoteOwner::Records, rec.id, !is_owner).await {
+        Ok(notes) => notes.into_iter().map(NoteView::from).collect::<Vec<_>>(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let mut ctx = tera::Context::new();
+    ctx.insert("record", &RecordView::from(rec));
+    ctx.insert("entries", &entries);
+    ctx.insert("notes", &notes);
+    let s = data.tera.read().unwrap().render("journal.html", &ctx)
             .map_err(|_| actix_web::error::ErrorInternalServerError("Template error"))
             .unwrap_or_default();
     HttpResponse::Ok().content_type("text/html").body(s)
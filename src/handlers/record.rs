@@ -1,16 +1,42 @@
 use uuid::Uuid;
-use crate::state::State;
+use crate::{
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::{auth::validate, tag::filter_tagged},
+    models::{CreateRecord, UpdateRecord, RecordView, Resp, TagFilter, if_match, patched, stale},
+};
 use actix_session::Session;
 use actix_web::{
     get, delete, put, post,
-    web::{self, delete, get, post, put, resource, scope, ServiceConfig},
-    HttpRequest, HttpResponse, Scope,
+    web::{self, delete, get, patch, post, put, resource, scope, ServiceConfig},
+    HttpRequest, HttpResponse, ResponseError, Scope,
 };
-use div_db::models::{Item, Model, Record, User};
+use div_db::{Db, Status, models::{Item, Model, Record, TagOwner, User}};
 
 pub fn routes(base: &str) -> Scope {
     scope(base)
+        .route("", get().to(get_own))
+        .route("/archived", get().to(get_archived))
+        .service(by_name())
         .service(by_id("/{rid}"))
+        .route("/{rid}/archive", post().to(archive_by_id))
+        .route("/{rid}/unarchive", post().to(unarchive_by_id))
+        .route("/{rid}/restore", post().to(restore_by_id))
+        .route("/{rid}/history", get().to(super::history::record_history))
+        .route("/{rid}/template", get().to(super::template::record_template))
+        .service(resource("/{rid}/entries")
+            .route(get().to(super::journal::get_entries))
+            .route(post().to(super::journal::add_entry)))
+        .service(resource("/{rid}/entries/{eid}")
+            .route(get().to(super::journal::get_entry))
+            .route(patch().to(super::journal::update_entry))
+            .route(delete().to(super::journal::delete_entry)))
+}
+
+/// A record found by its owner and name rather than its id
+pub fn by_name() -> actix_web::Resource {
+    web::resource("/user/{uid}/{name}")
+        .route(patch().to(update_user_record))
 }
 
 pub fn by_id(base: &str) -> actix_web::Resource {
@@ -18,39 +44,161 @@ pub fn by_id(base: &str) -> actix_web::Resource {
         .route(get().to(get_by_id))
         .route(delete().to(delete_by_id))
         .route(put().to(update_by_id))
-
+        .route(patch().to(patch_by_id))
 }
 
 pub async fn get_by_id(
     rid: web::Path<Uuid>,
     data: web::Data<State>) -> actix_web::Result<HttpResponse> {
     match Record::get_by_id(&data.db.lock().unwrap(), *rid).await {
-        Ok(rec) => Ok(HttpResponse::Ok().json(&rec)),
-        Err(_) => Ok(HttpResponse::NotFound().json("{}")),
+        Ok(Some(rec)) => Ok(Resp::<RecordView>::view(rec.clone()).with_etag(rec.updated_at).into()),
+        _ => Ok(HttpResponse::NotFound().json("{}")),
     }
 }
 
+/// Moves the record to the trash, from where it can be restored until it
+/// is purged
 pub async fn delete_by_id(
+    session: Session,
+    req: HttpRequest,
     rid: web::Path<Uuid>,
     data: web::Data<State>) -> actix_web::Result<HttpResponse> {
-    match Record::delete_by_id(&data.db.lock().unwrap(), *rid).await {
-        Ok(rec) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&rec)), //PgRow -> JSon?
-        Err(_) => Ok(HttpResponse::NotFound().finish()),
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return Ok(resp) };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return Ok(resp) }
+    Ok(status_changed(Record::soft_delete(&db, rec.id).await, "Record is already in the trash"))
+}
+
+pub async fn archive_by_id(
+    session: Session,
+    req: HttpRequest,
+    rid: web::Path<Uuid>,
+    data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return resp }
+    status_changed(Record::set_archived(&db, rec.id, true).await,
+        "Record is already archived or in the trash")
+}
+
+pub async fn unarchive_by_id(
+    session: Session,
+    req: HttpRequest,
+    rid: web::Path<Uuid>,
+    data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return resp }
+    status_changed(Record::set_archived(&db, rec.id, false).await, "Record is not archived")
+}
+
+pub async fn restore_by_id(
+    session: Session,
+    req: HttpRequest,
+    rid: web::Path<Uuid>,
+    data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return resp }
+    status_changed(Record::restore(&db, rec.id).await, "Record is not in the trash")
+}
+
+/// The session user's records, leaving out archived ones and the trash,
+/// narrowed by `?tags=`
+pub async fn get_own(session: Session, query: web::Query<TagFilter>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let recs = match Record::get_all_by_user(&db, user.id).await {
+        Ok(recs) => recs,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match filter_tagged(&db, TagOwner::Records, &query, recs, |r| r.id).await {
+        Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
+        Err(resp) => resp,
+    }
+}
+
+pub async fn get_archived(session: Session, query: web::Query<TagFilter>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let recs = match Record::get_by_status(&db, user.id, Status::Archived).await {
+        Ok(recs) => recs,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match filter_tagged(&db, TagOwner::Records, &query, recs, |r| r.id).await {
+        Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
+        Err(resp) => resp,
+    }
+}
+
+/// `None` from a status change means the record wasn't in a state it could
+/// move from
+fn status_changed(res: div_db::sqlx::Result<Option<Record>>, conflict: &str) -> HttpResponse {
+    match res {
+        Ok(Some(rec)) => Resp::<RecordView>::view(rec.clone()).with_etag(rec.updated_at).into(),
+        Ok(None) => HttpResponse::Conflict().body(conflict.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Checks that `rec` belongs to the session user and that the client saw
+/// its current version
+fn check_owned(session: &Session, req: &HttpRequest, rec: &Record) -> Result<(), HttpResponse> {
+    let user = validate(session)?;
+    if rec.uid != user.id {
+        return Err(HttpResponse::Forbidden().body("Not your record"));
+    }
+    if_match(req, rec.updated_at)
+}
+
+/// Writes `update` over `current`, failing with 412 if the row changed in
+/// the meantime
+async fn save_record(db: &Db, current: Record, update: UpdateRecord) -> HttpResponse {
+    let name = update.name.clone();
+    let (id, since) = (current.id, current.updated_at);
+    match Record::update_by_id(db, id, update.apply(current), since).await {
+        Ok(Some(rec)) => Resp::<RecordView>::view(rec.clone()).with_etag(rec.updated_at).into(),
+        Ok(None) => stale(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
+pub(crate) async fn load_record(db: &Db, rid: Uuid) -> Result<Record, HttpResponse> {
+    match Record::get_by_id(db, rid).await {
+        Ok(Some(rec)) => Ok(rec),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Replaces every editable field of the record
 pub async fn update_by_id(
+    session: Session,
+    req: HttpRequest,
+    rid: web::Path<Uuid>,
+    data: web::Data<State>,
+    record: actix_web_validator::Json<UpdateRecord>,) -> actix_web::Result<HttpResponse> {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return Ok(resp) };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return Ok(resp) }
+    Ok(save_record(&db, rec, record.into_inner()).await)
+}
+
+/// Applies a JSON merge patch to the record's editable fields
+pub async fn patch_by_id(
+    session: Session,
+    req: HttpRequest,
     rid: web::Path<Uuid>,
     data: web::Data<State>,
-    record: web::Json<Record>,) -> actix_web::Result<HttpResponse> {
-    let rec: Record = record.into_inner();
-    match Record::update_by_id(&data.db.lock().unwrap(), *rid, rec).await {
-        Ok(rec) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&rec)), //PgRow -> JSon?
-        Err(_) => Ok(HttpResponse::NotFound().finish()),
+    body: web::Json<serde_json::Value>,) -> actix_web::Result<HttpResponse> {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return Ok(resp) };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return Ok(resp) }
+    match patched(UpdateRecord::from(rec.clone()), body.into_inner()) {
+        Ok(update) => Ok(save_record(&db, rec, update).await),
+        Err(e) => Ok(e.error_response()),
     }
 }
 
@@ -67,7 +215,8 @@ pub fn user_record_routes() -> Scope {
                 .service(
                     resource("/{name}")
                         .route(get().to(get_user_record_by_name))
-                        .route(post().to(add_user_record_by_name)),
+                        .route(post().to(add_user_record_by_name))
+                        .route(patch().to(update_user_record)),
                 ),
         )
         // ------------ /user/{uid}/{rid} -------- ///
@@ -103,12 +252,19 @@ pub fn user_record_routes() -> Scope {
         )
 }
 
-pub async fn get_user_records(data: web::Data<State>, uid: web::Path<Uuid>) -> HttpResponse {
-    match User::get_by_id(&data.db.lock().unwrap(), *uid).await {
+pub async fn get_user_records(
+    data: web::Data<State>, uid: web::Path<Uuid>, query: web::Query<TagFilter>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match User::get_by_id(&db, *uid).await {
         Ok(Some(user)) => {
-            match User::get_all_records(&data.db.lock().unwrap(), user.id).await {
-                Ok(recs) => HttpResponse::Ok().json(recs),
-                Err(_) => HttpResponse::NotFound().body(""),
+            let recs = match User::get_all_records(&db, user.id).await {
+                Ok(recs) => recs,
+                Err(_) => return HttpResponse::NotFound().body(""),
+            };
+            match filter_tagged(&db, TagOwner::Records, &query, recs, |r| r.id).await {
+                Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
+                Err(resp) => resp,
             }
         }
         _ => HttpResponse::NotFound().body(""),
@@ -118,27 +274,49 @@ pub async fn get_user_records(data: web::Data<State>, uid: web::Path<Uuid>) -> H
 pub async fn add_new_record_to_user_auth(id: web::Path<Uuid>, user: Session) {}
 
 
+/// The owner is always the session user, who must also be the `{uid}` in the path
 pub async fn create_user_record(
-    id: Session,
+    session: Session,
     path: web::Path<Uuid>,
     data: web::Data<State>,
-    record: web::Json<Record>,
-) -> HttpResponse //Should be RecordIn
+    record: actix_web_validator::Json<CreateRecord>,
+) -> HttpResponse
 {
-    match record.into_inner().insert(&data.db.lock().unwrap()).await {
-        Ok(rec) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&rec), //PgRow -> JSon?
-        Err(_) => HttpResponse::NotFound().finish(),
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    if user.id != path.into_inner() {
+        return HttpResponse::Forbidden().body("Can only create your own records");
+    }
+    let record = record.into_inner().into_record(user.id);
+    let name = record.name.clone();
+    match record.insert(&data.db.lock().unwrap()).await {
+        Ok(rec) => Resp::<RecordView>::created(rec.into()).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 
-/// TODO implement
-pub async fn update_user_record(path: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
-    match User::get_all_records(&data.db.lock().unwrap(), *path).await {
-        Ok(recs) => HttpResponse::Ok().json(&recs), //PgRow -> JSon?
-        Err(_) => HttpResponse::NotFound().json("{}"),
+/// Merge-patches one of the session user's records, found by name
+pub async fn update_user_record(
+    session: Session,
+    req: HttpRequest,
+    path: web::Path<(Uuid, String)>,
+    data: web::Data<State>,
+    body: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    let (uid, name) = path.into_inner();
+    if let Err(resp) = validate(&session) { return resp }
+    let db = data.db.lock().unwrap();
+    let rec = match Record::get_by_name(&db, uid, name).await {
+        Ok(Some(rec)) => rec,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if let Err(resp) = check_owned(&session, &req, &rec) { return resp }
+    match patched(UpdateRecord::from(rec.clone()), body.into_inner()) {
+        Ok(update) => save_record(&db, rec, update).await,
+        Err(e) => e.error_response(),
     }
 }
 
@@ -163,9 +341,7 @@ pub async fn add_existing_item_to_record(
 
 pub async fn get_records_linked_with(path: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
     match User::get_linked_records(&data.db.lock().unwrap(), *path).await {
-        Ok(recs) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&recs), //PgRow -> JSon?
+        Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
         Err(_) => HttpResponse::NotFound().finish(),
     }
 }
@@ -175,7 +351,7 @@ pub async fn get_records_with_relation(
     data: web::Data<State>,
 ) -> HttpResponse {
     match User::get_linked_records(&data.db.lock().unwrap(), *path).await {
-        Ok(recs) => HttpResponse::Ok().json(&recs), //PgRow -> JSon?
+        Ok(recs) => Resp::<Vec<RecordView>>::views(recs).into(),
         Err(_) => HttpResponse::NotFound().json("{}"),
     }
 }
@@ -187,23 +363,23 @@ pub async fn get_user_record_by_name(
 ) -> HttpResponse {
     let (uid, rec_name) = path.into_inner();
     match User::get_named_record(&data.db.lock().unwrap(), uid, rec_name).await {
-        Ok(rec) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&rec),
+        Ok(rec) => Resp::<RecordView>::view(rec).into(),
         Err(_) => HttpResponse::NotFound().json("{}"),
     }
 }
 
 pub async fn add_user_record_by_name(
-    id: Session,
+    session: Session,
     path: web::Path<(Uuid, String)>,
     data: web::Data<State>,
 ) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
     let (uid, rec_name) = path.into_inner();
-    match User::add_new_record(&data.db.lock().unwrap(), uid, rec_name).await {
-        Ok(rec) => HttpResponse::Created()
-            .content_type("application/json")
-            .json(&rec),
+    if user.id != uid {
+        return HttpResponse::Forbidden().body("Can only create your own records");
+    }
+    match User::add_new_record(&data.db.lock().unwrap(), user.id, rec_name).await {
+        Ok(rec) => Resp::<RecordView>::created(rec.into()).into(),
         Err(_) => HttpResponse::InternalServerError().body(""),
     }
 }
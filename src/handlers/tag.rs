@@ -0,0 +1,187 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::auth::validate,
+    models::{TagLabel, TagPrefix, TagFilter, TagView, Resp},
+};
+use actix_web::{
+    web::{self, delete, get, patch, post, resource, scope},
+    HttpResponse, ResponseError, Scope,
+};
+use div_db::{
+    Db, InvalidField,
+    models::{Tag, TagExpr, TagOwner, tag::parse_label},
+};
+
+/// Most suggestions returned by the autocomplete endpoint
+const MAX_SUGGESTIONS: i64 = 50;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_tags))
+        .route("/autocomplete", get().to(autocomplete))
+        .service(resource("/{entity}/{id}")
+            .route(get().to(get_row_tags))
+            .route(post().to(add_row_tag)))
+        .route("/{entity}/{id}/{tag}", delete().to(remove_row_tag))
+        .service(resource("/{tag}")
+            .route(patch().to(rename_tag))
+            .route(delete().to(delete_tag)))
+}
+
+fn known_owner(name: &str) -> Result<TagOwner, HttpResponse> {
+    TagOwner::parse(name)
+        .ok_or_else(|| HttpResponse::NotFound().body("Tags go on records, items, fact_types or groups"))
+}
+
+fn label_of(field: &str, label: &str) -> Result<(String, String), HttpResponse> {
+    parse_label(label).ok_or_else(|| UserError::ValidationError {
+        errors: vec![InvalidField::new(field, "must be a tag like name or namespace:name")],
+    }.error_response())
+}
+
+/// The session user's row, answering 404 for rows that don't exist or
+/// belong to someone else
+async fn owned_row(db: &Db, session: &Session, entity: &str, id: Uuid) -> Result<(Uuid, TagOwner), HttpResponse> {
+    let user = validate(session)?;
+    let owner = known_owner(entity)?;
+    match owner.row_owner(db, id).await {
+        Ok(Some(uid)) if uid == user.id => Ok((uid, owner)),
+        Ok(_) => Err(HttpResponse::NotFound().body("No such row")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+async fn own_tag(db: &Db, uid: Uuid, label: &str) -> Result<Tag, HttpResponse> {
+    let (namespace, name) = parse_label(label).ok_or_else(|| HttpResponse::NotFound().body("No such tag"))?;
+    match Tag::get(db, uid, &namespace, &name).await {
+        Ok(Some(tag)) => Ok(tag),
+        Ok(None) => Err(HttpResponse::NotFound().body("No such tag")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Keeps the rows matching the `?tags=` expression, or all of them when
+/// there is none. A malformed expression is a 422.
+pub(crate) async fn filter_tagged<T>(
+    db: &Db, owner: TagOwner, filter: &TagFilter, rows: Vec<T>, id: impl Fn(&T) -> Uuid,
+) -> Result<Vec<T>, HttpResponse> {
+    let expr = match filter.tags.as_deref().map(str::trim) {
+        None | Some("") => return Ok(rows),
+        Some(tags) => TagExpr::parse(tags).map_err(|e| UserError::ValidationError {
+            errors: vec![InvalidField::new("tags", e)],
+        }.error_response())?,
+    };
+    let ids = rows.iter().map(&id).collect::<Vec<_>>();
+    let matching = Tag::matching(db, owner, &expr, &ids).await
+        .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+    Ok(rows.into_iter().filter(|row| matching.contains(&id(row))).collect())
+}
+
+/// The session user's tags with how many rows carry each
+pub async fn get_tags(session: Session, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match Tag::counts(&db, uid).await {
+        Ok(tags) => Resp::<Vec<TagView>>::views(tags).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Tags starting with `prefix`, most used first
+pub async fn autocomplete(
+    session: Session,
+    query: web::Query<TagPrefix>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let prefix = query.prefix.trim().to_lowercase();
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_SUGGESTIONS);
+    let db = data.db.lock().unwrap();
+    match Tag::autocomplete(&db, uid, &prefix, limit).await {
+        Ok(tags) => Resp::<Vec<TagView>>::views(tags).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_row_tags(
+    session: Session,
+    path: web::Path<(String, Uuid)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (_, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    match Tag::get_for(&db, owner, id).await {
+        Ok(tags) => Resp::<Vec<TagView>>::views(tags).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Tags the row, creating the tag the first time its label is used
+pub async fn add_row_tag(
+    session: Session,
+    path: web::Path<(String, Uuid)>,
+    body: web::Json<TagLabel>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id) = path.into_inner();
+    let (namespace, name) = match label_of("tag", &body.tag) { Ok(l) => l, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let (uid, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    let tag = match Tag::get_or_create(&db, uid, &namespace, &name).await {
+        Ok(tag) => tag,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match tag.attach(&db, owner, id).await {
+        Ok(_) => Resp::created(TagView::from(tag)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn remove_row_tag(
+    session: Session,
+    path: web::Path<(String, Uuid, String)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (entity, id, label) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let (uid, owner) = match owned_row(&db, &session, &entity, id).await { Ok(o) => o, Err(resp) => return resp };
+    let tag = match own_tag(&db, uid, &label).await { Ok(tag) => tag, Err(resp) => return resp };
+    match tag.detach(&db, owner, id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("The row doesn't have this tag"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Renames the tag everywhere it's used. Renaming to a label the user
+/// already has merges the two, and the answer is the merged tag.
+pub async fn rename_tag(
+    session: Session,
+    label: web::Path<String>,
+    body: web::Json<TagLabel>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let (namespace, name) = match label_of("tag", &body.tag) { Ok(l) => l, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let tag = match own_tag(&db, uid, &label).await { Ok(tag) => tag, Err(resp) => return resp };
+    match tag.rename(&db, &namespace, &name).await {
+        Ok(tag) => Resp::<TagView>::view(tag).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Deletes the tag and takes it off every row
+pub async fn delete_tag(session: Session, label: web::Path<String>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let tag = match own_tag(&db, uid, &label).await { Ok(tag) => tag, Err(resp) => return resp };
+    match tag.delete(&db).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
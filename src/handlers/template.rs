@@ -0,0 +1,189 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::{auth::validate, record::load_record},
+    models::{
+        CreateTemplate, NewTemplateVersion, InstantiateTemplate, MigrateRecords, MigratedRecords,
+        TemplatesQuery, TemplateView, RecordView, Resp,
+    },
+};
+use actix_web::{
+    web::{self, get, post, scope},
+    HttpResponse, ResponseError, Scope,
+};
+use div_db::{Db, Visibility, models::{RecordTemplate, TemplateDefinition}};
+
+/// Most public templates returned in one page
+const MAX_TEMPLATES_PAGE: i64 = 100;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_templates))
+        .route("", post().to(create_template))
+        .route("/public", get().to(get_public_templates))
+        .route("/{id}", get().to(get_template))
+        .route("/{id}/versions", get().to(get_versions))
+        .route("/{id}/versions", post().to(add_version))
+        .route("/{id}/instantiate", post().to(instantiate))
+        .route("/{id}/migrate", post().to(migrate))
+}
+
+fn check_definition(def: &TemplateDefinition) -> Result<(), HttpResponse> {
+    let errors = def.check();
+    if errors.is_empty() { Ok(()) } else { Err(UserError::ValidationError { errors }.error_response()) }
+}
+
+async fn load_template(db: &Db, id: Uuid) -> Result<RecordTemplate, HttpResponse> {
+    match RecordTemplate::get_by_id(db, id).await {
+        Ok(Some(t)) => Ok(t),
+        Ok(None) => Err(HttpResponse::NotFound().body("No such template")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// A template version the session user may use: their own, or a public one
+async fn usable_template(db: &Db, session: &Session, id: Uuid) -> Result<(Uuid, RecordTemplate), HttpResponse> {
+    let user = validate(session)?;
+    let template = load_template(db, id).await?;
+    if template.uid != user.id && template.visibility != Visibility::Public {
+        return Err(HttpResponse::NotFound().body("No such template"));
+    }
+    Ok((user.id, template))
+}
+
+/// The newest version of each of the session user's templates
+pub async fn get_templates(session: Session, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match RecordTemplate::get_all_by_user(&db, uid).await {
+        Ok(templates) => Resp::<Vec<TemplateView>>::views(templates).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_public_templates(query: web::Query<TemplatesQuery>, data: web::Data<State>) -> HttpResponse {
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_TEMPLATES_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let db = data.db.lock().unwrap();
+    match RecordTemplate::get_public(&db, limit, offset).await {
+        Ok(templates) => Resp::<Vec<TemplateView>>::views(templates).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn create_template(
+    session: Session,
+    body: actix_web_validator::Json<CreateTemplate>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    if let Err(resp) = check_definition(&body.definition) { return resp }
+    let name = body.name.clone();
+    let db = data.db.lock().unwrap();
+    match body.into_inner().into_template(uid).insert(&db).await {
+        Ok(template) => Resp::created(TemplateView::from(template)).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_template(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let template = match load_template(&db, *id).await { Ok(t) => t, Err(resp) => return resp };
+    let is_owner = validate(&session).map(|user| user.id == template.uid).unwrap_or(false);
+    if !is_owner && template.visibility != Visibility::Public {
+        return HttpResponse::NotFound().body("No such template");
+    }
+    Resp::<TemplateView>::view(template).into()
+}
+
+/// Every version of the template, newest first. Others see the versions
+/// of public templates only.
+pub async fn get_versions(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let (uid, template) = match usable_template(&db, &session, *id).await { Ok(t) => t, Err(resp) => return resp };
+    match template.versions(&db).await {
+        Ok(versions) => Resp::<Vec<TemplateView>>::views(versions.into_iter()
+            .filter(|v| v.uid == uid || v.visibility == Visibility::Public)
+            .collect()).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Saves the next version of the template, starting from the version in
+/// the path
+pub async fn add_version(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: web::Json<NewTemplateVersion>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let base = match load_template(&db, *id).await { Ok(t) => t, Err(resp) => return resp };
+    if base.uid != uid {
+        return HttpResponse::Forbidden().body("Not your template");
+    }
+    let (description, definition, visibility) = body.into_inner().apply(&base);
+    if let Err(resp) = check_definition(&definition) { return resp }
+    match base.new_version(&db, description, definition, visibility).await {
+        Ok(template) => Resp::created(TemplateView::from(template)).into(),
+        Err(e) if is_unique_violation(&e) => HttpResponse::Conflict()
+            .body("Another version was saved at the same time, try again"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Creates a record of the session user from the template version
+pub async fn instantiate(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: actix_web_validator::Json<InstantiateTemplate>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let (uid, template) = match usable_template(&db, &session, *id).await { Ok(t) => t, Err(resp) => return resp };
+    let name = body.into_inner().name;
+    match template.instantiate(&db, uid, name.clone()).await {
+        Ok(rec) => Resp::created(RecordView::from(rec)).into(),
+        Err(e) if is_unique_violation(&e) => UserError::AlreadyExists {
+            field: "name".into(), val: name }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The template version a record was made from or last migrated to, for
+/// anyone who can see the record
+pub async fn record_template(session: Session, rid: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let rec = match load_record(&db, *rid).await { Ok(rec) => rec, Err(resp) => return resp };
+    let is_owner = validate(&session).map(|user| user.id == rec.uid).unwrap_or(false);
+    if !is_owner && rec.visibility != Visibility::Public {
+        return HttpResponse::NotFound().finish();
+    }
+    match RecordTemplate::for_record(&db, rec.id).await {
+        Ok(Some(template)) => Resp::<TemplateView>::view(template).into(),
+        Ok(None) => HttpResponse::NotFound().body("The record wasn't made from a template"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Moves the session user's records made from older versions of the
+/// template to the version in the path
+pub async fn migrate(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: Option<web::Json<MigrateRecords>>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let (uid, template) = match usable_template(&db, &session, *id).await { Ok(t) => t, Err(resp) => return resp };
+    let only = body.map(|b| b.into_inner()).unwrap_or_default().records;
+    match template.migrate(&db, uid, only.as_deref()).await {
+        Ok(records) => Resp::ok(MigratedRecords { template: template.id, version: template.version, records }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
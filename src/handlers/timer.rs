@@ -0,0 +1,270 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::auth::validate,
+    models::{
+        StartTimer, EditTimer, TimersQuery, TotalsQuery, TimerView, StoppedTimer, TimerTotalsView,
+        FactEntryView, Resp,
+    },
+};
+use actix_web::{
+    web::{self, delete, get, patch, post, resource, scope},
+    HttpResponse, ResponseError, Scope,
+};
+use div_db::{
+    Db, InvalidField,
+    models::{
+        Timer, TimerScope, TotalPeriod, FactType, Record, Item,
+        fact::kind::ValueType, timer::default_window,
+    },
+};
+
+/// Most timers returned in one page
+const MAX_TIMERS_PAGE: i64 = 200;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_timers))
+        .route("", post().to(start_timer))
+        .route("/totals", get().to(get_totals))
+        .service(resource("/{id}")
+            .route(get().to(get_timer))
+            .route(patch().to(edit_timer))
+            .route(delete().to(delete_timer)))
+        .route("/{id}/pause", post().to(pause_timer))
+        .route("/{id}/resume", post().to(resume_timer))
+        .route("/{id}/stop", post().to(stop_timer))
+}
+
+fn invalid(field: &str, reason: &str) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
+
+/// 409 naming the timers of the same fact type that ran at the same time
+fn overlap(timers: Vec<Uuid>) -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Another timer of this fact type ran at the same time",
+        "timers": timers,
+    }))
+}
+
+async fn own_timer(db: &Db, session: &Session, id: Uuid) -> Result<Timer, HttpResponse> {
+    let user = validate(session)?;
+    match Timer::get_by_id(db, id).await {
+        Ok(Some(timer)) if timer.uid == user.id => Ok(timer),
+        Ok(_) => Err(HttpResponse::NotFound().body("No such timer")),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+async fn timer_view(db: &Db, timer: Timer) -> HttpResponse {
+    match timer.spans(db).await {
+        Ok(spans) => Resp::ok(TimerView::new(timer, spans)).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Checks that the timer's fact type, record and item are the user's, and
+/// that the type is a duration
+async fn check_start(db: &Db, uid: Uuid, body: &StartTimer) -> Result<(), HttpResponse> {
+    let err = |e: div_db::Error| HttpResponse::InternalServerError().body(e.to_string());
+    match FactType::get_by_id(db, body.tid).await.map_err(err)? {
+        Some(kind) if kind.uid == uid && kind.value_type == ValueType::Duration => {},
+        Some(kind) if kind.uid == uid => return Err(invalid("tid", "must be a duration fact type")),
+        _ => return Err(invalid("tid", "must be a fact type of yours")),
+    }
+    if let Some(rid) = body.record {
+        if !matches!(Record::get_by_id(db, rid).await.map_err(err)?, Some(rec) if rec.uid == uid) {
+            return Err(invalid("record", "must be a record of yours"));
+        }
+    }
+    if let Some(iid) = body.item {
+        if !matches!(Item::get_by_id(db, iid).await.map_err(err)?, Some(item) if item.uid == uid) {
+            return Err(invalid("item", "must be an item of yours"));
+        }
+    }
+    Ok(())
+}
+
+/// The session user's timers, newest first. Stopped ones only with
+/// `?stopped=true`.
+pub async fn get_timers(session: Session, query: web::Query<TimersQuery>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_TIMERS_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let db = data.db.lock().unwrap();
+    let timers = match Timer::get_all_by_user(&db, uid, query.stopped, limit, offset).await {
+        Ok(timers) => timers,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let mut views = Vec::with_capacity(timers.len());
+    for timer in timers {
+        match timer.spans(&db).await {
+            Ok(spans) => views.push(TimerView::new(timer, spans)),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        }
+    }
+    Resp::ok(views).into()
+}
+
+/// Starts a timer. Only one timer of a fact type may run at a time.
+pub async fn start_timer(
+    session: Session,
+    body: actix_web_validator::Json<StartTimer>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    if let Err(resp) = check_start(&db, uid, &body).await { return resp }
+    let body = body.into_inner();
+    let timer = Timer::new(uid, body.tid, body.record, body.item, body.note);
+    match timer.overlapping(&db, chrono::Utc::now(), None).await {
+        Ok(others) if !others.is_empty() => return overlap(others),
+        Ok(_) => {},
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match timer.start(&db).await {
+        Ok(span) => Resp::created(TimerView::new(timer, vec![span])).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn get_timer(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match own_timer(&db, &session, *id).await {
+        Ok(timer) => timer_view(&db, timer).await,
+        Err(resp) => resp,
+    }
+}
+
+pub async fn pause_timer(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let timer = match own_timer(&db, &session, *id).await { Ok(timer) => timer, Err(resp) => return resp };
+    if timer.stopped_at.is_some() {
+        return HttpResponse::Conflict().body("The timer is stopped");
+    }
+    match timer.pause(&db).await {
+        Ok(true) => timer_view(&db, timer).await,
+        Ok(false) => HttpResponse::Conflict().body("The timer is already paused"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn resume_timer(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let timer = match own_timer(&db, &session, *id).await { Ok(timer) => timer, Err(resp) => return resp };
+    if timer.stopped_at.is_some() {
+        return HttpResponse::Conflict().body("The timer is stopped");
+    }
+    match timer.overlapping(&db, chrono::Utc::now(), None).await {
+        Ok(others) if !others.is_empty() => return overlap(others),
+        Ok(_) => {},
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match timer.resume(&db).await {
+        Ok(Some(_)) => timer_view(&db, timer).await,
+        Ok(None) => HttpResponse::Conflict().body("The timer is already running"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Stops the timer and logs the time it ran as a duration entry
+pub async fn stop_timer(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let timer = match own_timer(&db, &session, *id).await { Ok(timer) => timer, Err(resp) => return resp };
+    if timer.stopped_at.is_some() {
+        return HttpResponse::Conflict().body("The timer is already stopped");
+    }
+    let kind = match FactType::get_by_id(&db, timer.tid).await {
+        Ok(Some(kind)) => kind,
+        Ok(None) => return HttpResponse::NotFound().body("The timer's fact type is gone"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let entry = match timer.stop(&db, &kind).await {
+        Ok(entry) => entry,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let timer = match Timer::get_by_id(&db, timer.id).await {
+        Ok(Some(timer)) => timer,
+        Ok(None) => return HttpResponse::NotFound().body("No such timer"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match timer.spans(&db).await {
+        Ok(spans) => Resp::ok(StoppedTimer {
+            timer: TimerView::new(timer, spans),
+            entry: FactEntryView::from(entry),
+        }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Sets when the timer really started and ended, replacing its pauses. A
+/// stopped timer's entry is updated to match. Times that overlap another
+/// timer of the same fact type are refused.
+pub async fn edit_timer(
+    session: Session,
+    id: web::Path<Uuid>,
+    body: web::Json<EditTimer>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let timer = match own_timer(&db, &session, *id).await { Ok(timer) => timer, Err(resp) => return resp };
+    if let Err((field, reason)) = body.check(timer.stopped_at.is_some()) {
+        return invalid(field, reason);
+    }
+    match timer.overlapping(&db, body.started_at, body.ended_at).await {
+        Ok(others) if !others.is_empty() => return overlap(others),
+        Ok(_) => {},
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match timer.set_times(&db, body.started_at, body.ended_at).await {
+        Ok(()) => timer_view(&db, timer).await,
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Deletes the timer. An entry it logged when stopped is kept.
+pub async fn delete_timer(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match Timer::delete(&db, uid, *id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("No such timer"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Seconds timed per day or week, over all the session user's timers or
+/// those of `?record=` or `?item=`
+pub async fn get_totals(session: Session, query: web::Query<TotalsQuery>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let scope = match (query.record, query.item) {
+        (Some(_), Some(_)) => return invalid("record", "give either record or item, not both"),
+        (Some(rid), None) => TimerScope::Record(rid),
+        (None, Some(iid)) => TimerScope::Item(iid),
+        (None, None) => TimerScope::User,
+    };
+    let period = query.period.unwrap_or(TotalPeriod::Day);
+    let (from, to) = match period {
+        TotalPeriod::Day => default_window(period, 30),
+        TotalPeriod::Week => default_window(period, 12),
+    };
+    let (from, to) = (query.from.unwrap_or(from), query.to.unwrap_or(to));
+    if from >= to {
+        return invalid("from", "must be before to");
+    }
+    let db = data.db.lock().unwrap();
+    match Timer::totals(&db, uid, scope, period, from, to).await {
+        Ok(totals) => Resp::ok(TimerTotalsView {
+            period,
+            from,
+            to,
+            seconds: totals.iter().map(|t| t.seconds).sum(),
+            totals,
+        }).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
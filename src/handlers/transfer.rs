@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+use actix_session::Session;
+use futures::{stream, StreamExt};
+use uuid::Uuid;
+use div_db::{Db, Entity, BulkRow, InvalidField};
+use crate::{
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::auth::validate,
+    models::transfer::{self, Format, ImportQuery, ExportQuery, ImportReport, RowError},
+};
+use actix_web::{
+    web::{self, get, post, scope, Bytes},
+    HttpRequest, HttpResponse, ResponseError, Scope,
+};
+
+/// Largest upload accepted for an import, in bytes
+const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+/// Rows read from the database per chunk of an export
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+pub fn import_routes(base: &str) -> Scope {
+    scope(base)
+        .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+        .route("/{entity}", post().to(import))
+}
+
+pub fn export_routes(base: &str) -> Scope {
+    scope(base)
+        .route("/{entity}", get().to(export))
+}
+
+fn known_entity(path: web::Path<String>) -> Result<Entity, HttpResponse> {
+    Entity::parse(&path.into_inner())
+        .ok_or_else(|| HttpResponse::NotFound().body("Can import and export records, items, fact_types or fact_entries"))
+}
+
+fn invalid(field: InvalidField) -> HttpResponse {
+    UserError::ValidationError { errors: vec![field] }.error_response()
+}
+
+/// Imports rows for the session user. Nothing is stored unless every row is
+/// valid; with `dry_run` nothing is stored either way and the report says
+/// what would have happened.
+pub async fn import(
+    session: Session,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ImportQuery>,
+    body: Bytes,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let entity = match known_entity(path) { Ok(e) => e, Err(resp) => return resp };
+    let query = query.into_inner();
+    let format = match query.format
+        .or_else(|| req.headers().get("Content-Type")
+            .and_then(|ct| ct.to_str().ok())
+            .and_then(|ct| Format::from_content_type(ct.split(';').next().unwrap_or("").trim())))
+    {
+        Some(format) => format,
+        None => return HttpResponse::UnsupportedMediaType()
+            .body("Send CSV, JSON or NDJSON, or set ?format="),
+    };
+    let map = match query.map.as_deref().map(transfer::column_map).transpose() {
+        Ok(map) => map.unwrap_or_default(),
+        Err(field) => return invalid(field),
+    };
+    let parsed = match format.parse(entity, &body) {
+        Ok(rows) => rows,
+        Err(field) => return invalid(field),
+    };
+
+    let total = parsed.len();
+    let mut rows = Vec::with_capacity(total);
+    let mut errors = Vec::new();
+    for (i, row) in parsed.into_iter().enumerate() {
+        match row.map_err(|e| vec![e])
+            .and_then(|row| transfer::bulk_row(entity, user.id, transfer::remap(row, &map)))
+        {
+            Ok(row) => rows.push((i + 1, row)),
+            Err(errs) => errors.push(RowError { row: i + 1, errors: errs }),
+        }
+    }
+
+    let db = data.db.lock().unwrap();
+    match name_conflicts(&db, entity, user.id, &rows).await {
+        Ok(conflicts) => errors.extend(conflicts),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    errors.sort_by_key(|e| e.row);
+
+    let mut report = ImportReport { entity, rows: total, imported: 0, dry_run: query.dry_run, errors };
+    if !report.errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(report);
+    }
+    if report.dry_run {
+        return HttpResponse::Ok().json(report);
+    }
+    let rows = rows.into_iter().map(|(_, row)| row).collect::<Vec<BulkRow>>();
+    match db.import_rows(&rows).await {
+        Ok(imported) => {
+            report.imported = imported;
+            HttpResponse::Created().json(report)
+        },
+        Err(e) if is_unique_violation(&e) => HttpResponse::Conflict()
+            .body("A row with one of these names was added during the import, nothing was imported"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Where names are unique per user, a row clashes with an existing row or
+/// with an earlier row of the same upload
+async fn name_conflicts(db: &Db, entity: Entity, uid: Uuid, rows: &[(usize, BulkRow)])
+    -> div_db::sqlx::Result<Vec<RowError>>
+{
+    if !entity.unique_names() {
+        return Ok(Vec::new());
+    }
+    let names = rows.iter().map(|(_, row)| transfer::bulk_name(row).to_string()).collect::<Vec<_>>();
+    let taken = db.taken_names(entity, uid, &names).await?.into_iter().collect::<HashSet<_>>();
+    let mut seen = HashMap::new();
+    let mut errors = Vec::new();
+    for (n, row) in rows {
+        let name = transfer::bulk_name(row);
+        if taken.contains(name) {
+            errors.push(RowError { row: *n, errors: vec![InvalidField::new("name", "already exists")] });
+        } else if let Some(first) = seen.insert(name.to_string(), *n) {
+            errors.push(RowError { row: *n,
+                errors: vec![InvalidField::new("name", format!("repeats row {}", first))] });
+        }
+    }
+    Ok(errors)
+}
+
+/// Streams the session user's rows a page at a time, so large exports never
+/// sit in memory whole
+pub async fn export(
+    session: Session,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let entity = match known_entity(path) { Ok(e) => e, Err(resp) => return resp };
+    let ExportQuery { format, show } = query.into_inner();
+    let db = data.db.lock().unwrap().clone();
+
+    let pages = stream::unfold(Some((None, true)), move |cursor| {
+        let db = db.clone();
+        async move {
+            let (after, first) = cursor?;
+            let rows = match db.export_rows(entity, uid, show, after, EXPORT_PAGE_SIZE).await {
+                Ok(rows) => rows,
+                Err(e) => return Some((Err(actix_web::error::ErrorInternalServerError(e)), None)),
+            };
+            if rows.is_empty() {
+                return None;
+            }
+            let last = rows.last().map(BulkRow::id);
+            let next = if (rows.len() as i64) < EXPORT_PAGE_SIZE { None } else { Some((last, false)) };
+            let rows = rows.into_iter().map(transfer::view).collect::<Vec<_>>();
+            Some((Ok(Bytes::from(format.page(entity, &rows, first))), next))
+        }
+    });
+    let body = stream::once(async move { Ok::<_, actix_web::Error>(Bytes::from(format.head(entity))) })
+        .chain(pages)
+        .chain(stream::once(async move { Ok(Bytes::from_static(format.tail().as_bytes())) }));
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .set_header("Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", entity.table(), format.extension()))
+        .streaming(body)
+}
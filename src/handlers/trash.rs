@@ -0,0 +1,90 @@
+use actix_session::Session;
+use serde::{Serialize, Deserialize};
+use crate::{
+    state::State,
+    config::AppConfig,
+    handlers::{auth::validate, tag::filter_tagged},
+    models::{FactTypeView, GroupView, ItemView, RecordView, Resp, TagFilter},
+};
+use actix_web::{web::{self, get, post, scope}, HttpResponse, Scope};
+use div_db::{Status, models::{FactType, Group, Item, Record, TagOwner}};
+use uuid::Uuid;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_trash))
+        .route("/groups/{id}/restore", post().to(restore_group))
+}
+
+/// Everything the user has deleted but that hasn't been purged yet.
+/// Entries are restored through `POST /api/{record,item}/{id}/restore`,
+/// `POST /api/fact/types/{id}/restore` and `POST /api/trash/groups/{id}/restore`.
+#[derive(Serialize, Deserialize)]
+pub struct Trash {
+    pub retention_days: i64,
+    pub records: Vec<RecordView>,
+    pub items: Vec<ItemView>,
+    pub fact_types: Vec<FactTypeView>,
+    pub groups: Vec<GroupView>,
+}
+
+pub async fn get_trash(session: Session, query: web::Query<TagFilter>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let records = match Record::get_by_status(&db, user.id, Status::Deleted).await {
+        Ok(recs) => recs,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let items = match Item::get_by_status(&db, user.id, Status::Deleted).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let records = match filter_tagged(&db, TagOwner::Records, &query, records, |r| r.id).await {
+        Ok(recs) => recs,
+        Err(resp) => return resp,
+    };
+    let items = match filter_tagged(&db, TagOwner::Items, &query, items, |i| i.id).await {
+        Ok(items) => items,
+        Err(resp) => return resp,
+    };
+    let kinds = match FactType::get_by_status(&db, user.id, Status::Deleted).await {
+        Ok(kinds) => kinds,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let groups = match Group::get_by_status(&db, user.id, Status::Deleted).await {
+        Ok(groups) => groups,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let kinds = match filter_tagged(&db, TagOwner::FactTypes, &query, kinds, |t| t.id).await {
+        Ok(kinds) => kinds,
+        Err(resp) => return resp,
+    };
+    let groups = match filter_tagged(&db, TagOwner::Groups, &query, groups, |g| g.id).await {
+        Ok(groups) => groups,
+        Err(resp) => return resp,
+    };
+    HttpResponse::Ok().json(Trash {
+        retention_days: AppConfig::trash_retention_days(),
+        records: records.into_iter().map(RecordView::from).collect(),
+        items: items.into_iter().map(ItemView::from).collect(),
+        fact_types: kinds.into_iter().map(FactTypeView::from).collect(),
+        groups: groups.into_iter().map(GroupView::from).collect(),
+    })
+}
+
+/// Takes one of the user's groups back out of the trash
+pub async fn restore_group(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let user = match validate(&session) { Ok(user) => user, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match Group::get_by_id(&db, *id).await {
+        Ok(Some(group)) if group.uid == user.id => (),
+        Ok(Some(_)) => return HttpResponse::Forbidden().body("Not your group"),
+        Ok(None) => return HttpResponse::NotFound().body("No such group"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match Group::restore(&db, *id).await {
+        Ok(Some(group)) => Resp::<GroupView>::view(group).into(),
+        Ok(None) => HttpResponse::Conflict().body("Group is not in the trash"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,121 @@
+use actix_session::Session;
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::UserError,
+    handlers::auth::validate,
+    models::{CreateUnit, UnitQuery, UnitView, FactStatsView, FactEntryView, Resp},
+};
+use actix_web::{
+    web::{self, delete, get, post, scope},
+    HttpResponse, ResponseError, Scope,
+};
+use div_db::{Db, InvalidField, models::{FactType, Unit, UnitRegistry}};
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_units))
+        .route("", post().to(create_unit))
+        .route("/{id}", delete().to(delete_unit))
+}
+
+fn invalid(field: &str, reason: &str) -> HttpResponse {
+    UserError::ValidationError { errors: vec![InvalidField::new(field, reason)] }.error_response()
+}
+
+/// The built-in units and the user's own
+pub(crate) async fn unit_registry(db: &Db, uid: Option<Uuid>) -> Result<UnitRegistry, HttpResponse> {
+    UnitRegistry::load(db, uid).await
+        .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))
+}
+
+/// Checks an entry's unit against its fact type's units
+pub(crate) fn check_unit(units: &UnitRegistry, kind: &FactType, unit: Option<&str>) -> Result<(), HttpResponse> {
+    match unit {
+        Some(unit) => units.check(&kind.units, unit).map_err(|reason| invalid("units", &reason)),
+        None => Ok(()),
+    }
+}
+
+/// Converts entries to `?unit=`, if given, which must be a known unit
+pub(crate) fn convert_entries(
+    units: &UnitRegistry,
+    query: &UnitQuery,
+    entries: Vec<FactEntryView>,
+) -> Result<Vec<FactEntryView>, HttpResponse> {
+    let unit = match &query.unit { Some(unit) => unit, None => return Ok(entries) };
+    if units.find(unit).is_none() {
+        return Err(invalid("unit", "is not a known unit"));
+    }
+    Ok(entries.into_iter().map(|e| e.in_unit(units, unit)).collect())
+}
+
+pub async fn get_units(session: Session, data: web::Data<State>) -> HttpResponse {
+    let uid = validate(&session).map(|user| user.id).ok();
+    let db = data.db.lock().unwrap();
+    match Unit::get_all(&db, uid).await {
+        Ok(units) => Resp::<Vec<UnitView>>::views(units).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn create_unit(
+    session: Session,
+    body: actix_web_validator::Json<CreateUnit>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let units = match unit_registry(&db, Some(uid)).await { Ok(units) => units, Err(resp) => return resp };
+    if let Err((field, reason)) = body.check(&units) {
+        return invalid(field, &reason);
+    }
+    let symbol = body.symbol.clone();
+    match body.into_inner().into_unit(uid).insert(&db).await {
+        Ok(Some(unit)) => Resp::created(UnitView::from(unit)).into(),
+        Ok(None) => UserError::AlreadyExists { field: "symbol".into(), val: symbol }.error_response(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Deletes one of the session user's units. Built-in units can't be
+/// deleted.
+pub async fn delete_unit(session: Session, id: web::Path<Uuid>, data: web::Data<State>) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    match Unit::delete(&db, uid, *id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("No such unit of yours"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Count, sum and bounds of the session user's numeric entries of one of
+/// their fact types, per dimension, in `?unit=` or else the dimension's
+/// canonical unit
+pub async fn get_fact_stats(
+    session: Session,
+    id: web::Path<Uuid>,
+    query: web::Query<UnitQuery>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let uid = match validate(&session) { Ok(user) => user.id, Err(resp) => return resp };
+    let db = data.db.lock().unwrap();
+    let kind = match FactType::get_by_id(&db, *id).await {
+        Ok(Some(kind)) if kind.uid == uid => kind,
+        Ok(_) => return HttpResponse::NotFound().body("No such fact type of yours"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let units = match unit_registry(&db, Some(kind.uid)).await { Ok(units) => units, Err(resp) => return resp };
+    if let Some(unit) = &query.unit {
+        if units.find(unit).is_none() {
+            return invalid("unit", "is not a known unit");
+        }
+    }
+    match Unit::stats(&db, kind.uid, &kind.name).await {
+        Ok(stats) => Resp::ok(stats.into_iter()
+            .map(|s| FactStatsView::new(s, &units, query.unit.as_deref()))
+            .collect::<Vec<_>>()).into(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
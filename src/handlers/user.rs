@@ -1,14 +1,24 @@
+pub mod info;
+
 use actix_multipart::Multipart;
 use tokio::io::AsyncWriteExt;
 use futures::{StreamExt, TryStreamExt};
 use uuid::Uuid;
-use crate::state::State;
+use actix_session::Session;
+use serde::{Serialize, Deserialize};
+use crate::{
+    account,
+    state::State,
+    error::{UserError, is_unique_violation},
+    handlers::auth::{validate, verify},
+    models::{Resp, UserIn, UserView, UpdateUser, if_match, patched, stale},
+};
 use actix_web::{Scope,
     get, post, put, delete,
     web::{self, scope, ServiceConfig},
-    HttpResponse, HttpRequest,
+    HttpResponse, HttpRequest, ResponseError,
 };
-use div_db::models::User;
+use div_db::{Db, models::User};
 
 pub fn routes(base: &str) -> actix_web::Scope {
     scope(base)
@@ -26,6 +36,9 @@ pub fn by_username() -> actix_web::Scope {
         .route("", web::get().to(get_by_username))
         .route("", web::delete().to(delete_by_username))
         .route("", web::put().to(update_by_username))
+        .route("", web::patch().to(patch_by_username))
+        .route("/export", web::get().to(export_by_username))
+        .service(info::routes("/info"))
 }
 
 pub fn by_uid() -> actix_web::Scope {
@@ -33,6 +46,7 @@ pub fn by_uid() -> actix_web::Scope {
         .route("", web::get().to(get_by_id))
         .route("", web::delete().to(delete_by_id))
         .route("", web::put().to(update_by_id))
+        .route("", web::patch().to(patch_by_id))
 }
 
 pub async fn query_user(
@@ -47,9 +61,7 @@ pub async fn get_all(
     data: web::Data<State>,) -> actix_web::Result<HttpResponse>
 {
     match User::get_all(&data.db.lock().unwrap()).await {
-        Ok(users) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&users)),
+        Ok(users) => Ok(Resp::<Vec<UserView>>::views(users).into()),
         Err(_) => Ok(HttpResponse::NotFound().json(""))
     }
 }
@@ -60,35 +72,54 @@ pub async fn get_by_id(
 {
     let id: Uuid = Uuid::parse_str(id.into_inner().as_mut_str()).unwrap();
     match User::get_by_id(&data.db.lock().unwrap(), id).await {
-        Ok(Some(user)) => Ok(HttpResponse::Ok()
-                .content_type("application/json")
-                .json(&user)),
+        Ok(Some(user)) => Ok(Resp::<UserView>::view(user).into()),
         _ => Ok(HttpResponse::NotFound().json(""))
     }
 }
 
 pub async fn update_by_id(
+    session: Session,
+    req: HttpRequest,
     path: web::Path<Uuid>,
+    body: actix_web_validator::Json<UpdateUser>,
+    data: web::Data<State>) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    match own_account(&db, &session, &req, User::get_by_id(&db, *path).await).await {
+        Ok(user) => save_account(&db, &session, &data, user, body.into_inner()).await,
+        Err(resp) => resp,
+    }
+}
+
+pub async fn patch_by_id(
+    session: Session,
     req: HttpRequest,
-    data: web::Data<State>) -> actix_web::Result<HttpResponse>
+    path: web::Path<Uuid>,
+    body: web::Json<serde_json::Value>,
+    data: web::Data<State>) -> HttpResponse
 {
-    match User::delete_by_id(&data.db.lock().unwrap(), *path).await {
-        Ok(Some(id)) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .body(format!("Deleted user with id {}", id))),
-        _ => Ok(HttpResponse::NotFound().body("Could not delete"))
+    let db = data.db.lock().unwrap();
+    let user = match own_account(&db, &session, &req, User::get_by_id(&db, *path).await).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match patched(UpdateUser::from(user.clone()), body.into_inner()) {
+        Ok(update) => save_account(&db, &session, &data, user, update).await,
+        Err(e) => e.error_response(),
     }
 }
 
 pub async fn delete_by_id(
-    data: web::Data<State>,
-    id: web::Path<Uuid>) -> actix_web::Result<HttpResponse>
+    session: Session,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    body: web::Json<DeleteConfirm>,
+    data: web::Data<State>) -> HttpResponse
 {
-    match User::delete_by_id(&data.db.lock().unwrap(), *id).await {
-        Ok(Some(id)) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .body(format!("Deleted user {:?}", id))),
-        _ => Ok(HttpResponse::NotFound().json(""))
+    let db = data.db.lock().unwrap();
+    match own_account(&db, &session, &req, User::get_by_id(&db, *path).await).await {
+        Ok(user) => close_account(&db, &session, &data, user, &body.confirm).await,
+        Err(resp) => resp,
     }
 }
 
@@ -99,48 +130,157 @@ pub async fn get_by_username(
     let db = data.db.lock().unwrap();
     let u =  User::get_by_username(&db, username.into_inner());
     match u.await {
-        Ok(Some(user)) => Ok(HttpResponse::Ok()
-                .content_type("application/json")
-                .json(&user)),
+        Ok(Some(user)) => Ok(Resp::<UserView>::view(user).into()),
         Ok(None) => Ok(HttpResponse::NotFound().json("Sorry")),
         _ => Ok(HttpResponse::NotFound().json("ERRROR")),
     }
 }
 
 pub async fn delete_by_username(
-    id: actix_session::Session,
-    data: web::Data<State>,
-    username: web::Path<String>,) -> actix_web::Result<HttpResponse>
+    session: Session,
+    req: HttpRequest,
+    username: web::Path<String>,
+    body: web::Json<DeleteConfirm>,
+    data: web::Data<State>) -> HttpResponse
 {
     let db = data.db.lock().unwrap();
-    let u = User::delete_by_username(&db, username.to_string());
-    match u.await {
-        Ok(id) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .body(format!("Deleted user {}", id))),
-        Err(_) => Ok(HttpResponse::NotFound().json(""))
+    let found = User::get_by_username(&db, username.into_inner()).await;
+    match own_account(&db, &session, &req, found).await {
+        Ok(user) => close_account(&db, &session, &data, user, &body.confirm).await,
+        Err(resp) => resp,
+    }
+}
+
+/// Everything the user has stored, as a ZIP of JSON files and attachments
+pub async fn export_by_username(
+    session: Session,
+    req: HttpRequest,
+    username: web::Path<String>,
+    data: web::Data<State>) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    let found = User::get_by_username(&db, username.into_inner()).await;
+    let user = match own_account(&db, &session, &req, found).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match account::export_account(&db, &user).await {
+        Ok(zip) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .set_header("Content-Disposition",
+                format!("attachment; filename=\"{}-export.zip\"", user.username))
+            .body(zip),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 pub async fn update_by_username(
-    id: actix_session::Session,
-    data: web::Data<State>,
-    username: web::Path<String>) -> actix_web::Result<HttpResponse> {
-    match User::delete_by_username(&data.db.lock().unwrap(), username.to_string()).await {
-        Ok(id) => Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .body(format!("Deleted user {}", id))),
-        Err(_) => Ok(HttpResponse::NotFound().json(""))
+    session: Session,
+    req: HttpRequest,
+    username: web::Path<String>,
+    body: actix_web_validator::Json<UpdateUser>,
+    data: web::Data<State>) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    let found = User::get_by_username(&db, username.into_inner()).await;
+    match own_account(&db, &session, &req, found).await {
+        Ok(user) => save_account(&db, &session, &data, user, body.into_inner()).await,
+        Err(resp) => resp,
     }
 }
 
+pub async fn patch_by_username(
+    session: Session,
+    req: HttpRequest,
+    username: web::Path<String>,
+    body: web::Json<serde_json::Value>,
+    data: web::Data<State>) -> HttpResponse
+{
+    let db = data.db.lock().unwrap();
+    let found = User::get_by_username(&db, username.into_inner()).await;
+    let user = match own_account(&db, &session, &req, found).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match patched(UpdateUser::from(user.clone()), body.into_inner()) {
+        Ok(update) => save_account(&db, &session, &data, user, update).await,
+        Err(e) => e.error_response(),
+    }
+}
 
-pub async fn get_user_info(data: web::Data<State>, rid: web::Path<Uuid>) -> HttpResponse {
-    HttpResponse::Ok().body("delete_record")
+/// Users may only edit their own account, and only the version they last saw
+async fn own_account(
+    db: &Db, session: &Session, req: &HttpRequest, found: div_db::sqlx::Result<Option<User>>,
+) -> Result<User, HttpResponse> {
+    let sess_user = validate(session)?;
+    let user = match found {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(HttpResponse::NotFound().body("No user found")),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    if user.id != sess_user.id {
+        return Err(HttpResponse::Forbidden().body("Can only edit your own account"));
+    }
+    if_match(req, user.updated_at)?;
+    Ok(user)
 }
 
-pub async fn update_user_info(data: web::Data<State>, rid: web::Path<Uuid>) -> HttpResponse {
-    HttpResponse::Ok().body("delete_record")
+/// Deleting an account must repeat the username back
+#[derive(Serialize, Deserialize)]
+pub struct DeleteConfirm {
+    pub confirm: String,
+}
+
+/// Deletes the account and everything it owns, then ends the session
+async fn close_account(
+    db: &Db, session: &Session, data: &State, user: User, confirm: &str,
+) -> HttpResponse {
+    if confirm != user.username {
+        return HttpResponse::PreconditionFailed()
+            .body(format!("Set confirm to \"{}\" to delete this account", user.username));
+    }
+    match account::delete_account(db, &data.cognito, &user).await {
+        Ok(Some(deletion)) => {
+            session.purge();
+            HttpResponse::Ok().json(deletion)
+        },
+        Ok(None) => HttpResponse::NotFound().body("No user found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Saves the account, refreshes the session copy and, when the email
+/// changed, sends a new verification mail
+async fn save_account(
+    db: &Db, session: &Session, data: &State, current: User, update: UpdateUser,
+) -> HttpResponse {
+    let (id, since) = (current.id, current.updated_at);
+    let email_changed = update.email != current.email;
+    let (email, username) = (update.email.clone(), update.username.clone());
+    match update.apply(current).update(db, since).await {
+        Ok(Some(user)) => {
+            if session.set("uid", UserIn::from(user.clone())).is_err() {
+                return HttpResponse::InternalServerError().body("Could not refresh session");
+            }
+            if email_changed {
+                if let Err(e) = verify::send_verification(db, data.mailer.as_ref(), &user).await {
+                    return HttpResponse::InternalServerError().body(e.to_string());
+                }
+            }
+            Resp::<UserView>::view(user.clone()).with_etag(user.updated_at).into()
+        },
+        Ok(None) => stale(),
+        Err(e) if is_unique_violation(&e) => {
+            let taken_name = matches!(User::get_by_username(db, username.clone()).await,
+                Ok(Some(other)) if other.id != id);
+            if taken_name {
+                UserError::AlreadyExists { field: "username".into(), val: username }.error_response()
+            } else {
+                UserError::AlreadyExists { field: "email".into(), val: email }.error_response()
+            }
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
 
 pub async fn get_user_feed(data: web::Data<State>, rid: web::Path<Uuid>) -> HttpResponse {
@@ -155,25 +295,6 @@ pub async fn new_uid_fact(data: web::Data<State>, rid: web::Path<Uuid>) -> HttpR
     HttpResponse::Ok().body("delete_record")
 }
 
-async fn upload_profile_picture(mut payload: Multipart) -> Result<HttpResponse, actix_web::Error> {
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_type = field
-            .content_disposition()
-            .ok_or_else(|| actix_web::error::ParseError::Incomplete)?;
-        let filename = content_type
-            .get_filename()
-            .ok_or_else(|| actix_web::error::ParseError::Incomplete)?;
-        let filepath = format!("./tmp/{}", &filename);
-        let mut f = tokio::fs::File::create(&filepath).await?;
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            f.write_all(&data).await?;
-        }
-    }
-    Ok(HttpResponse::Ok().into())
-}
-
-
 pub struct UserQuery {
     id: Option<Uuid>,
     u: Option<String>,
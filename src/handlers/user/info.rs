@@ -0,0 +1,251 @@
+use std::path::{Path, PathBuf};
+use actix_multipart::{Field, Multipart};
+use tokio::io::AsyncWriteExt;
+use actix_session::Session;
+use futures::{StreamExt, TryStreamExt};
+use serde::{Serialize, Deserialize};
+use div_db::{Db, Json, models::{User, UserInfo, userinfo::{UserInfoPatch, SocialProvider}}};
+use crate::{state::State, config::AppConfig, error::UserError, handlers::auth::validate};
+use actix_web::{
+    web::{self, delete, get, patch, post, put, scope},
+    HttpResponse, ResponseError, Scope,
+};
+
+/// Largest avatar accepted, in bytes
+const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+
+pub fn routes(base: &str) -> Scope {
+    scope(base)
+        .route("", get().to(get_user_info))
+        .route("", patch().to(update_user_info))
+        .route("/avatar", get().to(get_avatar))
+        .route("/avatar", post().to(upload_avatar))
+        .route("/links/{provider}", put().to(set_social_link))
+        .route("/links/{provider}", delete().to(remove_social_link))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SocialLinkIn {
+    pub url: String,
+}
+
+/// Loads the profile of `username`, creating it if it is missing, and
+/// whether the session user is its owner
+async fn load_profile(db: &Db, session: &Session, username: String)
+    -> Result<(UserInfo, bool), HttpResponse>
+{
+    let sess_user = validate(session)?;
+    let user = match User::get_by_username(db, username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(HttpResponse::NotFound().body("No user found")),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let info = match UserInfo::get_by_user(db, user.id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => UserInfo::insert_empty(db, user.id).await
+            .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    Ok((info, sess_user.id == user.id))
+}
+
+async fn owned_profile(db: &Db, session: &Session, username: String) -> Result<UserInfo, HttpResponse> {
+    match load_profile(db, session, username).await? {
+        (info, true) => Ok(info),
+        (_, false) => Err(HttpResponse::Forbidden().body("Can only edit your own profile")),
+    }
+}
+
+/// Other users see the profile without contact details or birth date
+pub async fn get_user_info(
+    session: Session,
+    path: web::Path<String>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    match load_profile(&db, &session, path.into_inner()).await {
+        Ok((info, true)) => HttpResponse::Ok().json(info),
+        Ok((info, false)) => HttpResponse::Ok().json(UserInfo {
+            phone_number: None, birth_date: None, ..info
+        }),
+        Err(resp) => resp,
+    }
+}
+
+pub async fn update_user_info(
+    session: Session,
+    path: web::Path<String>,
+    body: web::Json<UserInfoPatch>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let info = match owned_profile(&db, &session, path.into_inner()).await {
+        Ok(info) => info,
+        Err(resp) => return resp,
+    };
+    let info = match info.apply(body.into_inner()) {
+        Ok(info) => info,
+        Err(errors) => return UserError::ValidationError { errors }.error_response(),
+    };
+    match info.update(&db).await {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn set_social_link(
+    session: Session,
+    path: web::Path<(String, SocialProvider)>,
+    body: web::Json<SocialLinkIn>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (username, provider) = path.into_inner();
+    let link = body.into_inner().url;
+    match url::Url::parse(&link) {
+        Ok(u) if u.scheme() == "https" || u.scheme() == "http" => (),
+        _ => return HttpResponse::UnprocessableEntity().body("Link must be an http(s) URL"),
+    }
+    let db = data.db.lock().unwrap();
+    let mut info = match owned_profile(&db, &session, username).await {
+        Ok(info) => info,
+        Err(resp) => return resp,
+    };
+    let mut links = info.links();
+    links.set(provider, link);
+    info.social_links = Some(Json(links));
+    match info.update(&db).await {
+        Ok(info) => HttpResponse::Ok().json(info.links()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn remove_social_link(
+    session: Session,
+    path: web::Path<(String, SocialProvider)>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let (username, provider) = path.into_inner();
+    let db = data.db.lock().unwrap();
+    let mut info = match owned_profile(&db, &session, username).await {
+        Ok(info) => info,
+        Err(resp) => return resp,
+    };
+    let mut links = info.links();
+    if links.remove(provider).is_none() {
+        return HttpResponse::NotFound().body("No link for that provider");
+    }
+    info.social_links = Some(Json(links));
+    match info.update(&db).await {
+        Ok(info) => HttpResponse::Ok().json(info.links()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+fn avatar_extension(mime: &str) -> Option<&'static str> {
+    match mime {
+        "image/png" => Some("png"),
+        "image/jpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
+
+fn avatar_mime(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Stores the first file of a multipart upload as the user's avatar and
+/// points `img_path` at it, relative to the upload directory. The upload is
+/// only read once the session user is known to own the profile, and is
+/// streamed to a temporary file that replaces the avatar once complete.
+pub async fn upload_avatar(
+    session: Session,
+    path: web::Path<String>,
+    mut payload: Multipart,
+    data: web::Data<State>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut info = {
+        let db = data.db.lock().unwrap();
+        match owned_profile(&db, &session, path.into_inner()).await {
+            Ok(info) => info,
+            Err(resp) => return Ok(resp),
+        }
+    };
+    let mut field = match payload.try_next().await? {
+        Some(field) => field,
+        None => return Ok(HttpResponse::BadRequest().body("No file uploaded")),
+    };
+    let ext = match avatar_extension(field.content_type().essence_str()) {
+        Some(ext) => ext,
+        None => return Ok(HttpResponse::UnsupportedMediaType()
+            .body("Avatar must be a PNG, JPEG, GIF or WebP image")),
+    };
+    let key = format!("avatars/{}.{}", info.uid, ext);
+    let dir = PathBuf::from(AppConfig::upload_dir());
+    tokio::fs::create_dir_all(dir.join("avatars")).await?;
+    let part = dir.join(format!("{}.part", key));
+    if !save_field(&mut field, &part, MAX_AVATAR_BYTES).await? {
+        return Ok(HttpResponse::PayloadTooLarge()
+            .body(format!("Avatar must be at most {} bytes", MAX_AVATAR_BYTES)));
+    }
+    tokio::fs::rename(&part, dir.join(&key)).await?;
+    if let Some(old) = info.img_path.as_ref().filter(|old| **old != key) {
+        let _ = tokio::fs::remove_file(dir.join(old)).await;
+    }
+    info.img_path = Some(key);
+    let db = data.db.lock().unwrap();
+    match info.update(&db).await {
+        Ok(info) => Ok(HttpResponse::Created().json(info)),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Streams the field to `path`. `false` if it grew past `max` bytes; the
+/// partial file is removed then, and when the upload fails.
+async fn save_field(field: &mut Field, path: &Path, max: usize) -> Result<bool, actix_web::Error> {
+    async fn write(field: &mut Field, path: &Path, max: usize) -> Result<bool, actix_web::Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let mut written = 0;
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            written += chunk.len();
+            if written > max {
+                return Ok(false);
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(true)
+    }
+    let res = write(field, path, max).await;
+    if !matches!(res, Ok(true)) {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    res
+}
+
+pub async fn get_avatar(
+    session: Session,
+    path: web::Path<String>,
+    data: web::Data<State>,
+) -> HttpResponse {
+    let db = data.db.lock().unwrap();
+    let img_path = match load_profile(&db, &session, path.into_inner()).await {
+        Ok((info, _)) => info.img_path,
+        Err(resp) => return resp,
+    };
+    match img_path {
+        Some(img) => match std::fs::read(PathBuf::from(AppConfig::upload_dir()).join(&img)) {
+            Ok(bytes) => HttpResponse::Ok().content_type(avatar_mime(&img)).body(bytes),
+            Err(_) => HttpResponse::NotFound().body("Avatar file missing"),
+        },
+        None => HttpResponse::NotFound().body("No avatar uploaded"),
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+use actix::{Actor, AsyncContext, Context};
+use chrono::Utc;
+use div_db::{Db, models::{FactType, Group, Item, Record}};
+use crate::config::AppConfig;
+
+/// How often the trash is checked for rows past their retention period
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Permanently deletes records, items, fact types and groups that have been
+/// in the trash for longer than [`AppConfig::trash_retention_days`]
+pub struct TrashPurger {
+    db: Db,
+    retention: chrono::Duration,
+}
+
+impl TrashPurger {
+
+    pub fn new(db: Db) -> Self {
+        Self { db, retention: chrono::Duration::days(AppConfig::trash_retention_days()) }
+    }
+}
+
+impl Actor for TrashPurger {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PURGE_INTERVAL, |purger, ctx| {
+            let db = purger.db.clone();
+            let cutoff = Utc::now() - purger.retention;
+            ctx.spawn(actix::fut::wrap_future(async move {
+                match purge_trash(&db, cutoff).await {
+                    Ok(0) => (),
+                    Ok(n) => log::info!("Purged {} rows from the trash", n),
+                    Err(e) => log::error!("Could not purge the trash: {}", e),
+                }
+            }));
+        });
+    }
+}
+
+pub async fn purge_trash(db: &Db, cutoff: chrono::DateTime<Utc>) -> div_db::sqlx::Result<u64> {
+    let records = Record::purge_deleted(db, cutoff).await?;
+    let items = Item::purge_deleted(db, cutoff).await?;
+    let kinds = FactType::purge_deleted(db, cutoff).await?;
+    let groups = Group::purge_deleted(db, cutoff).await?;
+    Ok(records + items + kinds + groups)
+}
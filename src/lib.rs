@@ -9,6 +9,9 @@ pub mod state;
 pub mod util;
 pub mod auth;
 pub mod config;
+pub mod mail;
+pub mod jobs;
+pub mod account;
 
 // pub mod gql;
 
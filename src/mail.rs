@@ -0,0 +1,92 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use crate::config::AppConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Io(std::io::Error),
+    Send(String),
+}
+
+/// Outgoing mail transport. Handlers only ever talk to this trait so the
+/// delivery backend can be swapped per environment.
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), MailError>;
+}
+
+/// Writes mail to the application log, for local development
+pub struct LogMailer;
+
+/// Writes each mail as a separate `.eml` file in a directory, for dev and tests
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+impl Mail {
+    pub fn new<T, U, V>(to: T, subject: U, body: V) -> Self
+    where T: Into<String>, U: Into<String>, V: Into<String> {
+        Self { to: to.into(), subject: subject.into(), body: body.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        log::info!("MAIL to={} subject={:?}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S"), uuid::Uuid::new_v4()));
+        let content = format!("From: {}\nTo: {}\nSubject: {}\n\n{}\n",
+            AppConfig::mail_from(), mail.to, mail.subject, mail.body);
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Picks the mail transport from the environment: a [`FileMailer`] if
+/// MAIL_DIR is set, otherwise a [`LogMailer`]
+pub fn from_env() -> Arc<dyn Mailer> {
+    match AppConfig::mail_dir() {
+        Some(dir) => Arc::new(FileMailer::new(dir)),
+        None => Arc::new(LogMailer),
+    }
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "MAIL IO ERROR: {}", e),
+            Self::Send(e) => write!(f, "MAIL SEND ERROR: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
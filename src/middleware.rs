@@ -1,3 +1,6 @@
+pub mod rbac;
+pub mod audit;
+
 use actix_redis::RedisSession;
 use actix_cors::Cors;
 use actix_web::{
@@ -33,6 +36,12 @@ pub fn logger() -> Logger {
 pub fn oauth_middleware() {
 }
 
+/// Validated JSON bodies answer with field-level errors instead of a bare 400
+pub fn validated_json() -> actix_web_validator::JsonConfig {
+    actix_web_validator::JsonConfig::default()
+        .error_handler(|err, _req| crate::error::validation_error(err))
+}
+
 pub fn cors() -> Cors {
     Cors::default()
         .send_wildcard()
@@ -0,0 +1,78 @@
+use std::{cell::RefCell, pin::Pin, rc::Rc, task::{Context, Poll}};
+use futures::future::{ok, Future, Ready};
+use actix_service::{Service, Transform};
+use actix_session::UserSession;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::{HeaderName, HeaderValue},
+    Error,
+};
+use uuid::Uuid;
+use div_db::models::{audit, AuditContext};
+use crate::models::UserIn;
+
+type BoxedResponse<B> = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
+
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// Attributes every change written while serving a request to the session
+/// user and to the request's `X-Request-Id`, generating one if the client
+/// didn't send it. The id is echoed back on the response.
+pub struct AuditContextMiddleware;
+
+pub struct AuditContextService<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Transform<S> for AuditContextMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditContextService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditContextService { service: Rc::new(RefCell::new(service)) })
+    }
+}
+
+impl<S, B> Service for AuditContextService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = BoxedResponse<B>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = req.headers().get(REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= 128)
+            .map(String::from)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let actor = req.get_session().get::<UserIn>("uid").unwrap_or(None).map(|user| user.id);
+        let ctx = AuditContext { actor, request_id: Some(request_id.clone()) };
+        let service = self.service.clone();
+        Box::pin(audit::scope(ctx, async move {
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID), value);
+            }
+            Ok(res)
+        }))
+    }
+}
@@ -0,0 +1,150 @@
+use std::{cell::RefCell, pin::Pin, rc::Rc, task::{Context, Poll}};
+use futures::future::{ok, Future, Ready};
+use actix_service::{Service, Transform};
+use actix_session::UserSession;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    web, Error,
+};
+use div_db::models::{AdminAction, Permission, UserRole};
+use crate::{models::UserIn, state::State};
+
+type BoxedResponse<B> = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
+
+/// Rejects requests to the wrapped scope unless the session user holds
+/// the given permission, either through their role or as a direct grant
+pub struct RequirePermission(pub Permission);
+
+/// Records every request made to the wrapped scope, including rejected
+/// ones, in the `admin_actions` table
+pub struct AuditAdmin;
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    permission: Permission,
+}
+
+pub struct AuditAdminMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+fn session_user(req: &ServiceRequest) -> Option<UserIn> {
+    req.get_session().get::<UserIn>("uid").unwrap_or(None)
+}
+
+impl<S, B> Transform<S> for RequirePermission
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequirePermissionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            permission: self.0,
+        })
+    }
+}
+
+impl<S, B> Service for RequirePermissionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = BoxedResponse<B>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let permission = self.permission;
+        Box::pin(async move {
+            let user = session_user(&req).ok_or_else(|| ErrorUnauthorized("Unauthorized"))?;
+            let data = req.app_data::<web::Data<State>>().cloned()
+                .ok_or_else(|| ErrorInternalServerError("Missing app state"))?;
+            // The lock must be released before the inner handler takes it again
+            let role = {
+                let db = data.db.lock().unwrap();
+                UserRole::get_by_user(&db, user.id).await
+                    .map_err(ErrorInternalServerError)?
+            };
+            if !role.has_permission(permission) {
+                return Err(ErrorForbidden(format!("Missing permission {}", permission.as_str())));
+            }
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+impl<S, B> Transform<S> for AuditAdmin
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditAdminMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditAdminMiddleware { service: Rc::new(RefCell::new(service)) })
+    }
+}
+
+impl<S, B> Service for AuditAdminMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = BoxedResponse<B>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let uid = session_user(&req).map(|user| user.id);
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let data = req.app_data::<web::Data<State>>().cloned();
+        Box::pin(async move {
+            let fut = service.borrow_mut().call(req);
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            if let Some(data) = data {
+                let db = data.db.lock().unwrap();
+                let action = AdminAction::new(uid, method.as_str(), path.as_str(), status.as_u16());
+                if let Err(e) = action.insert(&db).await {
+                    log::error!("Could not record admin action {} {}: {}", method, path, e);
+                }
+            }
+            res
+        })
+    }
+}
@@ -2,11 +2,41 @@ pub mod request;
 pub mod response;
 pub mod user;
 pub mod auth;
+pub mod record;
+pub mod item;
+pub mod group;
+pub mod fact;
+pub mod choice;
+pub mod unit;
+pub mod place;
+pub mod attribute;
+pub mod note;
+pub mod journal;
+pub mod tag;
+pub mod field;
+pub mod template;
+pub mod timer;
+pub mod transfer;
+pub mod batch;
 
 pub use request::*;
 pub use response::*;
 pub use user::*;
 pub use auth::*;
+pub use record::*;
+pub use item::*;
+pub use group::*;
+pub use fact::*;
+pub use choice::*;
+pub use unit::*;
+pub use place::*;
+pub use attribute::*;
+pub use note::*;
+pub use journal::*;
+pub use tag::*;
+pub use field::*;
+pub use template::*;
+pub use timer::*;
 
 use serde::{Deserialize, Serialize};
 
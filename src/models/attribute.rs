@@ -0,0 +1,53 @@
+use div_db::models::{attribute::Attribute, fact::kind::ValueType};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Body of `PUT /api/attributes/{entity}/{id}/{key}`. Without a type, one
+/// is inferred from the JSON value.
+#[derive(Serialize, Deserialize)]
+pub struct SetAttribute {
+    pub value_type: Option<ValueType>,
+    pub value: Value,
+}
+
+/// Finds rows by one attribute. The value is read as JSON when it parses,
+/// so `value=3` matches the integer and `value="3"` the text.
+#[derive(Serialize, Deserialize)]
+pub struct AttributeQuery {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AttributeView {
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub key: String,
+    pub value_type: ValueType,
+    pub value: Value,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<Attribute> for AttributeView {
+    fn from(attr: Attribute) -> Self {
+        Self {
+            entity: attr.entity,
+            entity_id: attr.entity_id,
+            key: attr.key,
+            value_type: attr.value_type,
+            value: attr.value,
+            created_at: attr.created_at,
+            updated_at: attr.updated_at,
+        }
+    }
+}
+
+impl AttributeQuery {
+
+    pub fn json_value(&self) -> Value {
+        serde_json::from_str(&self.value).unwrap_or_else(|_| Value::String(self.value.clone()))
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use div_db::{Entity, BulkRow, BatchOutcome, InvalidField};
+use crate::{
+    error::UserError,
+    models::{patched, UpdateRecord, UpdateItem, UpdateFactType, UpdateFactEntry, transfer},
+};
+
+/// Most operations accepted in one batch
+pub const MAX_BATCH_OPS: usize = 100;
+
+/// Ids in a batch may name an earlier operation instead, as `$<ref>` for an
+/// operation with that `ref` or `$<index>` counting from 0
+#[derive(Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchRequestOp>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchRequestOp {
+    Create {
+        entity: Entity,
+        #[serde(rename = "ref")]
+        alias: Option<String>,
+        body: Map<String, Value>,
+    },
+    /// The row is merge-patched like `PATCH`: missing fields keep their
+    /// value and `null` clears one
+    Update {
+        entity: Entity,
+        id: String,
+        #[serde(rename = "ref")]
+        alias: Option<String>,
+        body: Value,
+        if_match: Option<String>,
+    },
+    Delete {
+        entity: Entity,
+        id: String,
+    },
+    /// Adds an item to a record
+    Link {
+        record: String,
+        item: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub status: u16,
+    pub id: Option<Uuid>,
+    pub body: Value,
+}
+
+/// Where a batch stopped. Nothing from the batch was kept.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchError {
+    pub index: usize,
+    pub errors: Vec<InvalidField>,
+    pub rolled_back: bool,
+}
+
+impl BatchRequestOp {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchRequestOp::Create { .. } => "create",
+            BatchRequestOp::Update { .. } => "update",
+            BatchRequestOp::Delete { .. } => "delete",
+            BatchRequestOp::Link { .. } => "link",
+        }
+    }
+}
+
+impl BatchError {
+
+    pub fn new<T: Into<String>, U: Into<String>>(index: usize, field: T, reason: U) -> Self {
+        Self { index, errors: vec![InvalidField::new(field, reason)], rolled_back: true }
+    }
+}
+
+/// Ids of the operations seen so far, by ref and by index
+#[derive(Default)]
+pub struct BatchRefs {
+    by_alias: HashMap<String, Uuid>,
+    by_index: Vec<Option<Uuid>>,
+}
+
+impl BatchRefs {
+
+    pub fn push(&mut self, alias: Option<&String>, id: Option<Uuid>) {
+        if let (Some(alias), Some(id)) = (alias, id) {
+            self.by_alias.insert(alias.clone(), id);
+        }
+        self.by_index.push(id);
+    }
+
+    /// An id, or a reference to one produced by an earlier operation
+    pub fn resolve(&self, field: &str, id: &str) -> Result<Uuid, InvalidField> {
+        let found = match id.strip_prefix('$') {
+            Some(name) => name.parse::<usize>().ok()
+                .and_then(|i| self.by_index.get(i).copied().flatten())
+                .or_else(|| self.by_alias.get(name).copied()),
+            None => Uuid::parse_str(id).ok(),
+        };
+        found.ok_or_else(|| InvalidField::new(field, match id.starts_with('$') {
+            true => format!("{} doesn't name an earlier operation with an id", id),
+            false => format!("{} is not an id", id),
+        }))
+    }
+}
+
+fn validation_errors(e: UserError) -> Vec<InvalidField> {
+    match e {
+        UserError::ValidationError { errors } => errors,
+        other => vec![InvalidField::new("body", other.to_string())],
+    }
+}
+
+/// The row as it will be after the update
+pub fn apply_update(current: BulkRow, body: Value) -> Result<BulkRow, Vec<InvalidField>> {
+    Ok(match current {
+        BulkRow::Record(rec) => BulkRow::Record(patched(UpdateRecord::from(rec.clone()), body)
+            .map_err(validation_errors)?
+            .apply(rec)),
+        BulkRow::Item(item) => BulkRow::Item(patched(UpdateItem::from(item.clone()), body)
+            .map_err(validation_errors)?
+            .apply(item)),
+        BulkRow::FactType(kind) => BulkRow::FactType(patched(UpdateFactType::from(kind.clone()), body)
+            .map_err(validation_errors)?
+            .apply(kind)),
+        BulkRow::FactEntry(entry) => BulkRow::FactEntry(patched(UpdateFactEntry::from(entry.clone()), body)
+            .map_err(validation_errors)?
+            .apply(entry)),
+    })
+}
+
+impl BatchResult {
+
+    pub fn new(index: usize, outcome: BatchOutcome, created: bool) -> Self {
+        match outcome {
+            BatchOutcome::Row(row) => Self {
+                index,
+                status: if created { 201 } else { 200 },
+                id: Some(row.id()),
+                body: transfer::view(row),
+            },
+            BatchOutcome::Deleted(_, id) => Self { index, status: 204, id: Some(id), body: Value::Null },
+            BatchOutcome::Linked { rid, iid } => Self {
+                index, status: 200, id: None,
+                body: serde_json::json!({ "record": rid, "item": iid }),
+            },
+        }
+    }
+}
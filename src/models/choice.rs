@@ -0,0 +1,126 @@
+use div_db::{models::{FactOption, FactRange, OptionCount, FactType, fact::kind::ValueType}, Visibility};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use super::FactTypeView;
+
+/// Body of `POST /api/fact/types`. Select types need at least one option,
+/// range types their bounds.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateChoiceType {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub options: Vec<String>,
+    pub range: Option<FactRange>,
+    #[serde(default)]
+    pub units: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct AddFactOption {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub label: String,
+}
+
+/// Body of `PATCH /api/fact/types/{id}/options/{oid}`. Renaming an option
+/// keeps the entries that chose it; `retired: false` brings a retired one
+/// back.
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateFactOption {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub label: Option<String>,
+    pub position: Option<i32>,
+    pub retired: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FactOptionView {
+    pub id: Uuid,
+    pub label: String,
+    pub position: i32,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// A select or range fact type with what its values may be
+#[derive(Serialize, Deserialize)]
+pub struct ChoiceTypeView {
+    pub kind: FactTypeView,
+    pub options: Vec<FactOptionView>,
+    pub range: Option<FactRange>,
+}
+
+/// How often each option was chosen. A select_many entry counts once for
+/// every option it chose.
+#[derive(Serialize, Deserialize)]
+pub struct OptionCounts {
+    pub tid: Uuid,
+    pub options: Vec<OptionCount>,
+}
+
+impl CreateChoiceType {
+
+    pub fn check(&self) -> Result<(), (&'static str, String)> {
+        match self.value_type {
+            ValueType::SelectOne | ValueType::SelectMany => {
+                if self.options.is_empty() {
+                    return Err(("options", "must list at least one option".into()));
+                }
+                if let Some(label) = self.options.iter().find(|l| l.trim().is_empty() || l.chars().count() > 79) {
+                    return Err(("options", format!("'{}' must be between 1 and 79 characters", label)));
+                }
+                if let Some((i, _)) = self.options.iter().enumerate().find(|(i, l)| self.options[..*i].contains(l)) {
+                    return Err(("options", format!("'{}' is listed twice", self.options[i])));
+                }
+                Ok(())
+            },
+            ValueType::Range => match &self.range {
+                Some(range) => range.check().map_err(|reason| ("range", reason)),
+                None => Err(("range", "is required".into())),
+            },
+            _ => Err(("value_type", "must be select_one, select_many or range".into())),
+        }
+    }
+
+    pub fn into_fact_type(self, uid: Uuid) -> (FactType, Vec<String>, Option<FactRange>) {
+        let kind = FactType {
+            uid,
+            name: self.name,
+            description: self.description,
+            value_type: self.value_type,
+            units: self.units,
+            visibility: self.visibility,
+            ..FactType::default()
+        };
+        match self.value_type {
+            ValueType::Range => (kind, Vec::new(), self.range),
+            _ => (kind, self.options, None),
+        }
+    }
+}
+
+impl UpdateFactOption {
+    pub fn apply(&self, option: FactOption) -> FactOption {
+        FactOption {
+            label: self.label.clone().unwrap_or(option.label),
+            position: self.position.unwrap_or(option.position),
+            ..option
+        }
+    }
+}
+
+impl From<FactOption> for FactOptionView {
+    fn from(option: FactOption) -> Self {
+        Self {
+            id: option.id,
+            label: option.label,
+            position: option.position,
+            retired_at: option.retired_at,
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use div_db::{models::{FactType, FactEntry, fact::kind::ValueType}, Visibility, Status};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateFactType {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub units: Vec<String>,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+impl CreateFactType {
+    pub fn into_fact_type(self, uid: Uuid) -> FactType {
+        FactType {
+            uid,
+            name: self.name,
+            description: self.description,
+            value_type: self.value_type,
+            units: self.units,
+            attributes: self.attributes,
+            notes: self.notes,
+            visibility: self.visibility,
+            ..FactType::default()
+        }
+    }
+}
+
+/// The fields of a fact type its owner may change. PUT replaces all of them,
+/// so missing ones take their defaults; PATCH merges into the current values.
+/// The value type is fixed once entries may exist for it, and the status only
+/// changes through archive, trash and restore.
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateFactType {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub units: Vec<String>,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+impl UpdateFactType {
+    pub fn apply(self, kind: FactType) -> FactType {
+        FactType {
+            name: self.name,
+            description: self.description,
+            units: self.units,
+            attributes: self.attributes,
+            notes: self.notes,
+            visibility: self.visibility,
+            ..kind
+        }
+    }
+}
+
+impl From<FactType> for UpdateFactType {
+    fn from(kind: FactType) -> Self {
+        Self {
+            name: kind.name,
+            description: kind.description,
+            units: kind.units,
+            attributes: kind.attributes,
+            notes: kind.notes,
+            visibility: kind.visibility,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FactTypeView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub value_type: ValueType,
+    pub units: Vec<String>,
+    pub attributes: Vec<String>,
+    pub notes: Vec<String>,
+    pub visibility: Visibility,
+    pub status: Status,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<FactType> for FactTypeView {
+    fn from(kind: FactType) -> Self {
+        Self {
+            id: kind.id,
+            uid: kind.uid,
+            name: kind.name,
+            description: kind.description,
+            value_type: kind.value_type,
+            units: kind.units,
+            attributes: kind.attributes,
+            notes: kind.notes,
+            visibility: kind.visibility,
+            status: kind.status,
+            created_at: kind.created_at,
+            deleted_at: kind.deleted_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateFactEntry {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub value: String,
+    pub units: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl CreateFactEntry {
+    pub fn into_entry(self, uid: Uuid) -> FactEntry {
+        FactEntry {
+            units: self.units,
+            visibility: self.visibility,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..FactEntry::new(uid, self.name, self.value)
+        }
+    }
+}
+
+/// The fields of a fact entry its owner may change. PUT replaces all of
+/// them, so missing ones take their defaults; PATCH merges into the current
+/// values.
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateFactEntry {
+    #[validate(length(min = 1, message = "is required"))]
+    pub value: String,
+    pub units: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl UpdateFactEntry {
+    pub fn apply(self, entry: FactEntry) -> FactEntry {
+        FactEntry {
+            value: self.value,
+            units: self.units,
+            visibility: self.visibility,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..entry
+        }
+    }
+}
+
+impl From<FactEntry> for UpdateFactEntry {
+    fn from(entry: FactEntry) -> Self {
+        Self {
+            value: entry.value,
+            units: entry.units,
+            visibility: entry.visibility,
+            attributes: entry.attributes,
+            notes: entry.notes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FactEntryView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub value: String,
+    pub units: Option<String>,
+    pub visibility: Visibility,
+    pub attributes: Vec<String>,
+    pub notes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<FactEntry> for FactEntryView {
+    fn from(entry: FactEntry) -> Self {
+        Self {
+            id: entry.id,
+            uid: entry.uid,
+            name: entry.name,
+            value: entry.value,
+            units: entry.units,
+            visibility: entry.visibility,
+            attributes: entry.attributes,
+            notes: entry.notes,
+            created_at: entry.created_at,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use div_db::{models::{FactEntry, ItemFieldValue, fact::kind::ValueType}, Visibility};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use uuid::Uuid;
+use super::{FactEntryView, FactTypeView, ItemView};
+
+/// Body of `POST /api/item/{iid}/fields`: either one of the user's fact
+/// types by id, or a name, which reuses the user's type of that name or
+/// creates it with the given type and units.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct AddItemField {
+    pub tid: Option<Uuid>,
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub units: Vec<String>,
+}
+
+/// Body of `POST /api/item/{iid}/fields/{tid}/entries`
+#[derive(Serialize, Deserialize, Validate)]
+pub struct LogFieldValue {
+    #[validate(length(min = 1, message = "is required"))]
+    pub value: String,
+    pub units: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FieldEntriesQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ItemFieldView {
+    pub field: FactTypeView,
+    pub latest: Option<FactEntryView>,
+    pub count: i64,
+}
+
+/// An item with each of its fields and the field's newest value
+#[derive(Serialize, Deserialize)]
+pub struct ItemWithFields {
+    pub item: ItemView,
+    pub fields: Vec<ItemFieldView>,
+}
+
+impl LogFieldValue {
+
+    pub fn into_entry(self, uid: Uuid, name: String) -> FactEntry {
+        FactEntry {
+            units: self.units,
+            visibility: self.visibility,
+            ..FactEntry::new(uid, name, self.value)
+        }
+    }
+}
+
+impl From<ItemFieldValue> for ItemFieldView {
+    fn from(value: ItemFieldValue) -> Self {
+        Self {
+            field: value.field.into(),
+            latest: value.latest.map(Into::into),
+            count: value.count,
+        }
+    }
+}
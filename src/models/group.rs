@@ -0,0 +1,96 @@
+use div_db::{models::Group, Visibility, Status};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateGroup {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+}
+
+impl CreateGroup {
+    pub fn into_group(self, uid: Uuid) -> Group {
+        Group {
+            description: self.description,
+            visibility: self.visibility,
+            status: self.status,
+            attributes: self.attributes,
+            ..Group::new(self.name, uid)
+        }
+    }
+}
+
+/// The fields of a group its owner may change. PUT replaces all of them, so
+/// missing ones take their defaults; PATCH merges into the current values.
+/// The status only changes through archive, trash and restore.
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateGroup {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+}
+
+impl UpdateGroup {
+    pub fn apply(self, group: Group) -> Group {
+        Group {
+            name: self.name,
+            description: self.description,
+            visibility: self.visibility,
+            attributes: self.attributes,
+            ..group
+        }
+    }
+}
+
+impl From<Group> for UpdateGroup {
+    fn from(group: Group) -> Self {
+        Self {
+            name: group.name,
+            description: group.description,
+            visibility: group.visibility,
+            attributes: group.attributes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GroupView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: Visibility,
+    pub status: Status,
+    pub attributes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Group> for GroupView {
+    fn from(group: Group) -> Self {
+        Self {
+            id: group.id,
+            uid: group.uid,
+            name: group.name,
+            description: group.description,
+            visibility: group.visibility,
+            status: group.status,
+            attributes: group.attributes,
+            created_at: group.created_at,
+            deleted_at: group.deleted_at,
+        }
+    }
+}
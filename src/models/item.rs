@@ -1,10 +1,8 @@
-
-pub use divdb::models::User;
+use div_db::{models::Item, Visibility, Status};
 use serde::{Serialize, Deserialize};
-use actix_identity::{Identity, RequestIdentity};
-use actix_web::dev::Payload;
-use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
-use futures::future::ready;
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
 pub struct ItemQuery {
@@ -13,3 +11,102 @@ pub struct ItemQuery {
     name: Option<String>,
     record_name: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateItem {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl CreateItem {
+    pub fn into_item(self, uid: Uuid) -> Item {
+        Item {
+            description: self.description,
+            visibility: self.visibility,
+            status: self.status,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..Item::new(uid, self.name)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateItem {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl UpdateItem {
+    pub fn apply(self, item: Item) -> Item {
+        Item {
+            name: self.name,
+            description: self.description,
+            visibility: self.visibility,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..item
+        }
+    }
+}
+
+impl From<Item> for UpdateItem {
+    fn from(item: Item) -> Self {
+        Self {
+            name: item.name,
+            description: item.description,
+            visibility: item.visibility,
+            attributes: item.attributes,
+            notes: item.notes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ItemView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: Visibility,
+    pub status: Status,
+    pub attributes: Vec<String>,
+    pub notes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Item> for ItemView {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id,
+            uid: item.uid,
+            name: item.name,
+            description: item.description,
+            visibility: item.visibility,
+            status: item.status,
+            attributes: item.attributes,
+            notes: item.notes,
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            deleted_at: item.deleted_at,
+        }
+    }
+}
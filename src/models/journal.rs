@@ -0,0 +1,95 @@
+use div_db::models::RecordEntry;
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::models::render_markdown;
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateRecordEntry {
+    #[validate(length(min = 1, max = 20000, message = "must be between 1 and 20000 characters"))]
+    pub content: String,
+    /// Defaults to now
+    pub logged_at: Option<DateTime<Utc>>,
+    /// Fact entries logged along with this entry
+    #[serde(default)]
+    pub facts: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateRecordEntry {
+    #[validate(length(min = 1, max = 20000, message = "must be between 1 and 20000 characters"))]
+    pub content: Option<String>,
+    pub logged_at: Option<DateTime<Utc>>,
+    pub facts: Option<Vec<Uuid>>,
+}
+
+/// Entries logged in `[from, to)`, either end open
+#[derive(Serialize, Deserialize, Default)]
+pub struct EntryRangeQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordEntryView {
+    pub id: Uuid,
+    pub rid: Uuid,
+    pub content: String,
+    pub html: String,
+    pub facts: Vec<Uuid>,
+    pub logged_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl CreateRecordEntry {
+    pub fn into_entry(self, rid: Uuid, uid: Uuid) -> RecordEntry {
+        RecordEntry {
+            logged_at: self.logged_at.unwrap_or_else(Utc::now),
+            facts: self.facts,
+            ..RecordEntry::new(rid, uid, self.content)
+        }
+    }
+}
+
+impl UpdateRecordEntry {
+    pub fn apply(self, entry: RecordEntry) -> RecordEntry {
+        RecordEntry {
+            content: self.content.unwrap_or(entry.content),
+            logged_at: self.logged_at.unwrap_or(entry.logged_at),
+            facts: self.facts.unwrap_or(entry.facts),
+            ..entry
+        }
+    }
+
+    /// Facts the update would link, if it changes them
+    pub fn facts(&self) -> &[Uuid] {
+        self.facts.as_deref().unwrap_or_default()
+    }
+}
+
+impl EntryRangeQuery {
+
+    /// Whether the range is empty because it ends before it starts
+    pub fn is_inverted(&self) -> bool {
+        matches!((self.from, self.to), (Some(from), Some(to)) if to < from)
+    }
+}
+
+impl From<RecordEntry> for RecordEntryView {
+    fn from(entry: RecordEntry) -> Self {
+        Self {
+            html: render_markdown(&entry.content),
+            id: entry.id,
+            rid: entry.rid,
+            content: entry.content,
+            facts: entry.facts,
+            logged_at: entry.logged_at,
+            created_at: entry.created_at,
+            updated_at: entry.updated_at,
+        }
+    }
+}
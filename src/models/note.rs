@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use div_db::{models::{Note, NoteRevision}, Visibility};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateNote {
+    #[validate(length(min = 1, max = 20000, message = "must be between 1 and 20000 characters"))]
+    pub content: String,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateNote {
+    #[validate(length(min = 1, max = 20000, message = "must be between 1 and 20000 characters"))]
+    pub content: Option<String>,
+    pub pinned: Option<bool>,
+    pub visibility: Option<Visibility>,
+}
+
+/// A note with its markdown rendered to sanitized HTML
+#[derive(Serialize, Deserialize)]
+pub struct NoteView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub content: String,
+    pub html: String,
+    pub pinned: bool,
+    pub visibility: Visibility,
+    pub edited: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NoteRevisionView {
+    pub id: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CreateNote {
+    pub fn apply(self, note: Note) -> Note {
+        Note { content: self.content, pinned: self.pinned, visibility: self.visibility, ..note }
+    }
+}
+
+impl UpdateNote {
+    pub fn apply(self, note: Note) -> Note {
+        Note {
+            content: self.content.unwrap_or(note.content),
+            pinned: self.pinned.unwrap_or(note.pinned),
+            visibility: self.visibility.unwrap_or(note.visibility),
+            ..note
+        }
+    }
+}
+
+impl From<Note> for NoteView {
+    fn from(note: Note) -> Self {
+        Self {
+            html: render_markdown(&note.content),
+            edited: note.updated_at > note.created_at,
+            id: note.id,
+            uid: note.uid,
+            entity: note.entity,
+            entity_id: note.entity_id,
+            content: note.content,
+            pinned: note.pinned,
+            visibility: note.visibility,
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+        }
+    }
+}
+
+impl From<NoteRevision> for NoteRevisionView {
+    fn from(rev: NoteRevision) -> Self {
+        Self { id: rev.id, content: rev.content, created_at: rev.created_at }
+    }
+}
+
+/// Markdown to HTML that is safe to embed in a page: scripts, event handlers
+/// and other active content are stripped, and links get `rel="noopener"`
+pub fn render_markdown(content: &str) -> String {
+    use pulldown_cmark::{html, Options, Parser};
+    let mut unsafe_html = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(content, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH));
+    ammonia::Builder::default()
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+/// Tera filter rendering markdown with [`render_markdown`], used in pages
+/// as `{{ note.content | markdown | safe }}`
+pub fn markdown_filter(value: &tera::Value, _args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let content = tera::try_get_value!("markdown", "value", String, value);
+    Ok(tera::Value::String(render_markdown(&content)))
+}
@@ -0,0 +1,101 @@
+use div_db::models::PlacedEntry;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// `GET /api/fact/places`: either `lat`, `lon` and `km` for entries within
+/// a radius, or `bbox=min_lon,min_lat,max_lon,max_lat`
+#[derive(Serialize, Deserialize, Default)]
+pub struct PlacesQuery {
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub km: Option<f64>,
+    pub bbox: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlaceEntryView {
+    pub id: Uuid,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub label: Option<String>,
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A GeoJSON (RFC 7946) feature collection of place entries
+#[derive(Serialize, Deserialize)]
+pub struct FeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: Uuid,
+    pub geometry: Point,
+    pub properties: FeatureProperties,
+}
+
+/// Coordinates are longitude first
+#[derive(Serialize, Deserialize)]
+pub struct Point {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub coordinates: [f64; 2],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FeatureProperties {
+    pub name: String,
+    pub label: Option<String>,
+    pub address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<PlacedEntry> for PlaceEntryView {
+    fn from(entry: PlacedEntry) -> Self {
+        Self {
+            id: entry.id,
+            name: entry.name,
+            lat: entry.lat,
+            lon: entry.lon,
+            label: entry.label,
+            address: entry.address,
+            distance_km: entry.distance_km,
+            created_at: entry.created_at,
+        }
+    }
+}
+
+impl From<PlacedEntry> for Feature {
+    fn from(entry: PlacedEntry) -> Self {
+        Self {
+            kind: "Feature".into(),
+            id: entry.id,
+            geometry: Point { kind: "Point".into(), coordinates: [entry.lon, entry.lat] },
+            properties: FeatureProperties {
+                name: entry.name,
+                label: entry.label,
+                address: entry.address,
+                created_at: entry.created_at,
+            },
+        }
+    }
+}
+
+impl From<Vec<PlacedEntry>> for FeatureCollection {
+    fn from(entries: Vec<PlacedEntry>) -> Self {
+        Self {
+            kind: "FeatureCollection".into(),
+            features: entries.into_iter().map(Feature::from).collect(),
+        }
+    }
+}
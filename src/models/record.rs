@@ -1,9 +1,8 @@
-pub use divdb::models::User;
+use div_db::{models::Record, Visibility, Status};
 use serde::{Serialize, Deserialize};
-use actix_identity::{Identity, RequestIdentity};
-use actix_web::dev::Payload;
-use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
-use futures::future::ready;
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
 pub struct RecordQuery {
@@ -11,3 +10,106 @@ pub struct RecordQuery {
     username: Option<String>,
     name: Option<String>,
 }
+
+/// Body of a new record. The id, owner and timestamps are set by the server.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateRecord {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl CreateRecord {
+    pub fn into_record(self, uid: Uuid) -> Record {
+        Record {
+            description: self.description,
+            visibility: self.visibility,
+            status: self.status,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..Record::new(uid, self.name)
+        }
+    }
+}
+
+/// The fields of a record its owner may change. PUT replaces all of them, so
+/// missing ones take their defaults; PATCH merges into the current values.
+/// The status only changes through archive, trash and restore.
+#[derive(Serialize, Deserialize, Validate, Default)]
+pub struct UpdateRecord {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+}
+
+impl UpdateRecord {
+    pub fn apply(self, rec: Record) -> Record {
+        Record {
+            name: self.name,
+            description: self.description,
+            visibility: self.visibility,
+            attributes: self.attributes,
+            notes: self.notes,
+            ..rec
+        }
+    }
+}
+
+impl From<Record> for UpdateRecord {
+    fn from(rec: Record) -> Self {
+        Self {
+            name: rec.name,
+            description: rec.description,
+            visibility: rec.visibility,
+            attributes: rec.attributes,
+            notes: rec.notes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: Visibility,
+    pub status: Status,
+    pub attributes: Vec<String>,
+    pub notes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Record> for RecordView {
+    fn from(rec: Record) -> Self {
+        Self {
+            id: rec.id,
+            uid: rec.uid,
+            name: rec.name,
+            description: rec.description,
+            visibility: rec.visibility,
+            status: rec.status,
+            attributes: rec.attributes,
+            notes: rec.notes,
+            created_at: rec.created_at,
+            updated_at: rec.updated_at,
+            deleted_at: rec.deleted_at,
+        }
+    }
+}
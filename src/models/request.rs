@@ -1,9 +1,12 @@
-use serde::{Serialize, Deserialize};
-use crate::state::State;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use crate::{state::State, error::UserError, models::response::etag};
 use actix_web::{
     HttpRequest, HttpResponse, web
 };
-use div_db::models::{Model, user::*};
+use div_db::{InvalidField, models::{Model, user::*}};
 
 pub struct AuthRequest<T: Serialize> {
     id: actix_session::Session,
@@ -27,3 +30,59 @@ pub struct ReqData<T> {
     json: web::Json<T>,
 }
 */
+
+/// Applies an RFC 7396 merge patch: objects merge member by member, `null`
+/// removes a member and any other value replaces the target outright
+pub fn merge_patch(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(members) => {
+            if !target.is_object() { *target = Value::Object(Default::default()); }
+            let obj = target.as_object_mut().unwrap();
+            for (key, val) in members {
+                if val.is_null() {
+                    obj.remove(&key);
+                } else {
+                    merge_patch(obj.entry(key).or_insert(Value::Null), val);
+                }
+            }
+        },
+        other => *target = other,
+    }
+}
+
+/// Merges `patch` into the JSON form of `current` and reads it back, so a
+/// removed member falls back to its serde default and is then validated
+/// like any other body
+pub fn patched<T>(current: T, patch: Value) -> Result<T, UserError>
+where T: Serialize + DeserializeOwned + Validate
+{
+    let body_error = |e: serde_json::Error| UserError::ValidationError {
+        errors: vec![InvalidField::new("body", e.to_string())]
+    };
+    let mut doc = serde_json::to_value(current).map_err(body_error)?;
+    merge_patch(&mut doc, patch);
+    let updated: T = serde_json::from_value(doc).map_err(body_error)?;
+    updated.validate().map_err(|e| UserError::from(&e))?;
+    Ok(updated)
+}
+
+/// Answers 412 when the request carries an `If-Match` header that doesn't
+/// name the version last modified at `updated_at`
+pub fn if_match(req: &HttpRequest, updated_at: DateTime<Utc>) -> Result<(), HttpResponse> {
+    let header = match req.headers().get("If-Match").and_then(|h| h.to_str().ok()) {
+        Some(header) => header,
+        None => return Ok(()),
+    };
+    let current = etag(updated_at);
+    let matches = header.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == current);
+    if matches { Ok(()) } else { Err(stale()) }
+}
+
+/// The entity changed since the client last read it
+pub fn stale() -> HttpResponse {
+    HttpResponse::PreconditionFailed()
+        .body("The resource was modified since it was last fetched")
+}
+
@@ -1,3 +1,68 @@
-use actix_web::{self, web, HttpRequest, HttpResponse};
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, Responder};
+use futures::future::{ready, Ready};
+use serde::Serialize;
+use chrono::{DateTime, Utc};
 
+/// Strong validator for a row last modified at `updated_at`
+pub fn etag(updated_at: DateTime<Utc>) -> String {
+    format!("\"{}\"", updated_at.timestamp_nanos())
+}
 
+/// A JSON response built from a view type, so handlers never serialize a
+/// database model directly. `Resp::view(record)` converts through the
+/// model's `Into<RecordView>` impl.
+pub struct Resp<V: Serialize> {
+    status: StatusCode,
+    etag: Option<String>,
+    body: V,
+}
+
+impl<V: Serialize> Resp<V> {
+
+    pub fn ok(body: V) -> Self {
+        Self { status: StatusCode::OK, etag: None, body }
+    }
+
+    pub fn created(body: V) -> Self {
+        Self { status: StatusCode::CREATED, etag: None, body }
+    }
+
+    pub fn view<M: Into<V>>(model: M) -> Self {
+        Self::ok(model.into())
+    }
+
+    pub fn with_status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
+    }
+
+    /// Sets the `ETag` clients send back in `If-Match` when they update
+    pub fn with_etag(self, updated_at: DateTime<Utc>) -> Self {
+        Self { etag: Some(etag(updated_at)), ..self }
+    }
+}
+
+impl<V: Serialize> Resp<Vec<V>> {
+
+    pub fn views<M: Into<V>>(models: Vec<M>) -> Self {
+        Self::ok(models.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<V: Serialize> Responder for Resp<V> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<HttpResponse, actix_web::Error>>;
+
+    fn respond_to(self, _req: &HttpRequest) -> Self::Future {
+        ready(Ok(self.into()))
+    }
+}
+
+impl<V: Serialize> From<Resp<V>> for HttpResponse {
+    fn from(resp: Resp<V>) -> Self {
+        let mut builder = HttpResponse::build(resp.status);
+        if let Some(tag) = resp.etag {
+            builder.set_header("ETag", tag);
+        }
+        builder.json(&resp.body)
+    }
+}
@@ -0,0 +1,46 @@
+use div_db::models::{Tag, TagCount};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+/// Body of `POST /api/tags/{entity}/{id}` and `PATCH /api/tags/{label}`.
+/// Labels are `name` or `namespace:name`.
+#[derive(Serialize, Deserialize)]
+pub struct TagLabel {
+    pub tag: String,
+}
+
+/// `GET /api/tags/autocomplete?prefix=wo&limit=10`
+#[derive(Serialize, Deserialize)]
+pub struct TagPrefix {
+    #[serde(default)]
+    pub prefix: String,
+    pub limit: Option<i64>,
+}
+
+/// `?tags=` on list endpoints, e.g. `?tags=work AND NOT done`
+#[derive(Serialize, Deserialize, Default)]
+pub struct TagFilter {
+    pub tags: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagView {
+    pub id: Uuid,
+    pub label: String,
+    pub namespace: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+}
+
+impl From<Tag> for TagView {
+    fn from(tag: Tag) -> Self {
+        Self { label: tag.label(), id: tag.id, namespace: tag.namespace, name: tag.name, count: None }
+    }
+}
+
+impl From<TagCount> for TagView {
+    fn from(tag: TagCount) -> Self {
+        Self { label: tag.label(), id: tag.id, namespace: tag.namespace, name: tag.name, count: Some(tag.count) }
+    }
+}
@@ -0,0 +1,97 @@
+use div_db::{models::{RecordTemplate, TemplateDefinition}, Visibility};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateTemplate {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub definition: TemplateDefinition,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Body of `POST /api/templates/{id}/versions`. Fields left out are kept
+/// from the version the request names.
+#[derive(Serialize, Deserialize)]
+pub struct NewTemplateVersion {
+    pub description: Option<String>,
+    pub definition: Option<TemplateDefinition>,
+    pub visibility: Option<Visibility>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct InstantiateTemplate {
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+}
+
+/// Body of `POST /api/templates/{id}/migrate`. Without `records`, every
+/// record of an older version is migrated.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MigrateRecords {
+    pub records: Option<Vec<Uuid>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MigratedRecords {
+    pub template: Uuid,
+    pub version: i32,
+    pub records: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplatesQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateView {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    pub version: i32,
+    pub description: Option<String>,
+    pub definition: TemplateDefinition,
+    pub visibility: Visibility,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CreateTemplate {
+    pub fn into_template(self, uid: Uuid) -> RecordTemplate {
+        RecordTemplate {
+            visibility: self.visibility,
+            ..RecordTemplate::new(uid, self.name, self.description, self.definition)
+        }
+    }
+}
+
+impl NewTemplateVersion {
+    pub fn apply(self, base: &RecordTemplate) -> (Option<String>, TemplateDefinition, Visibility) {
+        (
+            self.description.or_else(|| base.description.clone()),
+            self.definition.unwrap_or_else(|| base.definition.0.clone()),
+            self.visibility.unwrap_or(base.visibility),
+        )
+    }
+}
+
+impl From<RecordTemplate> for TemplateView {
+    fn from(t: RecordTemplate) -> Self {
+        Self {
+            id: t.id,
+            uid: t.uid,
+            name: t.name,
+            version: t.version,
+            description: t.description,
+            definition: t.definition.0,
+            visibility: t.visibility,
+            created_at: t.created_at,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use div_db::models::{Timer, TimerSpan, TimerState, TimerTotal, TotalPeriod};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use super::FactEntryView;
+
+/// Body of `POST /api/timers`. `tid` is one of the user's duration fact
+/// types; `record` or `item` tie the timer to one of their records or
+/// items.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct StartTimer {
+    pub tid: Uuid,
+    pub record: Option<Uuid>,
+    pub item: Option<Uuid>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub note: Option<String>,
+}
+
+/// Body of `PATCH /api/timers/{id}`: the times the timer really ran. The
+/// end may only be left out while the timer hasn't been stopped.
+#[derive(Serialize, Deserialize)]
+pub struct EditTimer {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimersQuery {
+    #[serde(default)]
+    pub stopped: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// `GET /api/timers/totals`. Without `from`, totals cover the last 30 days
+/// or 12 weeks.
+#[derive(Serialize, Deserialize)]
+pub struct TotalsQuery {
+    pub record: Option<Uuid>,
+    pub item: Option<Uuid>,
+    pub period: Option<TotalPeriod>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimerView {
+    pub id: Uuid,
+    pub tid: Uuid,
+    pub rid: Option<Uuid>,
+    pub iid: Option<Uuid>,
+    pub eid: Option<Uuid>,
+    pub note: Option<String>,
+    pub state: TimerState,
+    pub seconds: i64,
+    pub spans: Vec<TimerSpan>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A stopped timer with the entry it logged
+#[derive(Serialize, Deserialize)]
+pub struct StoppedTimer {
+    pub timer: TimerView,
+    pub entry: FactEntryView,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimerTotalsView {
+    pub period: TotalPeriod,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub seconds: i64,
+    pub totals: Vec<TimerTotal>,
+}
+
+impl EditTimer {
+    pub fn check(&self, stopped: bool) -> Result<(), (&'static str, &'static str)> {
+        if self.started_at > Utc::now() {
+            return Err(("started_at", "must not be in the future"));
+        }
+        match self.ended_at {
+            Some(end) if end < self.started_at => Err(("ended_at", "must not be before started_at")),
+            Some(end) if end > Utc::now() => Err(("ended_at", "must not be in the future")),
+            None if stopped => Err(("ended_at", "is required once the timer is stopped")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TimerView {
+    pub fn new(timer: Timer, spans: Vec<TimerSpan>) -> Self {
+        Self {
+            state: TimerState::of(&timer, &spans),
+            seconds: div_db::models::timer::total_seconds(&spans, Utc::now()),
+            id: timer.id,
+            tid: timer.tid,
+            rid: timer.rid,
+            iid: timer.iid,
+            eid: timer.eid,
+            note: timer.note,
+            spans,
+            stopped_at: timer.stopped_at,
+            created_at: timer.created_at,
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use validator::Validate;
+use uuid::Uuid;
+use div_db::{Entity, Listing, BulkRow, InvalidField, browse,
+    models::{Record, Item, FactType, FactEntry},
+};
+use crate::models::{
+    CreateRecord, CreateItem, CreateFactType, CreateFactEntry,
+    RecordView, ItemView, FactTypeView, FactEntryView,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl Default for Format {
+    fn default() -> Self { Format::Json }
+}
+
+/// `?format=` wins over the request's content type. `map` renames source
+/// columns to fields, as `Source:field` pairs separated by commas.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ImportQuery {
+    pub format: Option<Format>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub map: Option<String>,
+}
+
+/// `show` selects rows the same way the list endpoints do
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default)]
+    pub show: Listing,
+}
+
+/// Why one row of an import was rejected. Rows are numbered from 1,
+/// not counting a CSV header.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub errors: Vec<InvalidField>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportReport {
+    pub entity: Entity,
+    pub rows: usize,
+    pub imported: u64,
+    pub dry_run: bool,
+    pub errors: Vec<RowError>,
+}
+
+impl Format {
+
+    pub fn from_content_type(mime: &str) -> Option<Self> {
+        match mime {
+            "text/csv" => Some(Format::Csv),
+            "application/json" => Some(Format::Json),
+            "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+        }
+    }
+
+    /// What comes before the first row of an export
+    pub fn head(&self, entity: Entity) -> String {
+        match self {
+            Format::Csv => browse::to_csv(&columns(entity), &[]),
+            Format::Json => "[".into(),
+            Format::Ndjson => String::new(),
+        }
+    }
+
+    /// One page of an export. `first` is whether any rows came before it.
+    pub fn page(&self, entity: Entity, rows: &[Value], first: bool) -> String {
+        match self {
+            Format::Csv => browse::csv_rows(&columns(entity), rows),
+            Format::Json => {
+                let rows = rows.iter().map(Value::to_string).collect::<Vec<_>>().join(",");
+                if first || rows.is_empty() { rows } else { format!(",{}", rows) }
+            },
+            Format::Ndjson => rows.iter().map(|row| format!("{}\n", row)).collect(),
+        }
+    }
+
+    pub fn tail(&self) -> &'static str {
+        match self {
+            Format::Json => "]",
+            _ => "",
+        }
+    }
+
+    /// Splits an upload into rows. A row that can't be read is reported on
+    /// its own rather than failing the whole file.
+    pub fn parse(&self, entity: Entity, body: &[u8]) -> Result<Vec<Result<Map<String, Value>, InvalidField>>, InvalidField> {
+        match self {
+            Format::Json => match serde_json::from_slice::<Vec<Value>>(body) {
+                Ok(rows) => Ok(rows.into_iter().map(object).collect()),
+                Err(e) => Err(InvalidField::new("body", e.to_string())),
+            },
+            Format::Ndjson => Ok(String::from_utf8_lossy(body).lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str::<Value>(line)
+                    .map_err(|e| InvalidField::new("row", e.to_string()))
+                    .and_then(object))
+                .collect()),
+            Format::Csv => {
+                let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+                let headers = reader.headers()
+                    .map_err(|e| InvalidField::new("body", e.to_string()))?
+                    .clone();
+                Ok(reader.records()
+                    .map(|rec| rec
+                        .map(|rec| csv_row(entity, &headers, &rec))
+                        .map_err(|e| InvalidField::new("row", e.to_string())))
+                    .collect())
+            },
+        }
+    }
+}
+
+fn object(row: Value) -> Result<Map<String, Value>, InvalidField> {
+    match row {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(InvalidField::new("row", "must be an object")),
+    }
+}
+
+/// Fields holding a list, which a CSV cell gives either as a JSON array or
+/// as values separated by `;`
+fn list_fields(entity: Entity) -> &'static [&'static str] {
+    match entity {
+        Entity::FactTypes => &["units", "attributes", "notes"],
+        _ => &["attributes", "notes"],
+    }
+}
+
+/// Empty cells are left out so the field takes its default
+fn csv_row(entity: Entity, headers: &csv::StringRecord, rec: &csv::StringRecord) -> Map<String, Value> {
+    headers.iter().zip(rec.iter())
+        .filter(|(_, cell)| !cell.is_empty())
+        .map(|(col, cell)| {
+            let val = if !list_fields(entity).contains(&col) {
+                Value::String(cell.to_string())
+            } else if cell.starts_with('[') {
+                serde_json::from_str(cell).unwrap_or_else(|_| Value::String(cell.to_string()))
+            } else {
+                Value::Array(cell.split(';')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| Value::String(v.to_string()))
+                    .collect())
+            };
+            (col.to_string(), val)
+        })
+        .collect()
+}
+
+/// Reads a `Source:field,Other:field` column mapping
+pub fn column_map(spec: &str) -> Result<HashMap<String, String>, InvalidField> {
+    spec.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| match pair.splitn(2, ':').map(str::trim).collect::<Vec<_>>().as_slice() {
+            [from, to] if !from.is_empty() && !to.is_empty() => Ok((from.to_string(), to.to_string())),
+            _ => Err(InvalidField::new("map", format!("{} is not a Source:field pair", pair))),
+        })
+        .collect()
+}
+
+/// Renames mapped columns. Columns the mapping doesn't mention keep their name.
+pub fn remap(row: Map<String, Value>, map: &HashMap<String, String>) -> Map<String, Value> {
+    row.into_iter()
+        .map(|(col, val)| (map.get(&col).cloned().unwrap_or(col), val))
+        .collect()
+}
+
+fn create<T: DeserializeOwned + Validate>(row: Map<String, Value>) -> Result<T, Vec<InvalidField>> {
+    let body = serde_json::from_value::<T>(Value::Object(row))
+        .map_err(|e| vec![InvalidField::new("row", e.to_string())])?;
+    body.validate().map_err(|e| InvalidField::from_errors(&e))?;
+    Ok(body)
+}
+
+/// Validates a row the way the create endpoint for `entity` would and
+/// builds what will be inserted, owned by `uid`
+pub fn bulk_row(entity: Entity, uid: Uuid, row: Map<String, Value>) -> Result<BulkRow, Vec<InvalidField>> {
+    Ok(match entity {
+        Entity::Records => BulkRow::Record(create::<CreateRecord>(row)?.into_record(uid)),
+        Entity::Items => BulkRow::Item(create::<CreateItem>(row)?.into_item(uid)),
+        Entity::FactTypes => BulkRow::FactType(create::<CreateFactType>(row)?.into_fact_type(uid)),
+        Entity::FactEntries => BulkRow::FactEntry(create::<CreateFactEntry>(row)?.into_entry(uid)),
+    })
+}
+
+pub fn bulk_name(row: &BulkRow) -> &str {
+    match row {
+        BulkRow::Record(rec) => &rec.name,
+        BulkRow::Item(item) => &item.name,
+        BulkRow::FactType(kind) => &kind.name,
+        BulkRow::FactEntry(entry) => &entry.name,
+    }
+}
+
+/// A row as the list endpoints show it
+pub fn view(row: BulkRow) -> Value {
+    let view = match row {
+        BulkRow::Record(rec) => serde_json::to_value(RecordView::from(rec)),
+        BulkRow::Item(item) => serde_json::to_value(ItemView::from(item)),
+        BulkRow::FactType(kind) => serde_json::to_value(FactTypeView::from(kind)),
+        BulkRow::FactEntry(entry) => serde_json::to_value(FactEntryView::from(entry)),
+    };
+    view.unwrap_or(Value::Null)
+}
+
+/// CSV columns of an export, in the order the view declares them
+pub fn columns(entity: Entity) -> Vec<String> {
+    let sample = match entity {
+        Entity::Records => BulkRow::Record(Record::default()),
+        Entity::Items => BulkRow::Item(Item::default()),
+        Entity::FactTypes => BulkRow::FactType(FactType::default()),
+        Entity::FactEntries => BulkRow::FactEntry(FactEntry::default()),
+    };
+    match view(sample) {
+        Value::Object(fields) => fields.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
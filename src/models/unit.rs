@@ -0,0 +1,134 @@
+use div_db::models::{Unit, UnitRegistry, UnitStats};
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use super::FactEntryView;
+
+/// Body of `POST /api/units`. A unit of a new dimension must come with a
+/// factor of 1 before others can be defined relative to it.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct CreateUnit {
+    #[validate(length(min = 1, max = 20, message = "must be between 1 and 20 characters"))]
+    pub symbol: String,
+    #[validate(length(min = 1, max = 79, message = "must be between 1 and 79 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, max = 40, message = "must be between 1 and 40 characters"))]
+    pub dimension: String,
+    pub factor: f64,
+    #[serde(default)]
+    pub shift: f64,
+}
+
+/// `?unit=` on fact queries
+#[derive(Serialize, Deserialize, Default)]
+pub struct UnitQuery {
+    pub unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnitView {
+    pub id: Uuid,
+    pub symbol: String,
+    pub name: String,
+    pub dimension: String,
+    pub factor: f64,
+    pub shift: f64,
+    pub builtin: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Aggregates of a fact type's entries of one dimension, in `unit`
+#[derive(Serialize, Deserialize)]
+pub struct FactStatsView {
+    pub dimension: String,
+    pub unit: String,
+    pub count: i64,
+    pub sum: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+}
+
+impl CreateUnit {
+
+    /// Checks the unit against the user's registry: a known dimension
+    /// already has a canonical unit, and a new one needs this to be it.
+    pub fn check(&self, units: &UnitRegistry) -> Result<(), (&'static str, String)> {
+        if !self.factor.is_finite() || self.factor <= 0.0 {
+            return Err(("factor", "must be above 0".into()));
+        }
+        if !self.shift.is_finite() {
+            return Err(("shift", "must be a number".into()));
+        }
+        let dimension = self.dimension.trim().to_lowercase();
+        let is_canonical = self.factor == 1.0 && self.shift == 0.0;
+        match units.canonical(&dimension) {
+            None if !is_canonical => Err(("factor", format!("the first unit of {} must have a factor of 1 and no shift", dimension))),
+            Some(_) if is_canonical => Err(("factor", format!("{} already has a canonical unit", dimension))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn into_unit(self, uid: Uuid) -> Unit {
+        Unit::new(uid, self.symbol.trim().to_string(), self.name, self.dimension.trim().to_lowercase(), self.factor, self.shift)
+    }
+}
+
+impl FactEntryView {
+
+    /// The entry with its value in `unit`. Entries that aren't numbers, or
+    /// whose unit can't be converted, are returned as they are.
+    pub fn in_unit(self, units: &UnitRegistry, unit: &str) -> Self {
+        let from = match &self.units { Some(from) => from, None => return self };
+        let converted = self.value.trim().parse::<f64>().ok()
+            .and_then(|v| units.convert(v, from, unit).ok());
+        match (converted, units.find(unit)) {
+            (Some(v), Some(to)) => Self { value: v.to_string(), units: Some(to.symbol.clone()), ..self },
+            _ => self,
+        }
+    }
+}
+
+impl FactStatsView {
+
+    /// The stats, converted from the canonical unit to `unit` when it is
+    /// of the same dimension
+    pub fn new(stats: UnitStats, units: &UnitRegistry, unit: Option<&str>) -> Self {
+        let target = unit.and_then(|u| units.find(u))
+            .filter(|u| u.dimension == stats.dimension)
+            .or_else(|| units.canonical(&stats.dimension));
+        let (symbol, to) = match target {
+            Some(u) => (u.symbol.clone(), Some(u)),
+            None => (String::new(), None),
+        };
+        let point = |v: Option<f64>| v.map(|v| to.map(|u| u.from_canonical(v)).unwrap_or(v));
+        // Every summed value carried the shift once
+        let count = stats.count as f64;
+        let sum = stats.sum.map(|v| to.map(|u| (v - count * u.shift) / u.factor).unwrap_or(v));
+        Self {
+            dimension: stats.dimension,
+            unit: symbol,
+            count: stats.count,
+            sum,
+            min: point(stats.min),
+            max: point(stats.max),
+            avg: point(stats.avg),
+        }
+    }
+}
+
+impl From<Unit> for UnitView {
+    fn from(unit: Unit) -> Self {
+        Self {
+            id: unit.id,
+            builtin: unit.uid.is_none(),
+            symbol: unit.symbol,
+            name: unit.name,
+            dimension: unit.dimension,
+            factor: unit.factor,
+            shift: unit.shift,
+            created_at: unit.created_at,
+        }
+    }
+}
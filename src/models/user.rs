@@ -1,15 +1,35 @@
 pub use div_db::models::{Record, User, Item};
-use actix_web::{Responder, HttpResponse, HttpRequest};
-use futures::future::{ready, Ready};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Validate)]
+pub struct UserLogin {
+    #[validate(length(min = 1, max = 39, message = "must be between 1 and 39 characters"))]
+    pub username: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub password: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Validate)]
+pub struct UserRegister {
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+    #[validate(
+        length(min = 3, max = 39, message = "must be between 3 and 39 characters"),
+        custom = "div_db::validate::valid_username",
+    )]
+    pub username: String,
+    #[validate(length(min = 8, max = 128, message = "must be between 8 and 128 characters"))]
+    pub password: String
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct UserIn {
-    id: Uuid,
-    email: String,
-    username: String,
+    pub id: Uuid,
+    pub email: String,
+    pub username: String,
 }
 
 impl From<User> for UserIn {
@@ -18,25 +38,60 @@ impl From<User> for UserIn {
     }
 }
 
+/// What clients see of a user. There is no password field, so the hash
+/// can't be written out by accident.
 #[derive(Serialize, Deserialize)]
-pub struct UserQuery {
-    id: Option<Uuid>,
-    username: Option<String>,
-    email: Option<String>
+pub struct UserView {
+    pub id: Uuid,
+    pub email: String,
+    pub username: String,
+    pub confirmed: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-pub struct Resp<'de, T: Deserialize<'de> + Serialize + div_db::models::Model>(&'de T);
+impl From<User> for UserView {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            confirmed: user.confirmed,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
 
-impl<'de, T> actix_web::Responder for Resp<'de, T>
-where
-    T: div_db::models::Model + Deserialize<'de> + Serialize {
-    type Error = actix_web::Error;
-    type Future = Ready<Result<HttpResponse, actix_web::Error>>;
+/// The account fields a user may change themselves. A new email address
+/// has to be verified again.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct UpdateUser {
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+    #[validate(
+        length(min = 3, max = 39, message = "must be between 3 and 39 characters"),
+        custom = "div_db::validate::valid_username",
+    )]
+    pub username: String,
+}
 
-    fn respond_to(self, req: &HttpRequest) -> Self::Future {
-        ready(Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .json(&self.0)))
+impl UpdateUser {
+    pub fn apply(self, user: User) -> User {
+        let confirmed = user.confirmed && self.email == user.email;
+        User { email: self.email, username: self.username, confirmed, ..user }
+    }
+}
 
+impl From<User> for UpdateUser {
+    fn from(user: User) -> Self {
+        Self { email: user.email, username: user.username }
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct UserQuery {
+    id: Option<Uuid>,
+    username: Option<String>,
+    email: Option<String>
+}
@@ -1,16 +1,49 @@
 use super::config::AppConfig;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, Ordering}};
+use chrono::{DateTime, Utc};
+use actix_web::dev::Server;
 use div_cloud::cognito::{types::*, CognitoClient};
 use actix::{Actor, Addr, Context, Handler};
 use div_db::db::Db;
+use crate::mail::{self, Mailer};
 
 pub struct LoggedInUsers {}
 
+/// Handle to the running HTTP server, filled in once the server has been
+/// started so admin handlers can drain or stop it
+#[derive(Clone, Default)]
+pub struct ServerHandle {
+    server: Arc<Mutex<Option<Server>>>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl ServerHandle {
+
+    pub fn set(&self, server: Server) {
+        *self.server.lock().unwrap() = Some(server);
+    }
+
+    pub fn get(&self) -> Option<Server> {
+        self.server.lock().unwrap().clone()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn set_stopping(&self) {
+        self.stopping.store(true, Ordering::SeqCst)
+    }
+}
+
 #[derive(Clone)]
 pub struct State {
     pub cognito: CognitoClient,
     pub db: Arc<Mutex<Db>>,
-    pub tera: tera::Tera,
+    pub tera: Arc<RwLock<tera::Tera>>,
+    pub mailer: Arc<dyn Mailer>,
+    pub server: ServerHandle,
+    pub started_at: DateTime<Utc>,
 }
 
 impl State {
@@ -22,7 +55,11 @@ impl State {
         let mut tera = tera::Tera::new("assets/static/templates/**/*")
             .expect("Could not load tera");
         tera.autoescape_on(vec!["html"]);
-        Self { db: Arc::new(Mutex::new(db)), cognito: idp, tera }
+        tera.register_filter("markdown", crate::models::note::markdown_filter);
+        Self {
+            db: Arc::new(Mutex::new(db)), cognito: idp, tera: Arc::new(RwLock::new(tera)),
+            mailer: mail::from_env(), server: ServerHandle::default(), started_at: Utc::now(),
+        }
     }
 
     pub fn new_blocking() -> Self {
@@ -31,7 +68,11 @@ impl State {
         let _config = AppConfig::default();
         let mut tera = tera::Tera::new("assets/static/templates/**/*").expect("Could not load tera");
         tera.autoescape_on(vec!["html"]);
-        Self { db: Arc::new(Mutex::new(db)), cognito: idp, tera }
+        tera.register_filter("markdown", crate::models::note::markdown_filter);
+        Self {
+            db: Arc::new(Mutex::new(db)), cognito: idp, tera: Arc::new(RwLock::new(tera)),
+            mailer: mail::from_env(), server: ServerHandle::default(), started_at: Utc::now(),
+        }
     }
 }
 
@@ -1,4 +1,4 @@
-use div_api::auth::PwVerifier;
+use div_api::auth::{PwVerifier, HashParams};
 
 #[test]
 fn it_hashes_password_ok() -> argon2::Result<String> {
@@ -15,3 +15,24 @@ fn it_verifies_hashed_password() -> argon2::Result<()> {
     debug_assert_eq!(true, ver.verify(pw, hash)?);
     Ok(())
 }
+
+#[test]
+fn it_salts_each_hash() -> argon2::Result<()> {
+    let ver = PwVerifier::new();
+    let (h1, h2) = (ver.hash("password")?, ver.hash("password")?);
+    assert_ne!(h1, h2);
+    assert!(ver.verify("password", &h1)? && ver.verify("password", &h2)?);
+    Ok(())
+}
+
+#[test]
+fn it_rehashes_when_params_change() -> argon2::Result<()> {
+    let params = HashParams { mem_cost: 4096, time_cost: 1, lanes: 1, hash_length: 32 };
+    let old = PwVerifier::with_params(b"secret", b"", params.clone());
+    let hash = old.hash("password")?;
+    assert!(!old.needs_rehash(&hash));
+    let new = PwVerifier::with_params(b"secret", b"", HashParams { time_cost: 2, ..params });
+    assert!(new.needs_rehash(&hash));
+    assert!(new.verify("password", &hash)?);
+    Ok(())
+}
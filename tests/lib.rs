@@ -1,4 +1,5 @@
 mod auth;
+mod models;
 mod public;
 mod session;
 mod user;
@@ -0,0 +1,446 @@
+use div_api::models::{merge_patch, patched, UpdateRecord};
+use div_db::Visibility;
+use serde_json::json;
+
+#[test]
+fn merge_patch_follows_rfc_7396() {
+    let mut doc = json!({ "a": "b", "c": { "d": "e", "f": "g" }, "tags": ["x"] });
+    merge_patch(&mut doc, json!({ "a": "z", "c": { "f": null }, "tags": ["y", "z"] }));
+    assert_eq!(doc, json!({ "a": "z", "c": { "d": "e" }, "tags": ["y", "z"] }));
+}
+
+#[test]
+fn patch_null_resets_to_default() {
+    let current = UpdateRecord {
+        name: "Sleep".into(),
+        description: Some("nightly".into()),
+        visibility: Visibility::Public,
+        ..UpdateRecord::default()
+    };
+    let updated = patched(current, json!({ "description": null, "visibility": null })).unwrap();
+    assert_eq!(updated.name, "Sleep");
+    assert!(updated.description.is_none());
+    assert!(updated.visibility == Visibility::Private);
+}
+
+#[test]
+fn patch_is_validated() {
+    let current = UpdateRecord { name: "Sleep".into(), ..UpdateRecord::default() };
+    assert!(patched(current, json!({ "name": "" })).is_err());
+}
+
+mod transfer {
+    use div_api::models::transfer::{bulk_row, column_map, remap, Format};
+    use div_db::{BulkRow, Entity};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn csv_rows_are_mapped_and_split() {
+        let csv = b"Title,Tags,Blurb\nSleep,health; nightly,\nRuns,\"[\"\"sport\"\"]\",5k\n";
+        let map = column_map("Title:name, Tags:attributes, Blurb:description").unwrap();
+        let rows = Format::Csv.parse(Entity::Records, csv).unwrap().into_iter()
+            .map(|row| remap(row.unwrap(), &map))
+            .collect::<Vec<_>>();
+        assert_eq!(serde_json::Value::Object(rows[0].clone()),
+            json!({ "name": "Sleep", "attributes": ["health", "nightly"] }));
+        assert_eq!(rows[1]["attributes"], json!(["sport"]));
+        assert_eq!(rows[1]["description"], json!("5k"));
+    }
+
+    #[test]
+    fn bad_rows_are_reported_alone() {
+        let rows = Format::Ndjson.parse(Entity::FactEntries, b"{\"name\":\"w\",\"value\":\"1\"}\nnot json\n\n[1]\n")
+            .unwrap();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].is_ok() && rows[1].is_err() && rows[2].is_err());
+        assert!(column_map("name").is_err());
+    }
+
+    #[test]
+    fn rows_are_validated_like_create() {
+        let uid = Uuid::new_v4();
+        let row = |v: serde_json::Value| v.as_object().unwrap().clone();
+        match bulk_row(Entity::Items, uid, row(json!({ "name": "Bike" }))) {
+            Ok(BulkRow::Item(item)) => assert_eq!(item.uid, uid),
+            _ => panic!("expected an item"),
+        }
+        let errors = bulk_row(Entity::Records, uid, row(json!({ "name": "" }))).err().unwrap();
+        assert_eq!(errors[0].field, "name");
+    }
+
+    #[test]
+    fn json_pages_join_into_one_array() {
+        let page = |rows: &[serde_json::Value], first| Format::Json.page(Entity::Records, rows, first);
+        let out = format!("{}{}{}{}", Format::Json.head(Entity::Records),
+            page(&[json!(1), json!(2)], true), page(&[json!(3)], false), Format::Json.tail());
+        assert_eq!(out, "[1,2,3]");
+    }
+}
+
+mod batch {
+    use div_api::models::batch::{apply_update, BatchRefs, BatchRequest, BatchRequestOp};
+    use div_db::{models::{FactEntry, FactType, Record}, BulkRow, Entity, Status};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn operations_parse_by_tag() {
+        let req: BatchRequest = serde_json::from_value(json!({ "operations": [
+            { "op": "create", "entity": "records", "ref": "trip", "body": { "name": "Trip" } },
+            { "op": "link", "record": "$trip", "item": "$0" },
+            { "op": "delete", "entity": "fact_entries", "id": Uuid::nil().to_string() },
+        ]})).unwrap();
+        assert!(matches!(&req.operations[0],
+            BatchRequestOp::Create { entity: Entity::Records, alias: Some(a), .. } if a == "trip"));
+        assert_eq!(req.operations[1].name(), "link");
+    }
+
+    #[test]
+    fn refs_resolve_by_alias_and_index() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut refs = BatchRefs::default();
+        refs.push(Some(&"trip".to_string()), Some(a));
+        refs.push(None, None);
+        refs.push(None, Some(b));
+        assert_eq!(refs.resolve("id", "$trip").unwrap(), a);
+        assert_eq!(refs.resolve("id", "$2").unwrap(), b);
+        assert_eq!(refs.resolve("id", &a.to_string()).unwrap(), a);
+        assert!(refs.resolve("id", "$1").is_err());
+        assert!(refs.resolve("id", "$later").is_err());
+    }
+
+    #[test]
+    fn updates_merge_into_the_pending_row() {
+        let rec = Record { description: Some("old".into()), ..Record::new(Uuid::new_v4(), "Trip") };
+        match apply_update(BulkRow::Record(rec), json!({ "description": "new" })) {
+            Ok(BulkRow::Record(rec)) => {
+                assert_eq!(rec.name, "Trip");
+                assert_eq!(rec.description.as_deref(), Some("new"));
+            },
+            _ => panic!("expected a record"),
+        }
+    }
+
+    #[test]
+    fn updates_leave_the_status_alone() {
+        let rec = Record { status: Status::Archived, ..Record::new(Uuid::new_v4(), "Trip") };
+        match apply_update(BulkRow::Record(rec), json!({ "status": "active" })) {
+            Ok(BulkRow::Record(rec)) => assert!(rec.status == Status::Archived),
+            _ => panic!("expected a record"),
+        }
+    }
+
+    #[test]
+    fn fact_updates_can_clear_nullable_fields() {
+        let kind = FactType { name: "pages".into(), description: Some("Pages read".into()), ..FactType::default() };
+        match apply_update(BulkRow::FactType(kind), json!({ "description": null })) {
+            Ok(BulkRow::FactType(kind)) => assert!(kind.description.is_none()),
+            _ => panic!("expected a fact type"),
+        }
+        let entry = FactEntry { value: "3".into(), units: Some("kg".into()), ..FactEntry::default() };
+        match apply_update(BulkRow::FactEntry(entry), json!({ "units": null })) {
+            Ok(BulkRow::FactEntry(entry)) => {
+                assert!(entry.units.is_none());
+                assert_eq!(entry.value, "3");
+            },
+            _ => panic!("expected a fact entry"),
+        }
+    }
+}
+
+mod attribute {
+    use div_api::models::{AttributeQuery, SetAttribute};
+    use div_db::models::fact::kind::ValueType;
+    use serde_json::json;
+
+    #[test]
+    fn query_values_are_read_as_json_when_they_parse() {
+        let query = |value: &str| AttributeQuery { key: "color".into(), value: value.into() };
+        assert_eq!(query("red").json_value(), json!("red"));
+        assert_eq!(query("3").json_value(), json!(3));
+        assert_eq!(query("\"3\"").json_value(), json!("3"));
+        assert_eq!(query("true").json_value(), json!(true));
+    }
+
+    #[test]
+    fn value_type_is_optional() {
+        let body: SetAttribute = serde_json::from_value(json!({ "value": "2021-03-01" })).unwrap();
+        assert!(body.value_type.is_none());
+        let body: SetAttribute = serde_json::from_value(json!({ "value_type": "date", "value": "2021-03-01" })).unwrap();
+        assert_eq!(body.value_type, Some(ValueType::Date));
+    }
+}
+
+mod note {
+    use div_api::models::{render_markdown, UpdateNote, NoteView};
+    use div_db::{models::{Note, NoteOwner}, Visibility};
+    use uuid::Uuid;
+
+    #[test]
+    fn markdown_is_rendered_and_sanitized() {
+        let html = render_markdown("**bold** <script>alert(1)</script> [x](javascript:alert(1))");
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn updates_keep_fields_they_leave_out() {
+        let note = Note::new(Uuid::nil(), NoteOwner::Items, Uuid::nil(), "first".into());
+        let note = UpdateNote { pinned: Some(true), ..UpdateNote::default() }.apply(note);
+        assert_eq!(note.content, "first");
+        assert!(note.pinned);
+        let view = NoteView::from(note);
+        assert_eq!(view.html, "<p>first</p>\n");
+        assert!(view.visibility == Visibility::Private);
+    }
+}
+
+mod journal {
+    use chrono::{Duration, Utc};
+    use div_api::models::{CreateRecordEntry, UpdateRecordEntry, EntryRangeQuery};
+    use uuid::Uuid;
+
+    #[test]
+    fn entries_default_to_now_and_keep_their_facts() {
+        let fact = Uuid::new_v4();
+        let entry = CreateRecordEntry { content: "ran 5k".into(), logged_at: None, facts: vec![fact] }
+            .into_entry(Uuid::nil(), Uuid::nil());
+        assert!(Utc::now() - entry.logged_at < Duration::seconds(5));
+        let yesterday = Utc::now() - Duration::days(1);
+        let entry = UpdateRecordEntry { logged_at: Some(yesterday), ..UpdateRecordEntry::default() }.apply(entry);
+        assert_eq!(entry.content, "ran 5k");
+        assert_eq!(entry.logged_at, yesterday);
+        assert_eq!(entry.facts, vec![fact]);
+    }
+
+    #[test]
+    fn ranges_ending_before_they_start_are_rejected() {
+        let now = Utc::now();
+        let range = |from, to| EntryRangeQuery { from, to, ..EntryRangeQuery::default() };
+        assert!(range(Some(now), Some(now - Duration::hours(1))).is_inverted());
+        assert!(!range(Some(now), None).is_inverted());
+        assert!(!range(Some(now), Some(now)).is_inverted());
+    }
+}
+
+mod tag {
+    use chrono::Utc;
+    use div_api::models::TagView;
+    use div_db::models::{Tag, TagCount};
+    use uuid::Uuid;
+
+    #[test]
+    fn views_carry_the_full_label() {
+        let tag = Tag { id: Uuid::new_v4(), uid: Uuid::new_v4(), namespace: "proj".into(), name: "alpha".into(), created_at: Utc::now() };
+        let view = TagView::from(tag);
+        assert_eq!(view.label, "proj:alpha");
+        assert!(view.count.is_none());
+        let count = TagCount { id: Uuid::new_v4(), namespace: String::new(), name: "urgent".into(), count: 3 };
+        let view = TagView::from(count);
+        assert_eq!(view.label, "urgent");
+        assert_eq!(view.count, Some(3));
+    }
+}
+
+mod field {
+    use div_api::models::LogFieldValue;
+    use div_db::Visibility;
+    use uuid::Uuid;
+
+    fn value(v: &str) -> LogFieldValue {
+        LogFieldValue { value: v.into(), units: None, visibility: Visibility::default() }
+    }
+
+    #[test]
+    fn entries_are_named_after_the_field() {
+        let uid = Uuid::new_v4();
+        let entry = LogFieldValue { units: Some("km".into()), ..value("48213") }.into_entry(uid, "mileage".into());
+        assert_eq!(entry.uid, uid);
+        assert_eq!(entry.name, "mileage");
+        assert_eq!(entry.units.as_deref(), Some("km"));
+    }
+}
+
+mod template {
+    use div_api::models::{CreateTemplate, NewTemplateVersion};
+    use div_db::Visibility;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn definitions_default_to_empty() {
+        let body: CreateTemplate = serde_json::from_value(json!({ "name": "Workout" })).unwrap();
+        let template = body.into_template(Uuid::new_v4());
+        assert_eq!(template.version, 1);
+        assert!(template.definition.0.items.is_empty());
+        assert!(template.definition.0.check().is_empty());
+    }
+
+    #[test]
+    fn new_versions_keep_what_they_leave_out() {
+        let body: CreateTemplate = serde_json::from_value(json!({
+            "name": "Book read",
+            "description": "One book",
+            "visibility": "public",
+            "definition": { "fact_types": [{ "name": "pages", "value_type": "integer" }] },
+        })).unwrap();
+        let base = body.into_template(Uuid::new_v4());
+        let update = NewTemplateVersion { description: None, definition: None, visibility: None };
+        let (description, definition, visibility) = update.apply(&base);
+        assert_eq!(description.as_deref(), Some("One book"));
+        assert_eq!(definition.fact_types[0].name, "pages");
+        assert!(visibility == Visibility::Public);
+    }
+}
+
+mod choice {
+    use div_api::models::CreateChoiceType;
+    use div_db::models::fact::kind::ValueType;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn body(v: serde_json::Value) -> CreateChoiceType {
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn select_types_need_distinct_options() {
+        assert!(body(json!({ "name": "mood", "value_type": "select_one" })).check().is_err());
+        assert!(body(json!({ "name": "mood", "value_type": "select_one", "options": ["good", "good"] })).check().is_err());
+        assert!(body(json!({ "name": "mood", "value_type": "integer", "options": ["good"] })).check().is_err());
+        let ok = body(json!({ "name": "mood", "value_type": "select_many", "options": ["good", "bad"] }));
+        assert!(ok.check().is_ok());
+        let (kind, options, range) = ok.into_fact_type(Uuid::new_v4());
+        assert!(kind.value_type == ValueType::SelectMany);
+        assert_eq!(options, vec!["good", "bad"]);
+        assert!(range.is_none());
+    }
+
+    #[test]
+    fn range_types_need_valid_bounds() {
+        assert!(body(json!({ "name": "pain", "value_type": "range" })).check().is_err());
+        assert!(body(json!({ "name": "pain", "value_type": "range", "range": { "min": 10, "max": 1 } })).check().is_err());
+        let ok = body(json!({ "name": "pain", "value_type": "range", "options": ["x"], "range": { "min": 1, "max": 10, "step": 1 } }));
+        assert!(ok.check().is_ok());
+        let (_, options, range) = ok.into_fact_type(Uuid::new_v4());
+        assert!(options.is_empty());
+        assert_eq!(range.map(|r| r.max), Some(10.0));
+    }
+}
+
+mod unit {
+    use div_api::models::{CreateUnit, FactEntryView, FactStatsView};
+    use div_db::{models::{Unit, UnitRegistry, UnitStats}, Visibility};
+    use uuid::Uuid;
+
+    fn registry() -> UnitRegistry {
+        let unit = |symbol: &str, dimension: &str, factor: f64| Unit {
+            uid: None,
+            ..Unit::new(Uuid::nil(), symbol.into(), symbol.into(), dimension.into(), factor, 0.0)
+        };
+        UnitRegistry { units: vec![unit("kg", "mass", 1.0), unit("lb", "mass", 0.45359237)] }
+    }
+
+    fn entry(value: &str, units: Option<&str>) -> FactEntryView {
+        FactEntryView {
+            id: Uuid::new_v4(),
+            uid: Uuid::new_v4(),
+            name: "weight".into(),
+            value: value.into(),
+            units: units.map(Into::into),
+            visibility: Visibility::default(),
+            attributes: Vec::new(),
+            notes: Vec::new(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn entries_convert_when_they_can() {
+        let units = registry();
+        let lb = entry("10", Some("kg")).in_unit(&units, "lb");
+        assert_eq!(lb.units.as_deref(), Some("lb"));
+        assert!((lb.value.parse::<f64>().unwrap() - 22.046226218).abs() < 1e-6);
+        assert_eq!(entry("heavy", Some("kg")).in_unit(&units, "lb").value, "heavy");
+        assert_eq!(entry("10", None).in_unit(&units, "lb").value, "10");
+        let stats = UnitStats { dimension: "mass".into(), count: 2, sum: Some(4.5359237), min: None, max: None, avg: None };
+        let view = FactStatsView::new(stats, &units, Some("lb"));
+        assert_eq!(view.unit, "lb");
+        assert!((view.sum.unwrap() - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn new_dimensions_start_with_a_canonical_unit() {
+        let units = registry();
+        let body = |dimension: &str, factor: f64| CreateUnit {
+            symbol: "x".into(), name: "x".into(), dimension: dimension.into(), factor, shift: 0.0,
+        };
+        assert!(body("mass", 14.0).check(&units).is_ok());
+        assert!(body("mass", 1.0).check(&units).is_err());
+        assert!(body("steps", 1.0).check(&units).is_ok());
+        assert!(body("steps", 1000.0).check(&units).is_err());
+        assert!(body("mass", 0.0).check(&units).is_err());
+    }
+}
+
+mod timer {
+    use div_api::models::{EditTimer, TimerView};
+    use div_db::models::{Timer, TimerSpan, TimerState};
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn edits_need_an_end_once_stopped() {
+        let now = Utc::now();
+        let edit = |start: Duration, end: Option<Duration>| EditTimer {
+            started_at: now - start,
+            ended_at: end.map(|e| now - e),
+        };
+        assert!(edit(Duration::hours(2), Some(Duration::hours(1))).check(true).is_ok());
+        assert!(edit(Duration::hours(2), None).check(false).is_ok());
+        assert!(edit(Duration::hours(2), None).check(true).is_err());
+        assert!(edit(Duration::hours(1), Some(Duration::hours(2))).check(true).is_err());
+        assert!(edit(-Duration::hours(1), None).check(false).is_err());
+    }
+
+    #[test]
+    fn views_sum_spans() {
+        let timer = Timer::new(Uuid::new_v4(), Uuid::new_v4(), None, None, None);
+        let start = Utc::now() - Duration::hours(1);
+        let spans = vec![
+            TimerSpan { timer: timer.id, started_at: start, ended_at: Some(start + Duration::minutes(20)) },
+            TimerSpan { timer: timer.id, started_at: start + Duration::minutes(30), ended_at: Some(start + Duration::minutes(40)) },
+        ];
+        let view = TimerView::new(timer, spans);
+        assert_eq!(view.seconds, 30 * 60);
+        assert!(view.state == TimerState::Paused);
+    }
+}
+
+mod place {
+    use div_api::models::FeatureCollection;
+    use div_db::models::PlacedEntry;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn geojson_puts_longitude_first() {
+        let entry = PlacedEntry {
+            id: Uuid::new_v4(),
+            name: "visited".into(),
+            lat: 51.5,
+            lon: -0.12,
+            label: Some("London".into()),
+            address: None,
+            distance_km: None,
+            created_at: chrono::Utc::now(),
+        };
+        let doc = serde_json::to_value(FeatureCollection::from(vec![entry])).unwrap();
+        assert_eq!(doc["type"], "FeatureCollection");
+        assert_eq!(doc["features"][0]["type"], "Feature");
+        assert_eq!(doc["features"][0]["geometry"], json!({ "type": "Point", "coordinates": [-0.12, 51.5] }));
+        assert_eq!(doc["features"][0]["properties"]["label"], "London");
+    }
+}
@@ -16,3 +16,49 @@ async fn get_all_users_ok() -> actix_web::Result<()> {
     assert_eq!(resp.status(), http::StatusCode::OK);
     Ok(())
 }
+
+#[actix_rt::test]
+async fn patch_record_by_name_is_routed() -> actix_web::Result<()> {
+    let mut app = test::init_service(create_app()).await;
+    let req = test::TestRequest::patch()
+        .uri(&format!("/api/record/user/{}/Sleep", uuid::Uuid::new_v4()))
+        .header(http::header::IF_MATCH, "\"0\"")
+        .set_json(&serde_json::json!({ "description": "naps count" }))
+        .to_request();
+    let resp = app.call(req).await?;
+    assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    Ok(())
+}
+
+#[test]
+fn user_view_omits_password_hash() {
+    use div_api::models::{User, UserView};
+    let user = User::new("a@b.co", "alice", Some("$argon2id$hash".to_string()));
+    let view = serde_json::to_value(UserView::from(user.clone())).unwrap();
+    assert!(view.get("password").is_none());
+    assert!(serde_json::to_value(user).unwrap().get("password").is_none());
+}
+
+#[test]
+fn register_reports_each_invalid_field() {
+    use div_api::models::UserRegister;
+    use div_db::InvalidField;
+    use validator::Validate;
+    let user = UserRegister {
+        email: "not-an-email".into(),
+        username: "no spaces".into(),
+        password: "short".into(),
+    };
+    let fields = InvalidField::from_errors(&user.validate().unwrap_err())
+        .into_iter().map(|e| e.field).collect::<Vec<String>>();
+    assert_eq!(fields, vec!["email", "password", "username"]);
+}
+
+#[test]
+fn create_record_owner_comes_from_session() {
+    use div_api::models::CreateRecord;
+    let body = serde_json::json!({ "name": "Sleep", "uid": uuid::Uuid::new_v4() });
+    let owner = uuid::Uuid::new_v4();
+    let rec = serde_json::from_value::<CreateRecord>(body).unwrap().into_record(owner);
+    assert_eq!(rec.uid, owner);
+}